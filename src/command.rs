@@ -1,12 +1,15 @@
-use std::{collections::HashMap, time::Duration};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Context;
 
 use crate::{
-    db::stream::{ReqStreamEntryID, StreamEntryID},
+    db::stream::{ReqStreamEntryID, StreamEntryData, StreamEntryID, StreamTrim, TrimStrategy},
     resp::{decode_array_of_bulkstrings, RespValue},
 };
 
+/// Number of pending entries XAUTOCLAIM returns without COUNT.
+const DEFAULT_XAUTOCLAIM_COUNT: usize = 100;
+
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) enum InfoArg {
     Replication,
@@ -23,12 +26,125 @@ pub(crate) enum ReplConfArg {
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) enum ConfigArg {
     Get(String),
+    Set(Vec<(String, String)>),
+}
+
+/// Where reading a stream starts from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum XReadStart {
+    /// Entries after this ID.
+    After(StreamEntryID),
+    /// Entries added from now on with `$` in XREAD, entries never delivered to the group with
+    /// `>` in XREADGROUP.
+    New,
+    /// The last entry of the stream, `+` in XREAD.
+    Last,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct XReadStreamArg {
     pub(crate) key: Vec<u8>,
-    pub(crate) start: Option<StreamEntryID>,
+    pub(crate) start: XReadStart,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum XGroupArg {
+    /// `id` is `None` for `$`, the last ID of the stream.
+    Create {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        id: Option<StreamEntryID>,
+        mkstream: bool,
+        entries_read: Option<u64>,
+    },
+    SetId {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        id: Option<StreamEntryID>,
+        entries_read: Option<u64>,
+    },
+    Destroy {
+        key: Vec<u8>,
+        group: Vec<u8>,
+    },
+    CreateConsumer {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        consumer: Vec<u8>,
+    },
+    DelConsumer {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        consumer: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum XInfoArg {
+    /// `full` is the COUNT of FULL, 0 meaning no limit.
+    Stream {
+        key: Vec<u8>,
+        full: Option<usize>,
+    },
+    Groups(Vec<u8>),
+    Consumers {
+        key: Vec<u8>,
+        group: Vec<u8>,
+    },
+}
+
+/// Extended form of XPENDING, listing the pending entries in a range.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct XPendingRange {
+    /// Only list entries idle for at least this many milliseconds.
+    pub(crate) min_idle_time: Option<u64>,
+    pub(crate) start: StreamEntryID,
+    pub(crate) end: StreamEntryID,
+    pub(crate) count: usize,
+    pub(crate) consumer: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct XClaimOptions {
+    /// Idle time in milliseconds to give the claimed entries.
+    pub(crate) idle: Option<u64>,
+    /// Unix time in milliseconds to record as the last delivery of the claimed entries.
+    pub(crate) time: Option<u64>,
+    /// Delivery count to give the claimed entries instead of incrementing it.
+    pub(crate) retry_count: Option<u64>,
+    /// Also claim existing entries that are not pending in the group.
+    pub(crate) force: bool,
+    /// Reply with IDs only, without incrementing delivery counts.
+    pub(crate) just_id: bool,
+    /// Moves the last delivered ID of the group forward to this ID.
+    pub(crate) last_id: Option<StreamEntryID>,
+}
+
+/// Expiry given to SET.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SetExpiry {
+    /// Milliseconds from now, as clients usually send it.
+    Px(Duration),
+    /// Unix time in milliseconds. Writes are propagated in this form so that replicas and the
+    /// AOF expire the key at the same moment as the master.
+    PxAt(u64),
+}
+
+impl SetExpiry {
+    pub(crate) fn at(deadline: SystemTime) -> Self {
+        let millis = deadline
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Self::PxAt(millis as u64)
+    }
+
+    pub(crate) fn deadline(&self) -> SystemTime {
+        match self {
+            Self::Px(dur) => SystemTime::now() + *dur,
+            Self::PxAt(millis) => UNIX_EPOCH + Duration::from_millis(*millis),
+        }
+    }
 }
 
 // TODO: remove Clone trait
@@ -39,7 +155,7 @@ pub(crate) enum Command {
     Set {
         key: Vec<u8>,
         value: Vec<u8>,
-        px: Option<Duration>,
+        expiry: Option<SetExpiry>,
     },
     Get(Vec<u8>),
     Info(Option<InfoArg>),
@@ -58,35 +174,125 @@ pub(crate) enum Command {
     XAdd {
         key: Vec<u8>,
         entry_id: Option<ReqStreamEntryID>,
-        data: HashMap<Vec<u8>, Vec<u8>>,
+        data: StreamEntryData,
+        /// Do not create the stream if it does not exist.
+        nomkstream: bool,
+        trim: Option<StreamTrim>,
+    },
+    XLen(Vec<u8>),
+    XDel {
+        key: Vec<u8>,
+        ids: Vec<StreamEntryID>,
+    },
+    XTrim {
+        key: Vec<u8>,
+        trim: StreamTrim,
+    },
+    XSetId {
+        key: Vec<u8>,
+        last_id: StreamEntryID,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamEntryID>,
     },
     XRange {
         key: Vec<u8>,
         start: StreamEntryID,
         end: StreamEntryID,
+        count: Option<usize>,
+    },
+    /// Same as XRANGE, from the last entry of the range. `start` is still the smaller bound.
+    XRevRange {
+        key: Vec<u8>,
+        start: StreamEntryID,
+        end: StreamEntryID,
+        count: Option<usize>,
     },
     XRead {
+        count: Option<usize>,
         block: Option<Duration>,
         streams: Vec<XReadStreamArg>,
     },
+    XGroup(XGroupArg),
+    XInfo(XInfoArg),
+    XReadGroup {
+        group: Vec<u8>,
+        consumer: Vec<u8>,
+        count: Option<usize>,
+        block: Option<Duration>,
+        noack: bool,
+        streams: Vec<XReadStreamArg>,
+    },
+    XAck {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        ids: Vec<StreamEntryID>,
+    },
+    /// Summary of the pending entries of a group, or the entries themselves with a range.
+    XPending {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        range: Option<XPendingRange>,
+    },
+    XClaim {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        consumer: Vec<u8>,
+        min_idle_time: u64,
+        ids: Vec<StreamEntryID>,
+        options: XClaimOptions,
+    },
+    XAutoClaim {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        consumer: Vec<u8>,
+        min_idle_time: u64,
+        start: StreamEntryID,
+        count: usize,
+        just_id: bool,
+    },
+    Save,
+    BgSave,
+    BgRewriteAof,
+    LastSave,
+    /// Replicate from the master at the given host and port, or become a master with `None`.
+    ReplicaOf(Option<(String, u16)>),
 }
 
 impl Command {
+    /// Whether the command modifies the dataset. Writes are propagated to replicas and logged
+    /// to the AOF.
+    pub(crate) fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set { .. }
+                | Command::XAdd { .. }
+                | Command::XDel { .. }
+                | Command::XTrim { .. }
+                | Command::XSetId { .. }
+                | Command::XGroup(_)
+                | Command::XReadGroup { .. }
+                | Command::XAck { .. }
+                | Command::XClaim { .. }
+                | Command::XAutoClaim { .. }
+        )
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let args = match self {
             Command::Ping => vec![b"PING".to_vec()],
             Command::Echo(arg) => vec![b"ECHO".to_vec(), arg.clone()],
-            Command::Set { key, value, px } => {
+            Command::Set { key, value, expiry } => {
                 let mut vec = vec![b"SET".to_vec(), key.clone(), value.clone()];
-                match px {
-                    Some(val) => {
-                        let mut px_args = vec![
-                            b"px".to_vec(),
-                            val.as_millis().to_string().as_bytes().to_vec(),
-                        ];
-                        vec.append(&mut px_args);
+                match expiry {
+                    Some(SetExpiry::Px(dur)) => {
+                        vec.push(b"px".to_vec());
+                        vec.push(dur.as_millis().to_string().into_bytes());
+                    }
+                    Some(SetExpiry::PxAt(millis)) => {
+                        vec.push(b"pxat".to_vec());
+                        vec.push(millis.to_string().into_bytes());
                     }
-                    _ => {}
+                    None => {}
                 }
                 vec
             }
@@ -143,10 +349,55 @@ impl Command {
 
                 vec
             }
-            Command::XRead { block, streams } => {
+            Command::XRange {
+                key,
+                start,
+                end,
+                count,
+            } => {
+                let mut vec = vec![
+                    b"XRANGE".to_vec(),
+                    key.clone(),
+                    start.as_bytes(),
+                    end.as_bytes(),
+                ];
+                if let Some(count) = count {
+                    vec.push(b"COUNT".to_vec());
+                    vec.push(count.to_string().into_bytes());
+                }
+                vec
+            }
+            Command::XRevRange {
+                key,
+                start,
+                end,
+                count,
+            } => {
+                let mut vec = vec![
+                    b"XREVRANGE".to_vec(),
+                    key.clone(),
+                    end.as_bytes(),
+                    start.as_bytes(),
+                ];
+                if let Some(count) = count {
+                    vec.push(b"COUNT".to_vec());
+                    vec.push(count.to_string().into_bytes());
+                }
+                vec
+            }
+            Command::XRead {
+                count,
+                block,
+                streams,
+            } => {
                 let mut vec = Vec::with_capacity(1 + block.map_or(0, |_| 2) + streams.len() * 2);
                 vec.push(b"XREAD".to_vec());
 
+                if let Some(count) = count {
+                    vec.push(b"count".to_vec());
+                    vec.push(count.to_string().into_bytes());
+                }
+
                 if let Some(dur) = block {
                     vec.push(b"block".to_vec());
                     vec.push(dur.as_millis().to_string().as_bytes().to_vec());
@@ -158,18 +409,300 @@ impl Command {
                 });
                 streams.iter().for_each(|arg| {
                     vec.push(match &arg.start {
-                        Some(entry_id) => entry_id.as_bytes(),
-                        None => b"$".to_vec(),
+                        XReadStart::After(entry_id) => entry_id.as_bytes(),
+                        XReadStart::New => b"$".to_vec(),
+                        XReadStart::Last => b"+".to_vec(),
                     });
                 });
 
                 vec
             }
+            Command::XAdd {
+                key,
+                entry_id,
+                data,
+                nomkstream,
+                trim,
+            } => {
+                let mut vec = Vec::with_capacity(3 + data.len() * 2);
+                vec.push(b"XADD".to_vec());
+                vec.push(key.clone());
+                if *nomkstream {
+                    vec.push(b"NOMKSTREAM".to_vec());
+                }
+                if let Some(trim) = trim {
+                    vec.extend(trim_to_args(trim));
+                }
+                vec.push(match entry_id {
+                    Some(ReqStreamEntryID {
+                        millis,
+                        seq_num: Some(seq_num),
+                    }) => format!("{}-{}", millis, seq_num).into_bytes(),
+                    Some(ReqStreamEntryID {
+                        millis,
+                        seq_num: None,
+                    }) => format!("{}-*", millis).into_bytes(),
+                    None => b"*".to_vec(),
+                });
+                for (k, v) in data.iter() {
+                    vec.push(k.clone());
+                    vec.push(v.clone());
+                }
+                vec
+            }
+            Command::XLen(key) => vec![b"XLEN".to_vec(), key.clone()],
+            Command::XDel { key, ids } => {
+                let mut vec = vec![b"XDEL".to_vec(), key.clone()];
+                vec.extend(ids.iter().map(StreamEntryID::as_bytes));
+                vec
+            }
+            Command::XTrim { key, trim } => {
+                let mut vec = vec![b"XTRIM".to_vec(), key.clone()];
+                vec.extend(trim_to_args(trim));
+                vec
+            }
+            Command::XSetId {
+                key,
+                last_id,
+                entries_added,
+                max_deleted_id,
+            } => {
+                let mut vec = vec![b"XSETID".to_vec(), key.clone(), last_id.as_bytes()];
+                if let Some(entries_added) = entries_added {
+                    vec.push(b"ENTRIESADDED".to_vec());
+                    vec.push(entries_added.to_string().into_bytes());
+                }
+                if let Some(max_deleted_id) = max_deleted_id {
+                    vec.push(b"MAXDELETEDID".to_vec());
+                    vec.push(max_deleted_id.as_bytes());
+                }
+                vec
+            }
+            Command::XGroup(arg) => match arg {
+                XGroupArg::Create {
+                    key,
+                    group,
+                    id,
+                    mkstream,
+                    entries_read,
+                } => {
+                    let mut vec = vec![
+                        b"XGROUP".to_vec(),
+                        b"CREATE".to_vec(),
+                        key.clone(),
+                        group.clone(),
+                        id.as_ref().map_or(b"$".to_vec(), StreamEntryID::as_bytes),
+                    ];
+                    if *mkstream {
+                        vec.push(b"MKSTREAM".to_vec());
+                    }
+                    if let Some(entries_read) = entries_read {
+                        vec.push(b"ENTRIESREAD".to_vec());
+                        vec.push(entries_read.to_string().into_bytes());
+                    }
+                    vec
+                }
+                XGroupArg::SetId {
+                    key,
+                    group,
+                    id,
+                    entries_read,
+                } => {
+                    let mut vec = vec![
+                        b"XGROUP".to_vec(),
+                        b"SETID".to_vec(),
+                        key.clone(),
+                        group.clone(),
+                        id.as_ref().map_or(b"$".to_vec(), StreamEntryID::as_bytes),
+                    ];
+                    if let Some(entries_read) = entries_read {
+                        vec.push(b"ENTRIESREAD".to_vec());
+                        vec.push(entries_read.to_string().into_bytes());
+                    }
+                    vec
+                }
+                XGroupArg::Destroy { key, group } => vec![
+                    b"XGROUP".to_vec(),
+                    b"DESTROY".to_vec(),
+                    key.clone(),
+                    group.clone(),
+                ],
+                XGroupArg::CreateConsumer {
+                    key,
+                    group,
+                    consumer,
+                } => vec![
+                    b"XGROUP".to_vec(),
+                    b"CREATECONSUMER".to_vec(),
+                    key.clone(),
+                    group.clone(),
+                    consumer.clone(),
+                ],
+                XGroupArg::DelConsumer {
+                    key,
+                    group,
+                    consumer,
+                } => vec![
+                    b"XGROUP".to_vec(),
+                    b"DELCONSUMER".to_vec(),
+                    key.clone(),
+                    group.clone(),
+                    consumer.clone(),
+                ],
+            },
+            Command::XReadGroup {
+                group,
+                consumer,
+                count,
+                block,
+                noack,
+                streams,
+            } => {
+                let mut vec = vec![
+                    b"XREADGROUP".to_vec(),
+                    b"GROUP".to_vec(),
+                    group.clone(),
+                    consumer.clone(),
+                ];
+                if let Some(count) = count {
+                    vec.push(b"COUNT".to_vec());
+                    vec.push(count.to_string().into_bytes());
+                }
+                if let Some(dur) = block {
+                    vec.push(b"BLOCK".to_vec());
+                    vec.push(dur.as_millis().to_string().into_bytes());
+                }
+                if *noack {
+                    vec.push(b"NOACK".to_vec());
+                }
+                vec.push(b"STREAMS".to_vec());
+                vec.extend(streams.iter().map(|arg| arg.key.clone()));
+                vec.extend(streams.iter().map(|arg| match &arg.start {
+                    XReadStart::After(entry_id) => entry_id.as_bytes(),
+                    _ => b">".to_vec(),
+                }));
+                vec
+            }
+            Command::XInfo(arg) => match arg {
+                XInfoArg::Stream { key, full } => {
+                    let mut vec = vec![b"XINFO".to_vec(), b"STREAM".to_vec(), key.clone()];
+                    if let Some(count) = full {
+                        vec.push(b"FULL".to_vec());
+                        vec.push(b"COUNT".to_vec());
+                        vec.push(count.to_string().into_bytes());
+                    }
+                    vec
+                }
+                XInfoArg::Groups(key) => vec![b"XINFO".to_vec(), b"GROUPS".to_vec(), key.clone()],
+                XInfoArg::Consumers { key, group } => vec![
+                    b"XINFO".to_vec(),
+                    b"CONSUMERS".to_vec(),
+                    key.clone(),
+                    group.clone(),
+                ],
+            },
+            Command::XAck { key, group, ids } => {
+                let mut vec = vec![b"XACK".to_vec(), key.clone(), group.clone()];
+                vec.extend(ids.iter().map(StreamEntryID::as_bytes));
+                vec
+            }
+            Command::XPending { key, group, range } => {
+                let mut vec = vec![b"XPENDING".to_vec(), key.clone(), group.clone()];
+                if let Some(range) = range {
+                    if let Some(min_idle_time) = range.min_idle_time {
+                        vec.push(b"IDLE".to_vec());
+                        vec.push(min_idle_time.to_string().into_bytes());
+                    }
+                    vec.push(range.start.as_bytes());
+                    vec.push(range.end.as_bytes());
+                    vec.push(range.count.to_string().into_bytes());
+                    if let Some(consumer) = &range.consumer {
+                        vec.push(consumer.clone());
+                    }
+                }
+                vec
+            }
+            Command::XClaim {
+                key,
+                group,
+                consumer,
+                min_idle_time,
+                ids,
+                options,
+            } => {
+                let mut vec = vec![
+                    b"XCLAIM".to_vec(),
+                    key.clone(),
+                    group.clone(),
+                    consumer.clone(),
+                    min_idle_time.to_string().into_bytes(),
+                ];
+                vec.extend(ids.iter().map(StreamEntryID::as_bytes));
+                if let Some(idle) = options.idle {
+                    vec.push(b"IDLE".to_vec());
+                    vec.push(idle.to_string().into_bytes());
+                }
+                if let Some(time) = options.time {
+                    vec.push(b"TIME".to_vec());
+                    vec.push(time.to_string().into_bytes());
+                }
+                if let Some(retry_count) = options.retry_count {
+                    vec.push(b"RETRYCOUNT".to_vec());
+                    vec.push(retry_count.to_string().into_bytes());
+                }
+                if options.force {
+                    vec.push(b"FORCE".to_vec());
+                }
+                if options.just_id {
+                    vec.push(b"JUSTID".to_vec());
+                }
+                if let Some(last_id) = &options.last_id {
+                    vec.push(b"LASTID".to_vec());
+                    vec.push(last_id.as_bytes());
+                }
+                vec
+            }
+            Command::XAutoClaim {
+                key,
+                group,
+                consumer,
+                min_idle_time,
+                start,
+                count,
+                just_id,
+            } => {
+                let mut vec = vec![
+                    b"XAUTOCLAIM".to_vec(),
+                    key.clone(),
+                    group.clone(),
+                    consumer.clone(),
+                    min_idle_time.to_string().into_bytes(),
+                    start.as_bytes(),
+                    b"COUNT".to_vec(),
+                    count.to_string().into_bytes(),
+                ];
+                if *just_id {
+                    vec.push(b"JUSTID".to_vec());
+                }
+                vec
+            }
+            Command::Save => vec![b"SAVE".to_vec()],
+            Command::BgSave => vec![b"BGSAVE".to_vec()],
+            Command::BgRewriteAof => vec![b"BGREWRITEAOF".to_vec()],
+            Command::LastSave => vec![b"LASTSAVE".to_vec()],
+            Command::ReplicaOf(master) => match master {
+                Some((host, port)) => vec![
+                    b"REPLICAOF".to_vec(),
+                    host.as_bytes().to_vec(),
+                    port.to_string().into_bytes(),
+                ],
+                None => vec![b"REPLICAOF".to_vec(), b"NO".to_vec(), b"ONE".to_vec()],
+            },
             _ => todo!(),
         };
         let args = args
             .into_iter()
-            .map(|v| RespValue::BulkString(v))
+            .map(RespValue::BulkString)
             .collect::<Vec<RespValue>>();
         RespValue::Array(args).to_bytes()
     }
@@ -195,35 +728,35 @@ impl Command {
                 let (key, _remaining) = remaining.split_first().context("Extract SET key")?;
                 let (value, _remaining) = _remaining.split_first().context("Extract SET value")?;
 
-                let (is_px_present, _remaining) =
-                    if let Some((px_key, __remaining)) = _remaining.split_first() {
-                        match &px_key.to_ascii_lowercase()[..] {
-                            b"px" => (true, __remaining),
-                            arg => return Err(anyhow::anyhow!("Invalid SET argument: {:?}", arg)),
+                let (expiry, _remaining) =
+                    if let Some((option, __remaining)) = _remaining.split_first() {
+                        let option = option.to_ascii_lowercase();
+                        if option != b"px" && option != b"pxat" {
+                            return Err(anyhow::anyhow!("Invalid SET argument: {:?}", option));
                         }
-                    } else {
-                        (false, _remaining)
-                    };
-                let (px, _remaining) = match is_px_present {
-                    true => {
-                        let (px, __remaining) = _remaining
+                        let (millis, __remaining) = __remaining
                             .split_first()
                             .context("Extract expiry argument")?;
-                        let px = std::str::from_utf8(px)
+                        let millis = std::str::from_utf8(millis)
                             .context("UTF-8 decode expiry string")?
                             .parse::<u64>()
                             .context("Parse expiry string to number")?;
-                        (Some(px), __remaining)
-                    }
-                    false => (None, _remaining),
-                };
+                        let expiry = if option == b"px" {
+                            SetExpiry::Px(Duration::from_millis(millis))
+                        } else {
+                            SetExpiry::PxAt(millis)
+                        };
+                        (Some(expiry), __remaining)
+                    } else {
+                        (None, _remaining)
+                    };
 
                 remaining = _remaining;
 
                 Command::Set {
                     key: key.clone(),
                     value: value.clone(),
-                    px: px.map(|millis| Duration::from_millis(millis)),
+                    expiry,
                 }
             }
             b"info" => {
@@ -269,12 +802,7 @@ impl Command {
                         capas.push(_capa);
                         _remaining = __remaining;
 
-                        loop {
-                            let (arg, __remaining) = if let Some(x) = _remaining.split_first() {
-                                x
-                            } else {
-                                break;
-                            };
+                        while let Some((arg, __remaining)) = _remaining.split_first() {
                             assert_eq!(&arg[..], b"capa");
                             let (capa, mut __remaining) = __remaining
                                 .split_first()
@@ -320,7 +848,7 @@ impl Command {
                 let repl_id = match &repl_id[..] {
                     b"?" => None,
                     bytes => Some(
-                        std::str::from_utf8(&bytes)
+                        std::str::from_utf8(bytes)
                             .context("UTF-8 decode replication ID")?
                             .chars()
                             .collect::<Vec<char>>()
@@ -336,7 +864,7 @@ impl Command {
                 let repl_offset = match &repl_offset[..] {
                     b"-1" => None,
                     bytes => Some(
-                        std::str::from_utf8(&bytes)
+                        std::str::from_utf8(bytes)
                             .context("UTF-8 decode replication offset")?
                             .parse::<usize>()
                             .context("Parse replication offset from string to number")?,
@@ -384,6 +912,25 @@ impl Command {
                         remaining = _remaining;
                         Command::Config(ConfigArg::Get(key.to_string()))
                     }
+                    b"set" => {
+                        if _remaining.is_empty() || !_remaining.len().is_multiple_of(2) {
+                            return Err(anyhow::anyhow!(
+                                "Expect CONFIG SET <parameter> <value> pairs"
+                            ));
+                        }
+                        let params = _remaining
+                            .chunks_exact(2)
+                            .map(|pair| {
+                                let key = std::str::from_utf8(&pair[0])
+                                    .context("Parse CONFIG SET parameter")?;
+                                let value = std::str::from_utf8(&pair[1])
+                                    .context("Parse CONFIG SET value")?;
+                                Ok((key.to_ascii_lowercase(), value.to_string()))
+                            })
+                            .collect::<anyhow::Result<Vec<_>>>()?;
+                        remaining = &[];
+                        Command::Config(ConfigArg::Set(params))
+                    }
                     s => panic!("Unrecognized CONFIG argument: {:?}", s),
                 }
             }
@@ -398,7 +945,22 @@ impl Command {
                 Command::LookupType(val.clone())
             }
             b"xadd" => {
-                let (key, _remaining) = remaining.split_first().context("Extract XADD key")?;
+                let (key, mut _remaining) = remaining.split_first().context("Extract XADD key")?;
+                let mut nomkstream = false;
+                let mut trim = TrimArgs::default();
+                loop {
+                    match _remaining {
+                        [kw, __remaining @ ..] if kw.eq_ignore_ascii_case(b"nomkstream") => {
+                            nomkstream = true;
+                            _remaining = __remaining;
+                        }
+                        args => match trim.parse(args)? {
+                            Some(__remaining) => _remaining = __remaining,
+                            None => break,
+                        },
+                    }
+                }
+                let trim = trim.finish()?;
                 let (entry_id, _remaining) =
                     _remaining.split_first().context("Extract entry ID")?;
 
@@ -431,100 +993,170 @@ impl Command {
                     Some(ReqStreamEntryID { millis, seq_num })
                 };
 
-                let data = HashMap::with_capacity(_remaining.len() / 2);
-                let data = _remaining.chunks_exact(2).fold(data, |mut acc, chunk| {
-                    acc.insert(chunk[0].clone(), chunk[1].clone());
-                    acc
-                });
-
-                let (_, _remaining) = _remaining.split_at(data.len() * 2);
-                remaining = _remaining;
+                if _remaining.is_empty() || !_remaining.len().is_multiple_of(2) {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xadd' command"
+                    ));
+                }
+                let data = _remaining
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                remaining = &[];
 
                 Command::XAdd {
                     key: key.clone(),
                     entry_id,
                     data,
+                    nomkstream,
+                    trim,
                 }
             }
-            b"xrange" => {
-                let (key, _remaining) = remaining.split_first().context("Extract XRANGE key")?;
-                let (start, _remaining) = _remaining
-                    .split_first()
-                    .context("Extract XRANGE start argument")?;
-
-                let start = if start == b"-" {
-                    StreamEntryID {
-                        millis: 0,
-                        seq_num: 0,
-                    }
-                } else if let Some(idx) = start.iter().position(|&c| c == b'-') {
-                    let (millis, seq_num) = start.split_at(idx);
-                    let seq_num = &seq_num[1..];
-                    StreamEntryID {
-                        millis: std::str::from_utf8(millis)
-                            .context("UTF-8 decode millis")?
-                            .parse()
-                            .context("Convert millis string to u64")?,
-                        seq_num: std::str::from_utf8(seq_num)
-                            .context("UTF-8 decode seq_num")?
-                            .parse()
-                            .context("Convert seq_num string to u64")?,
+            b"xlen" => {
+                let [key] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xlen' command"
+                    ));
+                };
+                remaining = &[];
+                Command::XLen(key.clone())
+            }
+            b"xdel" => {
+                let [key, ids @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xdel' command"
+                    ));
+                };
+                if ids.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xdel' command"
+                    ));
+                }
+                let ids = ids
+                    .iter()
+                    .map(|id| parse_stream_id(id, 0))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                remaining = &[];
+                Command::XDel {
+                    key: key.clone(),
+                    ids,
+                }
+            }
+            b"xtrim" => {
+                let [key, args @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xtrim' command"
+                    ));
+                };
+                let mut args = args;
+                let mut trim = TrimArgs::default();
+                while !args.is_empty() {
+                    args = trim
+                        .parse(args)?
+                        .ok_or_else(|| anyhow::anyhow!("ERR syntax error"))?;
+                }
+                remaining = &[];
+                Command::XTrim {
+                    key: key.clone(),
+                    trim: trim.finish()?.context("ERR syntax error")?,
+                }
+            }
+            b"xsetid" => {
+                let [key, last_id, args @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xsetid' command"
+                    ));
+                };
+                let last_id = parse_stream_id(last_id, 0)
+                    .context("ERR Invalid stream ID specified as stream command argument")?;
+                let mut entries_added = None;
+                let mut max_deleted_id = None;
+                let mut args = args;
+                while let [option, value, _args @ ..] = args {
+                    args = _args;
+                    match &option.to_ascii_lowercase()[..] {
+                        b"entriesadded" => {
+                            entries_added = Some(
+                                parse_number(value, "entries added")
+                                    .context("ERR entries_added must be positive")?,
+                            );
+                        }
+                        b"maxdeletedid" => {
+                            max_deleted_id = Some(parse_stream_id(value, 0).context(
+                                "ERR Invalid stream ID specified as stream command argument",
+                            )?);
+                        }
+                        _ => return Err(anyhow::anyhow!("ERR syntax error")),
                     }
-                } else {
-                    StreamEntryID {
-                        millis: std::str::from_utf8(start)
-                            .context("UTF-8 decode millis")?
-                            .parse()
-                            .context("Convert millis string to u64")?,
-                        seq_num: u64::MIN,
+                }
+                if !args.is_empty() {
+                    return Err(anyhow::anyhow!("ERR syntax error"));
+                }
+                remaining = &[];
+                Command::XSetId {
+                    key: key.clone(),
+                    last_id,
+                    entries_added,
+                    max_deleted_id,
+                }
+            }
+            verb @ (b"xrange" | b"xrevrange") => {
+                let [key, first, second, args @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for '{}' command",
+                        String::from_utf8_lossy(verb)
+                    ));
+                };
+                let (start, end) = match verb {
+                    b"xrange" => (first, second),
+                    _ => (second, first),
+                };
+                let count = match args {
+                    [] => None,
+                    [kw, count] if kw.eq_ignore_ascii_case(b"count") => {
+                        let count = parse_number::<i64>(count, "count").map_err(|_| {
+                            anyhow::anyhow!("ERR value is not an integer or out of range")
+                        })?;
+                        Some(count.max(0) as usize)
                     }
+                    _ => return Err(anyhow::anyhow!("ERR syntax error")),
                 };
+                let (key, start, end) = (
+                    key.clone(),
+                    parse_range_start(start)?,
+                    parse_range_end(end)?,
+                );
+                remaining = &[];
 
-                let (end, _remaining) = _remaining
-                    .split_first()
-                    .context("Extract XRANGE end argument")?;
-                let end = if end == b"+" {
-                    StreamEntryID {
-                        millis: u64::MAX,
-                        seq_num: u64::MAX,
-                    }
-                } else if let Some(idx) = end.iter().position(|&c| c == b'-') {
-                    let (millis, seq_num) = end.split_at(idx);
-                    let seq_num = &seq_num[1..];
-                    StreamEntryID {
-                        millis: std::str::from_utf8(millis)
-                            .context("UTF-8 decode millis")?
-                            .parse()
-                            .context("Convert millis string to u64")?,
-                        seq_num: std::str::from_utf8(seq_num)
-                            .context("UTF-8 decode seq_num")?
-                            .parse()
-                            .context("Convert seq_num string to u64")?,
+                if verb == b"xrange" {
+                    Command::XRange {
+                        key,
+                        start,
+                        end,
+                        count,
                     }
                 } else {
-                    StreamEntryID {
-                        millis: std::str::from_utf8(end)
-                            .context("UTF-8 decode millis")?
-                            .parse()
-                            .context("Convert millis string to u64")?,
-                        seq_num: u64::MAX,
+                    Command::XRevRange {
+                        key,
+                        start,
+                        end,
+                        count,
                     }
-                };
-
-                remaining = _remaining;
-
-                Command::XRange {
-                    key: key.clone(),
-                    start,
-                    end,
                 }
             }
             b"xread" => {
+                let mut count = None;
                 let mut block = None;
                 let mut streams = None;
 
                 while let Some((kw, mut _remaining)) = remaining.split_first() {
-                    match &kw[..] {
+                    match &kw.to_ascii_lowercase()[..] {
+                        b"count" => {
+                            let (n, _remaining) =
+                                _remaining.split_first().context("Extract COUNT")?;
+                            count = Some(parse_number(n, "count")?);
+                            remaining = _remaining;
+                        }
                         b"block" => {
                             let (dur, _remaining) = _remaining
                                 .split_first()
@@ -539,48 +1171,28 @@ impl Command {
                             remaining = _remaining;
                         }
                         b"streams" => {
-                            let (keys, mut starts) = _remaining.split_at(_remaining.len() / 2);
-                            if starts.len() == keys.len() {
-                                _remaining = &starts[starts.len()..];
-                            } else {
-                                starts = &starts[..starts.len() - 1];
-                                _remaining = &starts[starts.len() - 1..];
+                            if _remaining.is_empty() || !_remaining.len().is_multiple_of(2) {
+                                return Err(anyhow::anyhow!(
+                                    "ERR Unbalanced 'xread' list of streams: for each stream key an ID, '+', or '$' must be specified."
+                                ));
                             }
-
-                            let mut args = Vec::with_capacity(keys.len());
-                            for (key, start) in keys.into_iter().zip(starts.into_iter()) {
-                                let start = if start == b"$" {
-                                    None
-                                } else if let Some(idx) = start.iter().position(|&c| c == b'-') {
-                                    let (millis, seq_num) = start.split_at(idx);
-                                    let seq_num = &seq_num[1..];
-                                    Some(StreamEntryID {
-                                        millis: std::str::from_utf8(millis)
-                                            .context("UTF-8 decode millis")?
-                                            .parse()
-                                            .context("Convert millis string to u64")?,
-                                        seq_num: std::str::from_utf8(seq_num)
-                                            .context("UTF-8 decode seq_num")?
-                                            .parse()
-                                            .context("Convert seq_num string to u64")?,
+                            let (keys, starts) = _remaining.split_at(_remaining.len() / 2);
+                            let args = keys
+                                .iter()
+                                .zip(starts)
+                                .map(|(key, start)| {
+                                    let start = match &start[..] {
+                                        b"$" => XReadStart::New,
+                                        b"+" => XReadStart::Last,
+                                        start => XReadStart::After(parse_stream_id(start, 0)?),
+                                    };
+                                    Ok(XReadStreamArg {
+                                        key: key.clone(),
+                                        start,
                                     })
-                                } else {
-                                    Some(StreamEntryID {
-                                        millis: std::str::from_utf8(start)
-                                            .context("UTF-8 decode millis")?
-                                            .parse()
-                                            .context("Convert millis string to u64")?,
-                                        seq_num: u64::MIN,
-                                    })
-                                };
-                                let arg = XReadStreamArg {
-                                    key: key.clone(),
-                                    start,
-                                };
-                                args.push(arg);
-                            }
-
-                            remaining = _remaining;
+                                })
+                                .collect::<anyhow::Result<Vec<_>>>()?;
+                            remaining = &[];
                             streams = Some(args);
                         }
                         kw => {
@@ -593,10 +1205,393 @@ impl Command {
                 }
 
                 Command::XRead {
+                    count,
                     block,
                     streams: streams.context("streams argument must not be None")?,
                 }
             }
+            b"xgroup" => {
+                let [subcommand, key, group, args @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xgroup' command"
+                    ));
+                };
+                let (key, group) = (key.clone(), group.clone());
+                let arg = match &subcommand.to_ascii_lowercase()[..] {
+                    subcommand @ (b"create" | b"setid") => {
+                        let (id, mut args) = args.split_first().context("Extract XGROUP ID")?;
+                        let id = if id == b"$" {
+                            None
+                        } else {
+                            Some(parse_stream_id(id, 0)?)
+                        };
+                        let mut mkstream = false;
+                        let mut entries_read = None;
+                        while let Some((option, _args)) = args.split_first() {
+                            args = _args;
+                            match &option.to_ascii_lowercase()[..] {
+                                b"mkstream" if subcommand == b"create" => mkstream = true,
+                                b"entriesread" => {
+                                    let (n, _args) =
+                                        args.split_first().context("Extract ENTRIESREAD")?;
+                                    entries_read = Some(parse_number(n, "entries read")?);
+                                    args = _args;
+                                }
+                                o => return Err(anyhow::anyhow!("Invalid XGROUP option: {:?}", o)),
+                            }
+                        }
+                        if subcommand == b"create" {
+                            XGroupArg::Create {
+                                key,
+                                group,
+                                id,
+                                mkstream,
+                                entries_read,
+                            }
+                        } else {
+                            XGroupArg::SetId {
+                                key,
+                                group,
+                                id,
+                                entries_read,
+                            }
+                        }
+                    }
+                    b"destroy" if args.is_empty() => XGroupArg::Destroy { key, group },
+                    b"createconsumer" | b"delconsumer" => {
+                        let [consumer] = args else {
+                            return Err(anyhow::anyhow!("Expect XGROUP consumer name"));
+                        };
+                        let consumer = consumer.clone();
+                        if subcommand.eq_ignore_ascii_case(b"createconsumer") {
+                            XGroupArg::CreateConsumer {
+                                key,
+                                group,
+                                consumer,
+                            }
+                        } else {
+                            XGroupArg::DelConsumer {
+                                key,
+                                group,
+                                consumer,
+                            }
+                        }
+                    }
+                    s => return Err(anyhow::anyhow!("Invalid XGROUP subcommand: {:?}", s)),
+                };
+                remaining = &[];
+                Command::XGroup(arg)
+            }
+            b"xreadgroup" => {
+                let [group_kw, group, consumer, args @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xreadgroup' command"
+                    ));
+                };
+                if !group_kw.eq_ignore_ascii_case(b"group") {
+                    return Err(anyhow::anyhow!("ERR Missing GROUP option for XREADGROUP"));
+                }
+
+                let mut count = None;
+                let mut block = None;
+                let mut noack = false;
+                let mut streams = None;
+                let mut args = args;
+                while let Some((kw, _args)) = args.split_first() {
+                    args = _args;
+                    match &kw.to_ascii_lowercase()[..] {
+                        b"count" => {
+                            let (n, _args) = args.split_first().context("Extract COUNT")?;
+                            count = Some(parse_number(n, "count")?);
+                            args = _args;
+                        }
+                        b"block" => {
+                            let (dur, _args) =
+                                args.split_first().context("Extract blocking duration")?;
+                            block = Some(Duration::from_millis(parse_number(
+                                dur,
+                                "blocking duration",
+                            )?));
+                            args = _args;
+                        }
+                        b"noack" => noack = true,
+                        b"streams" => {
+                            if args.is_empty() || !args.len().is_multiple_of(2) {
+                                return Err(anyhow::anyhow!(
+                                    "ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified."
+                                ));
+                            }
+                            let (keys, starts) = args.split_at(args.len() / 2);
+                            let args = keys
+                                .iter()
+                                .zip(starts)
+                                .map(|(key, start)| {
+                                    let start = if start == b">" {
+                                        XReadStart::New
+                                    } else {
+                                        XReadStart::After(parse_stream_id(start, 0)?)
+                                    };
+                                    Ok(XReadStreamArg {
+                                        key: key.clone(),
+                                        start,
+                                    })
+                                })
+                                .collect::<anyhow::Result<Vec<_>>>()?;
+                            streams = Some(args);
+                            break;
+                        }
+                        kw => {
+                            return Err(anyhow::anyhow!(
+                                "Unknown keyword argument for XREADGROUP: {:?}",
+                                kw
+                            ));
+                        }
+                    }
+                }
+                remaining = &[];
+
+                Command::XReadGroup {
+                    group: group.clone(),
+                    consumer: consumer.clone(),
+                    count,
+                    block,
+                    noack,
+                    streams: streams.context("streams argument must not be None")?,
+                }
+            }
+            b"xinfo" => {
+                let [subcommand, args @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xinfo' command"
+                    ));
+                };
+                let subcommand = subcommand.to_ascii_lowercase();
+                let wrong_args = || {
+                    anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xinfo|{}' command",
+                        String::from_utf8_lossy(&subcommand)
+                    )
+                };
+                let arg = match &subcommand[..] {
+                    b"stream" => {
+                        let [key, args @ ..] = args else {
+                            return Err(wrong_args());
+                        };
+                        let full = match args {
+                            [] => None,
+                            [full] if full.eq_ignore_ascii_case(b"full") => Some(10),
+                            [full, count_kw, count]
+                                if full.eq_ignore_ascii_case(b"full")
+                                    && count_kw.eq_ignore_ascii_case(b"count") =>
+                            {
+                                Some(
+                                    parse_number(count, "count")
+                                        .context("ERR value is not an integer or out of range")?,
+                                )
+                            }
+                            _ => return Err(anyhow::anyhow!("ERR syntax error")),
+                        };
+                        XInfoArg::Stream {
+                            key: key.clone(),
+                            full,
+                        }
+                    }
+                    b"groups" => {
+                        let [key] = args else {
+                            return Err(wrong_args());
+                        };
+                        XInfoArg::Groups(key.clone())
+                    }
+                    b"consumers" => {
+                        let [key, group] = args else {
+                            return Err(wrong_args());
+                        };
+                        XInfoArg::Consumers {
+                            key: key.clone(),
+                            group: group.clone(),
+                        }
+                    }
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "ERR unknown subcommand '{}'. Try XINFO HELP.",
+                            String::from_utf8_lossy(&subcommand)
+                        ))
+                    }
+                };
+                remaining = &[];
+                Command::XInfo(arg)
+            }
+            b"xack" => {
+                let [key, group, ids @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xack' command"
+                    ));
+                };
+                if ids.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xack' command"
+                    ));
+                }
+                let ids = ids
+                    .iter()
+                    .map(|id| parse_stream_id(id, 0))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                remaining = &[];
+                Command::XAck {
+                    key: key.clone(),
+                    group: group.clone(),
+                    ids,
+                }
+            }
+            b"xpending" => {
+                let [key, group, args @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xpending' command"
+                    ));
+                };
+                let range = match args {
+                    [] => None,
+                    args => {
+                        let (min_idle_time, args) = match args {
+                            [kw, idle, args @ ..] if kw.eq_ignore_ascii_case(b"idle") => {
+                                (Some(parse_number(idle, "min idle time")?), args)
+                            }
+                            args => (None, args),
+                        };
+                        let (start, end, count, consumer) = match args {
+                            [start, end, count] => (start, end, count, None),
+                            [start, end, count, consumer] => {
+                                (start, end, count, Some(consumer.clone()))
+                            }
+                            _ => return Err(anyhow::anyhow!("ERR syntax error")),
+                        };
+                        Some(XPendingRange {
+                            min_idle_time,
+                            start: parse_range_start(start)?,
+                            end: parse_range_end(end)?,
+                            count: parse_number(count, "count")?,
+                            consumer,
+                        })
+                    }
+                };
+                remaining = &[];
+                Command::XPending {
+                    key: key.clone(),
+                    group: group.clone(),
+                    range,
+                }
+            }
+            b"xclaim" => {
+                let [key, group, consumer, min_idle_time, args @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xclaim' command"
+                    ));
+                };
+                let ids_len = args
+                    .iter()
+                    .position(|arg| parse_stream_id(arg, 0).is_err())
+                    .unwrap_or(args.len());
+                let (ids, mut args) = args.split_at(ids_len);
+                if ids.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xclaim' command"
+                    ));
+                }
+                let ids = ids
+                    .iter()
+                    .map(|id| parse_stream_id(id, 0))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+
+                let mut options = XClaimOptions::default();
+                while let Some((option, _args)) = args.split_first() {
+                    args = _args;
+                    let option = option.to_ascii_lowercase();
+                    match &option[..] {
+                        b"force" => options.force = true,
+                        b"justid" => options.just_id = true,
+                        b"idle" | b"time" | b"retrycount" | b"lastid" => {
+                            let (value, _args) = args
+                                .split_first()
+                                .context(format!("Extract XCLAIM {:?} value", option))?;
+                            args = _args;
+                            match &option[..] {
+                                b"idle" => options.idle = Some(parse_number(value, "idle")?),
+                                b"time" => options.time = Some(parse_number(value, "time")?),
+                                b"retrycount" => {
+                                    options.retry_count = Some(parse_number(value, "retry count")?)
+                                }
+                                _ => options.last_id = Some(parse_stream_id(value, 0)?),
+                            }
+                        }
+                        o => return Err(anyhow::anyhow!("Invalid XCLAIM option: {:?}", o)),
+                    }
+                }
+                remaining = &[];
+
+                Command::XClaim {
+                    key: key.clone(),
+                    group: group.clone(),
+                    consumer: consumer.clone(),
+                    min_idle_time: parse_number(min_idle_time, "min idle time")?,
+                    ids,
+                    options,
+                }
+            }
+            b"xautoclaim" => {
+                let [key, group, consumer, min_idle_time, start, args @ ..] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'xautoclaim' command"
+                    ));
+                };
+                let mut count = DEFAULT_XAUTOCLAIM_COUNT;
+                let mut just_id = false;
+                let mut args = args;
+                while let Some((option, _args)) = args.split_first() {
+                    args = _args;
+                    match &option.to_ascii_lowercase()[..] {
+                        b"count" => {
+                            let (n, _args) = args.split_first().context("Extract COUNT")?;
+                            count = parse_number(n, "count")?;
+                            args = _args;
+                        }
+                        b"justid" => just_id = true,
+                        o => return Err(anyhow::anyhow!("Invalid XAUTOCLAIM option: {:?}", o)),
+                    }
+                }
+                remaining = &[];
+
+                Command::XAutoClaim {
+                    key: key.clone(),
+                    group: group.clone(),
+                    consumer: consumer.clone(),
+                    min_idle_time: parse_number(min_idle_time, "min idle time")?,
+                    start: parse_range_start(start)?,
+                    count,
+                    just_id,
+                }
+            }
+            b"save" => Command::Save,
+            b"bgsave" => Command::BgSave,
+            b"bgrewriteaof" => Command::BgRewriteAof,
+            b"lastsave" => Command::LastSave,
+            b"replicaof" | b"slaveof" => {
+                let [host, port] = remaining else {
+                    return Err(anyhow::anyhow!(
+                        "ERR wrong number of arguments for 'replicaof' command"
+                    ));
+                };
+                remaining = &[];
+                if host.eq_ignore_ascii_case(b"no") && port.eq_ignore_ascii_case(b"one") {
+                    Command::ReplicaOf(None)
+                } else {
+                    let host = std::str::from_utf8(host).context("Parse master host")?;
+                    let port = std::str::from_utf8(port)
+                        .ok()
+                        .and_then(|port| port.parse::<u16>().ok())
+                        .context("ERR Invalid master port")?;
+                    Command::ReplicaOf(Some((host.to_string(), port)))
+                }
+            }
             v => return Err(anyhow::anyhow!("Unknown verb: {:?}", v)),
         };
 
@@ -608,18 +1603,232 @@ impl Command {
     }
 }
 
+fn parse_number<T>(bytes: &[u8], what: &str) -> anyhow::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    std::str::from_utf8(bytes)
+        .context(format!("UTF-8 decode {}", what))?
+        .parse::<T>()
+        .context(format!("Parse {} to number", what))
+}
+
+/// Parses `<millis>-<seq_num>`, or `<millis>` which stands for `<millis>-<default_seq_num>`.
+fn parse_stream_id(bytes: &[u8], default_seq_num: u64) -> anyhow::Result<StreamEntryID> {
+    let (millis, seq_num) = match bytes.iter().position(|&c| c == b'-') {
+        Some(idx) => (&bytes[..idx], Some(&bytes[idx + 1..])),
+        None => (bytes, None),
+    };
+    Ok(StreamEntryID {
+        millis: parse_number(millis, "millis")?,
+        seq_num: match seq_num {
+            Some(seq_num) => parse_number(seq_num, "seq_num")?,
+            None => default_seq_num,
+        },
+    })
+}
+
+/// Trimming options of XADD and XTRIM, gathered while they are parsed.
+#[derive(Default)]
+struct TrimArgs {
+    strategy: Option<TrimStrategy>,
+    approx: bool,
+    limit: Option<usize>,
+}
+
+impl TrimArgs {
+    /// Parses the trimming option `args` starts with, returning the arguments after it, or
+    /// `None` if `args` does not start with one.
+    fn parse<'a>(&mut self, args: &'a [Vec<u8>]) -> anyhow::Result<Option<&'a [Vec<u8>]>> {
+        let Some((kw, args)) = args.split_first() else {
+            return Ok(None);
+        };
+        let kw = kw.to_ascii_lowercase();
+        match &kw[..] {
+            b"maxlen" | b"minid" => {
+                let (approx, args) = match args {
+                    [op, args @ ..] if op == b"~" => (true, args),
+                    [op, args @ ..] if op == b"=" => (false, args),
+                    args => (false, args),
+                };
+                let (threshold, args) = args.split_first().context("ERR syntax error")?;
+                let strategy = if kw == b"maxlen" {
+                    let max_len = parse_number::<i64>(threshold, "MAXLEN").map_err(|_| {
+                        anyhow::anyhow!("ERR value is not an integer or out of range")
+                    })?;
+                    let max_len = usize::try_from(max_len)
+                        .map_err(|_| anyhow::anyhow!("ERR The MAXLEN argument must be >= 0."))?;
+                    TrimStrategy::MaxLen(max_len)
+                } else {
+                    TrimStrategy::MinId(parse_stream_id(threshold, 0).map_err(|_| {
+                        anyhow::anyhow!(
+                            "ERR Invalid stream ID specified as stream command argument"
+                        )
+                    })?)
+                };
+                if self
+                    .strategy
+                    .as_ref()
+                    .is_some_and(|s| std::mem::discriminant(s) != std::mem::discriminant(&strategy))
+                {
+                    return Err(anyhow::anyhow!(
+                        "ERR syntax error, MAXLEN and MINID options at the same time are not compatible"
+                    ));
+                }
+                self.strategy = Some(strategy);
+                self.approx = approx;
+                Ok(Some(args))
+            }
+            b"limit" => {
+                let (limit, args) = args.split_first().context("ERR syntax error")?;
+                let limit = parse_number::<i64>(limit, "LIMIT")
+                    .map_err(|_| anyhow::anyhow!("ERR value is not an integer or out of range"))?;
+                self.limit = Some(
+                    usize::try_from(limit)
+                        .map_err(|_| anyhow::anyhow!("ERR The LIMIT argument must be >= 0."))?,
+                );
+                Ok(Some(args))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn finish(self) -> anyhow::Result<Option<StreamTrim>> {
+        match self.strategy {
+            None if self.limit.is_some() => Err(anyhow::anyhow!(
+                "ERR syntax error, LIMIT cannot be used without specifying a trimming strategy"
+            )),
+            None => Ok(None),
+            Some(_) if self.limit.is_some() && !self.approx => Err(anyhow::anyhow!(
+                "ERR syntax error, LIMIT cannot be used without the special ~ option"
+            )),
+            Some(strategy) => Ok(Some(StreamTrim {
+                strategy,
+                approx: self.approx,
+                limit: self.limit,
+            })),
+        }
+    }
+}
+
+fn trim_to_args(trim: &StreamTrim) -> Vec<Vec<u8>> {
+    let (kw, threshold) = match &trim.strategy {
+        TrimStrategy::MaxLen(max_len) => (b"MAXLEN".to_vec(), max_len.to_string().into_bytes()),
+        TrimStrategy::MinId(min_id) => (b"MINID".to_vec(), min_id.as_bytes()),
+    };
+    let op = if trim.approx { b"~" } else { b"=" };
+    let mut args = vec![kw, op.to_vec(), threshold];
+    if let Some(limit) = trim.limit {
+        args.push(b"LIMIT".to_vec());
+        args.push(limit.to_string().into_bytes());
+    }
+    args
+}
+
+/// Parses the start of a range, where `-` is the smallest ID and `(` makes the bound exclusive.
+fn parse_range_start(bytes: &[u8]) -> anyhow::Result<StreamEntryID> {
+    match bytes {
+        b"-" => Ok(StreamEntryID {
+            millis: 0,
+            seq_num: 0,
+        }),
+        [b'(', id @ ..] => parse_exclusive_id(id, u64::MIN)?
+            .next()
+            .context("ERR invalid start ID for the interval"),
+        id => parse_stream_id(id, u64::MIN),
+    }
+}
+
+/// Parses the end of a range, where `+` is the greatest ID and `(` makes the bound exclusive.
+fn parse_range_end(bytes: &[u8]) -> anyhow::Result<StreamEntryID> {
+    match bytes {
+        b"+" => Ok(StreamEntryID {
+            millis: u64::MAX,
+            seq_num: u64::MAX,
+        }),
+        [b'(', id @ ..] => parse_exclusive_id(id, u64::MAX)?
+            .prev()
+            .context("ERR invalid end ID for the interval"),
+        id => parse_stream_id(id, u64::MAX),
+    }
+}
+
+fn parse_exclusive_id(bytes: &[u8], default_seq_num: u64) -> anyhow::Result<StreamEntryID> {
+    parse_stream_id(bytes, default_seq_num)
+        .map_err(|_| anyhow::anyhow!("ERR Invalid stream ID specified as stream command argument"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// `args` encoded as a client sends a command, an array of bulk strings.
+    fn bulk_command(args: &[&str]) -> Vec<u8> {
+        RespValue::Array(
+            args.iter()
+                .map(|arg| RespValue::BulkString(arg.as_bytes().to_vec()))
+                .collect(),
+        )
+        .to_bytes()
+    }
+
+    #[test]
+    fn decode_set_with_expiry() {
+        for expiry in [
+            SetExpiry::Px(Duration::from_millis(100)),
+            SetExpiry::PxAt(1_700_000_000_000),
+        ] {
+            // Arrange
+            let command = Command::Set {
+                key: b"foo".to_vec(),
+                value: b"bar".to_vec(),
+                expiry: Some(expiry),
+            };
+            let bytes = command.to_bytes();
+
+            // Act
+            let (actual, remaining) = Command::from_bytes(&bytes[..]).unwrap();
+
+            // Assert
+            assert_eq!(actual, command);
+            assert!(remaining.is_empty());
+        }
+    }
+
+    #[test]
+    fn decode_replicaof() {
+        for master in [Some(("127.0.0.1".to_string(), 6380)), None] {
+            // Arrange
+            let command = Command::ReplicaOf(master);
+            let bytes = command.to_bytes();
+
+            // Act
+            let (actual, _) = Command::from_bytes(&bytes[..]).unwrap();
+
+            // Assert
+            assert_eq!(actual, command);
+        }
+        let slaveof = RespValue::Array(vec![
+            RespValue::BulkString(b"SLAVEOF".to_vec()),
+            RespValue::BulkString(b"no".to_vec()),
+            RespValue::BulkString(b"one".to_vec()),
+        ]);
+        assert_eq!(
+            Command::from_bytes(&slaveof.to_bytes()).unwrap().0,
+            Command::ReplicaOf(None)
+        );
+    }
+
     #[test]
     fn decode_xread_singlestream() {
         // Arrange
         let command = Command::XRead {
+            count: None,
             block: None,
             streams: vec![XReadStreamArg {
                 key: b"apple".to_vec(),
-                start: Some(StreamEntryID {
+                start: XReadStart::After(StreamEntryID {
                     millis: 0,
                     seq_num: 0,
                 }),
@@ -639,18 +1848,19 @@ mod tests {
     fn decode_xread_multistream() {
         // Arrange
         let command = Command::XRead {
+            count: None,
             block: None,
             streams: vec![
                 XReadStreamArg {
                     key: b"apple".to_vec(),
-                    start: Some(StreamEntryID {
+                    start: XReadStart::After(StreamEntryID {
                         millis: 0,
                         seq_num: 0,
                     }),
                 },
                 XReadStreamArg {
                     key: b"orange".to_vec(),
-                    start: Some(StreamEntryID {
+                    start: XReadStart::After(StreamEntryID {
                         millis: 0,
                         seq_num: 1,
                     }),
@@ -666,4 +1876,316 @@ mod tests {
         // Assert
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn decode_xread_special_ids() {
+        // Arrange
+        let command = Command::XRead {
+            count: Some(2),
+            block: Some(Duration::ZERO),
+            streams: vec![
+                XReadStreamArg {
+                    key: b"apple".to_vec(),
+                    start: XReadStart::Last,
+                },
+                XReadStreamArg {
+                    key: b"orange".to_vec(),
+                    start: XReadStart::New,
+                },
+            ],
+        };
+        let bytes = command.to_bytes();
+        let unbalanced = bulk_command(&["XREAD", "STREAMS", "apple", "orange", "$"]);
+
+        // Act
+        let actual = Command::from_bytes(&bytes[..]).ok();
+        let unbalanced = Command::from_bytes(&unbalanced[..]);
+
+        // Assert
+        assert_eq!(actual, Some((command, &bytes[bytes.len()..])));
+        assert!(unbalanced
+            .unwrap_err()
+            .to_string()
+            .starts_with("ERR Unbalanced 'xread' list of streams"));
+    }
+
+    #[test]
+    fn decode_consumer_group_commands() {
+        for command in [
+            Command::XGroup(XGroupArg::Create {
+                key: b"stream".to_vec(),
+                group: b"group".to_vec(),
+                id: None,
+                mkstream: true,
+                entries_read: Some(3),
+            }),
+            Command::XReadGroup {
+                group: b"group".to_vec(),
+                consumer: b"alice".to_vec(),
+                count: Some(10),
+                block: Some(Duration::from_millis(500)),
+                noack: true,
+                streams: vec![
+                    XReadStreamArg {
+                        key: b"apple".to_vec(),
+                        start: XReadStart::New,
+                    },
+                    XReadStreamArg {
+                        key: b"orange".to_vec(),
+                        start: XReadStart::After(StreamEntryID {
+                            millis: 0,
+                            seq_num: 1,
+                        }),
+                    },
+                ],
+            },
+            Command::XPending {
+                key: b"stream".to_vec(),
+                group: b"group".to_vec(),
+                range: Some(XPendingRange {
+                    min_idle_time: Some(1000),
+                    start: StreamEntryID {
+                        millis: 0,
+                        seq_num: 0,
+                    },
+                    end: StreamEntryID {
+                        millis: u64::MAX,
+                        seq_num: u64::MAX,
+                    },
+                    count: 10,
+                    consumer: Some(b"alice".to_vec()),
+                }),
+            },
+            Command::XClaim {
+                key: b"stream".to_vec(),
+                group: b"group".to_vec(),
+                consumer: b"bob".to_vec(),
+                min_idle_time: 0,
+                ids: vec![StreamEntryID {
+                    millis: 5,
+                    seq_num: 1,
+                }],
+                options: XClaimOptions {
+                    time: Some(1_700_000_000_000),
+                    retry_count: Some(2),
+                    force: true,
+                    just_id: true,
+                    last_id: Some(StreamEntryID {
+                        millis: 5,
+                        seq_num: 1,
+                    }),
+                    ..Default::default()
+                },
+            },
+        ] {
+            // Arrange
+            let bytes = command.to_bytes();
+
+            // Act
+            let (actual, remaining) = Command::from_bytes(&bytes[..]).unwrap();
+
+            // Assert
+            assert_eq!(actual, command);
+            assert!(remaining.is_empty());
+        }
+    }
+
+    #[test]
+    fn decode_stream_trimming_commands() {
+        // Arrange
+        let key = b"stream".to_vec();
+        let approx = StreamTrim {
+            strategy: TrimStrategy::MinId(StreamEntryID {
+                millis: 5,
+                seq_num: 0,
+            }),
+            approx: true,
+            limit: Some(10),
+        };
+
+        for (bytes, expected) in [
+            (
+                vec!["XADD", "stream", "NOMKSTREAM", "MAXLEN", "2", "*", "f", "v"],
+                Command::XAdd {
+                    key: key.clone(),
+                    entry_id: None,
+                    data: vec![(b"f".to_vec(), b"v".to_vec())],
+                    nomkstream: true,
+                    trim: Some(StreamTrim {
+                        strategy: TrimStrategy::MaxLen(2),
+                        approx: false,
+                        limit: None,
+                    }),
+                },
+            ),
+            (
+                vec!["XTRIM", "stream", "MINID", "~", "5", "LIMIT", "10"],
+                Command::XTrim {
+                    key: key.clone(),
+                    trim: approx.clone(),
+                },
+            ),
+            (
+                vec!["XDEL", "stream", "1-1", "2"],
+                Command::XDel {
+                    key: key.clone(),
+                    ids: vec![
+                        StreamEntryID {
+                            millis: 1,
+                            seq_num: 1,
+                        },
+                        StreamEntryID {
+                            millis: 2,
+                            seq_num: 0,
+                        },
+                    ],
+                },
+            ),
+        ] {
+            // Act
+            let bytes = bulk_command(&bytes);
+            let (actual, _) = Command::from_bytes(&bytes).unwrap();
+            let (reencoded, _) = Command::from_bytes(&expected.to_bytes()).unwrap();
+
+            // Assert
+            assert_eq!(actual, expected);
+            assert_eq!(reencoded, expected);
+        }
+
+        for args in [
+            vec!["XTRIM", "stream", "MAXLEN", "2", "LIMIT", "10"],
+            vec!["XTRIM", "stream", "MAXLEN", "-1"],
+            vec!["XTRIM", "stream", "MAXLEN", "1", "MINID", "1"],
+            vec!["XTRIM", "stream"],
+        ] {
+            let bytes = bulk_command(&args);
+            assert!(Command::from_bytes(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn decode_stream_introspection_commands() {
+        // Arrange
+        let key = b"stream".to_vec();
+        let id = |millis| StreamEntryID { millis, seq_num: 0 };
+
+        for (bytes, expected) in [
+            (
+                vec!["XINFO", "STREAM", "stream", "FULL"],
+                Command::XInfo(XInfoArg::Stream {
+                    key: key.clone(),
+                    full: Some(10),
+                }),
+            ),
+            (
+                vec!["xinfo", "consumers", "stream", "group"],
+                Command::XInfo(XInfoArg::Consumers {
+                    key: key.clone(),
+                    group: b"group".to_vec(),
+                }),
+            ),
+            (
+                vec![
+                    "XSETID",
+                    "stream",
+                    "5",
+                    "MAXDELETEDID",
+                    "3",
+                    "ENTRIESADDED",
+                    "4",
+                ],
+                Command::XSetId {
+                    key: key.clone(),
+                    last_id: id(5),
+                    entries_added: Some(4),
+                    max_deleted_id: Some(id(3)),
+                },
+            ),
+        ] {
+            // Act
+            let bytes = bulk_command(&bytes);
+            let (actual, _) = Command::from_bytes(&bytes).unwrap();
+            let (reencoded, _) = Command::from_bytes(&expected.to_bytes()).unwrap();
+
+            // Assert
+            assert_eq!(actual, expected);
+            assert_eq!(reencoded, expected);
+        }
+
+        for args in [
+            vec!["XINFO", "STREAM", "stream", "COUNT", "2"],
+            vec!["XINFO", "NOPE", "stream"],
+            vec!["XSETID", "stream", "5", "ENTRIESADDED", "-1"],
+            vec!["XSETID", "stream", "5", "ENTRIESADDED"],
+        ] {
+            let bytes = bulk_command(&args);
+            assert!(Command::from_bytes(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn decode_xrange_exclusive_bounds() {
+        // Arrange
+        let id = |millis, seq_num| StreamEntryID { millis, seq_num };
+
+        // Act
+        let (xrange, _) =
+            Command::from_bytes(&bulk_command(&["XRANGE", "s", "(1-5", "(3", "COUNT", "10"]))
+                .unwrap();
+        let (xrevrange, _) =
+            Command::from_bytes(&bulk_command(&["XREVRANGE", "s", "+", "(2-0"])).unwrap();
+        let invalid = Command::from_bytes(&bulk_command(&["XRANGE", "s", "-", "(0-0"])).map(|_| ());
+
+        // Assert
+        assert_eq!(
+            xrange,
+            Command::XRange {
+                key: b"s".to_vec(),
+                start: id(1, 6),
+                end: id(3, u64::MAX - 1),
+                count: Some(10),
+            }
+        );
+        assert_eq!(
+            xrevrange,
+            Command::XRevRange {
+                key: b"s".to_vec(),
+                start: id(2, 1),
+                end: id(u64::MAX, u64::MAX),
+                count: None,
+            }
+        );
+        assert_eq!(
+            invalid.unwrap_err().to_string(),
+            "ERR invalid end ID for the interval"
+        );
+    }
+
+    #[test]
+    fn decode_xadd_field_order() {
+        // Arrange
+        let bytes = bulk_command(&["XADD", "s", "*", "b", "1", "a", "2", "b", "3"]);
+        let odd = bulk_command(&["XADD", "s", "*", "b", "1", "a"]);
+
+        // Act
+        let (actual, _) = Command::from_bytes(&bytes).unwrap();
+        let odd = Command::from_bytes(&odd).map(|_| ());
+
+        // Assert
+        let Command::XAdd { data, .. } = actual else {
+            panic!("Expected XADD, got {:?}", actual);
+        };
+        assert_eq!(
+            data,
+            vec![
+                (b"b".to_vec(), b"1".to_vec()),
+                (b"a".to_vec(), b"2".to_vec()),
+                (b"b".to_vec(), b"3".to_vec()),
+            ]
+        );
+        assert_eq!(
+            odd.unwrap_err().to_string(),
+            "ERR wrong number of arguments for 'xadd' command"
+        );
+    }
 }
@@ -1,20 +1,31 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     fmt,
-    time::{Duration, SystemTime},
+    sync::Arc,
+    time::SystemTime,
 };
 
-use tokio::sync::broadcast;
+use tokio::sync::Notify;
 
-use crate::command::XReadStreamArg;
+use anyhow::Context;
 
-use self::stream::{RedisStream, ReqStreamEntryID, StreamEntryID};
+use crate::command::{XClaimOptions, XPendingRange, XReadStart, XReadStreamArg};
+
+use self::stream::{
+    ConsumerInfo, GroupInfo, RedisStream, ReqStreamEntryID, StreamEntryData, StreamEntryID,
+    StreamGroup, StreamInfo, StreamNodeLimits, StreamTrim, MAX_ID, MIN_ID,
+};
 
 pub(crate) mod stream;
+mod stream_node;
 mod trie;
 
 pub(crate) enum RedisValueType {
     String,
+    List,
+    Set,
+    SortedSet,
+    Hash,
     Stream,
 }
 
@@ -22,40 +33,145 @@ impl fmt::Display for RedisValueType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match self {
             RedisValueType::String => "string",
+            RedisValueType::List => "list",
+            RedisValueType::Set => "set",
+            RedisValueType::SortedSet => "zset",
+            RedisValueType::Hash => "hash",
             RedisValueType::Stream => "stream",
         };
         write!(f, "{}", s)
     }
 }
 
+/// Entries of a stream as (entry ID, flattened field-value pairs).
+pub(crate) type StreamEntries = Vec<(Vec<u8>, Vec<Vec<u8>>)>;
+
+/// Entries delivered to a consumer as (entry ID, flattened field-value pairs), without pairs
+/// for entries deleted since their delivery.
+pub(crate) type GroupEntries = Vec<(Vec<u8>, Option<Vec<Vec<u8>>>)>;
+
+/// Summary of a PEL: its size, smallest and greatest IDs, and size per consumer.
+pub(crate) type PendingSummary = (
+    usize,
+    Option<(StreamEntryID, StreamEntryID)>,
+    Vec<(Vec<u8>, usize)>,
+);
+
+/// Pending entries as (entry ID, consumer, idle milliseconds, delivery count).
+pub(crate) type PendingEntries = Vec<(StreamEntryID, Vec<u8>, u64, u64)>;
+
+fn no_group_error(key: &[u8], group: &[u8]) -> String {
+    format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    )
+}
+
+fn no_xgroup_group_error(key: &[u8], group: &[u8]) -> String {
+    format!(
+        "NOGROUP No such consumer group '{}' for key name '{}'",
+        String::from_utf8_lossy(group),
+        String::from_utf8_lossy(key)
+    )
+}
+
+pub(crate) type RedisList = VecDeque<Vec<u8>>;
+pub(crate) type RedisSet = HashSet<Vec<u8>>;
+/// Member to score.
+pub(crate) type RedisSortedSet = HashMap<Vec<u8>, f64>;
+/// Field to value and optional field expiry.
+pub(crate) type RedisHash = HashMap<Vec<u8>, (Vec<u8>, Option<SystemTime>)>;
+
+#[derive(Default)]
 pub(crate) struct RedisDb {
     pub(crate) nonexpire_table: HashMap<Vec<u8>, Vec<u8>>,
     pub(crate) expire_table: HashMap<Vec<u8>, (Vec<u8>, SystemTime)>,
+    pub(crate) lists: HashMap<Vec<u8>, RedisList>,
+    pub(crate) sets: HashMap<Vec<u8>, RedisSet>,
+    pub(crate) sorted_sets: HashMap<Vec<u8>, RedisSortedSet>,
+    pub(crate) hashes: HashMap<Vec<u8>, RedisHash>,
     pub(crate) streams: HashMap<Vec<u8>, RedisStream>,
-    pub(crate) stream_senders:
-        HashMap<Vec<u8>, broadcast::Sender<(StreamEntryID, HashMap<Vec<u8>, Vec<u8>>)>>,
+    /// Expiry of keys that are not strings; string expiries live in `expire_table`.
+    pub(crate) expire_at: HashMap<Vec<u8>, SystemTime>,
+    /// Clients blocked on a stream key, woken by the next entry added to it.
+    pub(crate) stream_waiters: HashMap<Vec<u8>, Vec<Arc<Notify>>>,
 }
 
 impl RedisDb {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies the dataset, leaving out any blocked-reader bookkeeping.
+    pub(crate) fn snapshot(&self) -> Self {
         Self {
-            nonexpire_table: HashMap::new(),
-            expire_table: HashMap::new(),
-            streams: HashMap::new(),
-            stream_senders: HashMap::new(),
+            nonexpire_table: self.nonexpire_table.clone(),
+            expire_table: self.expire_table.clone(),
+            lists: self.lists.clone(),
+            sets: self.sets.clone(),
+            sorted_sets: self.sorted_sets.clone(),
+            hashes: self.hashes.clone(),
+            streams: self.streams.clone(),
+            expire_at: self.expire_at.clone(),
+            stream_waiters: HashMap::new(),
         }
     }
 
-    pub(crate) fn get_stream_receiver(
+    fn is_expired(&self, key: &Vec<u8>) -> bool {
+        self.expire_at
+            .get(key)
+            .is_some_and(|expiry| SystemTime::now() >= *expiry)
+    }
+
+    /// Drops `key` if it is a non-string key whose expiry has passed.
+    fn expire_if_needed(&mut self, key: &Vec<u8>) {
+        if self.is_expired(key) {
+            eprintln!("Key has expired");
+            self.expire_at.remove(key);
+            self.lists.remove(key);
+            self.sets.remove(key);
+            self.sorted_sets.remove(key);
+            self.hashes.remove(key);
+            self.streams.remove(key);
+        }
+    }
+
+    /// Registers `waiter` to be notified once an entry is added to any of `keys`.
+    pub(crate) fn block_on_streams<'a>(
         &mut self,
-        key: &Vec<u8>,
-    ) -> broadcast::Receiver<(StreamEntryID, HashMap<Vec<u8>, Vec<u8>>)> {
-        if let Some(sender) = self.stream_senders.get(key) {
-            sender.subscribe()
-        } else {
-            let (sender, receiver) = broadcast::channel(1);
-            self.stream_senders.insert(key.clone(), sender);
-            receiver
+        keys: impl IntoIterator<Item = &'a Vec<u8>>,
+        waiter: &Arc<Notify>,
+    ) {
+        for key in keys {
+            let waiters = self.stream_waiters.entry(key.clone()).or_default();
+            if !waiters.iter().any(|other| Arc::ptr_eq(other, waiter)) {
+                waiters.push(waiter.clone());
+            }
+        }
+    }
+
+    /// Drops `waiter` from every key it is registered under.
+    pub(crate) fn unblock_streams<'a>(
+        &mut self,
+        keys: impl IntoIterator<Item = &'a Vec<u8>>,
+        waiter: &Arc<Notify>,
+    ) {
+        for key in keys {
+            if let Some(waiters) = self.stream_waiters.get_mut(key) {
+                waiters.retain(|other| !Arc::ptr_eq(other, waiter));
+                if waiters.is_empty() {
+                    self.stream_waiters.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Wakes every client blocked on `key`. A client not waiting yet keeps the wakeup for its
+    /// next wait, so entries added between its read and its wait are not missed.
+    fn wake_stream_waiters(&mut self, key: &Vec<u8>) {
+        for waiter in self.stream_waiters.remove(key).unwrap_or_default() {
+            waiter.notify_one();
         }
     }
 
@@ -79,15 +195,15 @@ impl RedisDb {
         }
     }
 
-    pub(crate) fn set(&mut self, key: &Vec<u8>, value: Vec<u8>, px: Option<Duration>) {
+    pub(crate) fn set(&mut self, key: &Vec<u8>, value: Vec<u8>, expire_at: Option<SystemTime>) {
         let nonexpire_val = self.nonexpire_table.get_mut(key);
         if let Some(stored_val) = nonexpire_val {
             eprintln!("Key found in nonexpire table: {:?}", key);
-            if let Some(dur) = px {
+            if let Some(expire_at) = expire_at {
                 eprintln!("KV has an expiry; remove key from nonexpire and add to expire table.");
                 self.nonexpire_table.remove(key);
                 self.expire_table
-                    .insert(key.clone(), (value.clone(), SystemTime::now() + dur));
+                    .insert(key.clone(), (value.clone(), expire_at));
             } else {
                 eprintln!("KV has no expiry; mutate value in-place.");
                 *stored_val = value.clone();
@@ -98,9 +214,9 @@ impl RedisDb {
         let expire_val = self.expire_table.get_mut(key);
         if let Some(stored_val) = expire_val {
             eprintln!("Key found in expire table: {:?}", key);
-            if let Some(dur) = px {
+            if let Some(expire_at) = expire_at {
                 eprintln!("KV has an expiry; mutate value and expiry in-place.");
-                *stored_val = (value.clone(), SystemTime::now() + dur);
+                *stored_val = (value.clone(), expire_at);
             } else {
                 eprintln!("KV has no expiry; remove key from expire table and add to nonexpire.");
                 self.expire_table.remove(key);
@@ -110,86 +226,465 @@ impl RedisDb {
         }
 
         eprintln!("New key: {:?}", key);
-        if let Some(dur) = px {
+        if let Some(expire_at) = expire_at {
             self.expire_table
-                .insert(key.clone(), (value.clone(), SystemTime::now() + dur));
+                .insert(key.clone(), (value.clone(), expire_at));
         } else {
             self.nonexpire_table.insert(key.clone(), value.clone());
         }
     }
 
     pub(crate) fn keys(&self) -> Vec<Vec<u8>> {
+        let now = SystemTime::now();
         let mut keys = self
             .nonexpire_table
             .keys()
-            .map(|k| k.clone())
+            .cloned()
             .collect::<Vec<Vec<u8>>>();
         let mut expire_keys = self
             .expire_table
-            .keys()
-            .map(|k| k.clone())
+            .iter()
+            .filter(|(_, (_, expiry))| *expiry > now)
+            .map(|(k, _)| k.clone())
             .collect::<Vec<Vec<u8>>>();
         keys.append(&mut expire_keys);
+
+        let collection_keys = self
+            .lists
+            .keys()
+            .chain(self.sets.keys())
+            .chain(self.sorted_sets.keys())
+            .chain(self.hashes.keys())
+            .chain(self.streams.keys())
+            .filter(|k| !self.is_expired(k))
+            .cloned();
+        keys.extend(collection_keys);
         keys
     }
 
     pub(crate) fn lookup_type(&mut self, key: &Vec<u8>) -> Option<RedisValueType> {
-        self.get(key).map(|_| RedisValueType::String).or_else(|| {
-            if self.streams.contains_key(key) {
-                Some(RedisValueType::Stream)
-            } else {
-                None
-            }
-        })
+        if self.get(key).is_some() {
+            return Some(RedisValueType::String);
+        }
+
+        self.expire_if_needed(key);
+        if self.lists.contains_key(key) {
+            Some(RedisValueType::List)
+        } else if self.sets.contains_key(key) {
+            Some(RedisValueType::Set)
+        } else if self.sorted_sets.contains_key(key) {
+            Some(RedisValueType::SortedSet)
+        } else if self.hashes.contains_key(key) {
+            Some(RedisValueType::Hash)
+        } else if self.streams.contains_key(key) {
+            Some(RedisValueType::Stream)
+        } else {
+            None
+        }
     }
 
+    /// Adds an entry to stream `key`. Returns `None` without adding it if the stream does not
+    /// exist and `nomkstream` is set.
     pub(crate) fn xadd(
         &mut self,
         key: &Vec<u8>,
         entry_id: Option<ReqStreamEntryID>,
-        data: HashMap<Vec<u8>, Vec<u8>>,
-    ) -> anyhow::Result<StreamEntryID> {
-        let res = if let Some(stream) = self.streams.get_mut(key) {
-            stream.insert(entry_id, data.clone())
+        data: StreamEntryData,
+        nomkstream: bool,
+        limits: &StreamNodeLimits,
+    ) -> anyhow::Result<Option<StreamEntryID>> {
+        self.expire_if_needed(key);
+        let entry_id = if let Some(stream) = self.streams.get_mut(key) {
+            stream.insert(entry_id, data, limits)?
+        } else if nomkstream {
+            return Ok(None);
         } else {
             let mut stream = RedisStream::new();
-            let res = stream.insert(entry_id, data.clone())?;
+            let entry_id = stream.insert(entry_id, data, limits)?;
             self.streams.insert(key.clone(), stream);
-            Ok(res)
+            entry_id
         };
+        self.wake_stream_waiters(key);
 
-        if let Ok(entry_id) = &res {
-            if let Some(sender) = self.stream_senders.get(key) {
-                sender.send((entry_id.clone(), data))?;
-            }
-        }
+        Ok(Some(entry_id))
+    }
 
-        res
+    pub(crate) fn xlen(&self, key: &Vec<u8>) -> usize {
+        self.streams
+            .get(key)
+            .filter(|_| !self.is_expired(key))
+            .map_or(0, RedisStream::len)
     }
 
+    /// Deletes the entries `ids` of stream `key`, returning how many existed.
+    pub(crate) fn xdel(&mut self, key: &Vec<u8>, ids: &[StreamEntryID]) -> usize {
+        self.expire_if_needed(key);
+        self.streams
+            .get_mut(key)
+            .map_or(0, |stream| stream.delete(ids))
+    }
+
+    /// Trims stream `key`, returning how many entries were removed.
+    pub(crate) fn xtrim(
+        &mut self,
+        key: &Vec<u8>,
+        trim: &StreamTrim,
+        limits: &StreamNodeLimits,
+    ) -> usize {
+        self.expire_if_needed(key);
+        self.streams
+            .get_mut(key)
+            .map_or(0, |stream| stream.trim(trim, limits))
+    }
+
+    /// Stream `key`, which XINFO and XSETID require to exist.
+    fn existing_stream(&self, key: &Vec<u8>) -> anyhow::Result<&RedisStream> {
+        self.streams
+            .get(key)
+            .filter(|_| !self.is_expired(key))
+            .context("ERR no such key")
+    }
+
+    /// Sets the metadata of stream `key`, see [`RedisStream::set_id`].
+    pub(crate) fn xsetid(
+        &mut self,
+        key: &Vec<u8>,
+        last_id: StreamEntryID,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamEntryID>,
+    ) -> anyhow::Result<()> {
+        self.expire_if_needed(key);
+        self.streams
+            .get_mut(key)
+            .context("ERR no such key")?
+            .set_id(last_id, entries_added, max_deleted_id)
+    }
+
+    /// Reports on stream `key`, listing up to `full` entries with FULL, 0 meaning all of them.
+    pub(crate) fn xinfo_stream(
+        &self,
+        key: &Vec<u8>,
+        full: Option<usize>,
+    ) -> anyhow::Result<StreamInfo> {
+        let full = full.map(|count| if count == 0 { usize::MAX } else { count });
+        Ok(self.existing_stream(key)?.info(full))
+    }
+
+    pub(crate) fn xinfo_groups(&self, key: &Vec<u8>) -> anyhow::Result<Vec<GroupInfo>> {
+        let stream = self.existing_stream(key)?;
+        Ok(stream
+            .groups()
+            .keys()
+            .filter_map(|name| stream.group_info(name, 0))
+            .collect())
+    }
+
+    pub(crate) fn xinfo_consumers(
+        &self,
+        key: &Vec<u8>,
+        group: &[u8],
+    ) -> anyhow::Result<Vec<ConsumerInfo>> {
+        Ok(self
+            .existing_stream(key)?
+            .group_info(group, 0)
+            .context(no_xgroup_group_error(key, group))?
+            .consumers)
+    }
+
+    /// Entries of stream `key` from `start` to `end`, see [`RedisStream::xrange`].
     pub(crate) fn xrange(
         &self,
         key: &Vec<u8>,
-        start: StreamEntryID,
-        end: StreamEntryID,
-    ) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
+        start: &StreamEntryID,
+        end: &StreamEntryID,
+        count: Option<usize>,
+        rev: bool,
+    ) -> StreamEntries {
         self.streams
             .get(key)
-            .map_or(vec![], |stream| stream.xrange(start, end))
+            .filter(|_| !self.is_expired(key))
+            .map_or(vec![], |stream| stream.xrange(start, end, count, rev))
     }
 
-    pub(crate) fn xread(
+    /// Group `group` of stream `key`, if both exist.
+    pub(crate) fn stream_group(&self, key: &Vec<u8>, group: &[u8]) -> Option<&StreamGroup> {
+        self.streams
+            .get(key)
+            .filter(|_| !self.is_expired(key))?
+            .groups()
+            .get(group)
+    }
+
+    /// Stream `key`, which XGROUP subcommands require to exist.
+    fn xgroup_stream(&mut self, key: &Vec<u8>) -> anyhow::Result<&mut RedisStream> {
+        self.expire_if_needed(key);
+        self.streams.get_mut(key).context(
+            "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+        )
+    }
+
+    /// Creates a group, returning the ID it starts after.
+    pub(crate) fn xgroup_create(
+        &mut self,
+        key: &Vec<u8>,
+        group: &[u8],
+        id: Option<StreamEntryID>,
+        mkstream: bool,
+        entries_read: Option<u64>,
+    ) -> anyhow::Result<StreamEntryID> {
+        self.expire_if_needed(key);
+        if mkstream && !self.streams.contains_key(key) {
+            self.streams.insert(key.clone(), RedisStream::new());
+        }
+        self.xgroup_stream(key)?
+            .create_group(group, id, entries_read)
+    }
+
+    /// Moves the last delivered ID of a group, returning the new one.
+    pub(crate) fn xgroup_setid(
+        &mut self,
+        key: &Vec<u8>,
+        group: &[u8],
+        id: Option<StreamEntryID>,
+        entries_read: Option<u64>,
+    ) -> anyhow::Result<StreamEntryID> {
+        self.xgroup_stream(key)?
+            .set_group_id(group, id, entries_read)
+            .context(no_xgroup_group_error(key, group))
+    }
+
+    pub(crate) fn xgroup_destroy(&mut self, key: &Vec<u8>, group: &[u8]) -> anyhow::Result<bool> {
+        Ok(self.xgroup_stream(key)?.destroy_group(group))
+    }
+
+    pub(crate) fn xgroup_createconsumer(
+        &mut self,
+        key: &Vec<u8>,
+        group: &[u8],
+        consumer: &[u8],
+        now: u64,
+    ) -> anyhow::Result<bool> {
+        Ok(self
+            .xgroup_stream(key)?
+            .group_mut(group)
+            .context(no_xgroup_group_error(key, group))?
+            .create_consumer(consumer, now))
+    }
+
+    /// Deletes a consumer, returning the number of entries that were pending for it.
+    pub(crate) fn xgroup_delconsumer(
+        &mut self,
+        key: &Vec<u8>,
+        group: &[u8],
+        consumer: &[u8],
+    ) -> anyhow::Result<usize> {
+        Ok(self
+            .xgroup_stream(key)?
+            .group_mut(group)
+            .context(no_xgroup_group_error(key, group))?
+            .delete_consumer(consumer))
+    }
+
+    /// Reads `streams` as `consumer` of `group`, see [`RedisStream::read_group`]. Nothing is
+    /// read if the group is missing from any of them.
+    pub(crate) fn xreadgroup(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        count: Option<usize>,
+        noack: bool,
+        streams: &[XReadStreamArg],
+        now: u64,
+    ) -> anyhow::Result<Vec<Vec<StreamEntryID>>> {
+        for arg in streams {
+            self.expire_if_needed(&arg.key);
+            if self.stream_group(&arg.key, group).is_none() {
+                return Err(anyhow::anyhow!(
+                    "{} in XREADGROUP with GROUP option",
+                    no_group_error(&arg.key, group)
+                ));
+            }
+        }
+        Ok(streams
+            .iter()
+            .map(|arg| {
+                self.streams
+                    .get_mut(&arg.key)
+                    .and_then(|stream| {
+                        let start = match &arg.start {
+                            XReadStart::After(id) => Some(id),
+                            _ => None,
+                        };
+                        stream.read_group(group, consumer, start, count, noack, now)
+                    })
+                    .expect("Group exists")
+            })
+            .collect())
+    }
+
+    /// Acknowledges entries of a group, returning how many were pending.
+    pub(crate) fn xack(&mut self, key: &Vec<u8>, group: &[u8], ids: &[StreamEntryID]) -> usize {
+        self.expire_if_needed(key);
+        let Some(group) = self
+            .streams
+            .get_mut(key)
+            .and_then(|stream| stream.group_mut(group))
+        else {
+            return 0;
+        };
+        ids.iter().filter(|id| group.ack(id)).count()
+    }
+
+    pub(crate) fn xpending_summary(
         &self,
-        args: &Vec<XReadStreamArg>,
-    ) -> Vec<(Vec<u8>, Vec<(Vec<u8>, Vec<Vec<u8>>)>)> {
-        args.into_iter()
+        key: &Vec<u8>,
+        group: &[u8],
+    ) -> anyhow::Result<PendingSummary> {
+        let stream_group = self
+            .stream_group(key, group)
+            .context(no_group_error(key, group))?;
+        let bounds = stream_group
+            .pel
+            .first_key_value()
+            .zip(stream_group.pel.last_key_value())
+            .map(|((first, _), (last, _))| (first.clone(), last.clone()));
+        let consumers = stream_group
+            .pending_per_consumer()
+            .into_iter()
+            .map(|(name, count)| (name.to_vec(), count))
+            .collect();
+        Ok((stream_group.pel.len(), bounds, consumers))
+    }
+
+    pub(crate) fn xpending_range(
+        &self,
+        key: &Vec<u8>,
+        group: &[u8],
+        range: &XPendingRange,
+        now: u64,
+    ) -> anyhow::Result<PendingEntries> {
+        let group = self
+            .stream_group(key, group)
+            .context(no_group_error(key, group))?;
+        Ok(group
+            .pending_range(
+                &range.start,
+                &range.end,
+                range.count,
+                range.consumer.as_deref(),
+                range.min_idle_time,
+                now,
+            )
+            .into_iter()
+            .map(|(id, entry)| {
+                (
+                    id.clone(),
+                    entry.consumer.clone(),
+                    now.saturating_sub(entry.delivery_time),
+                    entry.delivery_count,
+                )
+            })
+            .collect())
+    }
+
+    /// Claims pending entries, see [`RedisStream::claim`].
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn xclaim(
+        &mut self,
+        key: &Vec<u8>,
+        group: &[u8],
+        consumer: &[u8],
+        min_idle_time: u64,
+        ids: &[StreamEntryID],
+        options: &XClaimOptions,
+        now: u64,
+    ) -> anyhow::Result<(Vec<StreamEntryID>, Vec<StreamEntryID>)> {
+        self.expire_if_needed(key);
+        self.streams
+            .get_mut(key)
+            .and_then(|stream| stream.claim(group, consumer, min_idle_time, ids, options, now))
+            .context(no_group_error(key, group))
+    }
+
+    /// Claims idle pending entries, see [`RedisStream::auto_claim`].
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn xautoclaim(
+        &mut self,
+        key: &Vec<u8>,
+        group: &[u8],
+        consumer: &[u8],
+        min_idle_time: u64,
+        start: &StreamEntryID,
+        count: usize,
+        just_id: bool,
+        now: u64,
+    ) -> anyhow::Result<(StreamEntryID, Vec<StreamEntryID>, Vec<StreamEntryID>)> {
+        self.expire_if_needed(key);
+        self.streams
+            .get_mut(key)
+            .and_then(|stream| {
+                stream.auto_claim(group, consumer, min_idle_time, start, count, just_id, now)
+            })
+            .context(no_group_error(key, group))
+    }
+
+    /// Entries `ids` of stream `key` as delivered to a consumer.
+    pub(crate) fn group_entries(&self, key: &Vec<u8>, ids: &[StreamEntryID]) -> GroupEntries {
+        let stream = self.streams.get(key);
+        ids.iter()
+            .map(|id| {
+                let data = stream
+                    .and_then(|stream| stream.get(id))
+                    .map(|data| data.into_iter().flat_map(|(k, v)| [k, v]).collect());
+                (id.as_bytes(), data)
+            })
+            .collect()
+    }
+
+    /// Pins `$` and `+` starts of `args` to concrete IDs, so that a blocked XREAD keeps
+    /// reading after the same entry however many times it is woken.
+    pub(crate) fn xread_resolve(&self, args: &[XReadStreamArg]) -> Vec<XReadStreamArg> {
+        args.iter()
             .map(|arg| {
-                return (
-                    arg.key.clone(),
-                    self.streams
-                        .get(&arg.key)
-                        .map_or(vec![], |stream| stream.xread(&arg.start)),
-                );
+                let stream = self
+                    .streams
+                    .get(&arg.key)
+                    .filter(|_| !self.is_expired(&arg.key));
+                let last_id = || stream.map_or(MIN_ID, |stream| stream.last_entry_id().clone());
+                let start = match &arg.start {
+                    XReadStart::After(id) => id.clone(),
+                    XReadStart::New => last_id(),
+                    XReadStart::Last => stream
+                        .and_then(RedisStream::newest_entry_id)
+                        .and_then(|id| id.prev())
+                        .unwrap_or_else(last_id),
+                };
+                XReadStreamArg {
+                    key: arg.key.clone(),
+                    start: XReadStart::After(start),
+                }
+            })
+            .collect()
+    }
+
+    /// Up to `count` entries per stream of `args`, leaving out streams with none.
+    pub(crate) fn xread(
+        &self,
+        args: &[XReadStreamArg],
+        count: Option<usize>,
+    ) -> Vec<(Vec<u8>, StreamEntries)> {
+        args.iter()
+            .filter_map(|arg| {
+                let stream = self
+                    .streams
+                    .get(&arg.key)
+                    .filter(|_| !self.is_expired(&arg.key))?;
+                let entries = match &arg.start {
+                    XReadStart::After(id) => stream.xread(id, count),
+                    XReadStart::New => vec![],
+                    XReadStart::Last => stream.xrange(&MIN_ID, &MAX_ID, Some(1), true),
+                };
+                (!entries.is_empty()).then(|| (arg.key.clone(), entries))
             })
             .collect()
     }
@@ -210,7 +705,9 @@ mod tests {
                 millis: 0,
                 seq_num: Some(1),
             }),
-            HashMap::from([(b"temperature".to_vec(), b"23".to_vec())]),
+            vec![(b"temperature".to_vec(), b"23".to_vec())],
+            false,
+            &StreamNodeLimits::default(),
         );
 
         let _ = db.xadd(
@@ -219,7 +716,9 @@ mod tests {
                 millis: 0,
                 seq_num: Some(2),
             }),
-            HashMap::from([(b"temperature".to_vec(), b"24".to_vec())]),
+            vec![(b"temperature".to_vec(), b"24".to_vec())],
+            false,
+            &StreamNodeLimits::default(),
         );
 
         let _ = db.xadd(
@@ -228,7 +727,9 @@ mod tests {
                 millis: 0,
                 seq_num: Some(4),
             }),
-            HashMap::from([(b"temperature".to_vec(), b"20".to_vec())]),
+            vec![(b"temperature".to_vec(), b"20".to_vec())],
+            false,
+            &StreamNodeLimits::default(),
         );
 
         db
@@ -238,9 +739,9 @@ mod tests {
     fn test_xread_singlestream() {
         // Arrange
         let db = get_sample_db();
-        let args = &vec![XReadStreamArg {
+        let args = &[XReadStreamArg {
             key: b"apple".to_vec(),
-            start: Some(StreamEntryID {
+            start: XReadStart::After(StreamEntryID {
                 millis: 0,
                 seq_num: 1,
             }),
@@ -254,7 +755,7 @@ mod tests {
         )];
 
         // Act
-        let actual = db.xread(args);
+        let actual = db.xread(args, None);
 
         // Assert
         assert_eq!(actual, expected);
@@ -264,17 +765,17 @@ mod tests {
     fn test_xread_multistream() {
         // Arrange
         let db = get_sample_db();
-        let args = &vec![
+        let args = &[
             XReadStreamArg {
                 key: b"apple".to_vec(),
-                start: Some(StreamEntryID {
+                start: XReadStart::After(StreamEntryID {
                     millis: 0,
                     seq_num: 1,
                 }),
             },
             XReadStreamArg {
                 key: b"orange".to_vec(),
-                start: Some(StreamEntryID {
+                start: XReadStart::After(StreamEntryID {
                     millis: 0,
                     seq_num: 3,
                 }),
@@ -298,9 +799,130 @@ mod tests {
         ];
 
         // Act
-        let actual = db.xread(args);
+        let actual = db.xread(args, None);
 
         // Assert
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_xread_special_ids() {
+        // Arrange
+        let db = get_sample_db();
+        let arg = |key: &[u8], start| XReadStreamArg {
+            key: key.to_vec(),
+            start,
+        };
+        let after = |seq_num| XReadStart::After(StreamEntryID { millis: 0, seq_num });
+
+        // Act
+        let last = db.xread(&[arg(b"apple", XReadStart::Last)], None);
+        let new = db.xread(&[arg(b"apple", XReadStart::New)], None);
+        let resolved = db.xread_resolve(&[
+            arg(b"apple", XReadStart::New),
+            arg(b"apple", XReadStart::Last),
+            arg(b"banana", XReadStart::Last),
+        ]);
+
+        // Assert
+        assert_eq!(
+            last,
+            vec![(
+                b"apple".to_vec(),
+                vec![(
+                    b"0-2".to_vec(),
+                    vec![b"temperature".to_vec(), b"24".to_vec()],
+                )],
+            )]
+        );
+        assert!(new.is_empty());
+        assert_eq!(
+            resolved,
+            vec![
+                arg(b"apple", after(2)),
+                arg(b"apple", after(1)),
+                arg(b"banana", after(0)),
+            ]
+        );
+    }
+
+    async fn wait_notified(waiter: &Notify) {
+        tokio::time::timeout(std::time::Duration::from_secs(1), waiter.notified())
+            .await
+            .expect("Waiter is notified");
+    }
+
+    fn add_temperature(db: &mut RedisDb, key: &[u8], temperature: &str) {
+        db.xadd(
+            &key.to_vec(),
+            None,
+            vec![(b"temperature".to_vec(), temperature.as_bytes().to_vec())],
+            false,
+            &StreamNodeLimits::default(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_xadd_wakes_blocked_streams() {
+        // Arrange
+        let mut db = get_sample_db();
+        let waiter = Arc::new(Notify::new());
+        let keys = [b"apple".to_vec(), b"banana".to_vec()];
+        db.block_on_streams(&keys, &waiter);
+
+        // Act
+        add_temperature(&mut db, b"banana", "21");
+        wait_notified(&waiter).await;
+        let still_on_apple = db.stream_waiters.contains_key(&keys[0]);
+        // Registered again, as a blocked read does after every empty read.
+        db.block_on_streams(&keys, &waiter);
+        add_temperature(&mut db, b"apple", "25");
+        wait_notified(&waiter).await;
+        db.block_on_streams(&keys, &waiter);
+        let registered = db.stream_waiters.len();
+        db.unblock_streams(&keys, &waiter);
+
+        // Assert
+        assert!(still_on_apple);
+        assert_eq!(registered, 2);
+        assert!(db.stream_waiters.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_xread_after_wakeup_gets_every_entry_added_before_wait() {
+        // Arrange
+        let mut db = get_sample_db();
+        let waiter = Arc::new(Notify::new());
+        let args = db.xread_resolve(&[XReadStreamArg {
+            key: b"apple".to_vec(),
+            start: XReadStart::New,
+        }]);
+        let keys = [b"apple".to_vec()];
+        let before = db.xread(&args, None);
+        db.block_on_streams(&keys, &waiter);
+
+        // Act
+        for temperature in ["25", "26", "27"] {
+            add_temperature(&mut db, b"apple", temperature);
+        }
+        wait_notified(&waiter).await;
+        let counted = db.xread(&args, Some(2));
+        let all = db.xread(&args, None);
+        db.unblock_streams(&keys, &waiter);
+
+        // Assert
+        let temperatures = |data: &[(Vec<u8>, StreamEntries)]| {
+            data.iter()
+                .flat_map(|(_, entries)| entries.iter().map(|(_, fields)| fields[1].clone()))
+                .collect::<Vec<_>>()
+        };
+        assert!(before.is_empty());
+        assert_eq!(temperatures(&counted), vec![b"25".to_vec(), b"26".to_vec()]);
+        assert_eq!(
+            temperatures(&all),
+            vec![b"25".to_vec(), b"26".to_vec(), b"27".to_vec()]
+        );
+        assert!(db.stream_waiters.is_empty());
+    }
 }
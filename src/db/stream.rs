@@ -1,10 +1,26 @@
 use std::{
     cmp::Ordering,
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet},
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use super::trie::Trie;
+use anyhow::Context;
+
+use crate::command::XClaimOptions;
+
+use super::{stream_node::StreamNode, trie::Trie};
+
+pub(crate) use super::stream_node::StreamNodeLimits;
+
+/// XAUTOCLAIM scans at most this many pending entries per entry it may claim.
+const XAUTOCLAIM_ATTEMPTS_FACTOR: usize = 10;
+
+/// Nodes approximate trimming removes at most without an explicit LIMIT.
+const TRIM_DEFAULT_LIMIT_NODES: usize = 100;
+
+/// Entries approximate trimming removes at most without an explicit LIMIT when nodes have no
+/// entry limit.
+const TRIM_DEFAULT_LIMIT_ENTRIES: usize = 10000;
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct ReqStreamEntryID {
@@ -12,7 +28,7 @@ pub(crate) struct ReqStreamEntryID {
     pub(crate) seq_num: Option<u64>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) struct StreamEntryID {
     pub(crate) millis: u64,
     pub(crate) seq_num: u64,
@@ -28,11 +44,75 @@ impl StreamEntryID {
         res.append(&mut seq_num);
         res
     }
+
+    /// The smallest ID greater than this one, if any.
+    pub(crate) fn next(&self) -> Option<Self> {
+        if self.seq_num == u64::MAX {
+            self.millis
+                .checked_add(1)
+                .map(|millis| Self { millis, seq_num: 0 })
+        } else {
+            Some(Self {
+                millis: self.millis,
+                seq_num: self.seq_num + 1,
+            })
+        }
+    }
+
+    /// Key of the node starting at this ID in the tree of stream nodes.
+    fn node_key(&self) -> u128 {
+        ((self.millis as u128) << 64) | self.seq_num as u128
+    }
+
+    fn from_node_key(key: u128) -> Self {
+        Self {
+            millis: (key >> 64) as u64,
+            seq_num: key as u64,
+        }
+    }
+
+    /// The greatest ID smaller than this one, if any.
+    pub(crate) fn prev(&self) -> Option<Self> {
+        if self.seq_num == 0 {
+            self.millis.checked_sub(1).map(|millis| Self {
+                millis,
+                seq_num: u64::MAX,
+            })
+        } else {
+            Some(Self {
+                millis: self.millis,
+                seq_num: self.seq_num - 1,
+            })
+        }
+    }
 }
 
+pub(crate) const MIN_ID: StreamEntryID = StreamEntryID {
+    millis: u64::MIN,
+    seq_num: u64::MIN,
+};
+
+pub(crate) const MAX_ID: StreamEntryID = StreamEntryID {
+    millis: u64::MAX,
+    seq_num: u64::MAX,
+};
+
+/// Current Unix time in milliseconds.
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+/// ID of an entry added after `last_entry`, as requested by `req` or generated from the time
+/// `now` with `None`. Generated IDs never go back even if the clock does: they keep the
+/// millisecond of the last entry until `now` passes it, and move to the next millisecond once
+/// its sequence numbers run out.
 fn make_stream_entry_id(
     req: Option<ReqStreamEntryID>,
     last_entry: &StreamEntryID,
+    now: u64,
 ) -> anyhow::Result<StreamEntryID> {
     if let Some(req) = req {
         if req.millis == 0 {
@@ -61,7 +141,9 @@ fn make_stream_entry_id(
                                 ));
                     }
                 } else {
-                    last_entry.seq_num + 1
+                    last_entry.seq_num.checked_add(1).context(
+                        "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+                    )?
                 }
             }
             Ordering::Greater => req.seq_num.unwrap_or(0),
@@ -71,25 +153,241 @@ fn make_stream_entry_id(
             millis: req.millis,
             seq_num,
         })
-    } else if last_entry.millis == 0 && last_entry.seq_num == 0 {
+    } else if now > last_entry.millis {
         Ok(StreamEntryID {
-            millis: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_millis() as u64,
+            millis: now,
             seq_num: 0,
         })
     } else {
-        Ok(StreamEntryID {
-            millis: last_entry.millis,
-            seq_num: last_entry.seq_num + 1,
-        })
+        last_entry
+            .next()
+            .context("ERR The stream has exhausted the last possible ID, unable to add more items")
+    }
+}
+
+/// Field/value pairs of an entry in the order they were given. Fields may repeat.
+pub(crate) type StreamEntryData = Vec<(Vec<u8>, Vec<u8>)>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TrimStrategy {
+    /// Keep at most this many entries.
+    MaxLen(usize),
+    /// Drop the entries with a smaller ID.
+    MinId(StreamEntryID),
+}
+
+/// Trimming requested with XTRIM or along with XADD.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StreamTrim {
+    pub(crate) strategy: TrimStrategy,
+    /// Only remove whole nodes, possibly leaving a few more entries than requested (`~`).
+    pub(crate) approx: bool,
+    /// Most entries to remove when `approx`, 0 meaning no limit.
+    pub(crate) limit: Option<usize>,
+}
+
+/// An entry delivered to a consumer of a group and not acknowledged yet.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PendingEntry {
+    pub(crate) consumer: Vec<u8>,
+    /// Unix time in milliseconds of the last delivery.
+    pub(crate) delivery_time: u64,
+    pub(crate) delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StreamConsumer {
+    /// Unix time in milliseconds the consumer last tried to read or claim entries.
+    pub(crate) seen_time: u64,
+    /// Unix time in milliseconds the consumer last got entries, if it ever did.
+    pub(crate) active_time: Option<u64>,
+    /// Entries delivered to the consumer, whose details are in the group's PEL.
+    pub(crate) pending: BTreeSet<StreamEntryID>,
+}
+
+impl StreamConsumer {
+    pub(crate) fn new(seen_time: u64) -> Self {
+        Self {
+            seen_time,
+            active_time: None,
+            pending: BTreeSet::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StreamGroup {
+    /// ID of the last entry delivered to any consumer of the group.
+    pub(crate) last_delivered: StreamEntryID,
+    /// Number of entries the group read, when it is known.
+    pub(crate) entries_read: Option<u64>,
+    /// Pending entries list: every entry delivered and not acknowledged yet.
+    pub(crate) pel: BTreeMap<StreamEntryID, PendingEntry>,
+    pub(crate) consumers: BTreeMap<Vec<u8>, StreamConsumer>,
+}
+
+impl StreamGroup {
+    pub(crate) fn new(last_delivered: StreamEntryID, entries_read: Option<u64>) -> Self {
+        Self {
+            last_delivered,
+            entries_read,
+            pel: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    /// Looks `name` up, creating it if needed, and records that it was seen at `now`.
+    fn touch_consumer(&mut self, name: &[u8], now: u64) -> &mut StreamConsumer {
+        let consumer = self
+            .consumers
+            .entry(name.to_vec())
+            .or_insert_with(|| StreamConsumer::new(now));
+        consumer.seen_time = now;
+        consumer
+    }
+
+    /// Creates consumer `name`, returning whether it did not exist yet.
+    pub(crate) fn create_consumer(&mut self, name: &[u8], now: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        self.consumers
+            .insert(name.to_vec(), StreamConsumer::new(now));
+        true
+    }
+
+    /// Deletes consumer `name` along with its pending entries, returning how many it had.
+    pub(crate) fn delete_consumer(&mut self, name: &[u8]) -> usize {
+        let Some(consumer) = self.consumers.remove(name) else {
+            return 0;
+        };
+        for id in consumer.pending.iter() {
+            self.pel.remove(id);
+        }
+        consumer.pending.len()
+    }
+
+    /// Makes `id` pending for `consumer`, taking it from whichever consumer it was pending for.
+    fn assign(&mut self, id: &StreamEntryID, consumer: &[u8], delivery_time: u64) {
+        let entry = self.pel.entry(id.clone()).or_insert_with(|| PendingEntry {
+            consumer: consumer.to_vec(),
+            delivery_time,
+            delivery_count: 0,
+        });
+        if entry.consumer != consumer {
+            if let Some(previous) = self.consumers.get_mut(&entry.consumer) {
+                previous.pending.remove(id);
+            }
+            entry.consumer = consumer.to_vec();
+        }
+        entry.delivery_time = delivery_time;
+        self.consumers
+            .get_mut(consumer)
+            .expect("Consumer exists")
+            .pending
+            .insert(id.clone());
+    }
+
+    /// Acknowledges `id`, returning whether it was pending.
+    pub(crate) fn ack(&mut self, id: &StreamEntryID) -> bool {
+        let Some(entry) = self.pel.remove(id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(id);
+        }
+        true
+    }
+
+    /// Number of pending entries per consumer, leaving out consumers with none.
+    pub(crate) fn pending_per_consumer(&self) -> Vec<(&[u8], usize)> {
+        self.consumers
+            .iter()
+            .filter(|(_, consumer)| !consumer.pending.is_empty())
+            .map(|(name, consumer)| (&name[..], consumer.pending.len()))
+            .collect()
     }
+
+    /// Pending entries from `start` to `end` inclusive, optionally only those of `consumer` or
+    /// idle for at least `min_idle_time` milliseconds.
+    pub(crate) fn pending_range(
+        &self,
+        start: &StreamEntryID,
+        end: &StreamEntryID,
+        count: usize,
+        consumer: Option<&[u8]>,
+        min_idle_time: Option<u64>,
+        now: u64,
+    ) -> Vec<(&StreamEntryID, &PendingEntry)> {
+        if start > end {
+            return vec![];
+        }
+        self.pel
+            .range(start..=end)
+            .filter(|(_, entry)| consumer.is_none_or(|consumer| entry.consumer == consumer))
+            .filter(|(_, entry)| {
+                min_idle_time.is_none_or(|idle| now.saturating_sub(entry.delivery_time) >= idle)
+            })
+            .take(count)
+            .collect()
+    }
+}
+
+/// What XINFO reports about a consumer.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ConsumerInfo {
+    pub(crate) name: Vec<u8>,
+    pub(crate) seen_time: u64,
+    pub(crate) active_time: Option<u64>,
+    pub(crate) pel_count: usize,
+    /// First pending entries as (entry ID, delivery time, delivery count), listed with FULL.
+    pub(crate) pending: Vec<(StreamEntryID, u64, u64)>,
 }
 
+/// What XINFO reports about a consumer group.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GroupInfo {
+    pub(crate) name: Vec<u8>,
+    pub(crate) last_delivered: StreamEntryID,
+    pub(crate) entries_read: Option<u64>,
+    /// Number of entries not delivered to the group yet, when it can be told.
+    pub(crate) lag: Option<u64>,
+    pub(crate) pel_count: usize,
+    /// First pending entries, listed with FULL.
+    pub(crate) pending: Vec<(StreamEntryID, PendingEntry)>,
+    pub(crate) consumers: Vec<ConsumerInfo>,
+}
+
+/// What XINFO STREAM reports about a stream.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StreamInfo {
+    pub(crate) length: usize,
+    /// Number of nodes packing the entries.
+    pub(crate) radix_tree_keys: usize,
+    /// Number of nodes of the radix tree indexing them.
+    pub(crate) radix_tree_nodes: usize,
+    pub(crate) last_generated_id: StreamEntryID,
+    pub(crate) max_deleted_entry_id: StreamEntryID,
+    pub(crate) entries_added: u64,
+    pub(crate) recorded_first_entry_id: StreamEntryID,
+    pub(crate) first_entry: Option<(Vec<u8>, Vec<Vec<u8>>)>,
+    pub(crate) last_entry: Option<(Vec<u8>, Vec<Vec<u8>>)>,
+    /// First entries, listed with FULL.
+    pub(crate) entries: Vec<(Vec<u8>, Vec<Vec<u8>>)>,
+    pub(crate) groups: Vec<GroupInfo>,
+}
+
+#[derive(Clone)]
 pub(crate) struct RedisStream {
-    root: Trie<Trie<HashMap<Vec<u8>, Vec<u8>>>>,
+    /// Nodes of entries keyed by the ID of their first entry.
+    root: Trie<StreamNode>,
     last_entry: StreamEntryID,
+    length: usize,
+    /// Largest ID deleted with XDEL.
+    max_deleted_entry_id: StreamEntryID,
+    /// Number of entries ever added, including the deleted ones.
+    entries_added: u64,
+    groups: BTreeMap<Vec<u8>, StreamGroup>,
 }
 
 impl RedisStream {
@@ -100,82 +398,693 @@ impl RedisStream {
                 millis: 0,
                 seq_num: 0,
             },
+            length: 0,
+            max_deleted_entry_id: StreamEntryID {
+                millis: 0,
+                seq_num: 0,
+            },
+            entries_added: 0,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.length
+    }
+
+    pub(crate) fn last_entry_id(&self) -> &StreamEntryID {
+        &self.last_entry
+    }
+
+    pub(crate) fn max_deleted_entry_id(&self) -> &StreamEntryID {
+        &self.max_deleted_entry_id
+    }
+
+    pub(crate) fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    /// Restores the metadata saved along with the entries: the last generated ID, which may be
+    /// ahead of the last entry after deletions, the largest deleted ID and the number of entries
+    /// ever added.
+    pub(crate) fn set_metadata(
+        &mut self,
+        last_entry: StreamEntryID,
+        max_deleted_entry_id: StreamEntryID,
+        entries_added: u64,
+    ) {
+        self.last_entry = last_entry;
+        self.max_deleted_entry_id = max_deleted_entry_id;
+        self.entries_added = entries_added;
+    }
+
+    pub(crate) fn first_entry_id(&self) -> Option<StreamEntryID> {
+        self.locate(&MIN_ID, &MAX_ID).next().map(|(id, ..)| id)
+    }
+
+    /// ID of the last entry still in the stream, unlike [`Self::last_entry_id`] which may have
+    /// been deleted.
+    pub(crate) fn newest_entry_id(&self) -> Option<StreamEntryID> {
+        self.locate(&MIN_ID, &MAX_ID).next_back().map(|(id, ..)| id)
+    }
+
+    /// Node holding `entry_id` if it is in the stream, as (master ID, node).
+    fn node_of(&self, entry_id: &StreamEntryID) -> Option<(StreamEntryID, &StreamNode)> {
+        self.root
+            .range(u128::MIN, entry_id.node_key())
+            .next_back()
+            .map(|(key, node)| (StreamEntryID::from_node_key(key), node))
+    }
+
+    /// Entries from `start` to `end` inclusive as (entry ID, master ID, node, position in the
+    /// node), without decoding their fields. Reversible.
+    fn locate(
+        &self,
+        start: &StreamEntryID,
+        end: &StreamEntryID,
+    ) -> impl DoubleEndedIterator<Item = (StreamEntryID, StreamEntryID, &StreamNode, usize)> {
+        // The entries from `start` on may begin in the node before it.
+        let first_key = self
+            .node_of(start)
+            .map_or(start.node_key(), |(master, _)| master.node_key());
+        let nodes = if start <= end {
+            self.root.range(first_key, end.node_key())
+        } else {
+            self.root.range(u128::MAX, u128::MIN)
+        };
+        let (start, end) = (start.clone(), end.clone());
+        nodes.flat_map(move |(key, node)| {
+            let master = StreamEntryID::from_node_key(key);
+            let (start, end) = (start.clone(), end.clone());
+            node.entries(&master)
+                .filter(move |(id, _)| start <= *id && *id <= end)
+                .map(move |(id, pos)| (id, master.clone(), node, pos))
+        })
+    }
+
+    /// Entries from `start` to `end` inclusive in ascending ID order, walked lazily so that
+    /// taking a few of them is cheap whatever the size of the stream. Reversible.
+    pub(crate) fn range(
+        &self,
+        start: &StreamEntryID,
+        end: &StreamEntryID,
+    ) -> impl DoubleEndedIterator<Item = (StreamEntryID, StreamEntryData)> + '_ {
+        self.locate(start, end)
+            .map(|(id, master, node, pos)| (id, node.data(&master, pos)))
+    }
+
+    /// All entries in ascending ID order.
+    pub(crate) fn entries(&self) -> Vec<(StreamEntryID, StreamEntryData)> {
+        self.range(&MIN_ID, &MAX_ID).collect()
+    }
+
+    /// Nodes of entries in ascending ID order, as (master ID, node).
+    pub(crate) fn nodes(&self) -> impl Iterator<Item = (StreamEntryID, &StreamNode)> {
+        self.root
+            .range(u128::MIN, u128::MAX)
+            .map(|(key, node)| (StreamEntryID::from_node_key(key), node))
+    }
+
+    pub(crate) fn get(&self, entry_id: &StreamEntryID) -> Option<StreamEntryData> {
+        self.range(entry_id, entry_id).next().map(|(_, data)| data)
+    }
+
+    pub(crate) fn contains(&self, entry_id: &StreamEntryID) -> bool {
+        self.locate(entry_id, entry_id).next().is_some()
+    }
+
+    /// IDs of the first `count` entries after `after`.
+    fn ids_after(&self, after: &StreamEntryID, count: usize) -> Vec<StreamEntryID> {
+        let Some(start) = after.next() else {
+            return vec![];
+        };
+        self.locate(&start, &MAX_ID)
+            .take(count)
+            .map(|(id, ..)| id)
+            .collect()
+    }
+
+    /// Number of entries added up to `entry_id`, if it can be told without counting them.
+    fn estimate_entries_read(&self, entry_id: &StreamEntryID) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        match entry_id.cmp(&self.last_entry) {
+            Ordering::Equal => return Some(self.entries_added),
+            Ordering::Less if self.length == 0 => return Some(self.entries_added),
+            Ordering::Greater => return None,
+            Ordering::Less => {}
+        }
+        // Without deletions past the first entry, the entries before it are all trimmed ones.
+        let first = self.first_entry_id()?;
+        let no_tombstones = self.max_deleted_entry_id.millis == 0
+            && self.max_deleted_entry_id.seq_num == 0
+            || self.max_deleted_entry_id < first;
+        let trimmed = self.entries_added - self.length as u64;
+        match entry_id.cmp(&first) {
+            Ordering::Less if no_tombstones => Some(trimmed),
+            Ordering::Equal if no_tombstones => Some(trimmed + 1),
+            _ => None,
+        }
+    }
+
+    /// Whether entries after `after` may have been deleted with XDEL.
+    fn has_tombstones_after(&self, after: &StreamEntryID) -> bool {
+        self.length > 0 && self.max_deleted_entry_id > *after
+    }
+
+    /// Number of entries `group` has yet to read, if it can be told without counting them.
+    fn lag(&self, group: &StreamGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(entries_read) if !self.has_tombstones_after(&group.last_delivered) => entries_read,
+            _ => self.estimate_entries_read(&group.last_delivered)?,
+        };
+        Some(self.entries_added.saturating_sub(entries_read))
+    }
+
+    /// Reports on the stream for XINFO STREAM, listing up to `full` entries and pending
+    /// entries per group and consumer with FULL.
+    pub(crate) fn info(&self, full: Option<usize>) -> StreamInfo {
+        let (first_entry, last_entry, entries) = match full {
+            None => (
+                self.xrange(&MIN_ID, &MAX_ID, Some(1), false).pop(),
+                self.xrange(&MIN_ID, &MAX_ID, Some(1), true).pop(),
+                vec![],
+            ),
+            Some(count) => (
+                None,
+                None,
+                self.xrange(&MIN_ID, &MAX_ID, Some(count), false),
+            ),
+        };
+        StreamInfo {
+            length: self.length,
+            radix_tree_keys: self.root.len(),
+            radix_tree_nodes: self.root.node_count(),
+            last_generated_id: self.last_entry.clone(),
+            max_deleted_entry_id: self.max_deleted_entry_id.clone(),
+            entries_added: self.entries_added,
+            recorded_first_entry_id: self.first_entry_id().unwrap_or(MIN_ID),
+            first_entry,
+            last_entry,
+            entries,
+            groups: self
+                .groups
+                .keys()
+                .filter_map(|name| self.group_info(name, full.unwrap_or(0)))
+                .collect(),
+        }
+    }
+
+    /// Reports on group `name` for XINFO, listing up to `pending` pending entries of the group
+    /// and of each consumer. `None` if the group does not exist.
+    pub(crate) fn group_info(&self, name: &[u8], pending: usize) -> Option<GroupInfo> {
+        let group = self.groups.get(name)?;
+        Some(GroupInfo {
+            name: name.to_vec(),
+            last_delivered: group.last_delivered.clone(),
+            entries_read: group.entries_read,
+            lag: self.lag(group),
+            pel_count: group.pel.len(),
+            pending: group
+                .pel
+                .iter()
+                .take(pending)
+                .map(|(id, entry)| (id.clone(), entry.clone()))
+                .collect(),
+            consumers: group
+                .consumers
+                .iter()
+                .map(|(name, consumer)| ConsumerInfo {
+                    name: name.clone(),
+                    seen_time: consumer.seen_time,
+                    active_time: consumer.active_time,
+                    pel_count: consumer.pending.len(),
+                    pending: consumer
+                        .pending
+                        .iter()
+                        .take(pending)
+                        .map(|id| {
+                            let entry = &group.pel[id];
+                            (id.clone(), entry.delivery_time, entry.delivery_count)
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Sets the metadata as XSETID does: the last generated ID, which may not be behind the
+    /// last entry, and optionally the number of entries ever added and the largest deleted ID.
+    pub(crate) fn set_id(
+        &mut self,
+        last_id: StreamEntryID,
+        entries_added: Option<u64>,
+        max_deleted_entry_id: Option<StreamEntryID>,
+    ) -> anyhow::Result<()> {
+        if max_deleted_entry_id
+            .as_ref()
+            .is_some_and(|max_deleted| last_id < *max_deleted)
+        {
+            return Err(anyhow::anyhow!(
+                "ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id"
+            ));
+        }
+        if entries_added.is_some_and(|entries_added| entries_added < self.length as u64) {
+            return Err(anyhow::anyhow!(
+                "ERR The entries_added specified in XSETID is smaller than the target stream length"
+            ));
+        }
+        if self
+            .newest_entry_id()
+            .is_some_and(|newest| last_id < newest)
+        {
+            return Err(anyhow::anyhow!(
+                "ERR The ID specified in XSETID is smaller than the target stream top item"
+            ));
+        }
+        self.last_entry = last_id;
+        if let Some(entries_added) = entries_added {
+            self.entries_added = entries_added;
+        }
+        if let Some(max_deleted_entry_id) = max_deleted_entry_id {
+            self.max_deleted_entry_id = max_deleted_entry_id;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn groups(&self) -> &BTreeMap<Vec<u8>, StreamGroup> {
+        &self.groups
+    }
+
+    pub(crate) fn group_mut(&mut self, name: &[u8]) -> Option<&mut StreamGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Adds a group as loaded from a snapshot.
+    pub(crate) fn insert_group(&mut self, name: Vec<u8>, group: StreamGroup) {
+        self.groups.insert(name, group);
+    }
+
+    /// Creates group `name` delivering the entries after `last_delivered`, or after the last
+    /// entry with `None`. Returns the ID the group starts after.
+    pub(crate) fn create_group(
+        &mut self,
+        name: &[u8],
+        last_delivered: Option<StreamEntryID>,
+        entries_read: Option<u64>,
+    ) -> anyhow::Result<StreamEntryID> {
+        if self.groups.contains_key(name) {
+            return Err(anyhow::anyhow!(
+                "BUSYGROUP Consumer Group name already exists"
+            ));
+        }
+        let last_delivered = last_delivered.unwrap_or_else(|| self.last_entry.clone());
+        let entries_read = entries_read.or_else(|| self.estimate_entries_read(&last_delivered));
+        self.groups.insert(
+            name.to_vec(),
+            StreamGroup::new(last_delivered.clone(), entries_read),
+        );
+        Ok(last_delivered)
+    }
+
+    /// Moves the last delivered ID of group `name`, to the last entry with `None`. Returns the
+    /// new last delivered ID, or `None` if the group does not exist.
+    pub(crate) fn set_group_id(
+        &mut self,
+        name: &[u8],
+        last_delivered: Option<StreamEntryID>,
+        entries_read: Option<u64>,
+    ) -> Option<StreamEntryID> {
+        let last_delivered = last_delivered.unwrap_or_else(|| self.last_entry.clone());
+        let entries_read = entries_read.or_else(|| self.estimate_entries_read(&last_delivered));
+        let group = self.groups.get_mut(name)?;
+        group.last_delivered = last_delivered.clone();
+        group.entries_read = entries_read;
+        Some(last_delivered)
+    }
+
+    pub(crate) fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Reads entries for `consumer` of `group`. With `start` unset, these are the entries never
+    /// delivered to the group, which become pending unless `noack`. Otherwise they are the
+    /// entries pending for the consumer after `start`, which count as delivered again. Returns
+    /// `None` if the group does not exist.
+    pub(crate) fn read_group(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        start: Option<&StreamEntryID>,
+        count: Option<usize>,
+        noack: bool,
+        now: u64,
+    ) -> Option<Vec<StreamEntryID>> {
+        let count = count.filter(|&count| count > 0).unwrap_or(usize::MAX);
+        let new_ids = match start {
+            None => self.ids_after(&self.groups.get(group)?.last_delivered, count),
+            Some(_) => vec![],
+        };
+        let group_ref = self.groups.get(group)?;
+        let mut entries_read = group_ref.entries_read;
+        let mut last_delivered = &group_ref.last_delivered;
+        for id in new_ids.iter() {
+            entries_read = match entries_read {
+                Some(entries_read) if !self.has_tombstones_after(last_delivered) => {
+                    Some(entries_read + 1)
+                }
+                _ => self.estimate_entries_read(id),
+            };
+            last_delivered = id;
+        }
+
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now);
+        let ids = match start {
+            None => {
+                if let Some(last) = new_ids.last() {
+                    group.last_delivered = last.clone();
+                    group.entries_read = entries_read;
+                }
+                if !noack {
+                    for id in new_ids.iter() {
+                        group.assign(id, consumer, now);
+                        group.pel.get_mut(id).expect("Just assigned").delivery_count = 1;
+                    }
+                }
+                new_ids
+            }
+            Some(start) => {
+                let ids = match start.next() {
+                    Some(after) => group.consumers[consumer]
+                        .pending
+                        .range(after..)
+                        .take(count)
+                        .cloned()
+                        .collect::<Vec<_>>(),
+                    None => vec![],
+                };
+                for id in ids.iter() {
+                    let entry = group
+                        .pel
+                        .get_mut(id)
+                        .expect("Pending entries are in the PEL");
+                    entry.delivery_time = now;
+                    entry.delivery_count += 1;
+                }
+                ids
+            }
+        };
+        if !ids.is_empty() {
+            group.touch_consumer(consumer, now).active_time = Some(now);
+        }
+        Some(ids)
+    }
+
+    /// Gives `consumer` of `group` the pending entries `ids` idle for at least `min_idle_time`
+    /// milliseconds. Pending entries deleted from the stream are dropped from the PEL instead.
+    /// Returns the claimed and the dropped IDs, or `None` if the group does not exist.
+    pub(crate) fn claim(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        min_idle_time: u64,
+        ids: &[StreamEntryID],
+        options: &XClaimOptions,
+        now: u64,
+    ) -> Option<(Vec<StreamEntryID>, Vec<StreamEntryID>)> {
+        let exist = ids
+            .iter()
+            .map(|id| self.contains(id))
+            .collect::<Vec<bool>>();
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now);
+        if let Some(last_id) = &options.last_id {
+            if *last_id > group.last_delivered {
+                group.last_delivered = last_id.clone();
+            }
+        }
+        let delivery_time = match (options.idle, options.time) {
+            (Some(idle), _) => now.saturating_sub(idle),
+            (None, Some(time)) => time.min(now),
+            (None, None) => now,
+        };
+
+        let mut claimed = Vec::new();
+        let mut dropped = Vec::new();
+        for (id, exists) in ids.iter().zip(exist) {
+            match group.pel.get(id) {
+                None if !(options.force && exists) => continue,
+                Some(_) if !exists => {
+                    group.ack(id);
+                    dropped.push(id.clone());
+                    continue;
+                }
+                Some(entry) if now.saturating_sub(entry.delivery_time) < min_idle_time => continue,
+                _ => {}
+            }
+
+            group.assign(id, consumer, delivery_time);
+            let entry = group.pel.get_mut(id).expect("Just assigned");
+            match options.retry_count {
+                Some(retry_count) => entry.delivery_count = retry_count,
+                None if !options.just_id => entry.delivery_count += 1,
+                None => {}
+            }
+            claimed.push(id.clone());
+        }
+        if !claimed.is_empty() {
+            group.touch_consumer(consumer, now).active_time = Some(now);
+        }
+        Some((claimed, dropped))
+    }
+
+    /// Scans the PEL of `group` from `start` and gives `consumer` up to `count` entries idle for
+    /// at least `min_idle_time` milliseconds, dropping the ones deleted from the stream. Returns
+    /// the ID to resume the scan from (0-0 once the PEL is exhausted), the claimed and the
+    /// dropped IDs, or `None` if the group does not exist.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn auto_claim(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        min_idle_time: u64,
+        start: &StreamEntryID,
+        count: usize,
+        just_id: bool,
+        now: u64,
+    ) -> Option<(StreamEntryID, Vec<StreamEntryID>, Vec<StreamEntryID>)> {
+        let scanned = self
+            .groups
+            .get(group)?
+            .pel
+            .range(start..)
+            .take(count.saturating_mul(XAUTOCLAIM_ATTEMPTS_FACTOR))
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        let exist = scanned
+            .iter()
+            .map(|id| self.contains(id))
+            .collect::<Vec<bool>>();
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now);
+
+        let mut claimed = Vec::new();
+        let mut dropped = Vec::new();
+        let mut next = None;
+        for (id, exists) in scanned.iter().zip(exist) {
+            if claimed.len() + dropped.len() == count {
+                next = Some(id.clone());
+                break;
+            }
+            if !exists {
+                group.ack(id);
+                dropped.push(id.clone());
+                continue;
+            }
+            if now.saturating_sub(group.pel[id].delivery_time) < min_idle_time {
+                continue;
+            }
+
+            group.assign(id, consumer, now);
+            if !just_id {
+                group.pel.get_mut(id).expect("Just assigned").delivery_count += 1;
+            }
+            claimed.push(id.clone());
+        }
+        if !claimed.is_empty() {
+            group.touch_consumer(consumer, now).active_time = Some(now);
         }
+
+        let next = next
+            .or_else(|| {
+                let after = scanned.last()?.next()?;
+                group.pel.range(after..).next().map(|(id, _)| id.clone())
+            })
+            .unwrap_or(StreamEntryID {
+                millis: 0,
+                seq_num: 0,
+            });
+        Some((next, claimed, dropped))
     }
 
+    /// Adds an entry after all the others, in a new node if the last one is full as `limits`
+    /// tells.
     pub(crate) fn insert(
         &mut self,
         entry_id: Option<ReqStreamEntryID>,
-        data: HashMap<Vec<u8>, Vec<u8>>,
+        data: StreamEntryData,
+        limits: &StreamNodeLimits,
     ) -> anyhow::Result<StreamEntryID> {
-        let entry_id = make_stream_entry_id(entry_id, &mut self.last_entry)?;
+        let entry_id = make_stream_entry_id(entry_id, &self.last_entry, now_millis())?;
 
-        if !self.root.contains_key(entry_id.millis) {
-            self.root.insert(entry_id.millis, Trie::new());
+        let last = self
+            .root
+            .range(u128::MIN, u128::MAX)
+            .next_back()
+            .and_then(|(key, node)| {
+                let entry = node.encode(&StreamEntryID::from_node_key(key), &entry_id, &data);
+                (!node.is_full(limits, entry.len())).then_some((key, entry))
+            });
+        match last {
+            Some((key, entry)) => self.root.get_mut(key).expect("Found").push(entry),
+            None => {
+                let mut node = StreamNode::new(&data);
+                node.push(node.encode(&entry_id, &entry_id, &data));
+                self.root.insert(entry_id.node_key(), node);
+            }
         }
-
-        let node = self.root.get_mut(entry_id.millis).expect("Not None");
-        node.insert(entry_id.seq_num, data);
         self.last_entry = entry_id.clone();
+        self.length += 1;
+        self.entries_added += 1;
         Ok(entry_id)
     }
 
+    /// Flags `entry_id` deleted in its node, dropping the node once it holds no entries.
+    fn remove(&mut self, entry_id: &StreamEntryID) -> bool {
+        let Some((master, _)) = self.node_of(entry_id) else {
+            return false;
+        };
+        let key = master.node_key();
+        let node = self.root.get_mut(key).expect("Found");
+        if !node.delete(&master, entry_id) {
+            return false;
+        }
+        if node.len() == 0 {
+            self.root.remove(key);
+        }
+        self.length -= 1;
+        true
+    }
+
+    /// Deletes the entries `ids`, returning how many existed.
+    pub(crate) fn delete(&mut self, ids: &[StreamEntryID]) -> usize {
+        let mut deleted = 0;
+        for id in ids {
+            if self.remove(id) {
+                deleted += 1;
+                if *id > self.max_deleted_entry_id {
+                    self.max_deleted_entry_id = id.clone();
+                }
+            }
+        }
+        deleted
+    }
+
+    /// Removes entries from the head of the stream as `trim` requests, returning how many.
+    /// Whole nodes go first; an approximate trim stops at the first node it cannot remove
+    /// whole, and at `limit` entries.
+    pub(crate) fn trim(&mut self, trim: &StreamTrim, limits: &StreamNodeLimits) -> usize {
+        let limit = match (trim.approx, trim.limit) {
+            (false, _) | (true, Some(0)) => usize::MAX,
+            (true, Some(limit)) => limit,
+            (true, None) if limits.max_entries == 0 => TRIM_DEFAULT_LIMIT_ENTRIES,
+            (true, None) => TRIM_DEFAULT_LIMIT_NODES * limits.max_entries,
+        };
+        let is_trimmed = |length: usize, id: &StreamEntryID| match &trim.strategy {
+            TrimStrategy::MaxLen(max_len) => length <= *max_len,
+            TrimStrategy::MinId(min_id) => id >= min_id,
+        };
+
+        let mut trimmed = 0;
+        while let Some((key, node)) = self.root.range(u128::MIN, u128::MAX).next() {
+            let master = StreamEntryID::from_node_key(key);
+            let (first, _) = node.entries(&master).next().expect("Nodes are not empty");
+            if is_trimmed(self.length, &first) || trimmed + node.len() > limit {
+                break;
+            }
+            let (last, _) = node
+                .entries(&master)
+                .next_back()
+                .expect("Nodes are not empty");
+            let whole = match &trim.strategy {
+                TrimStrategy::MaxLen(max_len) => self.length - node.len() >= *max_len,
+                TrimStrategy::MinId(min_id) => last < *min_id,
+            };
+            if whole {
+                trimmed += node.len();
+                self.length -= node.len();
+                self.root.remove(key);
+                continue;
+            }
+            if trim.approx {
+                break;
+            }
+
+            let ids = node.entries(&master).map(|(id, _)| id).collect::<Vec<_>>();
+            for id in ids {
+                if is_trimmed(self.length, &id) {
+                    break;
+                }
+                self.remove(&id);
+                trimmed += 1;
+            }
+            break;
+        }
+        trimmed
+    }
+
+    /// Up to `count` entries from `start` to `end` inclusive, from the last one with `rev`.
     pub(crate) fn xrange(
         &self,
-        start: StreamEntryID,
-        end: StreamEntryID,
+        start: &StreamEntryID,
+        end: &StreamEntryID,
+        count: Option<usize>,
+        rev: bool,
     ) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
-        self.root
-            .get_range_incl(start.millis, end.millis)
-            .into_iter()
-            .flat_map(|(millis, trie)| {
-                let start_seq_num = if millis == start.millis {
-                    start.seq_num
-                } else {
-                    u64::MIN
-                };
-                let end_seq_num = if millis == end.millis {
-                    end.seq_num
-                } else {
-                    u64::MAX
-                };
-                let entries = trie.get_range_incl(start_seq_num, end_seq_num);
-
-                entries
-                    .into_iter()
-                    .map(|(seq_num, v)| {
-                        let entry_id = format!("{}-{}", millis.to_string(), seq_num.to_string())
-                            .as_bytes()
-                            .to_vec();
-
-                        let mut kv_pairs = Vec::with_capacity(v.len() * 2);
-                        for (k, v) in v.iter() {
-                            kv_pairs.push(k.clone());
-                            kv_pairs.push(v.clone());
-                        }
-
-                        (entry_id, kv_pairs)
-                    })
-                    .collect::<Vec<(Vec<u8>, Vec<Vec<u8>>)>>()
+        let count = count.unwrap_or(usize::MAX);
+        let range = self.range(start, end);
+        let entries: Box<dyn Iterator<Item = _>> = if rev {
+            Box::new(range.rev())
+        } else {
+            Box::new(range)
+        };
+        entries
+            .take(count)
+            .map(|(entry_id, data)| {
+                let mut kv_pairs = Vec::with_capacity(data.len() * 2);
+                for (k, v) in data.iter() {
+                    kv_pairs.push(k.clone());
+                    kv_pairs.push(v.clone());
+                }
+                (entry_id.as_bytes(), kv_pairs)
             })
             .collect()
     }
 
-    pub(crate) fn xread(&self, start: &Option<StreamEntryID>) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
-        if let Some(start) = start {
-            let (millis, seq_num) = if start.seq_num == u64::MAX {
-                (start.millis + 1, 0)
-            } else {
-                (start.millis, start.seq_num + 1)
-            };
-            let start = StreamEntryID { millis, seq_num };
-            let end = StreamEntryID {
-                millis: u64::MAX,
-                seq_num: u64::MAX,
-            };
-            self.xrange(start, end)
-        } else {
-            vec![]
+    /// Up to `count` entries after `after`.
+    pub(crate) fn xread(
+        &self,
+        after: &StreamEntryID,
+        count: Option<usize>,
+    ) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
+        match after.next() {
+            Some(start) => self.xrange(&start, &MAX_ID, count, false),
+            None => vec![],
         }
     }
 }
@@ -192,30 +1101,33 @@ mod tests {
                 millis: 0,
                 seq_num: Some(2),
             }),
-            HashMap::from([
+            vec![
                 (b"foo".to_vec(), b"0".to_vec()),
                 (b"bar".to_vec(), b"2".to_vec()),
-            ]),
+            ],
+            &StreamNodeLimits::default(),
         );
         let _ = stream.insert(
             Some(ReqStreamEntryID {
                 millis: 0,
                 seq_num: Some(3),
             }),
-            HashMap::from([
+            vec![
                 (b"foo".to_vec(), b"0".to_vec()),
                 (b"bar".to_vec(), b"3".to_vec()),
-            ]),
+            ],
+            &StreamNodeLimits::default(),
         );
         let _ = stream.insert(
             Some(ReqStreamEntryID {
                 millis: 0,
                 seq_num: Some(4),
             }),
-            HashMap::from([
+            vec![
                 (b"foo".to_vec(), b"0".to_vec()),
                 (b"bar".to_vec(), b"4".to_vec()),
-            ]),
+            ],
+            &StreamNodeLimits::default(),
         );
         stream
     }
@@ -236,7 +1148,7 @@ mod tests {
         let expected_ids = vec![b"0-2".to_vec(), b"0-3".to_vec()];
 
         // Act
-        let actual = stream.xrange(start, end);
+        let actual = stream.xrange(&start, &end, None, false);
         let actual_ids = actual
             .into_iter()
             .map(|(id, _)| id)
@@ -262,7 +1174,7 @@ mod tests {
         let expected_ids = vec![b"0-2".to_vec()];
 
         // Act
-        let actual = stream.xrange(start, end);
+        let actual = stream.xrange(&start, &end, None, false);
         let actual_ids = actual
             .into_iter()
             .map(|(id, _)| id)
@@ -288,7 +1200,7 @@ mod tests {
         let expected_ids = vec![b"0-3".to_vec(), b"0-4".to_vec()];
 
         // Act
-        let actual = stream.xrange(start, end);
+        let actual = stream.xrange(&start, &end, None, false);
         let actual_ids = actual
             .into_iter()
             .map(|(id, _)| id)
@@ -297,4 +1209,255 @@ mod tests {
         // Assert
         assert_eq!(actual_ids, expected_ids);
     }
+
+    #[test]
+    fn test_stream_xrevrange_count() {
+        // Arrange
+        let mut stream = make_sample_stream();
+        let req = ReqStreamEntryID {
+            millis: 1,
+            seq_num: Some(0),
+        };
+        stream
+            .insert(Some(req), vec![], &StreamNodeLimits::default())
+            .unwrap();
+
+        // Act
+        let newest = stream.xrange(&MIN_ID, &MAX_ID, Some(2), true);
+        let oldest = stream.xrange(&MIN_ID, &MAX_ID, Some(2), false);
+        let after = stream.xread(&id(4).prev().unwrap(), Some(1));
+
+        // Assert
+        let ids = |entries: Vec<(Vec<u8>, Vec<Vec<u8>>)>| {
+            entries.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(newest), vec![b"1-0".to_vec(), b"0-4".to_vec()]);
+        assert_eq!(ids(oldest), vec![b"0-2".to_vec(), b"0-3".to_vec()]);
+        assert_eq!(ids(after), vec![b"0-4".to_vec()]);
+    }
+
+    fn id(seq_num: u64) -> StreamEntryID {
+        StreamEntryID { millis: 0, seq_num }
+    }
+
+    #[test]
+    fn test_stream_read_group() {
+        // Arrange
+        let mut stream = make_sample_stream();
+        stream.create_group(b"group", Some(id(0)), None).unwrap();
+
+        // Act
+        let alice = stream.read_group(b"group", b"alice", None, Some(2), false, 100);
+        let bob = stream.read_group(b"group", b"bob", None, None, true, 200);
+        let history = stream.read_group(b"group", b"alice", Some(&id(2)), None, false, 300);
+
+        // Assert
+        assert_eq!(alice, Some(vec![id(2), id(3)]));
+        assert_eq!(bob, Some(vec![id(4)]));
+        assert_eq!(history, Some(vec![id(3)]));
+        let group = &stream.groups()[&b"group".to_vec()];
+        assert_eq!(group.last_delivered, id(4));
+        assert_eq!(group.entries_read, Some(3));
+        assert_eq!(group.pel.len(), 2);
+        assert_eq!(group.pel[&id(2)].delivery_count, 1);
+        assert_eq!(group.pel[&id(3)].delivery_count, 2);
+        assert_eq!(group.pel[&id(3)].delivery_time, 300);
+        assert!(group.consumers[&b"bob".to_vec()].pending.is_empty());
+        assert_eq!(
+            stream
+                .create_group(b"group", None, None)
+                .unwrap_err()
+                .to_string(),
+            "BUSYGROUP Consumer Group name already exists"
+        );
+        assert_eq!(
+            stream.read_group(b"missing", b"alice", None, None, false, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stream_ack_and_claim() {
+        // Arrange
+        let mut stream = make_sample_stream();
+        stream.create_group(b"group", Some(id(0)), None).unwrap();
+        stream.read_group(b"group", b"alice", None, None, false, 100);
+        let group = stream.group_mut(b"group").unwrap();
+        assert!(group.ack(&id(2)));
+        assert!(!group.ack(&id(2)));
+
+        // Act
+        let too_recent = stream.claim(
+            b"group",
+            b"bob",
+            500,
+            &[id(3)],
+            &XClaimOptions::default(),
+            200,
+        );
+        let claimed = stream.claim(
+            b"group",
+            b"bob",
+            500,
+            &[id(3), id(2)],
+            &XClaimOptions::default(),
+            700,
+        );
+        let auto_claimed = stream.auto_claim(b"group", b"carol", 0, &id(0), 1, false, 800);
+
+        // Assert
+        assert_eq!(too_recent, Some((vec![], vec![])));
+        assert_eq!(claimed, Some((vec![id(3)], vec![])));
+        assert_eq!(auto_claimed, Some((id(4), vec![id(3)], vec![])));
+        let group = &stream.groups()[&b"group".to_vec()];
+        assert_eq!(group.pel[&id(3)].consumer, b"carol".to_vec());
+        assert_eq!(group.pel[&id(3)].delivery_count, 3);
+        assert!(group.consumers[&b"bob".to_vec()].pending.is_empty());
+        assert_eq!(
+            group.pending_per_consumer(),
+            vec![(&b"alice"[..], 1), (&b"carol"[..], 1)]
+        );
+    }
+
+    #[test]
+    fn test_stream_delete_and_trim() {
+        // Arrange
+        let mut stream = RedisStream::new();
+        let limits = StreamNodeLimits::default();
+        for seq_num in 1..=250 {
+            let req = ReqStreamEntryID {
+                millis: 0,
+                seq_num: Some(seq_num),
+            };
+            stream.insert(Some(req), vec![], &limits).unwrap();
+        }
+        let approx = StreamTrim {
+            strategy: TrimStrategy::MaxLen(10),
+            approx: true,
+            limit: None,
+        };
+        let exact = StreamTrim {
+            strategy: TrimStrategy::MinId(id(0)),
+            approx: false,
+            limit: None,
+        };
+
+        // Act
+        let deleted = stream.delete(&[id(2), id(1), id(2)]);
+        let approx_trimmed = stream.trim(&approx, &limits);
+        let exact_trimmed = stream.trim(
+            &StreamTrim {
+                strategy: TrimStrategy::MaxLen(10),
+                ..exact.clone()
+            },
+            &limits,
+        );
+        let none_trimmed = stream.trim(&exact, &limits);
+
+        // Assert
+        assert_eq!(deleted, 2);
+        // Nodes hold entries 1 to 100, 101 to 200 and 201 to 250, so approximate trimming stops
+        // before the last one.
+        assert_eq!(approx_trimmed, 198);
+        assert_eq!(exact_trimmed, 40);
+        assert_eq!(none_trimmed, 0);
+        assert_eq!(stream.len(), 10);
+        assert_eq!(stream.first_entry_id(), Some(id(241)));
+        assert_eq!(*stream.max_deleted_entry_id(), id(2));
+        assert_eq!(stream.entries_added(), 250);
+    }
+
+    #[test]
+    fn test_stream_group_entries_read_after_trim() {
+        // Arrange
+        let mut stream = make_sample_stream();
+        stream.trim(
+            &StreamTrim {
+                strategy: TrimStrategy::MinId(id(3)),
+                approx: false,
+                limit: None,
+            },
+            &StreamNodeLimits::default(),
+        );
+        stream.create_group(b"trimmed", Some(id(0)), None).unwrap();
+        stream.delete(&[id(3)]);
+        stream.create_group(b"deleted", Some(id(0)), None).unwrap();
+
+        // Act
+        stream.read_group(b"trimmed", b"alice", None, None, true, 0);
+        stream.read_group(b"deleted", b"alice", None, None, true, 0);
+
+        // Assert
+        assert_eq!(stream.groups()[&b"trimmed".to_vec()].entries_read, Some(3));
+        assert_eq!(stream.groups()[&b"deleted".to_vec()].entries_read, Some(3));
+    }
+
+    #[test]
+    fn test_stream_info_lag() {
+        // Arrange
+        let mut stream = make_sample_stream();
+        stream.create_group(b"group", Some(id(0)), None).unwrap();
+        stream.read_group(b"group", b"alice", None, Some(1), false, 7);
+
+        // Act
+        let info = stream.info(Some(usize::MAX));
+        stream.delete(&[id(4)]);
+        let after_delete = stream.group_info(b"group", 0).unwrap();
+
+        // Assert
+        assert_eq!(info.length, 3);
+        assert_eq!(info.entries.len(), 3);
+        assert_eq!(info.recorded_first_entry_id, id(2));
+        let group = &info.groups[0];
+        assert_eq!((group.entries_read, group.lag), (Some(1), Some(2)));
+        assert_eq!(group.consumers[0].pending, vec![(id(2), 7, 1)]);
+        assert_eq!(after_delete.lag, None);
+    }
+
+    #[test]
+    fn test_stream_set_id() {
+        // Arrange
+        let mut stream = make_sample_stream();
+
+        // Act
+        let behind_top = stream.set_id(id(3), None, None);
+        let below_length = stream.set_id(id(9), Some(2), None);
+        let behind_deleted = stream.set_id(id(9), None, Some(id(10)));
+        let res = stream.set_id(id(9), Some(5), Some(id(8)));
+
+        // Assert
+        assert!(behind_top.is_err());
+        assert!(below_length.is_err());
+        assert!(behind_deleted.is_err());
+        assert!(res.is_ok());
+        assert_eq!(stream.last_entry_id(), &id(9));
+        assert_eq!(stream.entries_added(), 5);
+        assert_eq!(stream.max_deleted_entry_id(), &id(8));
+    }
+
+    #[test]
+    fn test_make_stream_entry_id_auto() {
+        // Arrange
+        let at = |millis, seq_num| StreamEntryID { millis, seq_num };
+        let auto_seq = |millis| ReqStreamEntryID {
+            millis,
+            seq_num: None,
+        };
+
+        // Act
+        let later = make_stream_entry_id(None, &at(5, 3), 9).unwrap();
+        let clock_behind = make_stream_entry_id(None, &at(5, 3), 2).unwrap();
+        let seq_overflow = make_stream_entry_id(None, &at(5, u64::MAX), 5).unwrap();
+        let exhausted = make_stream_entry_id(None, &at(u64::MAX, u64::MAX), 5);
+        let req_overflow = make_stream_entry_id(Some(auto_seq(5)), &at(5, u64::MAX), 5);
+        let req_last_millis = make_stream_entry_id(Some(auto_seq(u64::MAX)), &at(5, 3), 5).unwrap();
+
+        // Assert
+        assert_eq!(later, at(9, 0));
+        assert_eq!(clock_behind, at(5, 4));
+        assert_eq!(seq_overflow, at(6, 0));
+        assert!(exhausted.is_err());
+        assert!(req_overflow.is_err());
+        assert_eq!(req_last_millis, at(u64::MAX, 0));
+    }
 }
@@ -0,0 +1,370 @@
+use super::stream::{StreamEntryData, StreamEntryID, MIN_ID};
+
+/// Entry flag: deleted, its bytes staying in the node until the whole node goes.
+const FLAG_DELETED: u8 = 1;
+/// Entry flag: same fields as the master entry, so only the values are stored.
+const FLAG_SAMEFIELDS: u8 = 2;
+
+/// Appends `value` as a LEB128 varint.
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Reads the varint at `*pos`, moving `pos` past it.
+fn read_varint(buf: &[u8], pos: &mut usize) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+fn varint_len(value: u64) -> usize {
+    (64 - value.leading_zeros() as usize).max(1).div_ceil(7)
+}
+
+/// Appends the length of an entry as a varint with its bytes reversed, so that it can be read
+/// backwards from the end of the entry.
+fn write_backlen(buf: &mut Vec<u8>, len: usize) {
+    let start = buf.len();
+    write_varint(buf, len as u64);
+    buf[start..].reverse();
+}
+
+/// Reads the backlen ending at `end`, returning it and where it starts.
+fn read_backlen(buf: &[u8], end: usize) -> (usize, usize) {
+    let mut value = 0;
+    let mut pos = end;
+    let mut shift = 0;
+    loop {
+        pos -= 1;
+        let byte = buf[pos];
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return (value, pos);
+        }
+        shift += 7;
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn read_bytes(buf: &[u8], pos: &mut usize) -> Vec<u8> {
+    let len = read_varint(buf, pos) as usize;
+    let bytes = buf[*pos..*pos + len].to_vec();
+    *pos += len;
+    bytes
+}
+
+fn skip_bytes(buf: &[u8], pos: &mut usize) {
+    let len = read_varint(buf, pos) as usize;
+    *pos += len;
+}
+
+/// Limits on the size of a node past which entries go to a new one, 0 meaning no limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct StreamNodeLimits {
+    pub(crate) max_bytes: usize,
+    pub(crate) max_entries: usize,
+}
+
+impl Default for StreamNodeLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 4096,
+            max_entries: 100,
+        }
+    }
+}
+
+/// Consecutive entries of a stream packed into a single buffer, as in the listpacks of Redis.
+/// IDs are stored as deltas from the master ID the node is keyed by, and entries with the same
+/// fields as the first entry of the node, the master entry, store only their values.
+///
+/// An entry is laid out as its flags, its millis and sequence number deltas, its field count
+/// and fields unless flagged [`FLAG_SAMEFIELDS`], its values, and its length so far as a
+/// backlen so that entries can be walked from the end.
+#[derive(Clone)]
+pub(crate) struct StreamNode {
+    master_fields: Vec<Vec<u8>>,
+    buf: Vec<u8>,
+    /// Number of entries not deleted.
+    len: usize,
+    /// Number of entries flagged deleted.
+    deleted: usize,
+}
+
+impl StreamNode {
+    /// An empty node whose master entry has the fields of `data`.
+    pub(crate) fn new(data: &StreamEntryData) -> Self {
+        Self {
+            master_fields: data.iter().map(|(field, _)| field.clone()).collect(),
+            buf: Vec::new(),
+            len: 0,
+            deleted: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn master_fields(&self) -> &[Vec<u8>] {
+        &self.master_fields
+    }
+
+    /// Whether adding an entry of `entry_len` bytes would take the node past `limits`.
+    pub(crate) fn is_full(&self, limits: &StreamNodeLimits, entry_len: usize) -> bool {
+        (limits.max_entries > 0 && self.len + self.deleted >= limits.max_entries)
+            || (limits.max_bytes > 0 && self.buf.len() + entry_len > limits.max_bytes)
+    }
+
+    /// Encodes an entry of the node keyed by `master`.
+    pub(crate) fn encode(
+        &self,
+        master: &StreamEntryID,
+        id: &StreamEntryID,
+        data: &StreamEntryData,
+    ) -> Vec<u8> {
+        let same_fields = data.len() == self.master_fields.len()
+            && data
+                .iter()
+                .zip(self.master_fields.iter())
+                .all(|((field, _), master_field)| field == master_field);
+
+        let mut buf = Vec::new();
+        buf.push(if same_fields { FLAG_SAMEFIELDS } else { 0 });
+        write_varint(&mut buf, id.millis - master.millis);
+        // The sequence number of a later millisecond may be below the master's.
+        let seq_delta = id.seq_num.wrapping_sub(master.seq_num) as i64;
+        write_varint(&mut buf, ((seq_delta << 1) ^ (seq_delta >> 63)) as u64);
+        if same_fields {
+            for (_, value) in data.iter() {
+                write_bytes(&mut buf, value);
+            }
+        } else {
+            write_varint(&mut buf, data.len() as u64);
+            for (field, value) in data.iter() {
+                write_bytes(&mut buf, field);
+                write_bytes(&mut buf, value);
+            }
+        }
+        let len = buf.len();
+        write_backlen(&mut buf, len);
+        buf
+    }
+
+    /// Appends an entry encoded with [`Self::encode`], after all the others.
+    pub(crate) fn push(&mut self, entry: Vec<u8>) {
+        self.buf.extend(entry);
+        self.len += 1;
+    }
+
+    /// Reads the flags and ID of the entry at `pos`, returning them and where its fields start.
+    fn header(&self, master: &StreamEntryID, mut pos: usize) -> (u8, StreamEntryID, usize) {
+        let flags = self.buf[pos];
+        pos += 1;
+        let millis = master.millis + read_varint(&self.buf, &mut pos);
+        let zigzag = read_varint(&self.buf, &mut pos);
+        let seq_delta = ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64);
+        let seq_num = master.seq_num.wrapping_add(seq_delta as u64);
+        (flags, StreamEntryID { millis, seq_num }, pos)
+    }
+
+    /// Position right after the entry at `pos`.
+    fn entry_end(&self, pos: usize) -> usize {
+        let start = pos;
+        let (flags, _, mut pos) = self.header(&MIN_ID, pos);
+        if flags & FLAG_SAMEFIELDS != 0 {
+            for _ in 0..self.master_fields.len() {
+                skip_bytes(&self.buf, &mut pos);
+            }
+        } else {
+            let fields = read_varint(&self.buf, &mut pos);
+            for _ in 0..fields * 2 {
+                skip_bytes(&self.buf, &mut pos);
+            }
+        }
+        pos + varint_len((pos - start) as u64)
+    }
+
+    /// Position of the entry ending at `end`.
+    fn entry_start(&self, end: usize) -> usize {
+        let (len, backlen_start) = read_backlen(&self.buf, end);
+        backlen_start - len
+    }
+
+    /// Fields and values of the entry at `pos`.
+    pub(crate) fn data(&self, master: &StreamEntryID, pos: usize) -> StreamEntryData {
+        let (flags, _, mut pos) = self.header(master, pos);
+        if flags & FLAG_SAMEFIELDS != 0 {
+            self.master_fields
+                .iter()
+                .map(|field| (field.clone(), read_bytes(&self.buf, &mut pos)))
+                .collect()
+        } else {
+            let fields = read_varint(&self.buf, &mut pos);
+            (0..fields)
+                .map(|_| {
+                    let field = read_bytes(&self.buf, &mut pos);
+                    (field, read_bytes(&self.buf, &mut pos))
+                })
+                .collect()
+        }
+    }
+
+    /// IDs and positions of the entries not deleted, in ascending ID order. Reversible.
+    pub(crate) fn entries(
+        &self,
+        master: &StreamEntryID,
+    ) -> impl DoubleEndedIterator<Item = (StreamEntryID, usize)> + '_ {
+        NodeEntries {
+            node: self,
+            master: master.clone(),
+            front: 0,
+            back: self.buf.len(),
+        }
+        .filter(|(flags, _, _)| flags & FLAG_DELETED == 0)
+        .map(|(_, id, pos)| (id, pos))
+    }
+
+    /// Flags the entry `id` deleted, returning whether it was there.
+    pub(crate) fn delete(&mut self, master: &StreamEntryID, id: &StreamEntryID) -> bool {
+        let Some(pos) = self
+            .entries(master)
+            .find(|(entry_id, _)| entry_id >= id)
+            .filter(|(entry_id, _)| entry_id == id)
+            .map(|(_, pos)| pos)
+        else {
+            return false;
+        };
+        self.buf[pos] |= FLAG_DELETED;
+        self.len -= 1;
+        self.deleted += 1;
+        true
+    }
+}
+
+/// Walk over all the entries of a node, deleted ones included, as (flags, ID, position).
+struct NodeEntries<'a> {
+    node: &'a StreamNode,
+    master: StreamEntryID,
+    /// Start of the next entry from the front.
+    front: usize,
+    /// End of the next entry from the back.
+    back: usize,
+}
+
+impl Iterator for NodeEntries<'_> {
+    type Item = (u8, StreamEntryID, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        let pos = self.front;
+        let (flags, id, _) = self.node.header(&self.master, pos);
+        self.front = self.node.entry_end(pos);
+        Some((flags, id, pos))
+    }
+}
+
+impl DoubleEndedIterator for NodeEntries<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+        let pos = self.node.entry_start(self.back);
+        let (flags, id, _) = self.node.header(&self.master, pos);
+        self.back = pos;
+        Some((flags, id, pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(millis: u64, seq_num: u64) -> StreamEntryID {
+        StreamEntryID { millis, seq_num }
+    }
+
+    #[test]
+    fn test_stream_node_roundtrip() {
+        // Arrange
+        let master = id(5, 7);
+        let same = vec![(b"f".to_vec(), b"1".to_vec())];
+        let other = vec![(b"g".to_vec(), vec![b'x'; 300]), (b"f".to_vec(), vec![])];
+        let mut node = StreamNode::new(&same);
+        let entries = [
+            (id(5, 7), same.clone()),
+            (id(5, u64::MAX), other.clone()),
+            (id(9, 0), vec![]),
+            (id(u64::MAX, 1), same.clone()),
+        ];
+        for (entry_id, data) in entries.iter() {
+            node.push(node.encode(&master, entry_id, data));
+        }
+
+        // Act
+        let forward = node
+            .entries(&master)
+            .map(|(entry_id, pos)| (entry_id, node.data(&master, pos)))
+            .collect::<Vec<_>>();
+        let backward = node
+            .entries(&master)
+            .rev()
+            .map(|(entry_id, _)| entry_id)
+            .collect::<Vec<_>>();
+
+        // Assert
+        assert_eq!(forward, entries.to_vec());
+        assert_eq!(
+            backward,
+            vec![id(u64::MAX, 1), id(9, 0), id(5, u64::MAX), id(5, 7)]
+        );
+    }
+
+    #[test]
+    fn test_stream_node_delete() {
+        // Arrange
+        let master = id(1, 0);
+        let data = vec![(b"f".to_vec(), b"v".to_vec())];
+        let mut node = StreamNode::new(&data);
+        for seq_num in 0..3 {
+            node.push(node.encode(&master, &id(1, seq_num), &data));
+        }
+        let limits = StreamNodeLimits {
+            max_bytes: 0,
+            max_entries: 3,
+        };
+
+        // Act
+        let deleted = (
+            node.delete(&master, &id(1, 1)),
+            node.delete(&master, &id(1, 1)),
+        );
+
+        // Assert
+        assert_eq!(deleted, (true, false));
+        assert_eq!(node.len(), 2);
+        assert_eq!(
+            node.entries(&master).map(|(id, _)| id).collect::<Vec<_>>(),
+            vec![id(1, 0), id(1, 2)]
+        );
+        assert!(node.is_full(&limits, 0));
+    }
+}
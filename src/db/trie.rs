@@ -1,379 +1,277 @@
-const CHAR_BITSIZE: usize = 4; // Must divides 8
-const CHARSET_SIZE: usize = 1 << CHAR_BITSIZE;
+/// Length in bytes of the keys, big-endian `u128`s.
+const KEY_LEN: usize = 16;
 
+#[derive(Clone)]
 struct TrieNode<T> {
-    children: [Option<Box<Self>>; CHARSET_SIZE],
+    /// Key bytes of the edge from the parent, the path being compressed into a single node
+    /// where it does not branch.
+    prefix: Vec<u8>,
+    /// Set on the nodes ending a key, which are all leaves since keys have the same length.
     value: Option<T>,
+    /// Children by ascending first prefix byte.
+    children: Vec<TrieNode<T>>,
 }
 
-impl<'a, T> TrieNode<T> {
-    pub(crate) fn new() -> Self {
+impl<T> TrieNode<T> {
+    fn new(prefix: Vec<u8>, value: Option<T>) -> Self {
         Self {
-            children: std::array::from_fn(|_| None),
-            value: None,
+            prefix,
+            value,
+            children: Vec::new(),
         }
     }
 
-    pub(crate) fn get_all(&self) -> Vec<(Vec<u8>, &T)> {
-        // DFS
-        let mut data = Vec::new();
-        if let Some(v) = &self.value {
-            data.push((vec![], v));
-        }
+    /// Index of the child whose prefix starts with `byte`, or where it would be inserted.
+    fn child_index(&self, byte: u8) -> Result<usize, usize> {
+        self.children
+            .binary_search_by_key(&byte, |child| child.prefix[0])
+    }
 
-        let mut stack = Vec::new();
-        for idx in (0..CHARSET_SIZE).rev() {
-            if let Some(n) = &self.children[idx] {
-                stack.push((vec![idx as u8], n));
+    /// Stores `value` at the end of the path `rest`, returning the value it replaces.
+    fn insert(&mut self, rest: &[u8], value: T) -> Option<T> {
+        let Some(&byte) = rest.first() else {
+            return self.value.replace(value);
+        };
+        let i = match self.child_index(byte) {
+            Ok(i) => i,
+            Err(i) => {
+                self.children
+                    .insert(i, TrieNode::new(rest.to_vec(), Some(value)));
+                return None;
             }
+        };
+        let child = &mut self.children[i];
+        let common = child
+            .prefix
+            .iter()
+            .zip(rest)
+            .take_while(|(a, b)| a == b)
+            .count();
+        if common < child.prefix.len() {
+            // Split the edge where the paths part.
+            let mut split = TrieNode::new(child.prefix[..common].to_vec(), None);
+            child.prefix.drain(..common);
+            let leaf = TrieNode::new(rest[common..].to_vec(), Some(value));
+            let old = std::mem::replace(child, TrieNode::new(vec![], None));
+            split.children = if old.prefix[0] < leaf.prefix[0] {
+                vec![old, leaf]
+            } else {
+                vec![leaf, old]
+            };
+            *child = split;
+            return None;
         }
+        child.insert(&rest[common..], value)
+    }
 
-        while let Some((chars, n)) = stack.pop() {
-            if let Some(v) = &n.value {
-                data.push((chars.clone(), v));
-            }
-            for _idx in (0..CHARSET_SIZE).rev() {
-                if let Some(n) = &n.children[_idx] {
-                    let mut _chars = chars.clone();
-                    _chars.push(_idx as u8);
-                    stack.push((_chars, n));
+    fn get_mut(&mut self, rest: &[u8]) -> Option<&mut T> {
+        let Some(&byte) = rest.first() else {
+            return self.value.as_mut();
+        };
+        let i = self.child_index(byte).ok()?;
+        let child = &mut self.children[i];
+        let rest = rest.strip_prefix(&child.prefix[..])?;
+        child.get_mut(rest)
+    }
+
+    /// Removes the value at the end of the path `rest`, dropping the nodes it leaves empty and
+    /// merging the ones left with a single child into it.
+    fn remove(&mut self, rest: &[u8]) -> Option<T> {
+        let Some(&byte) = rest.first() else {
+            return self.value.take();
+        };
+        let i = self.child_index(byte).ok()?;
+        let child = &mut self.children[i];
+        let value = child.remove(rest.strip_prefix(&child.prefix[..])?);
+        if child.value.is_none() {
+            match child.children.len() {
+                0 => {
+                    self.children.remove(i);
                 }
+                1 => {
+                    let grandchild = child.children.pop().expect("One child");
+                    child.prefix.extend(grandchild.prefix);
+                    child.value = grandchild.value;
+                    child.children = grandchild.children;
+                }
+                _ => {}
             }
         }
+        value
+    }
 
-        data
+    fn node_count(&self) -> usize {
+        1 + self
+            .children
+            .iter()
+            .map(|child| child.node_count())
+            .sum::<usize>()
     }
 }
 
+/// Radix tree over `u128` keys, walked in key order.
+#[derive(Clone)]
 pub(crate) struct Trie<T> {
     root: TrieNode<T>,
-}
-
-fn u64_to_chars(val: u64) -> Vec<u8> {
-    val.to_be_bytes()
-        .into_iter()
-        .flat_map(|byte| {
-            [byte >> CHAR_BITSIZE, byte % (1 << CHAR_BITSIZE)]
-                .to_vec()
-                .into_iter()
-        })
-        .collect()
+    len: usize,
 }
 
 impl<T> Trie<T> {
     pub(crate) fn new() -> Self {
         Self {
-            root: TrieNode::new(),
+            root: TrieNode::new(vec![], None),
+            len: 0,
         }
     }
 
-    pub(crate) fn insert(&mut self, key: u64, value: T) {
-        // key is an array of 0..=15
-        let mut node = &mut self.root;
-
-        let mut chars = u64_to_chars(key).into_iter();
-        while let Some(c) = chars.next() {
-            let idx = c as usize;
-            if node.children[idx].is_none() {
-                node.children[idx] = Some(Box::new(TrieNode::new()));
-            }
-            node = node.children[idx].as_mut().expect("Not None");
+    pub(crate) fn insert(&mut self, key: u128, value: T) {
+        if self.root.insert(&key.to_be_bytes(), value).is_none() {
+            self.len += 1;
         }
-
-        node.value = Some(value);
     }
 
-    pub(crate) fn get_mut(&mut self, key: u64) -> Option<&mut T> {
-        let mut node = &mut self.root;
-
-        let mut chars = u64_to_chars(key).into_iter();
-        while let Some(c) = chars.next() {
-            let idx = c as usize;
-            node = match node.children[idx].is_some() {
-                true => node.children[idx].as_mut().expect("Not None"),
-                false => return None,
-            }
-        }
-
-        node.value.as_mut()
+    pub(crate) fn get_mut(&mut self, key: u128) -> Option<&mut T> {
+        self.root.get_mut(&key.to_be_bytes())
     }
 
-    pub(crate) fn contains_key(&self, key: u64) -> bool {
-        let mut node = &self.root;
-
-        let mut chars = u64_to_chars(key).into_iter();
-        while let Some(c) = chars.next() {
-            let idx = c as usize;
-            node = match node.children[idx].is_some() {
-                true => node.children[idx].as_ref().expect("Not None"),
-                false => return false,
-            }
+    pub(crate) fn remove(&mut self, key: u128) -> Option<T> {
+        let value = self.root.remove(&key.to_be_bytes());
+        if value.is_some() {
+            self.len -= 1;
         }
-
-        node.value.is_some()
+        value
     }
 
-    fn traverse_to_common_node(
-        &self,
-        start: u64,
-        end: u64,
-    ) -> Option<(&TrieNode<T>, Option<(u8, u8)>, u64, Vec<u8>, Vec<u8>)> {
-        let mut node = &self.root;
-        let start_iter = u64_to_chars(start).into_iter();
-        let end_iter = u64_to_chars(end).into_iter();
-        let mut cpair_iter = start_iter.zip(end_iter);
-        let mut common_chars = 0u64;
-        let mut cpair = None;
-
-        // find first u4 char that differs in start and end
-        while let Some((start_char, end_char)) = cpair_iter.next() {
-            // shift start_char to the first non-empty node
-            let start_char = node.children[(start_char as usize)..=(end_char as usize)]
-                .iter()
-                .position(|n| n.is_some())
-                .map(|c| start_char + c as u8)?;
-
-            // shift end_char to the first non-empty node
-            let end_char = node.children[(start_char as usize)..=(end_char as usize)]
-                .iter()
-                .rev()
-                .position(|n| n.is_some())
-                .map(|c| end_char - c as u8)?;
-
-            if start_char != end_char {
-                cpair = Some((start_char, end_char));
-                break;
-            }
-            common_chars = (common_chars << CHAR_BITSIZE) + start_char as u64;
-            node = node.children[start_char as usize]
-                .as_ref()
-                .expect("Not None");
-        }
-
-        let (start_chars, end_chars) = cpair_iter.fold(
-            (
-                Vec::with_capacity(CHARSET_SIZE),
-                Vec::with_capacity(CHARSET_SIZE),
-            ),
-            |(mut start_chars, mut end_chars), (start_c, end_c)| {
-                start_chars.push(start_c);
-                end_chars.push(end_c);
-                (start_chars, end_chars)
-            },
-        );
-
-        Some((node, cpair, common_chars, start_chars, end_chars))
+    pub(crate) fn len(&self) -> usize {
+        self.len
     }
 
-    fn collect_values_along_path_to_start<'a>(
-        &self,
-        common_node: &'a TrieNode<T>,
-        start_char: u8,
-        remaining_start_chars: Vec<u8>,
-        mut common_chars: u64,
-        data: &mut Vec<(u64, &'a T)>,
-    ) {
-        let mut stack = Vec::new();
-        let mut start_iter = remaining_start_chars.into_iter();
-        let mut node = common_node;
-        let mut char = start_char;
-        let mut has_passed_first_iter = false;
-        loop {
-            node = node.children[char as usize].as_ref().expect("Not None");
-            common_chars = (common_chars << CHAR_BITSIZE) + char as u64;
-
-            if has_passed_first_iter {
-                for idx in ((char + 1) as usize..CHARSET_SIZE).rev() {
-                    if let Some(n) = &node.children[idx] {
-                        stack.push((common_chars, n));
-                    }
-                }
-            }
-
-            if let Some(v) = &node.value {
-                data.push((common_chars, v));
-            }
-
-            if let Some(c) = start_iter.next() {
-                char = if let Some(_c) = node.children[(c as usize)..CHARSET_SIZE]
-                    .iter()
-                    .position(|n| n.is_some())
-                {
-                    c + _c as u8
-                } else {
-                    break;
-                };
-            } else {
-                break;
-            }
-
-            has_passed_first_iter = true;
-        }
+    /// Number of nodes, the root included.
+    pub(crate) fn node_count(&self) -> usize {
+        self.root.node_count()
+    }
 
-        while let Some((chars, n)) = stack.pop() {
-            let mut items = n
-                .get_all()
-                .into_iter()
-                .map(|(_chars, v)| {
-                    (
-                        (chars << (CHAR_BITSIZE * _chars.len()))
-                            + _chars
-                                .into_iter()
-                                .fold(0u64, |acc, c| (acc << CHAR_BITSIZE) + c as u64),
-                        v,
-                    )
-                })
-                .collect();
-            data.append(&mut items);
+    /// Entries with keys from `start` to `end` inclusive, walked lazily in either order.
+    pub(crate) fn range(&self, start: u128, end: u128) -> Range<'_, T> {
+        Range {
+            root: &self.root,
+            bounds: (start <= end).then_some((start, end)),
         }
     }
+}
 
-    fn collect_values_between_start_and_end<'a>(
-        &self,
-        common_node: &'a TrieNode<T>,
-        start_char: u8,
-        end_char: u8,
-        mut common_chars: u64,
-        data: &mut Vec<(u64, &'a T)>,
-    ) {
-        for c in (start_char + 1)..end_char {
-            if let Some(n) = &common_node.children[c as usize] {
-                common_chars = (common_chars << CHAR_BITSIZE) + c as u64;
-
-                let mut items = n
-                    .get_all()
-                    .into_iter()
-                    .map(|(_chars, v)| {
-                        (
-                            (common_chars << (4 * _chars.len()))
-                                + _chars
-                                    .into_iter()
-                                    .fold(0u64, |acc, c| (acc << CHAR_BITSIZE) + c as u64),
-                            v,
-                        )
-                    })
-                    .collect();
-                data.append(&mut items);
-
-                common_chars = common_chars >> CHAR_BITSIZE;
-            }
-        }
+/// Looks the entry with the smallest key between `lo` and `hi` up under `node`, whose parent
+/// is at `depth` on the path spelling `key` so far. `lo_tight` and `hi_tight` tell whether that
+/// path is still the one of `lo` and `hi`, which bounds the bytes to try.
+fn seek_first<'a, T>(
+    node: &'a TrieNode<T>,
+    depth: usize,
+    key: u128,
+    (lo, hi): (&[u8; KEY_LEN], &[u8; KEY_LEN]),
+    tight: (bool, bool),
+) -> Option<(u128, &'a T)> {
+    let (depth, key, (lo_tight, hi_tight)) = follow_prefix(node, depth, key, (lo, hi), tight)?;
+    if depth == KEY_LEN {
+        return node.value.as_ref().map(|v| (key, v));
     }
+    node.children
+        .iter()
+        .skip_while(|child| lo_tight && child.prefix[0] < lo[depth])
+        .take_while(|child| !hi_tight || child.prefix[0] <= hi[depth])
+        .find_map(|child| seek_first(child, depth, key, (lo, hi), (lo_tight, hi_tight)))
+}
 
-    fn collect_values_along_path_to_end<'a>(
-        &self,
-        common_node: &'a TrieNode<T>,
-        end_char: u8,
-        remaining_end_chars: Vec<u8>,
-        mut common_chars: u64,
-        data: &mut Vec<(u64, &'a T)>,
-    ) {
-        let mut node = common_node;
-        let mut end_iter = remaining_end_chars.iter();
-        let mut char = end_char;
-        let mut has_passed_first_iter = false;
-        loop {
-            node = node.children[char as usize].as_ref().expect("Not None");
-            common_chars = (common_chars << CHAR_BITSIZE) + char as u64;
-
-            if has_passed_first_iter {
-                for c in 0..char {
-                    if let Some(n) = &node.children[c as usize] {
-                        common_chars = (common_chars << CHAR_BITSIZE) + c as u64;
-
-                        let mut items = n
-                            .get_all()
-                            .into_iter()
-                            .map(|(mut _chars, v)| {
-                                (
-                                    (common_chars << (CHAR_BITSIZE * _chars.len()))
-                                        + _chars
-                                            .into_iter()
-                                            .fold(0u64, |acc, c| (acc << CHAR_BITSIZE) + c as u64),
-                                    v,
-                                )
-                            })
-                            .collect();
-                        data.append(&mut items);
-
-                        common_chars = common_chars >> CHAR_BITSIZE;
-                    }
-                }
-            }
+/// Same as [`seek_first`], for the entry with the greatest key.
+fn seek_last<'a, T>(
+    node: &'a TrieNode<T>,
+    depth: usize,
+    key: u128,
+    (lo, hi): (&[u8; KEY_LEN], &[u8; KEY_LEN]),
+    tight: (bool, bool),
+) -> Option<(u128, &'a T)> {
+    let (depth, key, (lo_tight, hi_tight)) = follow_prefix(node, depth, key, (lo, hi), tight)?;
+    if depth == KEY_LEN {
+        return node.value.as_ref().map(|v| (key, v));
+    }
+    node.children
+        .iter()
+        .rev()
+        .skip_while(|child| hi_tight && child.prefix[0] > hi[depth])
+        .take_while(|child| !lo_tight || child.prefix[0] >= lo[depth])
+        .find_map(|child| seek_last(child, depth, key, (lo, hi), (lo_tight, hi_tight)))
+}
 
-            if let Some(v) = &node.value {
-                data.push((common_chars.clone(), v));
+/// Appends the prefix of `node` to the path, returning its new depth, key and tightness, or
+/// `None` if the path leaves the bounds.
+fn follow_prefix<T>(
+    node: &TrieNode<T>,
+    mut depth: usize,
+    mut key: u128,
+    (lo, hi): (&[u8; KEY_LEN], &[u8; KEY_LEN]),
+    (mut lo_tight, mut hi_tight): (bool, bool),
+) -> Option<(usize, u128, (bool, bool))> {
+    for &byte in node.prefix.iter() {
+        if lo_tight {
+            if byte < lo[depth] {
+                return None;
             }
-
-            if let Some(&c) = end_iter.next() {
-                char = if let Some(_c) = node.children[0..=(c as usize)]
-                    .iter()
-                    .rev()
-                    .position(|n| n.is_some())
-                {
-                    c - _c as u8
-                } else {
-                    break;
-                };
-            } else {
-                break;
+            lo_tight = byte == lo[depth];
+        }
+        if hi_tight {
+            if byte > hi[depth] {
+                return None;
             }
-
-            has_passed_first_iter = true;
+            hi_tight = byte == hi[depth];
         }
+        key = (key << 8) + byte as u128;
+        depth += 1;
     }
+    Some((depth, key, (lo_tight, hi_tight)))
+}
 
-    pub(crate) fn get_range_incl(&self, start: u64, end: u64) -> Vec<(u64, &T)> {
-        let (common_node, cpair, common_chars, remaining_start_chars, remaining_end_chars) =
-            if let Some(args) = self.traverse_to_common_node(start, end) {
-                args
-            } else {
-                return vec![];
-            };
+/// Lazy walk over the entries of a [`Trie`] within a key range. Each step seeks the next entry
+/// from the root, so only the entries actually taken are visited.
+pub(crate) struct Range<'a, T> {
+    root: &'a TrieNode<T>,
+    /// Keys still to walk, `None` once exhausted.
+    bounds: Option<(u128, u128)>,
+}
 
-        if let Some((start_char, end_char)) = cpair {
-            let mut data = Vec::new();
-            self.collect_values_along_path_to_start(
-                common_node,
-                start_char,
-                remaining_start_chars,
-                common_chars,
-                &mut data,
-            );
-            self.collect_values_between_start_and_end(
-                common_node,
-                start_char,
-                end_char,
-                common_chars,
-                &mut data,
-            );
-
-            if end_char > start_char {
-                self.collect_values_along_path_to_end(
-                    common_node,
-                    end_char,
-                    remaining_end_chars,
-                    common_chars,
-                    &mut data,
-                );
-            }
+impl<'a, T> Iterator for Range<'a, T> {
+    type Item = (u128, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (lo, hi) = self.bounds?;
+        let found = seek_first(
+            self.root,
+            0,
+            0,
+            (&lo.to_be_bytes(), &hi.to_be_bytes()),
+            (true, true),
+        );
+        self.bounds = found
+            .and_then(|(key, _)| key.checked_add(1))
+            .filter(|&lo| lo <= hi)
+            .map(|lo| (lo, hi));
+        found
+    }
+}
 
-            data
-        } else {
-            common_node
-                .get_all()
-                .into_iter()
-                .map(|(chars, v)| {
-                    (
-                        (common_chars << (CHAR_BITSIZE * chars.len()))
-                            + chars
-                                .into_iter()
-                                .fold(0u64, |acc, c| (acc << CHAR_BITSIZE) + c as u64),
-                        v,
-                    )
-                })
-                .collect()
-        }
+impl<T> DoubleEndedIterator for Range<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (lo, hi) = self.bounds?;
+        let found = seek_last(
+            self.root,
+            0,
+            0,
+            (&lo.to_be_bytes(), &hi.to_be_bytes()),
+            (true, true),
+        );
+        self.bounds = found
+            .and_then(|(key, _)| key.checked_sub(1))
+            .filter(|&hi| lo <= hi)
+            .map(|hi| (lo, hi));
+        found
     }
 }
 
@@ -389,90 +287,138 @@ mod tests {
         trie
     }
 
+    fn collect<'a>(range: impl Iterator<Item = (u128, &'a String)>) -> Vec<(u128, String)> {
+        range.map(|(key, val)| (key, val.clone())).collect()
+    }
+
     #[test]
-    fn test_trie_getall() {
+    fn test_trie_range_all() {
         // Arrange
         let trie = get_sample_trie();
-        let expected_values: Vec<(Vec<u8>, String)> = vec![
-            (
-                vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2],
-                "test2".to_string(),
-            ),
-            (
-                vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4],
-                "test4".to_string(),
-            ),
-            (
-                vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0],
-                "test16".to_string(),
-            ),
+        let expected_values: Vec<(u128, String)> = vec![
+            (2, "test2".to_string()),
+            (4, "test4".to_string()),
+            (16, "test16".to_string()),
         ];
 
         // Act
-        let actual = trie.root.get_all();
-        let actual_values = actual
-            .into_iter()
-            .map(|(chars, val)| (chars, val.clone()))
-            .collect::<Vec<(Vec<u8>, String)>>();
+        let actual_values = collect(trie.range(u128::MIN, u128::MAX));
 
         // Assert
         assert_eq!(actual_values, expected_values);
     }
 
     #[test]
-    fn test_trie_getrangeinclusive() {
+    fn test_trie_range() {
         // Arrange
         let trie = get_sample_trie();
-        let (start, end) = (2u64, 5u64);
-        let expected_values: Vec<(u64, String)> =
+        let (start, end) = (2u128, 5u128);
+        let expected_values: Vec<(u128, String)> =
             vec![(2, "test2".to_string()), (4, "test4".to_string())];
 
         // Act
-        let actual = trie.get_range_incl(start, end);
-        let actual_values = actual
-            .into_iter()
-            .map(|(chars, val)| (chars, val.clone()))
-            .collect::<Vec<(u64, String)>>();
+        let actual_values = collect(trie.range(start, end));
 
         // Assert
         assert_eq!(actual_values, expected_values);
     }
 
     #[test]
-    fn test_trie_getrangeinclusive_startmin() {
+    fn test_trie_range_startmin() {
         // Arrange
         let trie = get_sample_trie();
-        let (start, end) = (0u64, 5u64);
-        let expected_values: Vec<(u64, String)> =
+        let (start, end) = (0u128, 5u128);
+        let expected_values: Vec<(u128, String)> =
             vec![(2, "test2".to_string()), (4, "test4".to_string())];
 
         // Act
-        let actual = trie.get_range_incl(start, end);
-        let actual_values = actual
-            .into_iter()
-            .map(|(chars, val)| (chars, val.clone()))
-            .collect::<Vec<(u64, String)>>();
+        let actual_values = collect(trie.range(start, end));
 
         // Assert
         assert_eq!(actual_values, expected_values);
     }
 
     #[test]
-    fn test_trie_getrangeinclusive_endmax() {
+    fn test_trie_range_endmax() {
         // Arrange
         let trie = get_sample_trie();
-        let (start, end) = (3, u64::MAX);
-        let expected_values: Vec<(u64, String)> =
+        let (start, end) = (3, u128::MAX);
+        let expected_values: Vec<(u128, String)> =
             vec![(4, "test4".to_string()), (16, "test16".to_string())];
 
         // Act
-        let actual = trie.get_range_incl(start, end);
-        let actual_values = actual
-            .into_iter()
-            .map(|(chars, val)| (chars, val.clone()))
-            .collect::<Vec<(u64, String)>>();
+        let actual_values = collect(trie.range(start, end));
 
         // Assert
         assert_eq!(actual_values, expected_values);
     }
+
+    #[test]
+    fn test_trie_range_rev() {
+        // Arrange
+        let mut trie = get_sample_trie();
+        trie.insert(u128::MAX, "testmax".to_string());
+        let expected_values: Vec<(u128, String)> = vec![
+            (u128::MAX, "testmax".to_string()),
+            (16, "test16".to_string()),
+            (4, "test4".to_string()),
+        ];
+
+        // Act
+        let actual_values = collect(trie.range(3, u128::MAX).rev());
+        let mut range = trie.range(u128::MIN, u128::MAX);
+        let ends = (range.next(), range.next_back(), range.next_back());
+
+        // Assert
+        assert_eq!(actual_values, expected_values);
+        assert_eq!(
+            ends,
+            (
+                Some((2, &"test2".to_string())),
+                Some((u128::MAX, &"testmax".to_string())),
+                Some((16, &"test16".to_string()))
+            )
+        );
+        assert_eq!(collect(range), vec![(4, "test4".to_string())]);
+        assert_eq!(trie.range(5, 4).next(), None);
+    }
+
+    #[test]
+    fn test_trie_remove() {
+        // Arrange
+        let mut trie = get_sample_trie();
+
+        // Act
+        let removed = (trie.remove(2), trie.remove(3));
+
+        // Assert
+        assert_eq!(removed, (Some("test2".to_string()), None));
+        assert_eq!(
+            trie.range(u128::MIN, u128::MAX).next(),
+            Some((4, &"test4".to_string()))
+        );
+        trie.remove(4);
+        trie.remove(16);
+        assert_eq!(trie.len(), 0);
+    }
+
+    #[test]
+    fn test_trie_compresses_paths() {
+        // Arrange
+        let mut trie = get_sample_trie();
+        let key = 1u128 << 100;
+
+        // Act
+        trie.insert(key, "testbig".to_string());
+        let with_branch = trie.node_count();
+        trie.remove(key);
+
+        // Assert
+        assert_eq!(trie.len(), 3);
+        // Root, the shared path of the small keys, and their leaves.
+        assert_eq!(trie.node_count(), 5);
+        assert_eq!(with_branch, 7);
+        assert_eq!(trie.get_mut(16), Some(&mut "test16".to_string()));
+        assert_eq!(trie.get_mut(3), None);
+    }
 }
@@ -1,11 +1,20 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use anyhow::Context;
-use clap::Parser;
+use clap::{ArgAction, Parser};
 use redis_starter_rust::server::{
-    master::MasterServer, replica::ReplicaServer, RedisServerHandler,
+    config::{
+        parse_memory, parse_positive, parse_save_params, parse_yes_no, AppendFsync,
+        ReplDisklessLoad, ServerConfig, DEFAULT_AUTO_AOF_REWRITE_MIN_SIZE,
+        DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE, DEFAULT_MIN_REPLICAS_MAX_LAG,
+        DEFAULT_REPL_BACKLOG_SIZE, DEFAULT_REPL_BACKLOG_TTL, DEFAULT_REPL_DISKLESS_SYNC_DELAY,
+        DEFAULT_REPL_PING_REPLICA_PERIOD, DEFAULT_REPL_TIMEOUT, DEFAULT_SAVE_PARAMS,
+        DEFAULT_STREAM_NODE_MAX_BYTES, DEFAULT_STREAM_NODE_MAX_ENTRIES,
+    },
+    redis_server::RedisServer,
+    RedisServerHandler,
 };
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -21,6 +30,93 @@ struct Cli {
 
     #[arg(long, num_args = 2, value_delimiter = ' ')]
     replicaof: Option<Vec<String>>,
+
+    /// Save points as "<seconds> <changes>" pairs; an empty string disables automatic saves
+    #[arg(long, default_value = DEFAULT_SAVE_PARAMS)]
+    save: String,
+
+    #[arg(long, default_value = "yes", value_parser = parse_yes_no, action = ArgAction::Set)]
+    rdbcompression: bool,
+
+    #[arg(long, default_value = "yes", value_parser = parse_yes_no, action = ArgAction::Set)]
+    rdbchecksum: bool,
+
+    #[arg(long, default_value = "no", value_parser = parse_yes_no, action = ArgAction::Set)]
+    appendonly: bool,
+
+    #[arg(long)]
+    appendfilename: Option<String>,
+
+    #[arg(long)]
+    appenddirname: Option<String>,
+
+    /// When to fsync the append-only file: always, everysec or no
+    #[arg(long, default_value = "everysec", value_parser = AppendFsync::parse)]
+    appendfsync: AppendFsync,
+
+    /// Write rewritten AOF bases in RDB format rather than as commands
+    #[arg(long, default_value = "yes", value_parser = parse_yes_no, action = ArgAction::Set)]
+    aof_use_rdb_preamble: bool,
+
+    /// Rewrite the AOF once it grew by this percentage since the last rewrite; 0 disables
+    #[arg(long, default_value_t = DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE)]
+    auto_aof_rewrite_percentage: u64,
+
+    /// Smallest AOF size that triggers an automatic rewrite
+    #[arg(long, default_value = DEFAULT_AUTO_AOF_REWRITE_MIN_SIZE, value_parser = parse_memory)]
+    auto_aof_rewrite_min_size: u64,
+
+    /// Size of the backlog replicas resume from after a disconnection
+    #[arg(long, default_value = DEFAULT_REPL_BACKLOG_SIZE, value_parser = parse_memory)]
+    repl_backlog_size: u64,
+
+    /// Seconds without replicas after which the backlog is freed; 0 keeps it forever
+    #[arg(long, default_value_t = DEFAULT_REPL_BACKLOG_TTL)]
+    repl_backlog_ttl: u64,
+
+    /// Whether a replica answers queries while its link with the master is down
+    #[arg(long, default_value = "yes", value_parser = parse_yes_no, action = ArgAction::Set)]
+    replica_serve_stale_data: bool,
+
+    /// Whether a replica refuses writes from its clients
+    #[arg(long, default_value = "yes", value_parser = parse_yes_no, action = ArgAction::Set)]
+    replica_read_only: bool,
+
+    /// Seconds between the PINGs a master sends its replicas
+    #[arg(long, default_value_t = DEFAULT_REPL_PING_REPLICA_PERIOD, value_parser = parse_positive)]
+    repl_ping_replica_period: u64,
+
+    /// Seconds without hearing from the other side after which a replication link is dropped
+    #[arg(long, default_value_t = DEFAULT_REPL_TIMEOUT, value_parser = parse_positive)]
+    repl_timeout: u64,
+
+    /// Refuse writes unless this many replicas are connected with a small enough lag; 0 disables
+    #[arg(long, default_value_t = 0)]
+    min_replicas_to_write: u64,
+
+    /// Largest lag in seconds of a replica counted by min-replicas-to-write
+    #[arg(long, default_value_t = DEFAULT_MIN_REPLICAS_MAX_LAG)]
+    min_replicas_max_lag: u64,
+
+    /// Whether to stream snapshots to replicas instead of sending them with their length
+    #[arg(long, default_value = "no", value_parser = parse_yes_no, action = ArgAction::Set)]
+    repl_diskless_sync: bool,
+
+    /// Seconds a diskless transfer waits for more replicas to join it
+    #[arg(long, default_value_t = DEFAULT_REPL_DISKLESS_SYNC_DELAY)]
+    repl_diskless_sync_delay: u64,
+
+    /// How a replica loads the snapshot of its master: disabled, on-empty-db or swapdb
+    #[arg(long, default_value = "disabled", value_parser = ReplDisklessLoad::parse)]
+    repl_diskless_load: ReplDisklessLoad,
+
+    /// Size in bytes past which stream entries go to a new node; 0 disables the limit
+    #[arg(long, default_value = DEFAULT_STREAM_NODE_MAX_BYTES, value_parser = parse_memory)]
+    stream_node_max_bytes: u64,
+
+    /// Number of entries past which stream entries go to a new node; 0 disables the limit
+    #[arg(long, default_value_t = DEFAULT_STREAM_NODE_MAX_ENTRIES)]
+    stream_node_max_entries: u64,
 }
 
 #[tokio::main]
@@ -30,55 +126,89 @@ async fn main() {
         dir,
         dbfilename,
         replicaof,
+        save,
+        rdbcompression,
+        rdbchecksum,
+        appendonly,
+        appendfilename,
+        appenddirname,
+        appendfsync,
+        aof_use_rdb_preamble,
+        auto_aof_rewrite_percentage,
+        auto_aof_rewrite_min_size,
+        repl_backlog_size,
+        repl_backlog_ttl,
+        replica_serve_stale_data,
+        replica_read_only,
+        repl_ping_replica_period,
+        repl_timeout,
+        min_replicas_to_write,
+        min_replicas_max_lag,
+        repl_diskless_sync,
+        repl_diskless_sync_delay,
+        repl_diskless_load,
+        stream_node_max_bytes,
+        stream_node_max_entries,
     } = Cli::parse();
 
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
-    if let Some(v) = replicaof {
+    let config = ServerConfig {
+        dir,
+        dbfilename,
+        save: parse_save_params(&save)
+            .context("Parse save points")
+            .unwrap(),
+        rdbcompression,
+        rdbchecksum,
+        appendonly,
+        appendfilename,
+        appenddirname,
+        appendfsync,
+        aof_use_rdb_preamble,
+        auto_aof_rewrite_percentage,
+        auto_aof_rewrite_min_size,
+        repl_backlog_size,
+        repl_backlog_ttl,
+        replica_serve_stale_data,
+        replica_read_only,
+        repl_ping_replica_period,
+        repl_timeout,
+        min_replicas_to_write,
+        min_replicas_max_lag,
+        repl_diskless_sync,
+        repl_diskless_sync_delay,
+        repl_diskless_load,
+        stream_node_max_bytes,
+        stream_node_max_entries,
+    };
+
+    let replicaof = replicaof.map(|v| {
         let (master_host, remaining) = v.split_first().expect("Host argument");
         let (master_port, remaining) = remaining.split_first().expect("Port argument");
         assert!(remaining.is_empty());
+        let master_port = master_port.parse().context("Parse master port").unwrap();
+        (master_host.clone(), master_port)
+    });
 
-        let master_addr = format!("{}:{}", master_host, master_port);
-        let master_conn = TcpStream::connect(master_addr)
-            .await
-            .context("Connect to master")
-            .unwrap();
-        let server = ReplicaServer::new(port, master_conn).await.unwrap();
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+    let server = RedisServer::new(config, port, replicaof)
+        .await
+        .context("Start server")
+        .unwrap();
 
-        let listener = TcpListener::bind(&addr)
-            .await
-            .context(format!("Listen at {}", addr))
-            .unwrap();
+    let listener = TcpListener::bind(&addr)
+        .await
+        .context(format!("Listen at {}", addr))
+        .unwrap();
 
-        loop {
-            let (socket, _) = listener
-                .accept()
-                .await
-                .context("Accept connection")
-                .unwrap();
-            eprintln!("Accept conn from {}", socket.peer_addr().unwrap());
-
-            let mut server = server.clone();
-            tokio::spawn(async move { server.handle_conn(socket).await });
-        }
-    } else {
-        let server = MasterServer::new(dir, dbfilename).await;
-
-        let listener = TcpListener::bind(&addr)
+    loop {
+        let (socket, _) = listener
+            .accept()
             .await
-            .context(format!("Listen at {}", addr))
+            .context("Accept connection")
             .unwrap();
+        eprintln!("Accept conn from {}", socket.peer_addr().unwrap());
 
-        loop {
-            let (socket, _) = listener
-                .accept()
-                .await
-                .context("Accept connection")
-                .unwrap();
-            eprintln!("Accept conn from {}", socket.peer_addr().unwrap());
-
-            let mut server = server.clone();
-            tokio::spawn(async move { server.handle_conn(socket).await });
-        }
+        let mut server = server.clone();
+        tokio::spawn(async move { server.handle_conn(socket).await });
     }
 }
@@ -0,0 +1,55 @@
+// CRC-64/Jones as used by Redis for RDB checksums: reflected input and output, no final XOR.
+const POLY_REFLECTED: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const fn make_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY_REFLECTED
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static TABLE: [u64; 256] = make_table();
+
+pub(crate) fn crc64(crc: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(crc, |crc, &b| {
+        TABLE[((crc ^ b as u64) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc64_check_value() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn test_crc64_incremental() {
+        let whole = crc64(0, b"123456789");
+        let split = crc64(crc64(0, b"1234"), b"56789");
+        assert_eq!(whole, split);
+    }
+
+    #[test]
+    fn test_crc64_empty_rdb_trailer() {
+        let bytes = hex::decode("524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2").expect("Valid HEX");
+        let (payload, checksum) = bytes.split_at(bytes.len() - 8);
+        let expected = u64::from_le_bytes(checksum.try_into().expect("8 bytes"));
+        assert_eq!(crc64(0, payload), expected);
+    }
+}
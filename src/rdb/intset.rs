@@ -0,0 +1,53 @@
+use anyhow::Context;
+
+use super::split_checked;
+
+/// Decodes an intset, rendering members as decimal strings.
+pub(crate) fn decode_intset(bytes: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let (header, contents) = split_checked(bytes, 8).context("Extract intset header")?;
+    let width = u32::from_le_bytes(header[..4].try_into().expect("4 bytes")) as usize;
+    let length = u32::from_le_bytes(header[4..].try_into().expect("4 bytes")) as usize;
+    if ![2, 4, 8].contains(&width) {
+        return Err(anyhow::anyhow!("Invalid intset encoding: {}", width));
+    }
+    if contents.len() != width * length {
+        return Err(anyhow::anyhow!(
+            "Intset declares {} members of {} bytes, found {} bytes",
+            length,
+            width,
+            contents.len()
+        ));
+    }
+
+    Ok(contents
+        .chunks_exact(width)
+        .map(|chunk| {
+            let v = match width {
+                2 => i16::from_le_bytes(chunk.try_into().expect("2 bytes")) as i64,
+                4 => i32::from_le_bytes(chunk.try_into().expect("4 bytes")) as i64,
+                _ => i64::from_le_bytes(chunk.try_into().expect("8 bytes")),
+            };
+            v.to_string().into_bytes()
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_intset() {
+        // Arrange
+        let mut bytes = 2u32.to_le_bytes().to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend((-3i16).to_le_bytes());
+        bytes.extend(700i16.to_le_bytes());
+
+        // Act
+        let actual = decode_intset(&bytes).unwrap();
+
+        // Assert
+        assert_eq!(actual, vec![b"-3".to_vec(), b"700".to_vec()]);
+    }
+}
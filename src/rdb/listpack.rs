@@ -0,0 +1,276 @@
+use anyhow::Context;
+
+use super::split_checked as take;
+
+const LISTPACK_HEADER_SIZE: usize = 6;
+const LISTPACK_EOF: u8 = 0xFF;
+const LISTPACK_UNKNOWN_NUM_ELEMENTS: u16 = u16::MAX;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ListpackEntry {
+    Int(i64),
+    Str(Vec<u8>),
+}
+
+impl ListpackEntry {
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        match self {
+            ListpackEntry::Int(v) => v.to_string().into_bytes(),
+            ListpackEntry::Str(s) => s,
+        }
+    }
+
+    pub(crate) fn as_int(&self) -> anyhow::Result<i64> {
+        match self {
+            ListpackEntry::Int(v) => Ok(*v),
+            ListpackEntry::Str(s) => std::str::from_utf8(s)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .context(format!("Expect listpack integer, found: {:?}", s)),
+        }
+    }
+}
+
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+fn encode_backlen(len: usize, buf: &mut Vec<u8>) {
+    // The backlen is stored big-endian in 7-bit groups so that it can be decoded right-to-left;
+    // every byte but the leftmost has its high bit set.
+    let size = backlen_size(len);
+    for i in (0..size).rev() {
+        let group = ((len >> (7 * i)) & 127) as u8;
+        buf.push(if i == size - 1 { group } else { group | 128 });
+    }
+}
+
+fn encode_entry(entry: &ListpackEntry, buf: &mut Vec<u8>) {
+    let start = buf.len();
+    match entry {
+        ListpackEntry::Int(v) => {
+            let v = *v;
+            if (0..=127).contains(&v) {
+                buf.push(v as u8);
+            } else if (-4096..=4095).contains(&v) {
+                let v = (v as u16) & 0x1FFF;
+                buf.push(0xC0 | (v >> 8) as u8);
+                buf.push(v as u8);
+            } else if i16::try_from(v).is_ok() {
+                buf.push(0xF1);
+                buf.extend((v as i16).to_le_bytes());
+            } else if (-(1 << 23)..(1 << 23)).contains(&v) {
+                buf.push(0xF2);
+                buf.extend(&(v as i32).to_le_bytes()[..3]);
+            } else if i32::try_from(v).is_ok() {
+                buf.push(0xF3);
+                buf.extend((v as i32).to_le_bytes());
+            } else {
+                buf.push(0xF4);
+                buf.extend(v.to_le_bytes());
+            }
+        }
+        ListpackEntry::Str(s) => {
+            let len = s.len();
+            if len < 1 << 6 {
+                buf.push(0x80 | len as u8);
+            } else if len < 1 << 12 {
+                buf.push(0xE0 | (len >> 8) as u8);
+                buf.push(len as u8);
+            } else {
+                buf.push(0xF0);
+                buf.extend((len as u32).to_le_bytes());
+            }
+            buf.extend(s);
+        }
+    }
+    encode_backlen(buf.len() - start, buf);
+}
+
+fn sign_extend(v: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((v << shift) as i64) >> shift
+}
+
+fn decode_entry(bytes: &[u8]) -> anyhow::Result<(ListpackEntry, &[u8])> {
+    let b0 = *bytes.first().context("Extract listpack entry encoding")?;
+    let (entry, header_len, data_len) = match b0 {
+        0x00..=0x7F => (ListpackEntry::Int(b0.into()), 1, 0),
+        0x80..=0xBF => (ListpackEntry::Str(vec![]), 1, (b0 & 0x3F) as usize),
+        0xC0..=0xDF => {
+            let (data, _) = take(bytes, 2)?;
+            let v = (((b0 & 0x1F) as u64) << 8) | data[1] as u64;
+            (ListpackEntry::Int(sign_extend(v, 13)), 2, 0)
+        }
+        0xE0..=0xEF => {
+            let (data, _) = take(bytes, 2)?;
+            let len = (((b0 & 0x0F) as usize) << 8) | data[1] as usize;
+            (ListpackEntry::Str(vec![]), 2, len)
+        }
+        0xF0 => {
+            let (data, _) = take(bytes, 5)?;
+            let len = u32::from_le_bytes(data[1..5].try_into().expect("4 bytes")) as usize;
+            (ListpackEntry::Str(vec![]), 5, len)
+        }
+        0xF1..=0xF4 => {
+            let width = match b0 {
+                0xF1 => 2,
+                0xF2 => 3,
+                0xF3 => 4,
+                _ => 8,
+            };
+            let (data, _) = take(bytes, 1 + width)?;
+            let mut le = [0u8; 8];
+            le[..width].copy_from_slice(&data[1..]);
+            let v = sign_extend(u64::from_le_bytes(le), width as u32 * 8);
+            (ListpackEntry::Int(v), 1 + width, 0)
+        }
+        o => return Err(anyhow::anyhow!("Invalid listpack encoding byte: {:#x}", o)),
+    };
+
+    let (_, remaining) = take(bytes, header_len)?;
+    let (data, remaining) = take(remaining, data_len)?;
+    let entry = match entry {
+        ListpackEntry::Str(_) => ListpackEntry::Str(data.to_vec()),
+        int => int,
+    };
+    let (_, remaining) = take(remaining, backlen_size(header_len + data_len))?;
+    Ok((entry, remaining))
+}
+
+pub(crate) fn decode_listpack(bytes: &[u8]) -> anyhow::Result<Vec<ListpackEntry>> {
+    let (header, mut remaining) = take(bytes, LISTPACK_HEADER_SIZE).context("Extract header")?;
+    let total_bytes = u32::from_le_bytes(header[..4].try_into().expect("4 bytes")) as usize;
+    if total_bytes != bytes.len() {
+        return Err(anyhow::anyhow!(
+            "Listpack header declares {} bytes, found {}",
+            total_bytes,
+            bytes.len()
+        ));
+    }
+    let num_elements = u16::from_le_bytes(header[4..].try_into().expect("2 bytes"));
+
+    let mut entries = Vec::new();
+    loop {
+        match remaining.first() {
+            Some(&LISTPACK_EOF) => break,
+            Some(_) => {
+                let (entry, _remaining) = decode_entry(remaining)?;
+                entries.push(entry);
+                remaining = _remaining;
+            }
+            None => return Err(anyhow::anyhow!("Listpack is missing its terminator")),
+        }
+    }
+
+    if num_elements != LISTPACK_UNKNOWN_NUM_ELEMENTS && num_elements as usize != entries.len() {
+        return Err(anyhow::anyhow!(
+            "Listpack header declares {} elements, found {}",
+            num_elements,
+            entries.len()
+        ));
+    }
+    Ok(entries)
+}
+
+/// Decodes a listpack whose elements are all used as strings.
+pub(crate) fn decode_listpack_strings(bytes: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    Ok(decode_listpack(bytes)?
+        .into_iter()
+        .map(ListpackEntry::into_bytes)
+        .collect())
+}
+
+pub(crate) fn encode_listpack(entries: &[ListpackEntry]) -> Vec<u8> {
+    let mut buf = vec![0u8; LISTPACK_HEADER_SIZE];
+    for entry in entries {
+        encode_entry(entry, &mut buf);
+    }
+    buf.push(LISTPACK_EOF);
+
+    let total_bytes = buf.len() as u32;
+    // Counts that do not fit are recorded as unknown, capped at u16::MAX.
+    let num_elements = u16::try_from(entries.len()).unwrap_or(LISTPACK_UNKNOWN_NUM_ELEMENTS);
+    buf[..4].copy_from_slice(&total_bytes.to_le_bytes());
+    buf[4..LISTPACK_HEADER_SIZE].copy_from_slice(&num_elements.to_le_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_listpack_small_values() {
+        // Arrange
+        let entries = vec![ListpackEntry::Int(5), ListpackEntry::Str(b"ab".to_vec())];
+        let expected = vec![
+            13, 0, 0, 0, 2, 0, // header
+            5, 1, // 7-bit uint
+            0x82, b'a', b'b', 3, // 6-bit string
+            0xFF,
+        ];
+
+        // Act
+        let actual = encode_listpack(&entries);
+
+        // Assert
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_encode_listpack_negative_int() {
+        // Arrange
+        let entries = vec![ListpackEntry::Int(-1)];
+        let expected = vec![10, 0, 0, 0, 1, 0, 0xDF, 0xFF, 2, 0xFF];
+
+        // Act
+        let actual = encode_listpack(&entries);
+
+        // Assert
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_decode_listpack_roundtrip() {
+        // Arrange
+        let entries = vec![
+            ListpackEntry::Int(0),
+            ListpackEntry::Int(127),
+            ListpackEntry::Int(-4096),
+            ListpackEntry::Int(30000),
+            ListpackEntry::Int(-(1 << 23)),
+            ListpackEntry::Int(i32::MIN.into()),
+            ListpackEntry::Int(i64::MAX),
+            ListpackEntry::Str(b"".to_vec()),
+            ListpackEntry::Str(b"x".repeat(200)),
+            ListpackEntry::Str(b"y".repeat(5000)),
+        ];
+
+        // Act
+        let actual = decode_listpack(&encode_listpack(&entries)).unwrap();
+
+        // Assert
+        assert_eq!(actual, entries);
+    }
+
+    #[test]
+    fn test_decode_listpack_truncated() {
+        let mut bytes = encode_listpack(&[ListpackEntry::Str(b"hello".to_vec())]);
+        bytes.truncate(bytes.len() - 3);
+        assert!(decode_listpack(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_encode_backlen_multibyte() {
+        let mut buf = Vec::new();
+        encode_backlen(200, &mut buf);
+        assert_eq!(buf, vec![1, (200 & 127) | 128]);
+    }
+}
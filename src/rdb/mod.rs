@@ -0,0 +1,831 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+
+use crate::db::{stream::StreamNodeLimits, RedisDb};
+
+use self::values::{extract_value, skip_module_value, RdbValue};
+
+mod crc64;
+mod intset;
+mod listpack;
+mod values;
+pub(crate) mod writer;
+mod ziplist;
+mod zipmap;
+
+static REDIS_MAGIC_STRING: &[u8; 5] = b"REDIS";
+
+pub(crate) struct Rdb {
+    // pub(crate) ver_num: u32,
+    // pub(crate) aux: HashMap<Vec<u8>, Vec<u8>>,
+    pub(crate) databases: HashMap<u32, RedisDb>,
+    pub(crate) repl_info: Option<RdbReplInfo>,
+}
+
+/// Replication history a dataset belongs to, saved in the `repl-id` and `repl-offset` aux
+/// fields so that a restarted server can resume it with a partial resync.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RdbReplInfo {
+    pub(crate) repl_id: [char; 40],
+    pub(crate) repl_offset: usize,
+}
+
+impl RdbReplInfo {
+    /// Reads the replication aux fields, if present and valid.
+    fn from_aux(aux: &HashMap<Vec<u8>, Vec<u8>>) -> Option<Self> {
+        let repl_id = std::str::from_utf8(aux.get(b"repl-id".as_slice())?).ok()?;
+        let repl_offset = std::str::from_utf8(aux.get(b"repl-offset".as_slice())?).ok()?;
+        Some(Self {
+            repl_id: repl_id.chars().collect::<Vec<char>>().try_into().ok()?,
+            repl_offset: repl_offset.parse().ok()?,
+        })
+    }
+}
+
+const OPCODE_EOF: u8 = 0xFF;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_EXPIRETIMEMS: u8 = 0xFC;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_FUNCTION_PRE_GA: u8 = 0xF6;
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_SLOT_INFO: u8 = 0xF4;
+
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+const RDB_TYPE_MODULE: u8 = 6;
+const RDB_TYPE_MODULE_2: u8 = 7;
+const RDB_TYPE_HASH_ZIPMAP: u8 = 9;
+const RDB_TYPE_LIST_ZIPLIST: u8 = 10;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_ZSET_ZIPLIST: u8 = 12;
+const RDB_TYPE_HASH_ZIPLIST: u8 = 13;
+const RDB_TYPE_LIST_QUICKLIST: u8 = 14;
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+const RDB_TYPE_HASH_METADATA_PRE_GA: u8 = 22;
+const RDB_TYPE_HASH_LISTPACK_EX_PRE_GA: u8 = 23;
+const RDB_TYPE_HASH_METADATA: u8 = 24;
+const RDB_TYPE_HASH_LISTPACK_EX: u8 = 25;
+
+// Oldest and newest RDB format versions the loader understands.
+const RDB_MIN_VERSION: u32 = 1;
+const RDB_MAX_VERSION: u32 = 12;
+// The trailing CRC64 checksum was introduced in RDB version 5.
+const RDB_CHECKSUM_MIN_VERSION: u32 = 5;
+const RDB_CHECKSUM_SIZE: usize = 8;
+
+const RDB_6BITLEN: u8 = 0;
+const RDB_14BITLEN: u8 = 1;
+const RDB_32BITLEN: u8 = 0x80;
+const RDB_64BITLEN: u8 = 0x81;
+const RDB_ENCVAL: u8 = 3;
+
+/// The input ended before a value was complete. `available` is the number of bytes that were
+/// left, so the offset into the file is `file length - available`.
+#[derive(Debug, thiserror::Error)]
+#[error("Unexpected end of RDB: need {needed} bytes, only {available} left")]
+struct Truncated {
+    needed: usize,
+    available: usize,
+}
+
+/// Splits `n` bytes off the RDB payload, failing with [`Truncated`] if there aren't enough.
+fn take(bytes: &[u8], n: usize) -> anyhow::Result<(&[u8], &[u8])> {
+    if bytes.len() < n {
+        return Err(Truncated {
+            needed: n,
+            available: bytes.len(),
+        }
+        .into());
+    }
+    Ok(bytes.split_at(n))
+}
+
+fn take_u8(bytes: &[u8]) -> anyhow::Result<(u8, &[u8])> {
+    let (b, remaining) = take(bytes, 1)?;
+    Ok((b[0], remaining))
+}
+
+/// Like [`take`], for the encodings embedded in RDB strings (listpacks, ziplists, ...), whose
+/// offsets are relative to the decoded string rather than to the file.
+fn split_checked(bytes: &[u8], n: usize) -> anyhow::Result<(&[u8], &[u8])> {
+    if bytes.len() < n {
+        return Err(anyhow::anyhow!(
+            "Need {} bytes, only {} left",
+            n,
+            bytes.len()
+        ));
+    }
+    Ok(bytes.split_at(n))
+}
+
+#[derive(Debug)]
+enum RdbLength {
+    Length(u64),
+    Format(u8),
+}
+
+fn extract_rdb_objlength(bytes: &[u8]) -> anyhow::Result<(RdbLength, &[u8])> {
+    let (b0, remaining) = take_u8(bytes).context("Extract first byte of RDB length")?;
+
+    match (b0 >> 6, b0) {
+        (RDB_6BITLEN, _) => Ok((RdbLength::Length((b0 & 0x3F).into()), remaining)),
+        (RDB_14BITLEN, _) => {
+            let (b1, remaining) = take_u8(remaining).context("Extract 14-bit length")?;
+            let len = (u64::from(b0 & 0x3F) << 8) | u64::from(b1);
+            Ok((RdbLength::Length(len), remaining))
+        }
+        (_, RDB_32BITLEN) => {
+            let (len, remaining) = take(remaining, 4).context("Extract 32-bit length")?;
+            let len = u32::from_be_bytes(len.try_into().expect("4 bytes"));
+            Ok((RdbLength::Length(len.into()), remaining))
+        }
+        (_, RDB_64BITLEN) => {
+            let (len, remaining) = take(remaining, 8).context("Extract 64-bit length")?;
+            let len = u64::from_be_bytes(len.try_into().expect("8 bytes"));
+            Ok((RdbLength::Length(len), remaining))
+        }
+        (RDB_ENCVAL, _) => Ok((RdbLength::Format(b0 & 0x3F), remaining)),
+        _ => Err(anyhow::anyhow!("Unknown length encoding: {:#04x}", b0)),
+    }
+}
+
+fn extract_rdb_length(bytes: &[u8]) -> anyhow::Result<(u64, &[u8])> {
+    let (objlength, remaining) = extract_rdb_objlength(bytes)?;
+    match objlength {
+        RdbLength::Length(l) => Ok((l, remaining)),
+        o => Err(anyhow::anyhow!("Unexpected number encoding: {:?}", o)),
+    }
+}
+
+/// Reads a length that is about to be used as a byte or element count.
+fn extract_rdb_usize(bytes: &[u8]) -> anyhow::Result<(usize, &[u8])> {
+    let (len, remaining) = extract_rdb_length(bytes)?;
+    let len = usize::try_from(len).context(format!("Length {} out of range", len))?;
+    Ok((len, remaining))
+}
+
+fn extract_rdb_string(bytes: &[u8]) -> anyhow::Result<(Vec<u8>, &[u8])> {
+    let (length, remaining) = extract_rdb_objlength(bytes).context("Extract length encoding")?;
+    let len = match length {
+        RdbLength::Length(l) => {
+            let l = usize::try_from(l).context(format!("String length {} out of range", l))?;
+            let (val, remaining) = take(remaining, l).context("Extract string")?;
+            return Ok((val.to_vec(), remaining));
+        }
+        RdbLength::Format(v) => v,
+    };
+
+    match len {
+        0..=2 => {
+            let (val, remaining) = take(remaining, 1 << len).context("Extract integer")?;
+            let val = match val {
+                [a] => i8::from_le_bytes([*a]) as i64,
+                [a, b] => i16::from_le_bytes([*a, *b]) as i64,
+                _ => i32::from_le_bytes(val.try_into().expect("4 bytes")) as i64,
+            };
+            Ok((val.to_string().into_bytes(), remaining))
+        }
+        3 => {
+            let (clen, remaining) =
+                extract_rdb_usize(remaining).context("Extract compressed length")?;
+            let (uclen, remaining) =
+                extract_rdb_usize(remaining).context("Extract uncompressed length")?;
+            let (compressed, remaining) =
+                take(remaining, clen).context("Extract compressed string")?;
+
+            let val = lzf::decompress(compressed, uclen)
+                .ok()
+                .context("Decompress LZF")?;
+            if val.len() != uclen {
+                return Err(anyhow::anyhow!(
+                    "LZF string decompressed to {} bytes, expected {}",
+                    val.len(),
+                    uclen
+                ));
+            }
+            Ok((val, remaining))
+        }
+        o => Err(anyhow::anyhow!(
+            "Unexpected special format for string: {}",
+            o
+        )),
+    }
+}
+
+/// Reads the `REDIS` magic and the 4-digit ASCII version that open every RDB file.
+fn extract_version(bytes: &[u8]) -> anyhow::Result<(u32, &[u8])> {
+    let (magic, remaining) = take(bytes, REDIS_MAGIC_STRING.len()).context("Extract magic")?;
+    if magic != REDIS_MAGIC_STRING {
+        return Err(anyhow::anyhow!("Expect REDIS string, found: {:?}", magic));
+    }
+
+    let (ver_num, remaining) = take(remaining, 4).context("Extract version")?;
+    let ver_num = std::str::from_utf8(ver_num)
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .context(format!("Invalid RDB version: {:?}", ver_num))?;
+    if !(RDB_MIN_VERSION..=RDB_MAX_VERSION).contains(&ver_num) {
+        return Err(anyhow::anyhow!(
+            "Can't handle RDB format version {}",
+            ver_num
+        ));
+    }
+    Ok((ver_num, remaining))
+}
+
+/// Attaches the file offset a truncation was detected at to `err`, given that the input ended
+/// `input_end` bytes into the file.
+fn with_offset(err: anyhow::Error, input_end: usize) -> anyhow::Error {
+    match err.chain().find_map(|e| e.downcast_ref::<Truncated>()) {
+        Some(t) => {
+            let offset = input_end - t.available;
+            err.context(format!("Truncated RDB file at offset {}", offset))
+        }
+        None => err,
+    }
+}
+
+fn is_truncated(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| e.is::<Truncated>())
+}
+
+/// Parses a whole RDB file. When `verify_checksum` is set, a non-zero trailing CRC64 must match
+/// the contents; a zero checksum means the writer had checksums disabled. Streams are packed
+/// into nodes of at most `stream_node_limits`.
+pub fn parse_rdb(
+    bytes: &[u8],
+    verify_checksum: bool,
+    stream_node_limits: StreamNodeLimits,
+) -> anyhow::Result<Rdb> {
+    parse_rdb_preamble(bytes, verify_checksum, stream_node_limits).map(|(rdb, _)| rdb)
+}
+
+/// Parses an RDB payload that may be followed by other data, such as the commands of an
+/// append-only file, returning it along with its length. Streams are packed into nodes of at
+/// most `stream_node_limits`.
+pub(crate) fn parse_rdb_preamble(
+    bytes: &[u8],
+    verify_checksum: bool,
+    stream_node_limits: StreamNodeLimits,
+) -> anyhow::Result<(Rdb, usize)> {
+    let mut loader = RdbLoader::new(verify_checksum, stream_node_limits);
+    let len = loader.parse(bytes, true)?;
+    Ok((loader.into_rdb(), len))
+}
+
+/// Part of the RDB the loader expects next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LoadState {
+    Header,
+    Records,
+    Checksum,
+    Done,
+}
+
+/// Incremental RDB parser, for payloads too large to buffer whole such as the snapshot a
+/// master streams to its replicas. Bytes are pushed as they arrive and every complete record
+/// is loaded right away, so only the record being received is kept in memory.
+pub(crate) struct RdbLoader {
+    verify_checksum: bool,
+    /// Size of the nodes loaded streams are packed into.
+    stream_node_limits: StreamNodeLimits,
+    state: LoadState,
+    ver_num: u32,
+    /// CRC64 of the bytes consumed so far, up to the checksum.
+    crc: u64,
+    /// Bytes consumed so far, i.e. the file offset of `pending`.
+    offset: usize,
+    /// Bytes pushed but not consumed yet, the beginning of an incomplete record.
+    pending: Vec<u8>,
+    /// Length `pending` has to reach before parsing is attempted again. It doubles after every
+    /// attempt that did not complete a record, so large records are not parsed over and over.
+    retry_len: usize,
+    aux: HashMap<Vec<u8>, Vec<u8>>,
+    databases: HashMap<u32, RedisDb>,
+    db_num: Option<u32>,
+    since_unix_epoch: Option<Duration>,
+}
+
+impl RdbLoader {
+    pub(crate) fn new(verify_checksum: bool, stream_node_limits: StreamNodeLimits) -> Self {
+        Self {
+            verify_checksum,
+            stream_node_limits,
+            state: LoadState::Header,
+            ver_num: 0,
+            crc: 0,
+            offset: 0,
+            pending: Vec::new(),
+            retry_len: 0,
+            aux: HashMap::new(),
+            databases: HashMap::new(),
+            db_num: None,
+            since_unix_epoch: None,
+        }
+    }
+
+    /// Loads the records completed by `bytes`, the next part of the payload.
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.pending.extend_from_slice(bytes);
+        if self.pending.len() < self.retry_len {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        let res = self.parse(&pending, false);
+        self.pending = pending;
+        let consumed = res?;
+        self.pending.drain(..consumed);
+        self.retry_len = if consumed == 0 {
+            self.pending.len() * 2
+        } else {
+            0
+        };
+        Ok(())
+    }
+
+    /// Returns the dataset once the whole payload was pushed. Fails if it is incomplete or
+    /// followed by anything else.
+    pub(crate) fn finish(mut self) -> anyhow::Result<Rdb> {
+        let pending = std::mem::take(&mut self.pending);
+        let consumed = self.parse(&pending, true)?;
+        if consumed < pending.len() {
+            return Err(anyhow::anyhow!(
+                "Unexpected {} bytes after the end of the RDB",
+                pending.len() - consumed
+            ));
+        }
+        Ok(self.into_rdb())
+    }
+
+    fn into_rdb(self) -> Rdb {
+        Rdb {
+            // ver_num,
+            // aux,
+            repl_info: RdbReplInfo::from_aux(&self.aux),
+            databases: self.databases,
+        }
+    }
+
+    /// Parses the complete records at the start of `bytes` and returns their length. With
+    /// `complete`, `bytes` holds the rest of the payload and must contain all of it.
+    fn parse(&mut self, bytes: &[u8], complete: bool) -> anyhow::Result<usize> {
+        let mut consumed = 0;
+        while self.state != LoadState::Done {
+            let remaining = &bytes[consumed..];
+            let len = match self.parse_next(remaining) {
+                Ok(len) => len,
+                Err(err) if !complete && is_truncated(&err) => break,
+                Err(err) => return Err(with_offset(err, self.offset + remaining.len())),
+            };
+            if self.state != LoadState::Done {
+                self.crc = crc64::crc64(self.crc, &remaining[..len]);
+            }
+            consumed += len;
+            self.offset += len;
+        }
+        Ok(consumed)
+    }
+
+    /// Parses the part of the file expected next from the start of `bytes`, returning its
+    /// length. State is only updated once the part is complete.
+    fn parse_next(&mut self, bytes: &[u8]) -> anyhow::Result<usize> {
+        let remaining = match self.state {
+            LoadState::Header => {
+                let (ver_num, remaining) = extract_version(bytes)?;
+                self.ver_num = ver_num;
+                self.state = LoadState::Records;
+                remaining
+            }
+            LoadState::Records => self.parse_record(bytes)?,
+            LoadState::Checksum => {
+                let (checksum, remaining) =
+                    take(bytes, RDB_CHECKSUM_SIZE).context("Extract checksum")?;
+                let expected = u64::from_le_bytes(checksum.try_into().expect("8 bytes"));
+                if self.verify_checksum && expected != 0 && self.crc != expected {
+                    return Err(anyhow::anyhow!(
+                        "Wrong RDB checksum, expected: {:#018x}, got: {:#018x}",
+                        expected,
+                        self.crc
+                    ));
+                }
+                self.state = LoadState::Done;
+                remaining
+            }
+            LoadState::Done => bytes,
+        };
+        Ok(bytes.len() - remaining.len())
+    }
+
+    /// Parses one record: an opcode and its arguments, or a key and its value.
+    fn parse_record<'a>(&mut self, bytes: &'a [u8]) -> anyhow::Result<&'a [u8]> {
+        let record_offset = self.offset;
+        let (opcode, _remaining) = take_u8(bytes).context("Extract opcode, expected EOF")?;
+        let remaining = match opcode {
+            OPCODE_EOF => {
+                eprintln!("EOF");
+                self.state = if self.ver_num >= RDB_CHECKSUM_MIN_VERSION {
+                    LoadState::Checksum
+                } else {
+                    LoadState::Done
+                };
+                _remaining
+            }
+            OPCODE_SELECTDB => {
+                let (db_num, _remaining) =
+                    extract_rdb_length(_remaining).context("Extract db number")?;
+                let db_num = u32::try_from(db_num).context("DB number out of range")?;
+                eprintln!("Parsing KVs for db number: {}", db_num);
+                self.databases.entry(db_num).or_default();
+                self.db_num = Some(db_num);
+                _remaining
+            }
+            OPCODE_RESIZEDB => {
+                let (table_size, _remaining) =
+                    extract_rdb_usize(_remaining).context("Extract hash table size")?;
+                let (expire_table_size, _remaining) =
+                    extract_rdb_usize(_remaining).context("Extract expire hash table size")?;
+                if let Some(db) = self.cur_db() {
+                    db.nonexpire_table.reserve(table_size);
+                    db.expire_table.reserve(expire_table_size);
+                }
+                _remaining
+            }
+            OPCODE_AUX => {
+                let (key, _remaining) =
+                    extract_rdb_string(_remaining).context("Extract aux key")?;
+                let (val, _remaining) =
+                    extract_rdb_string(_remaining).context("Extract aux value")?;
+                self.aux.insert(key, val);
+                _remaining
+            }
+            OPCODE_MODULE_AUX => skip_module_value(_remaining)
+                .context(format!("Skip module aux data at offset {}", record_offset))?,
+            OPCODE_FUNCTION2 => {
+                // Function libraries are not supported; their source is skipped.
+                extract_rdb_string(_remaining)
+                    .context("Extract function library")?
+                    .1
+            }
+            OPCODE_FUNCTION_PRE_GA => {
+                return Err(anyhow::anyhow!(
+                    "Pre-release function format is not supported"
+                ));
+            }
+            OPCODE_SLOT_INFO => {
+                let (_slot_id, _remaining) = extract_rdb_length(_remaining)?;
+                let (_slot_size, _remaining) = extract_rdb_length(_remaining)?;
+                extract_rdb_length(_remaining)?.1
+            }
+            OPCODE_EXPIRETIME => {
+                let (secs, _remaining) = take(_remaining, 4).context("Extract expire time")?;
+                let secs = i32::from_le_bytes(secs.try_into().expect("4 bytes"));
+                self.since_unix_epoch = Some(Duration::from_secs(secs.max(0) as u64));
+                _remaining
+            }
+            OPCODE_EXPIRETIMEMS => {
+                let (millis, _remaining) =
+                    values::extract_millis(_remaining).context("Extract expire time")?;
+                self.since_unix_epoch = Some(Duration::from_millis(millis));
+                _remaining
+            }
+            OPCODE_IDLE => {
+                extract_rdb_length(_remaining)
+                    .context("Extract LRU idle")?
+                    .1
+            }
+            OPCODE_FREQ => take(_remaining, 1).context("Extract LFU freq")?.1,
+            value_type => {
+                let expiry = self.since_unix_epoch.map(|dur| UNIX_EPOCH + dur);
+                let limits = self.stream_node_limits;
+                let db = self
+                    .cur_db()
+                    .context(format!("Key before SELECTDB at offset {}", record_offset))?;
+                let _remaining = load_key(db, value_type, _remaining, expiry, &limits)
+                    .context(format!("Parse key at offset {}", record_offset))?;
+                self.since_unix_epoch = None;
+                _remaining
+            }
+        };
+        Ok(remaining)
+    }
+
+    fn cur_db(&mut self) -> Option<&mut RedisDb> {
+        self.db_num
+            .and_then(|db_num| self.databases.get_mut(&db_num))
+    }
+}
+
+/// Reads one key and its value, storing it in `db` unless it already expired.
+fn load_key<'a>(
+    db: &mut RedisDb,
+    value_type: u8,
+    bytes: &'a [u8],
+    expiry: Option<SystemTime>,
+    stream_node_limits: &StreamNodeLimits,
+) -> anyhow::Result<&'a [u8]> {
+    let (key, remaining) = extract_rdb_string(bytes).context("Extract key")?;
+    let (value, remaining) = extract_value(value_type, remaining, stream_node_limits).context(
+        format!("Extract value of type {} for {:?}", value_type, key),
+    )?;
+
+    if expiry.is_some_and(|expiry| expiry <= SystemTime::now()) {
+        return Ok(remaining);
+    }
+
+    match (value, expiry) {
+        (RdbValue::String(value), Some(expiry)) => {
+            db.expire_table.insert(key, (value, expiry));
+            return Ok(remaining);
+        }
+        (RdbValue::String(value), None) => {
+            db.nonexpire_table.insert(key, value);
+            return Ok(remaining);
+        }
+        (RdbValue::List(list), _) => {
+            db.lists.insert(key.clone(), list);
+        }
+        (RdbValue::Set(set), _) => {
+            db.sets.insert(key.clone(), set);
+        }
+        (RdbValue::SortedSet(zset), _) => {
+            db.sorted_sets.insert(key.clone(), zset);
+        }
+        (RdbValue::Hash(hash), _) => {
+            db.hashes.insert(key.clone(), hash);
+        }
+        (RdbValue::Stream(stream), _) => {
+            db.streams.insert(key.clone(), *stream);
+        }
+        (RdbValue::Module, _) => return Ok(remaining),
+    }
+    if let Some(expiry) = expiry {
+        db.expire_at.insert(key, expiry);
+    }
+    Ok(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_empty_rdb() {
+        let bytes = hex::decode("524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2").expect("Valid HEX");
+        let rdb = parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid empty RDB");
+        assert!(rdb.databases.is_empty());
+    }
+
+    #[test]
+    fn test_parse_1kv_rdb() {
+        let bytes = hex::decode("524544495330303131fa0972656469732d76657205372e322e34fa0a72656469732d62697473c040fa056374696d65c247561266fa08757365642d6d656dc2e0461100fa08616f662d62617365c000fe00fb010000056d796b6579056d7976616cff59eeb542a15e83f7").expect("Valid HEX");
+        let rdb =
+            parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid RDB with 1 KV pair");
+        eprintln!("Keys: {:?}", rdb.databases.keys());
+        assert_eq!(rdb.databases.len(), 1);
+    }
+
+    fn rdb_string(s: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![s.len() as u8];
+        bytes.extend(s);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_compact_encodings() {
+        // Arrange
+        let listpack = |items: &[&[u8]]| {
+            listpack::encode_listpack(
+                &items
+                    .iter()
+                    .map(|item| listpack::ListpackEntry::Str(item.to_vec()))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let mut intset = Vec::new();
+        intset.extend(2u32.to_le_bytes());
+        intset.extend(2u32.to_le_bytes());
+        intset.extend(1i16.to_le_bytes());
+        intset.extend(300i16.to_le_bytes());
+
+        let mut bytes = b"REDIS0011".to_vec();
+        bytes.extend([OPCODE_SELECTDB, 0]);
+        bytes.push(RDB_TYPE_SET_LISTPACK);
+        bytes.extend(rdb_string(b"set"));
+        bytes.extend(rdb_string(&listpack(&[b"a", b"b"])));
+        bytes.push(OPCODE_EXPIRETIMEMS);
+        bytes.extend(u64::MAX.to_le_bytes());
+        bytes.push(RDB_TYPE_HASH_LISTPACK);
+        bytes.extend(rdb_string(b"hash"));
+        bytes.extend(rdb_string(&listpack(&[b"field", b"value"])));
+        bytes.push(RDB_TYPE_SET_INTSET);
+        bytes.extend(rdb_string(b"ints"));
+        bytes.extend(rdb_string(&intset));
+        bytes.push(OPCODE_EOF);
+        bytes.extend([0; 8]);
+
+        // Act
+        let rdb = parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid RDB");
+
+        // Assert
+        let db = &rdb.databases[&0];
+        assert_eq!(
+            db.sets[&b"set".to_vec()],
+            HashSet::from([b"a".to_vec(), b"b".to_vec()])
+        );
+        assert_eq!(
+            db.hashes[&b"hash".to_vec()][&b"field".to_vec()],
+            (b"value".to_vec(), None)
+        );
+        assert!(db.expire_at.contains_key(b"hash".as_slice()));
+        assert_eq!(
+            db.sets[&b"ints".to_vec()],
+            HashSet::from([b"1".to_vec(), b"300".to_vec()])
+        );
+    }
+
+    #[test]
+    fn test_extract_rdb_length() {
+        let cases: [(&[u8], u64); 5] = [
+            (&[0x0A], 10),
+            (&[0x42, 0xBC], 700),
+            (&[0x7F, 0xFF], 16383),
+            (&[0x80, 0x00, 0x00, 0x42, 0x68], 17000),
+            (
+                &[0x81, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00],
+                1 << 32,
+            ),
+        ];
+        for (bytes, expected) in cases {
+            let (len, remaining) = extract_rdb_length(bytes).expect("Valid length");
+            assert_eq!(len, expected, "bytes {:?}", bytes);
+            assert!(remaining.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_extract_rdb_length_unknown_encoding() {
+        assert!(extract_rdb_length(&[0x82, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_parse_rdb_long_string() {
+        // Arrange
+        let value = b"x".repeat(20000);
+        let mut db = RedisDb::new();
+        db.set(&b"key".to_vec(), value.clone(), None);
+        let bytes = writer::encode_rdb(&HashMap::from([(0, db)]), false, true, None);
+
+        // Act
+        let mut rdb = parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid RDB");
+
+        // Assert
+        let db = rdb.databases.get_mut(&0).expect("Database 0");
+        assert_eq!(db.get(&b"key".to_vec()), Some(value));
+    }
+
+    #[test]
+    fn test_parse_rdb_wrong_checksum() {
+        // Arrange
+        let mut bytes = writer::encode_rdb(&HashMap::new(), false, true, None);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+
+        // Act
+        let res = parse_rdb(&bytes, true, StreamNodeLimits::default());
+
+        // Assert
+        assert!(format!("{:#}", res.err().expect("Checksum error")).contains("checksum"));
+        assert!(parse_rdb(&bytes, false, StreamNodeLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn test_parse_rdb_zero_checksum_skips_verification() {
+        let bytes = writer::encode_rdb(&HashMap::new(), false, false, None);
+        assert!(bytes.ends_with(&[0; 8]));
+        assert!(parse_rdb(&bytes, true, StreamNodeLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn test_parse_rdb_truncated_offset() {
+        // Arrange
+        let mut db = RedisDb::new();
+        db.set(&b"key".to_vec(), b"value".to_vec(), None);
+        let bytes = writer::encode_rdb(&HashMap::from([(0, db)]), false, true, None);
+        let value_end = bytes.len() - 1 - RDB_CHECKSUM_SIZE;
+        let truncated = &bytes[..value_end - 2];
+
+        // Act
+        let err = parse_rdb(truncated, true, StreamNodeLimits::default())
+            .err()
+            .expect("Truncated RDB");
+
+        // Assert
+        assert!(
+            format!("{:#}", err).contains(&format!("at offset {}", value_end - 5)),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_parse_rdb_missing_eof() {
+        let bytes = writer::encode_rdb(&HashMap::new(), false, true, None);
+        let truncated = &bytes[..bytes.len() - 1 - RDB_CHECKSUM_SIZE];
+        let err = parse_rdb(truncated, true, StreamNodeLimits::default())
+            .err()
+            .expect("Missing EOF");
+        assert!(format!("{:#}", err).contains("Truncated RDB file"));
+    }
+
+    #[test]
+    fn test_parse_rdb_versions() {
+        // Version 3 predates the checksum and aux fields.
+        let mut bytes = b"REDIS0003".to_vec();
+        bytes.extend([
+            OPCODE_SELECTDB,
+            0,
+            RDB_TYPE_STRING,
+            1,
+            b'k',
+            1,
+            b'v',
+            OPCODE_EOF,
+        ]);
+        let mut rdb =
+            parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid version 3 RDB");
+        let db = rdb.databases.get_mut(&0).expect("Database 0");
+        assert_eq!(db.get(&b"k".to_vec()), Some(b"v".to_vec()));
+
+        assert!(parse_rdb(b"REDIS0013\xff", true, StreamNodeLimits::default()).is_err());
+        assert!(parse_rdb(b"REDIS0000\xff", true, StreamNodeLimits::default()).is_err());
+    }
+
+    #[test]
+    fn test_rdb_loader_chunks() {
+        // Arrange
+        let mut db = RedisDb::new();
+        db.set(&b"key".to_vec(), b"x".repeat(5000), None);
+        db.set(&b"other".to_vec(), b"value".to_vec(), None);
+        let bytes = writer::encode_rdb(&HashMap::from([(0, db)]), false, true, None);
+
+        for chunk_size in [1, 7, 4096] {
+            // Act
+            let mut loader = RdbLoader::new(true, StreamNodeLimits::default());
+            for chunk in bytes.chunks(chunk_size) {
+                loader.push(chunk).expect("Valid chunk");
+            }
+            let mut rdb = loader.finish().expect("Valid RDB");
+
+            // Assert
+            let db = rdb.databases.get_mut(&0).expect("Database 0");
+            assert_eq!(db.get(&b"key".to_vec()), Some(b"x".repeat(5000)));
+            assert_eq!(db.get(&b"other".to_vec()), Some(b"value".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_rdb_loader_incomplete_or_trailing() {
+        // Arrange
+        let mut bytes = writer::encode_rdb(&HashMap::new(), false, true, None);
+        let last = bytes.len() - 1;
+
+        // Act & Assert
+        let mut loader = RdbLoader::new(true, StreamNodeLimits::default());
+        loader.push(&bytes[..last]).expect("Valid chunk");
+        assert!(loader.finish().is_err());
+
+        bytes.push(b'x');
+        let mut loader = RdbLoader::new(true, StreamNodeLimits::default());
+        loader.push(&bytes).expect("Valid chunk");
+        assert!(loader.finish().is_err());
+
+        bytes.pop();
+        bytes[last] ^= 0xFF;
+        let mut loader = RdbLoader::new(true, StreamNodeLimits::default());
+        let res = loader
+            .push(&bytes)
+            .and_then(|_| loader.finish().map(|_| ()));
+        assert!(format!("{:#}", res.expect_err("Checksum error")).contains("checksum"));
+    }
+}
@@ -0,0 +1,577 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+
+use crate::db::{
+    stream::{
+        PendingEntry, RedisStream, ReqStreamEntryID, StreamConsumer, StreamEntryData,
+        StreamEntryID, StreamGroup, StreamNodeLimits,
+    },
+    RedisHash, RedisList, RedisSet, RedisSortedSet,
+};
+
+use super::{
+    extract_rdb_length, extract_rdb_string,
+    intset::decode_intset,
+    listpack::{decode_listpack, decode_listpack_strings, ListpackEntry},
+    take, take_u8,
+    ziplist::decode_ziplist,
+    zipmap::decode_zipmap,
+    RDB_TYPE_HASH, RDB_TYPE_HASH_LISTPACK, RDB_TYPE_HASH_LISTPACK_EX,
+    RDB_TYPE_HASH_LISTPACK_EX_PRE_GA, RDB_TYPE_HASH_METADATA, RDB_TYPE_HASH_METADATA_PRE_GA,
+    RDB_TYPE_HASH_ZIPLIST, RDB_TYPE_HASH_ZIPMAP, RDB_TYPE_LIST, RDB_TYPE_LIST_QUICKLIST,
+    RDB_TYPE_LIST_QUICKLIST_2, RDB_TYPE_LIST_ZIPLIST, RDB_TYPE_MODULE, RDB_TYPE_MODULE_2,
+    RDB_TYPE_SET, RDB_TYPE_SET_INTSET, RDB_TYPE_SET_LISTPACK, RDB_TYPE_STREAM_LISTPACKS,
+    RDB_TYPE_STREAM_LISTPACKS_2, RDB_TYPE_STREAM_LISTPACKS_3, RDB_TYPE_STRING, RDB_TYPE_ZSET,
+    RDB_TYPE_ZSET_2, RDB_TYPE_ZSET_LISTPACK, RDB_TYPE_ZSET_ZIPLIST,
+};
+
+const QUICKLIST_NODE_CONTAINER_PLAIN: u64 = 1;
+const QUICKLIST_NODE_CONTAINER_PACKED: u64 = 2;
+
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+const STREAM_ID_SIZE: usize = 16;
+
+const MODULE_OPCODE_EOF: u64 = 0;
+const MODULE_OPCODE_SINT: u64 = 1;
+const MODULE_OPCODE_UINT: u64 = 2;
+const MODULE_OPCODE_FLOAT: u64 = 3;
+const MODULE_OPCODE_DOUBLE: u64 = 4;
+const MODULE_OPCODE_STRING: u64 = 5;
+
+pub(super) enum RdbValue {
+    String(Vec<u8>),
+    List(RedisList),
+    Set(RedisSet),
+    SortedSet(RedisSortedSet),
+    Hash(RedisHash),
+    Stream(Box<RedisStream>),
+    /// Module values cannot be represented without the module and are skipped.
+    Module,
+}
+
+pub(super) fn extract_millis(bytes: &[u8]) -> anyhow::Result<(u64, &[u8])> {
+    let (millis, remaining) = take(bytes, 8).context("Extract millisecond time")?;
+    Ok((
+        u64::from_le_bytes(millis.try_into().expect("8 bytes")),
+        remaining,
+    ))
+}
+
+fn parse_double(bytes: &[u8]) -> anyhow::Result<f64> {
+    std::str::from_utf8(bytes)
+        .context("UTF-8 decode double")?
+        .parse::<f64>()
+        .context(format!("Parse double: {:?}", bytes))
+}
+
+fn extract_string_double(bytes: &[u8]) -> anyhow::Result<(f64, &[u8])> {
+    let (len, remaining) = take_u8(bytes).context("Extract double length")?;
+    match len {
+        253 => Ok((f64::NAN, remaining)),
+        254 => Ok((f64::INFINITY, remaining)),
+        255 => Ok((f64::NEG_INFINITY, remaining)),
+        len => {
+            let (val, remaining) = take(remaining, len as usize)?;
+            Ok((parse_double(val)?, remaining))
+        }
+    }
+}
+
+fn extract_binary_double(bytes: &[u8]) -> anyhow::Result<(f64, &[u8])> {
+    let (val, remaining) = take(bytes, 8).context("Extract binary double")?;
+    Ok((
+        f64::from_le_bytes(val.try_into().expect("8 bytes")),
+        remaining,
+    ))
+}
+
+fn extract_strings(bytes: &[u8]) -> anyhow::Result<(Vec<Vec<u8>>, &[u8])> {
+    let (len, mut remaining) = extract_rdb_length(bytes).context("Extract element count")?;
+    let mut values = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        let (val, _remaining) = extract_rdb_string(remaining).context("Extract element")?;
+        values.push(val);
+        remaining = _remaining;
+    }
+    Ok((values, remaining))
+}
+
+fn pairs(values: Vec<Vec<u8>>) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if !values.len().is_multiple_of(2) {
+        return Err(anyhow::anyhow!(
+            "Expect an even number of elements, found {}",
+            values.len()
+        ));
+    }
+    let mut values = values.into_iter();
+    let mut res = Vec::with_capacity(values.len() / 2);
+    while let (Some(k), Some(v)) = (values.next(), values.next()) {
+        res.push((k, v));
+    }
+    Ok(res)
+}
+
+fn sorted_set_from_pairs(values: Vec<Vec<u8>>) -> anyhow::Result<RedisSortedSet> {
+    pairs(values)?
+        .into_iter()
+        .map(|(member, score)| Ok((member, parse_double(&score)?)))
+        .collect()
+}
+
+fn hash_from_pairs(pairs: Vec<(Vec<u8>, Vec<u8>)>) -> RedisHash {
+    pairs
+        .into_iter()
+        .map(|(field, value)| (field, (value, None)))
+        .collect()
+}
+
+fn extract_quicklist(bytes: &[u8], version: u8) -> anyhow::Result<(RedisList, &[u8])> {
+    let (nodes, mut remaining) = extract_rdb_length(bytes).context("Extract quicklist nodes")?;
+    let mut list = VecDeque::new();
+    for _ in 0..nodes {
+        let container = if version == 2 {
+            let (container, _remaining) =
+                extract_rdb_length(remaining).context("Extract quicklist container")?;
+            remaining = _remaining;
+            container
+        } else {
+            QUICKLIST_NODE_CONTAINER_PACKED
+        };
+
+        let (node, _remaining) = extract_rdb_string(remaining).context("Extract quicklist node")?;
+        remaining = _remaining;
+        match (version, container) {
+            (_, QUICKLIST_NODE_CONTAINER_PLAIN) => list.push_back(node),
+            (2, QUICKLIST_NODE_CONTAINER_PACKED) => list.extend(decode_listpack_strings(&node)?),
+            (_, QUICKLIST_NODE_CONTAINER_PACKED) => list.extend(decode_ziplist(&node)?),
+            (_, o) => return Err(anyhow::anyhow!("Unknown quicklist container: {}", o)),
+        }
+    }
+    Ok((list, remaining))
+}
+
+/// Reads hash fields with per-field expiry. The metadata flavour stores each TTL relative to
+/// the minimum expiry (plus one, as zero means no TTL); the pre-GA flavour stores them absolute.
+fn extract_hash_metadata(bytes: &[u8], value_type: u8) -> anyhow::Result<(RedisHash, &[u8])> {
+    let (min_expire, mut remaining) = if value_type == RDB_TYPE_HASH_METADATA {
+        extract_millis(bytes).context("Extract hash minimum expiry")?
+    } else {
+        (0, bytes)
+    };
+
+    let (len, _remaining) = extract_rdb_length(remaining).context("Extract hash length")?;
+    remaining = _remaining;
+
+    let now = SystemTime::now();
+    let mut hash = HashMap::with_capacity(len as usize);
+    for _ in 0..len {
+        let (ttl, _remaining) = extract_rdb_length(remaining).context("Extract field TTL")?;
+        let (field, _remaining) = extract_rdb_string(_remaining).context("Extract field")?;
+        let (value, _remaining) = extract_rdb_string(_remaining).context("Extract value")?;
+        remaining = _remaining;
+
+        let expiry = match (ttl, value_type) {
+            (0, _) => None,
+            (ttl, RDB_TYPE_HASH_METADATA) => Some(ttl + min_expire - 1),
+            (ttl, _) => Some(ttl),
+        }
+        .map(|millis| UNIX_EPOCH + Duration::from_millis(millis));
+        if expiry.is_some_and(|expiry| expiry <= now) {
+            continue;
+        }
+        hash.insert(field, (value, expiry));
+    }
+    Ok((hash, remaining))
+}
+
+fn extract_hash_listpack_ex(bytes: &[u8], value_type: u8) -> anyhow::Result<(RedisHash, &[u8])> {
+    let remaining = if value_type == RDB_TYPE_HASH_LISTPACK_EX {
+        extract_millis(bytes)
+            .context("Extract hash minimum expiry")?
+            .1
+    } else {
+        bytes
+    };
+    let (lp, remaining) = extract_rdb_string(remaining).context("Extract hash listpack")?;
+
+    let entries = decode_listpack(&lp)?;
+    if !entries.len().is_multiple_of(3) {
+        return Err(anyhow::anyhow!(
+            "Expect field, value, TTL triplets, found {} elements",
+            entries.len()
+        ));
+    }
+
+    let now = SystemTime::now();
+    let mut hash = HashMap::with_capacity(entries.len() / 3);
+    for triplet in entries.chunks_exact(3) {
+        let ttl = triplet[2].as_int()?;
+        let expiry = (ttl != 0).then(|| UNIX_EPOCH + Duration::from_millis(ttl as u64));
+        if expiry.is_some_and(|expiry| expiry <= now) {
+            continue;
+        }
+        hash.insert(
+            triplet[0].clone().into_bytes(),
+            (triplet[1].clone().into_bytes(), expiry),
+        );
+    }
+    Ok((hash, remaining))
+}
+
+fn extract_stream_id(bytes: &[u8]) -> anyhow::Result<(StreamEntryID, &[u8])> {
+    let (millis, remaining) = extract_rdb_length(bytes).context("Extract ID millis")?;
+    let (seq_num, remaining) = extract_rdb_length(remaining).context("Extract ID seq")?;
+    Ok((StreamEntryID { millis, seq_num }, remaining))
+}
+
+fn parse_raw_stream_id(raw: &[u8]) -> anyhow::Result<StreamEntryID> {
+    if raw.len() != STREAM_ID_SIZE {
+        return Err(anyhow::anyhow!(
+            "Stream node key must be {} bytes, found {}",
+            STREAM_ID_SIZE,
+            raw.len()
+        ));
+    }
+    Ok(StreamEntryID {
+        millis: u64::from_be_bytes(raw[..8].try_into().expect("8 bytes")),
+        seq_num: u64::from_be_bytes(raw[8..].try_into().expect("8 bytes")),
+    })
+}
+
+/// Walks the entries of one stream listpack node, whose IDs are deltas from `master_id`.
+fn parse_stream_node(
+    master_id: &StreamEntryID,
+    lp: &[u8],
+) -> anyhow::Result<Vec<(StreamEntryID, StreamEntryData)>> {
+    let mut lp = decode_listpack(lp)?.into_iter();
+    let mut next = |what: &str| lp.next().context(format!("Extract stream node {}", what));
+
+    let _count = next("count")?.as_int()?;
+    let _deleted = next("deleted count")?.as_int()?;
+    let master_fields_num = next("master field count")?.as_int()?;
+    let master_fields = (0..master_fields_num)
+        .map(|_| next("master field").map(ListpackEntry::into_bytes))
+        .collect::<anyhow::Result<Vec<Vec<u8>>>>()?;
+    let _terminator = next("master entry terminator")?;
+
+    let mut entries = Vec::new();
+    while let Ok(flags) = next("entry flags") {
+        let flags = flags.as_int()?;
+        let millis_diff = next("entry ms diff")?.as_int()?;
+        let seq_diff = next("entry seq diff")?.as_int()?;
+
+        let data = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|f| Ok((f.clone(), next("entry value")?.into_bytes())))
+                .collect::<anyhow::Result<StreamEntryData>>()?
+        } else {
+            let fields_num = next("entry field count")?.as_int()?;
+            (0..fields_num)
+                .map(|_| {
+                    let field = next("entry field")?.into_bytes();
+                    let value = next("entry value")?.into_bytes();
+                    Ok((field, value))
+                })
+                .collect::<anyhow::Result<StreamEntryData>>()?
+        };
+        let _lp_count = next("entry lp-count")?;
+
+        if flags & STREAM_ITEM_FLAG_DELETED != 0 {
+            continue;
+        }
+        entries.push((
+            StreamEntryID {
+                millis: master_id.millis.wrapping_add(millis_diff as u64),
+                seq_num: master_id.seq_num.wrapping_add(seq_diff as u64),
+            },
+            data,
+        ));
+    }
+    Ok(entries)
+}
+
+/// Reads the consumer groups of a stream into it.
+fn extract_stream_groups<'a>(
+    bytes: &'a [u8],
+    value_type: u8,
+    stream: &mut RedisStream,
+) -> anyhow::Result<&'a [u8]> {
+    let (groups, mut remaining) = extract_rdb_length(bytes).context("Extract group count")?;
+    for _ in 0..groups {
+        let (name, _remaining) = extract_rdb_string(remaining).context("Extract group name")?;
+        let (last_id, mut _remaining) = extract_stream_id(_remaining)?;
+        let mut entries_read = None;
+        if value_type >= RDB_TYPE_STREAM_LISTPACKS_2 {
+            let (_entries_read, __remaining) =
+                extract_rdb_length(_remaining).context("Extract entries read")?;
+            // An unknown count is saved as -1.
+            entries_read = Some(_entries_read).filter(|&n| n != u64::MAX);
+            _remaining = __remaining;
+        }
+        let mut group = StreamGroup::new(last_id, entries_read);
+
+        let (pel_size, mut _remaining) =
+            extract_rdb_length(_remaining).context("Extract group PEL size")?;
+        for _ in 0..pel_size {
+            let (raw_id, __remaining) = take(_remaining, STREAM_ID_SIZE)?;
+            let (delivery_time, __remaining) = extract_millis(__remaining)?;
+            let (delivery_count, __remaining) =
+                extract_rdb_length(__remaining).context("Extract delivery count")?;
+            group.pel.insert(
+                parse_raw_stream_id(raw_id)?,
+                PendingEntry {
+                    consumer: Vec::new(),
+                    delivery_time,
+                    delivery_count,
+                },
+            );
+            _remaining = __remaining;
+        }
+
+        let (consumers, mut _remaining) =
+            extract_rdb_length(_remaining).context("Extract consumer count")?;
+        for _ in 0..consumers {
+            let (name, __remaining) =
+                extract_rdb_string(_remaining).context("Extract consumer name")?;
+            let (seen_time, mut __remaining) = extract_millis(__remaining)?;
+            let mut consumer = StreamConsumer::new(seen_time);
+            consumer.active_time = Some(seen_time);
+            if value_type >= RDB_TYPE_STREAM_LISTPACKS_3 {
+                let (active_time, ___remaining) = extract_millis(__remaining)?;
+                // A consumer that never got entries is saved with -1.
+                consumer.active_time = Some(active_time).filter(|&t| t != u64::MAX);
+                __remaining = ___remaining;
+            }
+            let (pel_size, mut __remaining) =
+                extract_rdb_length(__remaining).context("Extract consumer PEL size")?;
+            for _ in 0..pel_size {
+                let (raw_id, ___remaining) = take(__remaining, STREAM_ID_SIZE)?;
+                let id = parse_raw_stream_id(raw_id)?;
+                group
+                    .pel
+                    .get_mut(&id)
+                    .context("Consumer PEL entry missing from the group PEL")?
+                    .consumer = name.clone();
+                consumer.pending.insert(id);
+                __remaining = ___remaining;
+            }
+            group.consumers.insert(name, consumer);
+            _remaining = __remaining;
+        }
+        if group.pel.values().any(|entry| entry.consumer.is_empty()) {
+            return Err(anyhow::anyhow!("Group PEL entry without consumer"));
+        }
+        stream.insert_group(name, group);
+        remaining = _remaining;
+    }
+    Ok(remaining)
+}
+
+/// Reads a stream, packing its entries into nodes of at most `limits`.
+fn extract_stream<'a>(
+    bytes: &'a [u8],
+    value_type: u8,
+    limits: &StreamNodeLimits,
+) -> anyhow::Result<(RedisStream, &'a [u8])> {
+    let (nodes, mut remaining) = extract_rdb_length(bytes).context("Extract stream nodes")?;
+
+    let mut stream = RedisStream::new();
+    for _ in 0..nodes {
+        let (key, _remaining) = extract_rdb_string(remaining).context("Extract node key")?;
+        let (lp, _remaining) = extract_rdb_string(_remaining).context("Extract node listpack")?;
+        remaining = _remaining;
+
+        let master_id = parse_raw_stream_id(&key)?;
+        for (id, data) in parse_stream_node(&master_id, &lp)? {
+            let req = ReqStreamEntryID {
+                millis: id.millis,
+                seq_num: Some(id.seq_num),
+            };
+            stream
+                .insert(Some(req), data, limits)
+                .context("Insert stream entry")?;
+        }
+    }
+
+    let (length, _remaining) = extract_rdb_length(remaining).context("Extract length")?;
+    let (last_id, mut _remaining) = extract_stream_id(_remaining).context("Extract last ID")?;
+    let mut max_deleted_id = StreamEntryID {
+        millis: 0,
+        seq_num: 0,
+    };
+    // Older versions do not track deletions, as if every entry still in the stream was all that
+    // was ever added.
+    let mut entries_added = length;
+    if value_type >= RDB_TYPE_STREAM_LISTPACKS_2 {
+        let (_first_id, __remaining) = extract_stream_id(_remaining)?;
+        let (_max_deleted_id, __remaining) = extract_stream_id(__remaining)?;
+        let (_entries_added, __remaining) =
+            extract_rdb_length(__remaining).context("Extract entries added")?;
+        max_deleted_id = _max_deleted_id;
+        entries_added = _entries_added;
+        _remaining = __remaining;
+    }
+    stream.set_metadata(last_id, max_deleted_id, entries_added);
+
+    let remaining = extract_stream_groups(_remaining, value_type, &mut stream)?;
+    Ok((stream, remaining))
+}
+
+fn skip_module_data(bytes: &[u8]) -> anyhow::Result<&[u8]> {
+    let mut remaining = bytes;
+    loop {
+        let (opcode, _remaining) =
+            extract_rdb_length(remaining).context("Extract module opcode")?;
+        remaining = match opcode {
+            MODULE_OPCODE_EOF => return Ok(_remaining),
+            MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => extract_rdb_length(_remaining)?.1,
+            MODULE_OPCODE_FLOAT => take(_remaining, 4)?.1,
+            MODULE_OPCODE_DOUBLE => take(_remaining, 8)?.1,
+            MODULE_OPCODE_STRING => extract_rdb_string(_remaining)?.1,
+            o => return Err(anyhow::anyhow!("Unknown module opcode: {}", o)),
+        };
+    }
+}
+
+/// Skips a module value or auxiliary field: module ID followed by self-describing data.
+pub(super) fn skip_module_value(bytes: &[u8]) -> anyhow::Result<&[u8]> {
+    let (module_id, remaining) = extract_rdb_length(bytes).context("Extract module ID")?;
+    eprintln!("Skipping data of module {:#x}", module_id);
+    skip_module_data(remaining)
+}
+
+pub(super) fn extract_value<'a>(
+    value_type: u8,
+    bytes: &'a [u8],
+    stream_node_limits: &StreamNodeLimits,
+) -> anyhow::Result<(RdbValue, &'a [u8])> {
+    let (value, remaining) = match value_type {
+        RDB_TYPE_STRING => {
+            let (val, remaining) = extract_rdb_string(bytes)?;
+            (RdbValue::String(val), remaining)
+        }
+        RDB_TYPE_LIST => {
+            let (values, remaining) = extract_strings(bytes)?;
+            (RdbValue::List(values.into()), remaining)
+        }
+        RDB_TYPE_LIST_ZIPLIST => {
+            let (zl, remaining) = extract_rdb_string(bytes)?;
+            (RdbValue::List(decode_ziplist(&zl)?.into()), remaining)
+        }
+        RDB_TYPE_LIST_QUICKLIST => {
+            let (list, remaining) = extract_quicklist(bytes, 1)?;
+            (RdbValue::List(list), remaining)
+        }
+        RDB_TYPE_LIST_QUICKLIST_2 => {
+            let (list, remaining) = extract_quicklist(bytes, 2)?;
+            (RdbValue::List(list), remaining)
+        }
+        RDB_TYPE_SET => {
+            let (values, remaining) = extract_strings(bytes)?;
+            (RdbValue::Set(values.into_iter().collect()), remaining)
+        }
+        RDB_TYPE_SET_INTSET => {
+            let (is, remaining) = extract_rdb_string(bytes)?;
+            let set = decode_intset(&is)?.into_iter().collect::<HashSet<_>>();
+            (RdbValue::Set(set), remaining)
+        }
+        RDB_TYPE_SET_LISTPACK => {
+            let (lp, remaining) = extract_rdb_string(bytes)?;
+            let set = decode_listpack_strings(&lp)?.into_iter().collect();
+            (RdbValue::Set(set), remaining)
+        }
+        RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+            let (len, mut remaining) = extract_rdb_length(bytes).context("Extract zset length")?;
+            let mut zset = HashMap::with_capacity(len as usize);
+            for _ in 0..len {
+                let (member, _remaining) =
+                    extract_rdb_string(remaining).context("Extract zset member")?;
+                let (score, _remaining) = if value_type == RDB_TYPE_ZSET_2 {
+                    extract_binary_double(_remaining)?
+                } else {
+                    extract_string_double(_remaining)?
+                };
+                zset.insert(member, score);
+                remaining = _remaining;
+            }
+            (RdbValue::SortedSet(zset), remaining)
+        }
+        RDB_TYPE_ZSET_ZIPLIST => {
+            let (zl, remaining) = extract_rdb_string(bytes)?;
+            (
+                RdbValue::SortedSet(sorted_set_from_pairs(decode_ziplist(&zl)?)?),
+                remaining,
+            )
+        }
+        RDB_TYPE_ZSET_LISTPACK => {
+            let (lp, remaining) = extract_rdb_string(bytes)?;
+            (
+                RdbValue::SortedSet(sorted_set_from_pairs(decode_listpack_strings(&lp)?)?),
+                remaining,
+            )
+        }
+        RDB_TYPE_HASH => {
+            let (hash, remaining) = extract_hash(bytes)?;
+            (RdbValue::Hash(hash), remaining)
+        }
+        RDB_TYPE_HASH_ZIPMAP => {
+            let (zm, remaining) = extract_rdb_string(bytes)?;
+            (
+                RdbValue::Hash(hash_from_pairs(decode_zipmap(&zm)?)),
+                remaining,
+            )
+        }
+        RDB_TYPE_HASH_ZIPLIST => {
+            let (zl, remaining) = extract_rdb_string(bytes)?;
+            (
+                RdbValue::Hash(hash_from_pairs(pairs(decode_ziplist(&zl)?)?)),
+                remaining,
+            )
+        }
+        RDB_TYPE_HASH_LISTPACK => {
+            let (lp, remaining) = extract_rdb_string(bytes)?;
+            (
+                RdbValue::Hash(hash_from_pairs(pairs(decode_listpack_strings(&lp)?)?)),
+                remaining,
+            )
+        }
+        RDB_TYPE_HASH_METADATA | RDB_TYPE_HASH_METADATA_PRE_GA => {
+            let (hash, remaining) = extract_hash_metadata(bytes, value_type)?;
+            (RdbValue::Hash(hash), remaining)
+        }
+        RDB_TYPE_HASH_LISTPACK_EX | RDB_TYPE_HASH_LISTPACK_EX_PRE_GA => {
+            let (hash, remaining) = extract_hash_listpack_ex(bytes, value_type)?;
+            (RdbValue::Hash(hash), remaining)
+        }
+        RDB_TYPE_STREAM_LISTPACKS | RDB_TYPE_STREAM_LISTPACKS_2 | RDB_TYPE_STREAM_LISTPACKS_3 => {
+            let (stream, remaining) = extract_stream(bytes, value_type, stream_node_limits)?;
+            (RdbValue::Stream(Box::new(stream)), remaining)
+        }
+        RDB_TYPE_MODULE_2 => (RdbValue::Module, skip_module_value(bytes)?),
+        RDB_TYPE_MODULE => {
+            return Err(anyhow::anyhow!(
+                "Module values of RDB type 6 cannot be loaded without the module"
+            ));
+        }
+        o => return Err(anyhow::anyhow!("Unknown RDB value type: {}", o)),
+    };
+    Ok((value, remaining))
+}
+
+fn extract_hash(bytes: &[u8]) -> anyhow::Result<(RedisHash, &[u8])> {
+    let (len, mut remaining) = extract_rdb_length(bytes).context("Extract hash length")?;
+    let mut hash = HashMap::with_capacity(len as usize);
+    for _ in 0..len {
+        let (field, _remaining) = extract_rdb_string(remaining).context("Extract field")?;
+        let (value, _remaining) = extract_rdb_string(_remaining).context("Extract value")?;
+        hash.insert(field, (value, None));
+        remaining = _remaining;
+    }
+    Ok((hash, remaining))
+}
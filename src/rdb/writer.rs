@@ -0,0 +1,731 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::db::{
+    stream::{RedisStream, StreamEntryID},
+    RedisDb, RedisHash, RedisList, RedisSet, RedisSortedSet,
+};
+
+use super::{
+    crc64::crc64,
+    listpack::{encode_listpack, ListpackEntry},
+    RdbReplInfo, OPCODE_AUX, OPCODE_EOF, OPCODE_EXPIRETIMEMS, OPCODE_RESIZEDB, OPCODE_SELECTDB,
+    RDB_TYPE_HASH, RDB_TYPE_HASH_METADATA, RDB_TYPE_LIST, RDB_TYPE_SET,
+    RDB_TYPE_STREAM_LISTPACKS_3, RDB_TYPE_STRING, RDB_TYPE_ZSET_2, REDIS_MAGIC_STRING,
+};
+
+pub(crate) const RDB_VERSION: u32 = 12;
+
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+// Strings this short are never worth compressing.
+const LZF_MIN_LENGTH: usize = 20;
+// Longest decimal string that may still fit in a 32-bit integer encoding.
+const INT_ENCODING_MAX_LENGTH: usize = 11;
+
+const STREAM_ITEM_FLAG_NONE: i64 = 0;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+pub(crate) struct RdbWriter {
+    buf: Vec<u8>,
+    compression: bool,
+    checksum: bool,
+}
+
+impl RdbWriter {
+    pub(crate) fn new(compression: bool, checksum: bool) -> Self {
+        Self {
+            buf: Vec::new(),
+            compression,
+            checksum,
+        }
+    }
+
+    fn write_length(&mut self, len: u64) {
+        if len < 1 << 6 {
+            self.buf.push(len as u8);
+        } else if len < 1 << 14 {
+            self.buf.push(0x40 | (len >> 8) as u8);
+            self.buf.push(len as u8);
+        } else if let Ok(len) = u32::try_from(len) {
+            self.buf.push(0x80);
+            self.buf.extend(len.to_be_bytes());
+        } else {
+            self.buf.push(0x81);
+            self.buf.extend(len.to_be_bytes());
+        }
+    }
+
+    fn write_encoded_integer(&mut self, s: &[u8]) -> bool {
+        if s.len() > INT_ENCODING_MAX_LENGTH {
+            return false;
+        }
+        // Only strings that would be reproduced byte for byte are integer-encoded.
+        let value = match std::str::from_utf8(s)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+        {
+            Some(v) if v.to_string().as_bytes() == s => v,
+            _ => return false,
+        };
+
+        if let Ok(v) = i8::try_from(value) {
+            self.buf.push(0xC0 | RDB_ENC_INT8);
+            self.buf.extend(v.to_le_bytes());
+        } else if let Ok(v) = i16::try_from(value) {
+            self.buf.push(0xC0 | RDB_ENC_INT16);
+            self.buf.extend(v.to_le_bytes());
+        } else if let Ok(v) = i32::try_from(value) {
+            self.buf.push(0xC0 | RDB_ENC_INT32);
+            self.buf.extend(v.to_le_bytes());
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn write_compressed_string(&mut self, s: &[u8]) -> bool {
+        match lzf::compress(s) {
+            // Require a saving of at least 4 bytes, as Redis does.
+            Ok(compressed) if compressed.len() + 4 < s.len() => {
+                self.buf.push(0xC0 | RDB_ENC_LZF);
+                self.write_length(compressed.len() as u64);
+                self.write_length(s.len() as u64);
+                self.buf.extend(compressed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn write_string(&mut self, s: &[u8]) {
+        if self.write_encoded_integer(s) {
+            return;
+        }
+        if self.compression && s.len() > LZF_MIN_LENGTH && self.write_compressed_string(s) {
+            return;
+        }
+        self.write_length(s.len() as u64);
+        self.buf.extend(s);
+    }
+
+    fn write_millis(&mut self, millis: u64) {
+        self.buf.extend(millis.to_le_bytes());
+    }
+
+    pub(crate) fn write_header(&mut self, repl_info: Option<&RdbReplInfo>) {
+        self.buf.extend(REDIS_MAGIC_STRING);
+        self.buf.extend(format!("{:04}", RDB_VERSION).as_bytes());
+
+        let ctime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        let mut aux: Vec<(&[u8], Vec<u8>)> = vec![
+            (b"redis-ver", b"7.4.0".to_vec()),
+            (b"redis-bits", b"64".to_vec()),
+            (b"ctime", ctime.to_string().into_bytes()),
+            (b"aof-base", b"0".to_vec()),
+        ];
+        if let Some(repl_info) = repl_info {
+            aux.push((b"repl-stream-db", b"0".to_vec()));
+            aux.push((
+                b"repl-id",
+                repl_info.repl_id.iter().collect::<String>().into_bytes(),
+            ));
+            aux.push((
+                b"repl-offset",
+                repl_info.repl_offset.to_string().into_bytes(),
+            ));
+        }
+        for (key, val) in aux {
+            self.buf.push(OPCODE_AUX);
+            self.write_string(key);
+            self.write_string(&val);
+        }
+    }
+
+    fn write_expiry(&mut self, expiry: SystemTime) {
+        self.buf.push(OPCODE_EXPIRETIMEMS);
+        self.write_millis(unix_millis(expiry));
+    }
+
+    fn write_binary_double(&mut self, value: f64) {
+        self.buf.extend(value.to_le_bytes());
+    }
+
+    pub(crate) fn write_database(&mut self, db_num: u32, db: &RedisDb) {
+        let now = SystemTime::now();
+        let expire_table = db
+            .expire_table
+            .iter()
+            .filter(|(_, (_, expiry))| *expiry > now)
+            .collect::<Vec<_>>();
+        let is_live = |key: &Vec<u8>| db.expire_at.get(key).is_none_or(|expiry| *expiry > now);
+        let collection_keys = db
+            .lists
+            .keys()
+            .chain(db.sets.keys())
+            .chain(db.sorted_sets.keys())
+            .chain(db.hashes.keys())
+            .chain(db.streams.keys())
+            .filter(|key| is_live(key))
+            .collect::<Vec<_>>();
+        let collection_expires = collection_keys
+            .iter()
+            .filter(|key| db.expire_at.contains_key(**key))
+            .count();
+
+        let size = db.nonexpire_table.len() + expire_table.len() + collection_keys.len();
+        if size == 0 {
+            return;
+        }
+
+        self.buf.push(OPCODE_SELECTDB);
+        self.write_length(db_num.into());
+        self.buf.push(OPCODE_RESIZEDB);
+        self.write_length(size as u64);
+        self.write_length((expire_table.len() + collection_expires) as u64);
+
+        for (key, value) in db.nonexpire_table.iter() {
+            self.buf.push(RDB_TYPE_STRING);
+            self.write_string(key);
+            self.write_string(value);
+        }
+
+        for (key, (value, expiry)) in expire_table {
+            self.write_expiry(*expiry);
+            self.buf.push(RDB_TYPE_STRING);
+            self.write_string(key);
+            self.write_string(value);
+        }
+
+        for key in collection_keys {
+            if let Some(expiry) = db.expire_at.get(key) {
+                self.write_expiry(*expiry);
+            }
+            if let Some(list) = db.lists.get(key) {
+                self.buf.push(RDB_TYPE_LIST);
+                self.write_string(key);
+                self.write_list(list);
+            } else if let Some(set) = db.sets.get(key) {
+                self.buf.push(RDB_TYPE_SET);
+                self.write_string(key);
+                self.write_set(set);
+            } else if let Some(zset) = db.sorted_sets.get(key) {
+                self.buf.push(RDB_TYPE_ZSET_2);
+                self.write_string(key);
+                self.write_sorted_set(zset);
+            } else if let Some(hash) = db.hashes.get(key) {
+                self.write_hash(key, hash, now);
+            } else if let Some(stream) = db.streams.get(key) {
+                self.buf.push(RDB_TYPE_STREAM_LISTPACKS_3);
+                self.write_string(key);
+                self.write_stream(stream);
+            }
+        }
+    }
+
+    fn write_list(&mut self, list: &RedisList) {
+        self.write_length(list.len() as u64);
+        for item in list {
+            self.write_string(item);
+        }
+    }
+
+    fn write_set(&mut self, set: &RedisSet) {
+        self.write_length(set.len() as u64);
+        for member in set {
+            self.write_string(member);
+        }
+    }
+
+    fn write_sorted_set(&mut self, zset: &RedisSortedSet) {
+        self.write_length(zset.len() as u64);
+        for (member, score) in zset {
+            self.write_string(member);
+            self.write_binary_double(*score);
+        }
+    }
+
+    /// Hashes without field TTLs use the plain encoding; otherwise every field is written with
+    /// its expiry relative to the earliest one.
+    fn write_hash(&mut self, key: &[u8], hash: &RedisHash, now: SystemTime) {
+        let fields = hash
+            .iter()
+            .filter(|(_, (_, expiry))| expiry.is_none_or(|expiry| expiry > now))
+            .collect::<Vec<_>>();
+        let min_expire = fields
+            .iter()
+            .filter_map(|(_, (_, expiry))| expiry.map(unix_millis))
+            .min();
+
+        let Some(min_expire) = min_expire else {
+            self.buf.push(RDB_TYPE_HASH);
+            self.write_string(key);
+            self.write_length(fields.len() as u64);
+            for (field, (value, _)) in fields {
+                self.write_string(field);
+                self.write_string(value);
+            }
+            return;
+        };
+
+        self.buf.push(RDB_TYPE_HASH_METADATA);
+        self.write_string(key);
+        self.write_millis(min_expire);
+        self.write_length(fields.len() as u64);
+        for (field, (value, expiry)) in fields {
+            let ttl = expiry.map_or(0, |expiry| unix_millis(expiry) - min_expire + 1);
+            self.write_length(ttl);
+            self.write_string(field);
+            self.write_string(value);
+        }
+    }
+
+    fn write_stream(&mut self, stream: &RedisStream) {
+        let nodes = stream.nodes().collect::<Vec<_>>();
+        self.write_length(nodes.len() as u64);
+        for (master_id, node) in nodes {
+            let master_fields = node.master_fields();
+
+            // Master entry: count, deleted, master fields, terminator.
+            let mut lp = vec![
+                ListpackEntry::Int(node.len() as i64),
+                ListpackEntry::Int(0),
+                ListpackEntry::Int(master_fields.len() as i64),
+            ];
+            lp.extend(master_fields.iter().map(|f| ListpackEntry::Str(f.to_vec())));
+            lp.push(ListpackEntry::Int(0));
+
+            for (id, pos) in node.entries(&master_id) {
+                let data = node.data(&master_id, pos);
+                let same_fields = data.len() == master_fields.len()
+                    && data
+                        .iter()
+                        .zip(master_fields.iter())
+                        .all(|((a, _), b)| a == b);
+                let flags = if same_fields {
+                    STREAM_ITEM_FLAG_SAMEFIELDS
+                } else {
+                    STREAM_ITEM_FLAG_NONE
+                };
+
+                lp.push(ListpackEntry::Int(flags));
+                lp.push(ListpackEntry::Int(
+                    id.millis.wrapping_sub(master_id.millis) as i64
+                ));
+                lp.push(ListpackEntry::Int(
+                    id.seq_num.wrapping_sub(master_id.seq_num) as i64,
+                ));
+                if same_fields {
+                    lp.extend(data.iter().map(|(_, v)| ListpackEntry::Str(v.clone())));
+                } else {
+                    lp.push(ListpackEntry::Int(data.len() as i64));
+                    for (k, v) in data.iter() {
+                        lp.push(ListpackEntry::Str(k.clone()));
+                        lp.push(ListpackEntry::Str(v.clone()));
+                    }
+                }
+
+                // lp-count: number of listpack elements of this entry, so it can be walked
+                // backwards.
+                let lp_count = if same_fields {
+                    3 + data.len()
+                } else {
+                    4 + data.len() * 2
+                };
+                lp.push(ListpackEntry::Int(lp_count as i64));
+            }
+
+            let mut key = Vec::with_capacity(16);
+            key.extend(master_id.millis.to_be_bytes());
+            key.extend(master_id.seq_num.to_be_bytes());
+            self.write_string(&key);
+            self.write_string(&encode_listpack(&lp));
+        }
+
+        let last_id = stream.last_entry_id();
+        let first_id = stream.first_entry_id();
+        self.write_length(stream.len() as u64);
+        self.write_length(last_id.millis);
+        self.write_length(last_id.seq_num);
+        self.write_length(first_id.as_ref().map_or(0, |id| id.millis));
+        self.write_length(first_id.as_ref().map_or(0, |id| id.seq_num));
+        let max_deleted_id = stream.max_deleted_entry_id();
+        self.write_length(max_deleted_id.millis);
+        self.write_length(max_deleted_id.seq_num);
+        self.write_length(stream.entries_added());
+        self.write_stream_groups(stream);
+    }
+
+    fn write_stream_groups(&mut self, stream: &RedisStream) {
+        let write_raw_id = |buf: &mut Vec<u8>, id: &StreamEntryID| {
+            buf.extend(id.millis.to_be_bytes());
+            buf.extend(id.seq_num.to_be_bytes());
+        };
+
+        self.write_length(stream.groups().len() as u64);
+        for (name, group) in stream.groups() {
+            self.write_string(name);
+            self.write_length(group.last_delivered.millis);
+            self.write_length(group.last_delivered.seq_num);
+            // An unknown count is saved as -1.
+            self.write_length(group.entries_read.unwrap_or(u64::MAX));
+
+            self.write_length(group.pel.len() as u64);
+            for (id, entry) in group.pel.iter() {
+                write_raw_id(&mut self.buf, id);
+                self.write_millis(entry.delivery_time);
+                self.write_length(entry.delivery_count);
+            }
+
+            self.write_length(group.consumers.len() as u64);
+            for (name, consumer) in group.consumers.iter() {
+                self.write_string(name);
+                self.write_millis(consumer.seen_time);
+                self.write_millis(consumer.active_time.unwrap_or(u64::MAX));
+                self.write_length(consumer.pending.len() as u64);
+                for id in consumer.pending.iter() {
+                    write_raw_id(&mut self.buf, id);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        self.buf.push(OPCODE_EOF);
+        // A zero checksum tells loaders to skip verification.
+        let checksum = if self.checksum {
+            crc64(0, &self.buf)
+        } else {
+            0
+        };
+        self.buf.extend(checksum.to_le_bytes());
+        self.buf
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+pub(crate) fn encode_rdb(
+    databases: &HashMap<u32, RedisDb>,
+    compression: bool,
+    checksum: bool,
+    repl_info: Option<&RdbReplInfo>,
+) -> Vec<u8> {
+    let mut writer = RdbWriter::new(compression, checksum);
+    writer.write_header(repl_info);
+
+    let mut db_nums = databases.keys().copied().collect::<Vec<u32>>();
+    db_nums.sort();
+    for db_num in db_nums {
+        writer.write_database(db_num, &databases[&db_num]);
+    }
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        command::{XReadStart, XReadStreamArg},
+        db::stream::{ReqStreamEntryID, StreamNodeLimits},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_write_length() {
+        let cases: [(u64, &[u8]); 4] = [
+            (10, &[0x0A]),
+            (700, &[0x42, 0xBC]),
+            (17000, &[0x80, 0x00, 0x00, 0x42, 0x68]),
+            (
+                u64::MAX,
+                &[0x81, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+            ),
+        ];
+        for (len, expected) in cases {
+            let mut writer = RdbWriter::new(false, true);
+            writer.write_length(len);
+            assert_eq!(writer.buf, expected, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_write_string_integer_encoding() {
+        let cases: [(&[u8], &[u8]); 5] = [
+            (b"12", &[0xC0, 12]),
+            (b"-2", &[0xC0, 0xFE]),
+            (b"1000", &[0xC1, 0xE8, 0x03]),
+            (b"100000", &[0xC2, 0xA0, 0x86, 0x01, 0x00]),
+            (b"012", &[0x03, b'0', b'1', b'2']),
+        ];
+        for (s, expected) in cases {
+            let mut writer = RdbWriter::new(false, true);
+            writer.write_string(s);
+            assert_eq!(writer.buf, expected, "string {:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_write_string_lzf() {
+        // Arrange
+        let value = b"a".repeat(50);
+        let mut writer = RdbWriter::new(true, true);
+
+        // Act
+        writer.write_string(&value);
+
+        // Assert
+        assert_eq!(writer.buf[0], 0xC3);
+        let (clen, ulen) = (writer.buf[1] as usize, writer.buf[2] as usize);
+        assert_eq!(ulen, value.len());
+        assert_eq!(writer.buf.len(), 3 + clen);
+        assert_eq!(
+            lzf::decompress(&writer.buf[3..], ulen).expect("Valid LZF"),
+            value
+        );
+    }
+
+    #[test]
+    fn test_encode_rdb_checksum() {
+        // Arrange
+        let mut db = RedisDb::new();
+        db.set(&b"foo".to_vec(), b"bar".to_vec(), None);
+        db.set(
+            &b"baz".to_vec(),
+            b"qux".to_vec(),
+            Some(SystemTime::now() + Duration::from_secs(60)),
+        );
+        let databases = HashMap::from([(0, db)]);
+
+        // Act
+        let bytes = encode_rdb(&databases, true, true, None);
+
+        // Assert
+        assert!(bytes.starts_with(b"REDIS0012"));
+        let (payload, checksum) = bytes.split_at(bytes.len() - 8);
+        assert_eq!(payload.last(), Some(&OPCODE_EOF));
+        assert_eq!(
+            u64::from_le_bytes(checksum.try_into().expect("8 bytes")),
+            crc64(0, payload)
+        );
+    }
+
+    #[test]
+    fn test_encode_rdb_roundtrip_repl_info() {
+        // Arrange
+        let repl_info = RdbReplInfo {
+            repl_id: ['a'; 40],
+            repl_offset: 1234,
+        };
+
+        // Act
+        let bytes = encode_rdb(&HashMap::new(), false, true, Some(&repl_info));
+        let rdb =
+            super::super::parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid RDB");
+
+        // Assert
+        assert_eq!(rdb.repl_info, Some(repl_info));
+        let bytes = encode_rdb(&HashMap::new(), false, true, None);
+        let rdb =
+            super::super::parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid RDB");
+        assert_eq!(rdb.repl_info, None);
+    }
+
+    #[test]
+    fn test_encode_rdb_roundtrip_strings() {
+        // Arrange
+        let mut db = RedisDb::new();
+        db.set(&b"mykey".to_vec(), b"myval".to_vec(), None);
+        db.set(&b"counter".to_vec(), b"42".to_vec(), None);
+        let databases = HashMap::from([(0, db)]);
+
+        // Act
+        let bytes = encode_rdb(&databases, true, true, None);
+        let mut rdb =
+            super::super::parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid RDB");
+
+        // Assert
+        let db = rdb.databases.get_mut(&0).expect("Database 0");
+        assert_eq!(db.get(&b"mykey".to_vec()), Some(b"myval".to_vec()));
+        assert_eq!(db.get(&b"counter".to_vec()), Some(b"42".to_vec()));
+    }
+
+    #[test]
+    fn test_encode_rdb_roundtrip_collections() {
+        // Arrange
+        let mut db = RedisDb::new();
+        let expiry = SystemTime::now() + Duration::from_secs(60);
+        db.lists
+            .insert(b"list".to_vec(), [b"a".to_vec(), b"b".to_vec()].into());
+        db.sets.insert(b"set".to_vec(), [b"1".to_vec()].into());
+        db.sorted_sets
+            .insert(b"zset".to_vec(), [(b"m".to_vec(), 1.5)].into());
+        db.hashes.insert(
+            b"hash".to_vec(),
+            [
+                (b"f1".to_vec(), (b"v1".to_vec(), None)),
+                (b"f2".to_vec(), (b"v2".to_vec(), Some(expiry))),
+            ]
+            .into(),
+        );
+        db.expire_at.insert(b"list".to_vec(), expiry);
+        let databases = HashMap::from([(0, db)]);
+
+        // Act
+        let bytes = encode_rdb(&databases, false, true, None);
+        let rdb =
+            super::super::parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid RDB");
+
+        // Assert
+        let db = &rdb.databases[&0];
+        let expected_list: RedisList = [b"a".to_vec(), b"b".to_vec()].into();
+        assert_eq!(db.lists[&b"list".to_vec()], expected_list);
+        assert_eq!(
+            unix_millis(db.expire_at[&b"list".to_vec()]),
+            unix_millis(expiry)
+        );
+        assert!(db.sets[&b"set".to_vec()].contains(b"1".as_slice()));
+        assert_eq!(db.sorted_sets[&b"zset".to_vec()][&b"m".to_vec()], 1.5);
+        let hash = &db.hashes[&b"hash".to_vec()];
+        assert_eq!(hash[&b"f1".to_vec()], (b"v1".to_vec(), None));
+        assert_eq!(
+            hash[&b"f2".to_vec()].1.map(unix_millis),
+            Some(unix_millis(expiry))
+        );
+    }
+
+    #[test]
+    fn test_encode_rdb_roundtrip_stream_groups() {
+        // Arrange
+        let mut db = RedisDb::new();
+        let key = b"stream".to_vec();
+        for seq_num in 1..=3 {
+            db.xadd(
+                &key,
+                Some(ReqStreamEntryID {
+                    millis: 1,
+                    seq_num: Some(seq_num),
+                }),
+                [(b"f".to_vec(), b"v".to_vec())].into(),
+                false,
+                &StreamNodeLimits::default(),
+            )
+            .unwrap();
+        }
+        let start = StreamEntryID {
+            millis: 0,
+            seq_num: 0,
+        };
+        db.xgroup_create(&key, b"group", Some(start), false, None)
+            .unwrap();
+        db.xgroup_createconsumer(&key, b"group", b"idle", 1000)
+            .unwrap();
+        let arg = XReadStreamArg {
+            key: key.clone(),
+            start: XReadStart::New,
+        };
+        db.xreadgroup(b"group", b"alice", Some(2), false, &[arg], 2000)
+            .unwrap();
+        let databases = HashMap::from([(0, db)]);
+
+        // Act
+        let bytes = encode_rdb(&databases, false, true, None);
+        let rdb =
+            super::super::parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid RDB");
+
+        // Assert
+        let expected = databases[&0].stream_group(&key, b"group").unwrap();
+        let actual = rdb.databases[&0].stream_group(&key, b"group").unwrap();
+        assert_eq!(actual, expected);
+        assert_eq!(actual.pel.len(), 2);
+        assert_eq!(actual.consumers[&b"idle".to_vec()].active_time, None);
+    }
+
+    #[test]
+    fn test_encode_rdb_roundtrip_stream_field_order() {
+        // Arrange
+        let mut db = RedisDb::new();
+        let key = b"stream".to_vec();
+        let pair = |f: &[u8], v: &[u8]| (f.to_vec(), v.to_vec());
+        // Spread the entries over two nodes with different master fields.
+        let limits = StreamNodeLimits {
+            max_bytes: 0,
+            max_entries: 2,
+        };
+        for (seq_num, data) in [
+            (
+                1,
+                vec![pair(b"b", b"1"), pair(b"a", b"2"), pair(b"b", b"3")],
+            ),
+            (
+                2,
+                vec![pair(b"b", b"4"), pair(b"a", b"5"), pair(b"b", b"6")],
+            ),
+            (3, vec![pair(b"a", b"7"), pair(b"b", b"8")]),
+        ] {
+            let req = ReqStreamEntryID {
+                millis: 1,
+                seq_num: Some(seq_num),
+            };
+            db.xadd(&key, Some(req), data, false, &limits).unwrap();
+        }
+        let databases = HashMap::from([(0, db)]);
+
+        // Act
+        let bytes = encode_rdb(&databases, false, true, None);
+        let rdb =
+            super::super::parse_rdb(&bytes, true, StreamNodeLimits::default()).expect("Valid RDB");
+
+        // Assert
+        let expected = databases[&0].streams[&key].entries();
+        let actual = rdb.databases[&0].streams[&key].entries();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_encode_rdb_stream_loads_with_node_limits() {
+        // Arrange
+        let mut db = RedisDb::new();
+        let key = b"stream".to_vec();
+        for seq_num in 1..=10 {
+            let req = ReqStreamEntryID {
+                millis: 1,
+                seq_num: Some(seq_num),
+            };
+            let data = vec![(b"f".to_vec(), seq_num.to_string().into_bytes())];
+            db.xadd(&key, Some(req), data, false, &StreamNodeLimits::default())
+                .unwrap();
+        }
+        let databases = HashMap::from([(0, db)]);
+        let limits = StreamNodeLimits {
+            max_bytes: 0,
+            max_entries: 3,
+        };
+
+        // Act
+        let bytes = encode_rdb(&databases, false, true, None);
+        let rdb = super::super::parse_rdb(&bytes, true, limits).expect("Valid RDB");
+
+        // Assert
+        let expected = &databases[&0].streams[&key];
+        let actual = &rdb.databases[&0].streams[&key];
+        assert_eq!(expected.info(None).radix_tree_keys, 1);
+        assert_eq!(actual.info(None).radix_tree_keys, 4);
+        assert_eq!(actual.entries(), expected.entries());
+    }
+}
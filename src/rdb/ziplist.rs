@@ -0,0 +1,124 @@
+use anyhow::Context;
+
+use super::split_checked;
+
+const ZIPLIST_HEADER_SIZE: usize = 10;
+const ZIPLIST_END: u8 = 0xFF;
+const ZIPLIST_BIG_PREVLEN: u8 = 0xFE;
+
+fn decode_int(bytes: &[u8], width: usize) -> anyhow::Result<(i64, &[u8])> {
+    let (data, remaining) = split_checked(bytes, width)?;
+    let mut le = [0u8; 8];
+    le[..width].copy_from_slice(data);
+    let shift = 64 - width as u32 * 8;
+    Ok((
+        ((u64::from_le_bytes(le) << shift) as i64) >> shift,
+        remaining,
+    ))
+}
+
+fn decode_entry(bytes: &[u8]) -> anyhow::Result<(Vec<u8>, &[u8])> {
+    let (&prevlen, remaining) = bytes.split_first().context("Extract ziplist prevlen")?;
+    let remaining = if prevlen == ZIPLIST_BIG_PREVLEN {
+        split_checked(remaining, 4)?.1
+    } else {
+        remaining
+    };
+
+    let (&enc, remaining) = remaining
+        .split_first()
+        .context("Extract ziplist entry encoding")?;
+    let (len, remaining) = match enc >> 6 {
+        0 => ((enc & 0x3F) as usize, remaining),
+        1 => {
+            let (&b1, remaining) = remaining.split_first().context("Extract 14-bit length")?;
+            ((((enc & 0x3F) as usize) << 8) | b1 as usize, remaining)
+        }
+        2 => {
+            let (len, remaining) = split_checked(remaining, 4)?;
+            (
+                u32::from_be_bytes(len.try_into().expect("4 bytes")) as usize,
+                remaining,
+            )
+        }
+        _ => {
+            let (value, remaining) = match enc {
+                0xC0 => decode_int(remaining, 2)?,
+                0xD0 => decode_int(remaining, 4)?,
+                0xE0 => decode_int(remaining, 8)?,
+                0xF0 => decode_int(remaining, 3)?,
+                0xFE => decode_int(remaining, 1)?,
+                0xF1..=0xFD => ((enc & 0x0F) as i64 - 1, remaining),
+                o => return Err(anyhow::anyhow!("Invalid ziplist encoding byte: {:#x}", o)),
+            };
+            return Ok((value.to_string().into_bytes(), remaining));
+        }
+    };
+
+    let (data, remaining) = split_checked(remaining, len)?;
+    Ok((data.to_vec(), remaining))
+}
+
+/// Decodes a legacy ziplist, rendering integer entries as decimal strings.
+pub(crate) fn decode_ziplist(bytes: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let (header, mut remaining) =
+        split_checked(bytes, ZIPLIST_HEADER_SIZE).context("Extract ziplist header")?;
+    let total_bytes = u32::from_le_bytes(header[..4].try_into().expect("4 bytes")) as usize;
+    if total_bytes != bytes.len() {
+        return Err(anyhow::anyhow!(
+            "Ziplist header declares {} bytes, found {}",
+            total_bytes,
+            bytes.len()
+        ));
+    }
+
+    let mut entries = Vec::new();
+    loop {
+        match remaining.first() {
+            Some(&ZIPLIST_END) => break,
+            Some(_) => {
+                let (entry, _remaining) = decode_entry(remaining)?;
+                entries.push(entry);
+                remaining = _remaining;
+            }
+            None => return Err(anyhow::anyhow!("Ziplist is missing its terminator")),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_ziplist() {
+        // Arrange: ["a", 5, -2, 1000] as written by Redis.
+        let mut entries = vec![
+            0x00, 0x01, b'a', // string
+            0x03, 0xF6, // immediate 5
+            0x02, 0xFE, 0xFE, // int8 -2
+            0x02, 0xC0, 0xE8, 0x03, // int16 1000
+        ];
+        let total = (ZIPLIST_HEADER_SIZE + entries.len() + 1) as u32;
+        let mut bytes = total.to_le_bytes().to_vec();
+        bytes.extend(8u32.to_le_bytes());
+        bytes.extend(4u16.to_le_bytes());
+        bytes.append(&mut entries);
+        bytes.push(ZIPLIST_END);
+
+        // Act
+        let actual = decode_ziplist(&bytes).unwrap();
+
+        // Assert
+        assert_eq!(
+            actual,
+            vec![
+                b"a".to_vec(),
+                b"5".to_vec(),
+                b"-2".to_vec(),
+                b"1000".to_vec()
+            ]
+        );
+    }
+}
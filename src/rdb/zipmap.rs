@@ -0,0 +1,65 @@
+use anyhow::Context;
+
+use super::split_checked;
+
+const ZIPMAP_BIGLEN: u8 = 254;
+const ZIPMAP_END: u8 = 255;
+
+fn decode_length(bytes: &[u8]) -> anyhow::Result<(usize, &[u8])> {
+    let (&b0, remaining) = bytes.split_first().context("Extract zipmap length")?;
+    if b0 == ZIPMAP_BIGLEN {
+        let (len, remaining) = split_checked(remaining, 4)?;
+        Ok((
+            u32::from_le_bytes(len.try_into().expect("4 bytes")) as usize,
+            remaining,
+        ))
+    } else {
+        Ok((b0 as usize, remaining))
+    }
+}
+
+/// Decodes a legacy zipmap into its key-value pairs.
+pub(crate) fn decode_zipmap(bytes: &[u8]) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let (_zmlen, mut remaining) = bytes.split_first().context("Extract zipmap zmlen")?;
+
+    let mut pairs = Vec::new();
+    loop {
+        match remaining.first() {
+            Some(&ZIPMAP_END) => break,
+            Some(_) => {
+                let (len, _remaining) = decode_length(remaining)?;
+                let (key, _remaining) = split_checked(_remaining, len)?;
+                let (len, _remaining) = decode_length(_remaining)?;
+                let (&free, _remaining) =
+                    _remaining.split_first().context("Extract zipmap free")?;
+                let (value, _remaining) = split_checked(_remaining, len)?;
+                let (_, _remaining) = split_checked(_remaining, free as usize)?;
+                pairs.push((key.to_vec(), value.to_vec()));
+                remaining = _remaining;
+            }
+            None => return Err(anyhow::anyhow!("Zipmap is missing its terminator")),
+        }
+    }
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_zipmap() {
+        // Arrange
+        let bytes = vec![
+            1, // zmlen
+            3, b'f', b'o', b'o', 3, 2, b'b', b'a', b'r', 0, 0, // foo => bar, 2 free bytes
+            ZIPMAP_END,
+        ];
+
+        // Act
+        let actual = decode_zipmap(&bytes).unwrap();
+
+        // Assert
+        assert_eq!(actual, vec![(b"foo".to_vec(), b"bar".to_vec())]);
+    }
+}
@@ -41,11 +41,7 @@ impl RespValue {
 }
 
 pub(crate) fn decode(bytes: &[u8]) -> anyhow::Result<(RespValue, &[u8])> {
-    match bytes
-        .iter()
-        .nth(0)
-        .context("RESP-encoded must not be empty")?
-    {
+    match bytes.first().context("RESP-encoded must not be empty")? {
         b'+' => {
             // simple string
             let (data, bytes) =
@@ -60,12 +56,12 @@ pub(crate) fn decode(bytes: &[u8]) -> anyhow::Result<(RespValue, &[u8])> {
             let (length_bytes, bytes) = split_by_clrf(&bytes[1..])
                 .context("Bulk string length bytes and data are delimited by CLRF")?;
             let length = bytes2usize(&length_bytes)?;
-            let (data, bytes) =
-                split_by_clrf(bytes).context("Bulk string data terminates with CLRF")?;
-            if data.len() != length {
-                return Err(anyhow::anyhow!("Inconsistent length"));
+            // The payload is binary safe, so it is delimited by its length rather than by CLRF.
+            if bytes.len() < length + 2 || &bytes[length..length + 2] != b"\r\n" {
+                return Err(anyhow::anyhow!("Bulk string data terminates with CLRF"));
             }
-            return Ok((RespValue::BulkString(data.into()), bytes));
+            let (data, bytes) = bytes.split_at(length);
+            Ok((RespValue::BulkString(data.to_vec()), &bytes[2..]))
         }
         b'*' => {
             // list
@@ -79,7 +75,7 @@ pub(crate) fn decode(bytes: &[u8]) -> anyhow::Result<(RespValue, &[u8])> {
                 values.push(value);
                 bytes = _bytes;
             }
-            return Ok((RespValue::Array(values), bytes));
+            Ok((RespValue::Array(values), bytes))
         }
         b':' => {
             // integer
@@ -89,7 +85,7 @@ pub(crate) fn decode(bytes: &[u8]) -> anyhow::Result<(RespValue, &[u8])> {
                 .context("UTF-8 decode bytes for integer string")?
                 .parse::<i64>()
                 .context("Parse integer string")?;
-            return Ok((RespValue::Integer(value), bytes));
+            Ok((RespValue::Integer(value), bytes))
         }
         _ => Err(anyhow::anyhow!("Invalid RESP-encoded value: {:?}", bytes)),
     }
@@ -120,6 +116,43 @@ pub(crate) fn decode_array_of_bulkstrings(bytes: &[u8]) -> anyhow::Result<(Vec<V
     Ok((arr, remaining))
 }
 
+/// Reads a `<prefix><number>\r\n` header line, or `None` if the line is incomplete.
+fn frame_header(bytes: &[u8], prefix: u8) -> anyhow::Result<Option<(usize, usize)>> {
+    let Some(end) = bytes.windows(2).position(|w| w == b"\r\n") else {
+        return Ok(None);
+    };
+    if bytes.first() != Some(&prefix) {
+        return Err(anyhow::anyhow!(
+            "Expect '{}', found: {:?}",
+            prefix as char,
+            &bytes[..end]
+        ));
+    }
+    Ok(Some((bytes2usize(&bytes[1..end])?, end + 2)))
+}
+
+/// Length of the command (an array of bulk strings) at the start of `bytes`, or `None` if the
+/// command is not complete yet.
+pub(crate) fn command_frame_len(bytes: &[u8]) -> anyhow::Result<Option<usize>> {
+    let Some((args, mut pos)) = frame_header(bytes, b'*')? else {
+        return Ok(None);
+    };
+    for _ in 0..args {
+        let Some((len, header_len)) = frame_header(&bytes[pos..], b'$')? else {
+            return Ok(None);
+        };
+        pos += header_len;
+        if bytes.len() < pos + len + 2 {
+            return Ok(None);
+        }
+        if &bytes[pos + len..pos + len + 2] != b"\r\n" {
+            return Err(anyhow::anyhow!("Bulk string data terminates with CLRF"));
+        }
+        pos += len + 2;
+    }
+    Ok(Some(pos))
+}
+
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
@@ -154,4 +187,21 @@ mod tests {
         let actual = RespValue::Array(vec![RespValue::BulkString(b"PING".into())]).to_bytes();
         assert_eq!(actual, b"*1\r\n$4\r\nPING\r\n");
     }
+
+    #[test]
+    fn test_decode_binary_bulk_string() {
+        let (actual, remaining) = decode(b"$4\r\na\r\nb\r\n").unwrap();
+        assert_eq!(actual, RespValue::BulkString(b"a\r\nb".into()));
+        assert!(remaining.is_empty());
+    }
+
+    #[test]
+    fn test_command_frame_len() {
+        let cmd = b"*2\r\n$3\r\nGET\r\n$3\r\na\r\n\r\n";
+        assert_eq!(command_frame_len(cmd).unwrap(), Some(cmd.len()));
+        for end in 0..cmd.len() {
+            assert_eq!(command_frame_len(&cmd[..end]).unwrap(), None, "{}", end);
+        }
+        assert!(command_frame_len(b"+OK\r\n").is_err());
+    }
 }
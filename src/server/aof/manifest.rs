@@ -0,0 +1,263 @@
+use std::fmt;
+
+use anyhow::Context;
+
+const BASE_SUFFIX: &str = "base";
+const INCR_SUFFIX: &str = "incr";
+const RDB_FORMAT_SUFFIX: &str = ".rdb";
+const AOF_FORMAT_SUFFIX: &str = ".aof";
+const MANIFEST_SUFFIX: &str = ".manifest";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AofFileType {
+    /// Snapshot of the dataset every other file applies on top of.
+    Base,
+    /// Writes logged after the base, replayed in sequence order.
+    Incr,
+    /// Superseded by a rewrite and waiting to be deleted.
+    History,
+}
+
+impl AofFileType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Base => "b",
+            Self::Incr => "i",
+            Self::History => "h",
+        }
+    }
+
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "b" => Ok(Self::Base),
+            "i" => Ok(Self::Incr),
+            "h" => Ok(Self::History),
+            _ => Err(anyhow::anyhow!("Unknown AOF file type: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AofFileInfo {
+    pub(crate) name: String,
+    pub(crate) seq: u64,
+    pub(crate) file_type: AofFileType,
+}
+
+/// Lists the files making up a multi-part AOF, in the Redis 7 format:
+///
+/// ```text
+/// file appendonly.aof.1.base.rdb seq 1 type b
+/// file appendonly.aof.1.incr.aof seq 1 type i
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Manifest {
+    pub(crate) base: Option<AofFileInfo>,
+    pub(crate) incrs: Vec<AofFileInfo>,
+    pub(crate) history: Vec<AofFileInfo>,
+    cur_base_seq: u64,
+    cur_incr_seq: u64,
+}
+
+pub(crate) fn manifest_name(appendfilename: &str) -> String {
+    format!("{}{}", appendfilename, MANIFEST_SUFFIX)
+}
+
+impl Manifest {
+    pub(crate) fn parse(s: &str) -> anyhow::Result<Self> {
+        let mut manifest = Self::default();
+        for line in s.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let words = line.split_whitespace().collect::<Vec<&str>>();
+            let (mut name, mut seq, mut file_type) = (None, None, None);
+            for pair in words.chunks(2) {
+                match pair {
+                    ["file", v] => name = Some(v.to_string()),
+                    ["seq", v] => {
+                        seq = Some(
+                            v.parse::<u64>()
+                                .context(format!("Parse seq in {:?}", line))?,
+                        )
+                    }
+                    ["type", v] => file_type = Some(AofFileType::parse(v)?),
+                    // Unknown keys are skipped for forward compatibility.
+                    [_, _] => {}
+                    _ => return Err(anyhow::anyhow!("Invalid AOF manifest line: {:?}", line)),
+                }
+            }
+
+            let info = AofFileInfo {
+                name: name.context(format!("Missing file name in {:?}", line))?,
+                seq: seq.context(format!("Missing seq in {:?}", line))?,
+                file_type: file_type.context(format!("Missing type in {:?}", line))?,
+            };
+            match info.file_type {
+                AofFileType::Base => {
+                    if manifest.base.is_some() {
+                        return Err(anyhow::anyhow!("Found duplicate base file in AOF manifest"));
+                    }
+                    manifest.cur_base_seq = info.seq;
+                    manifest.base = Some(info);
+                }
+                AofFileType::Incr => {
+                    if info.seq <= manifest.cur_incr_seq {
+                        return Err(anyhow::anyhow!(
+                            "Found a non-monotonic sequence number in AOF manifest: {:?}",
+                            line
+                        ));
+                    }
+                    manifest.cur_incr_seq = info.seq;
+                    manifest.incrs.push(info);
+                }
+                AofFileType::History => manifest.history.push(info),
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// A manifest adopting a single-file AOF from before multi-part AOFs as its base.
+    pub(crate) fn from_legacy(name: &str) -> Self {
+        Self {
+            base: Some(AofFileInfo {
+                name: name.to_string(),
+                seq: 1,
+                file_type: AofFileType::Base,
+            }),
+            cur_base_seq: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Files to replay, in order.
+    pub(crate) fn files(&self) -> impl Iterator<Item = &AofFileInfo> {
+        self.base.iter().chain(self.incrs.iter())
+    }
+
+    /// Names a new base file without adding it.
+    pub(crate) fn next_base(&self, appendfilename: &str, rdb_format: bool) -> AofFileInfo {
+        let seq = self.cur_base_seq + 1;
+        let format = if rdb_format {
+            RDB_FORMAT_SUFFIX
+        } else {
+            AOF_FORMAT_SUFFIX
+        };
+        AofFileInfo {
+            name: format!("{}.{}.{}{}", appendfilename, seq, BASE_SUFFIX, format),
+            seq,
+            file_type: AofFileType::Base,
+        }
+    }
+
+    /// Names and adds a new incremental file.
+    pub(crate) fn add_incr(&mut self, appendfilename: &str) -> AofFileInfo {
+        self.cur_incr_seq += 1;
+        let info = AofFileInfo {
+            name: format!(
+                "{}.{}.{}{}",
+                appendfilename, self.cur_incr_seq, INCR_SUFFIX, AOF_FORMAT_SUFFIX
+            ),
+            seq: self.cur_incr_seq,
+            file_type: AofFileType::Incr,
+        };
+        self.incrs.push(info.clone());
+        info
+    }
+
+    /// Installs a rewritten base. Incremental files before `first_incr_seq` are covered by the
+    /// new base, so they and the old base move to the history.
+    pub(crate) fn install_base(&mut self, base: AofFileInfo, first_incr_seq: u64) {
+        let mut superseded = self.base.take().into_iter().collect::<Vec<_>>();
+        let (old, kept) = self
+            .incrs
+            .drain(..)
+            .partition::<Vec<_>, _>(|info| info.seq < first_incr_seq);
+        superseded.extend(old);
+        self.incrs = kept;
+        self.history
+            .extend(superseded.into_iter().map(|info| AofFileInfo {
+                file_type: AofFileType::History,
+                ..info
+            }));
+
+        self.cur_base_seq = base.seq;
+        self.base = Some(base);
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for info in self.base.iter().chain(&self.history).chain(&self.incrs) {
+            writeln!(
+                f,
+                "file {} seq {} type {}",
+                info.name,
+                info.seq,
+                info.file_type.as_str()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        // Arrange
+        let s = "file appendonly.aof.1.base.rdb seq 1 type b\n\
+                 file appendonly.aof.1.incr.aof seq 1 type i\n\
+                 file appendonly.aof.2.incr.aof seq 2 type i\n";
+
+        // Act
+        let manifest = Manifest::parse(s).unwrap();
+
+        // Assert
+        assert_eq!(
+            manifest.files().map(|f| &f.name[..]).collect::<Vec<_>>(),
+            [
+                "appendonly.aof.1.base.rdb",
+                "appendonly.aof.1.incr.aof",
+                "appendonly.aof.2.incr.aof"
+            ]
+        );
+        assert_eq!(manifest.to_string(), s);
+    }
+
+    #[test]
+    fn test_manifest_install_base() {
+        // Arrange
+        let mut manifest = Manifest::parse(
+            "file appendonly.aof.1.base.rdb seq 1 type b\n\
+             file appendonly.aof.1.incr.aof seq 1 type i\n",
+        )
+        .unwrap();
+        let incr = manifest.add_incr("appendonly.aof");
+        let base = manifest.next_base("appendonly.aof", true);
+
+        // Act
+        manifest.install_base(base, incr.seq);
+
+        // Assert
+        assert_eq!(
+            manifest.to_string(),
+            "file appendonly.aof.2.base.rdb seq 2 type b\n\
+             file appendonly.aof.1.base.rdb seq 1 type h\n\
+             file appendonly.aof.1.incr.aof seq 1 type h\n\
+             file appendonly.aof.2.incr.aof seq 2 type i\n"
+        );
+    }
+
+    #[test]
+    fn test_manifest_rejects_duplicate_base() {
+        assert!(Manifest::parse(
+            "file a.1.base.rdb seq 1 type b\nfile a.2.base.rdb seq 2 type b\n"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,1168 @@
+mod manifest;
+
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::{mpsc, watch, Mutex},
+    task, time,
+};
+
+use crate::{
+    command::{Command, SetExpiry, XClaimOptions, XGroupArg},
+    db::{
+        stream::{ReqStreamEntryID, StreamTrim, TrimStrategy},
+        RedisDb,
+    },
+    rdb::{parse_rdb_preamble, writer::encode_rdb},
+    resp::command_frame_len,
+};
+
+use self::manifest::{manifest_name, AofFileInfo, AofFileType, Manifest};
+
+use super::{
+    config::{AppendFsync, ServerConfig, SharedConfig},
+    persistence::write_atomically,
+    store::{RedisStore, WriteFeedItem, WriteSink},
+};
+
+const FSYNC_PERIOD: Duration = Duration::from_secs(1);
+const REWRITE_CRON_PERIOD: Duration = Duration::from_secs(1);
+
+struct AofState {
+    policy: AppendFsync,
+    dir: PathBuf,
+    appendfilename: String,
+    manifest: Manifest,
+    /// Incremental file writes are appended to, `None` while the AOF is off.
+    incr: Option<File>,
+    /// Writes up to this sequence number are already part of the base and are not logged.
+    base_seq: u64,
+    /// Incremental file opened by a rewrite, taking over for writes after the given sequence
+    /// number.
+    pending_incr: Option<(u64, File)>,
+    rewrite_in_progress: bool,
+    written_seq: u64,
+    fsynced_seq: u64,
+    /// Total size of the base and incremental files.
+    current_size: u64,
+    /// Value of `current_size` right after the last rewrite, used for automatic rewrites.
+    base_size: u64,
+}
+
+impl AofState {
+    async fn sync(&mut self) -> anyhow::Result<()> {
+        if let Some(file) = self.incr.as_mut() {
+            if self.fsynced_seq < self.written_seq {
+                file.sync_data().await.context("Fsync AOF")?;
+            }
+        }
+        self.fsynced_seq = self.written_seq;
+        Ok(())
+    }
+
+    /// Writes the manifest to a temporary file and renames it into place.
+    async fn persist_manifest(&self) -> anyhow::Result<()> {
+        let name = manifest_name(&self.appendfilename);
+        write_atomically(
+            &self.dir.join(&name),
+            &temp_name(&name),
+            self.manifest.to_string().as_bytes(),
+        )
+        .await
+        .context("Persist AOF manifest")?;
+        fsync_dir(&self.dir).await
+    }
+
+    /// Deletes the files superseded by the last rewrite.
+    async fn delete_history(&mut self) {
+        for info in self.manifest.history.drain(..) {
+            let path = self.dir.join(&info.name);
+            if let Err(err) = fs::remove_file(&path).await {
+                eprintln!("Unable to delete AOF history file {:?}: {:?}", path, err);
+            }
+        }
+    }
+
+    /// Switches logging over to the incremental file opened by a rewrite.
+    async fn switch_to_pending(&mut self) -> anyhow::Result<()> {
+        if let Some((_, file)) = self.pending_incr.take() {
+            self.sync().await?;
+            self.incr = Some(file);
+        }
+        Ok(())
+    }
+}
+
+/// A rewrite in progress: the snapshot to write as the new base and the manifest entries to
+/// install once it is on disk.
+struct RewriteJob {
+    databases: HashMap<u32, RedisDb>,
+    seq: u64,
+    base: AofFileInfo,
+    /// Incremental files from this sequence number on hold writes made after the snapshot.
+    first_incr_seq: u64,
+    /// Whether writes are being logged, in which case the rewrite is aborted if the AOF is
+    /// turned off before it completes.
+    logging: bool,
+    rdb_preamble: bool,
+    compression: bool,
+    checksum: bool,
+}
+
+/// Logs every write the store applies to a multi-part append-only file: a base holding a
+/// snapshot of the dataset and incremental files holding the writes made since, both listed
+/// in a manifest.
+#[derive(Clone)]
+pub(crate) struct Aof {
+    state: Arc<Mutex<AofState>>,
+    /// Sequence number of the last write handled by the feed task, fsynced if the policy is
+    /// `always`.
+    handled_seq: watch::Receiver<u64>,
+}
+
+/// Queues the store's writes for the feed task.
+struct FeedSink(mpsc::UnboundedSender<WriteFeedItem>);
+
+impl WriteSink for FeedSink {
+    fn write(&self, seq: u64, cmd: &Command) {
+        let _ = self.0.send((seq, cmd.clone()));
+    }
+}
+
+impl Aof {
+    /// Starts consuming the store's writes. Nothing is logged until [`Aof::start`].
+    pub(crate) fn new(store: &mut RedisStore, config: &ServerConfig) -> Self {
+        let (feed_tx, feed) = mpsc::unbounded_channel();
+        store.add_write_sink(Arc::new(FeedSink(feed_tx)));
+
+        let state = Arc::new(Mutex::new(AofState {
+            policy: config.appendfsync,
+            dir: config.aof_dir(),
+            appendfilename: config.appendfilename().to_string(),
+            manifest: Manifest::default(),
+            incr: None,
+            base_seq: 0,
+            pending_incr: None,
+            rewrite_in_progress: false,
+            written_seq: 0,
+            fsynced_seq: 0,
+            current_size: 0,
+            base_size: 0,
+        }));
+        let (handled_tx, handled_seq) = watch::channel(store.write_seq());
+
+        tokio::spawn(feed_loop(state.clone(), feed, handled_tx));
+        tokio::spawn(fsync_loop(state.clone()));
+
+        Self { state, handled_seq }
+    }
+
+    /// Starts logging. Writes are appended to the last incremental file of an AOF that was
+    /// just loaded; otherwise the AOF is rewritten from a snapshot of the dataset.
+    pub(crate) async fn start(
+        &self,
+        store: &RedisStore,
+        config: &ServerConfig,
+        loaded: Option<LoadedAof>,
+    ) -> anyhow::Result<()> {
+        let Some(loaded) = loaded else {
+            return self.rewrite(store, config).await;
+        };
+
+        let mut state = self.state.lock().await;
+        state.dir = config.aof_dir();
+        state.appendfilename = config.appendfilename().to_string();
+        state.manifest = loaded.manifest;
+        let file = match state.manifest.incrs.last() {
+            Some(info) => {
+                let path = state.dir.join(&info.name);
+                OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .await
+                    .context(format!("Open AOF {:?}", path))?
+            }
+            None => {
+                let appendfilename = state.appendfilename.clone();
+                let info = state.manifest.add_incr(&appendfilename);
+                let file = create_incr(&state.dir, &info).await?;
+                state.persist_manifest().await?;
+                file
+            }
+        };
+        if !state.manifest.history.is_empty() {
+            state.delete_history().await;
+            state.persist_manifest().await?;
+        }
+
+        let seq = store.write_seq();
+        state.incr = Some(file);
+        state.base_seq = seq;
+        state.written_seq = seq;
+        state.fsynced_seq = seq;
+        state.current_size = loaded.size;
+        state.base_size = loaded.size;
+        eprintln!("Logging writes to {:?}", state.dir);
+        Ok(())
+    }
+
+    /// Flushes and closes the incremental file; subsequent writes are no longer logged.
+    pub(crate) async fn stop(&self) -> anyhow::Result<()> {
+        let mut state = self.state.lock().await;
+        let res = state.sync().await;
+        state.incr = None;
+        state.pending_incr = None;
+        res
+    }
+
+    pub(crate) async fn set_fsync(&self, fsync: AppendFsync) {
+        self.state.lock().await.policy = fsync;
+    }
+
+    /// Rewrites the AOF in the background, replying as soon as the snapshot is taken.
+    pub(crate) async fn bgrewrite(
+        &self,
+        store: &RedisStore,
+        config: &ServerConfig,
+    ) -> anyhow::Result<()> {
+        let job = self.begin_rewrite(store, config).await?;
+        let aof = self.clone();
+        tokio::spawn(async move {
+            match aof.finish_rewrite(job).await {
+                Ok(_) => eprintln!("Background AOF rewrite terminated with success"),
+                Err(err) => eprintln!("Background AOF rewrite error: {:?}", err),
+            }
+        });
+        Ok(())
+    }
+
+    /// Rewrites the AOF, returning once the new base is installed. When the AOF is off, this
+    /// turns it on.
+    async fn rewrite(&self, store: &RedisStore, config: &ServerConfig) -> anyhow::Result<()> {
+        let enabling = self.state.lock().await.incr.is_none();
+        let job = self.begin_rewrite(store, config).await?;
+        let res = self.finish_rewrite(job).await;
+        if res.is_ok() && enabling {
+            let state = self.state.lock().await;
+            eprintln!("Logging writes to {:?}", state.dir);
+        }
+        res
+    }
+
+    /// Takes the snapshot for a rewrite and opens the incremental file for the writes made
+    /// after it. Writes keep going to the current incremental file until the snapshot's
+    /// writes have all been handled.
+    async fn begin_rewrite(
+        &self,
+        store: &RedisStore,
+        config: &ServerConfig,
+    ) -> anyhow::Result<RewriteJob> {
+        let mut state = self.state.lock().await;
+        if state.rewrite_in_progress {
+            return Err(anyhow::anyhow!(
+                "ERR Background append only file rewriting already in progress"
+            ));
+        }
+
+        let enabled = state.incr.is_some();
+        let logging = enabled || config.appendonly;
+        if !enabled {
+            // Continue the numbering of whatever is on disk, so the new files never collide
+            // with older ones.
+            state.dir = config.aof_dir();
+            state.appendfilename = config.appendfilename().to_string();
+            fs::create_dir_all(&state.dir)
+                .await
+                .context(format!("Create AOF directory {:?}", state.dir))?;
+            state.manifest = read_manifest(&state.dir, &state.appendfilename)
+                .await?
+                .unwrap_or_default();
+        }
+
+        let snapshot = store.snapshot().await;
+        let appendfilename = state.appendfilename.clone();
+        let first_incr_seq = if logging {
+            let info = state.manifest.add_incr(&appendfilename);
+            let file = create_incr(&state.dir, &info).await?;
+            if enabled {
+                // Writes after the snapshot must survive a crash before the rewrite
+                // completes, so the new file is listed right away.
+                state.persist_manifest().await?;
+                state.pending_incr = Some((snapshot.write_seq, file));
+            } else {
+                state.incr = Some(file);
+                state.base_seq = snapshot.write_seq;
+                state.written_seq = snapshot.write_seq;
+                state.fsynced_seq = snapshot.write_seq;
+            }
+            info.seq
+        } else {
+            u64::MAX
+        };
+
+        // Data loaded since the configuration was checked, as by a full resync from a master,
+        // may not fit in commands, in which case the base is written as RDB all the same so
+        // that the AOF keeps being compacted.
+        let rdb_preamble = config.aof_use_rdb_preamble
+            || match check_commands_base(snapshot.databases.values()) {
+                Ok(()) => false,
+                Err(err) => {
+                    eprintln!("{:#}, writing the AOF base as RDB", err);
+                    true
+                }
+            };
+        state.rewrite_in_progress = true;
+        Ok(RewriteJob {
+            databases: snapshot.databases,
+            seq: snapshot.write_seq,
+            base: state.manifest.next_base(&appendfilename, rdb_preamble),
+            first_incr_seq,
+            logging,
+            rdb_preamble,
+            compression: config.rdbcompression,
+            checksum: config.rdbchecksum,
+        })
+    }
+
+    /// Writes the new base, then installs it in the manifest along with the incremental file
+    /// opened by [`Aof::begin_rewrite`] and deletes the files it supersedes.
+    async fn finish_rewrite(&self, mut job: RewriteJob) -> anyhow::Result<()> {
+        let res = self.write_base(&mut job).await;
+        if let Err(err) = res {
+            let mut state = self.state.lock().await;
+            state.rewrite_in_progress = false;
+            if job.logging && state.pending_incr.is_none() {
+                // Turning the AOF on failed.
+                state.incr = None;
+            }
+            state.pending_incr = None;
+            return Err(err);
+        }
+
+        // Writes up to the snapshot belong in the old incremental file.
+        self.wait_handled(job.seq).await;
+
+        let mut state = self.state.lock().await;
+        state.rewrite_in_progress = false;
+        let base_path = state.dir.join(&job.base.name);
+        if job.logging && state.incr.is_none() {
+            let _ = fs::remove_file(&base_path).await;
+            return Err(anyhow::anyhow!("AOF was turned off during the rewrite"));
+        }
+        state.switch_to_pending().await?;
+
+        state.manifest.install_base(job.base, job.first_incr_seq);
+        let superseded = state.manifest.history.clone();
+        state.manifest.history.clear();
+        state.persist_manifest().await?;
+        state.manifest.history = superseded;
+        state.delete_history().await;
+
+        let mut size = file_len(&base_path).await;
+        for info in state.manifest.incrs.iter() {
+            size += file_len(&state.dir.join(&info.name)).await;
+        }
+        state.current_size = size;
+        state.base_size = size;
+        Ok(())
+    }
+
+    async fn write_base(&self, job: &mut RewriteJob) -> anyhow::Result<()> {
+        let dir = self.state.lock().await.dir.clone();
+        let databases = std::mem::take(&mut job.databases);
+        let (rdb_preamble, compression, checksum) =
+            (job.rdb_preamble, job.compression, job.checksum);
+        let bytes = task::spawn_blocking(move || {
+            if rdb_preamble {
+                Ok(encode_rdb(&databases, compression, checksum, None))
+            } else {
+                encode_aof_base(&databases)
+            }
+        })
+        .await
+        .context("Join AOF rewriter")??;
+
+        write_atomically(
+            &dir.join(&job.base.name),
+            &temp_name(&job.base.name),
+            &bytes,
+        )
+        .await
+        .context("Write AOF base")
+    }
+
+    /// Periodically checks `auto-aof-rewrite-percentage` and `auto-aof-rewrite-min-size` and
+    /// rewrites the AOF in the background once it has grown enough since the last rewrite.
+    pub(crate) fn spawn_rewrite_cron(&self, store: RedisStore, config: SharedConfig) {
+        let aof = self.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(REWRITE_CRON_PERIOD);
+            loop {
+                interval.tick().await;
+                let config = config.read().await.clone();
+                if aof.should_rewrite(&config).await {
+                    eprintln!("Starting automatic rewriting of AOF");
+                    if let Err(err) = aof.bgrewrite(&store, &config).await {
+                        eprintln!("Unable to start AOF rewrite: {:?}", err);
+                    }
+                }
+            }
+        });
+    }
+
+    async fn should_rewrite(&self, config: &ServerConfig) -> bool {
+        let state = self.state.lock().await;
+        if state.incr.is_none()
+            || state.rewrite_in_progress
+            || config.auto_aof_rewrite_percentage == 0
+            || state.current_size <= config.auto_aof_rewrite_min_size
+        {
+            return false;
+        }
+
+        let base = state.base_size.max(1);
+        let growth = (state.current_size * 100 / base).saturating_sub(100);
+        growth >= config.auto_aof_rewrite_percentage
+    }
+
+    /// With `appendfsync always`, waits until the write numbered `seq` is on disk, so the
+    /// client is only acknowledged once the write is durable.
+    pub(crate) async fn wait_durable(&self, seq: u64) {
+        {
+            let state = self.state.lock().await;
+            if state.incr.is_none() || state.policy != AppendFsync::Always {
+                return;
+            }
+        }
+        self.wait_handled(seq).await;
+    }
+
+    async fn wait_handled(&self, seq: u64) {
+        let mut handled_seq = self.handled_seq.clone();
+        while *handled_seq.borrow_and_update() < seq {
+            if handled_seq.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn create_incr(dir: &Path, info: &AofFileInfo) -> anyhow::Result<File> {
+    let path = dir.join(&info.name);
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .await
+        .context(format!("Create AOF {:?}", path))
+}
+
+async fn file_len(path: &Path) -> u64 {
+    fs::metadata(path).await.map_or(0, |m| m.len())
+}
+
+/// Name of the temporary file AOF file `name` is written to before being renamed into place.
+fn temp_name(name: &str) -> String {
+    format!("temp-{}", name)
+}
+
+/// Makes renames in `dir` durable.
+async fn fsync_dir(dir: &Path) -> anyhow::Result<()> {
+    let dir = File::open(dir)
+        .await
+        .context(format!("Open AOF directory {:?}", dir))?;
+    dir.sync_all().await.context("Fsync AOF directory")
+}
+
+async fn read_manifest(dir: &Path, appendfilename: &str) -> anyhow::Result<Option<Manifest>> {
+    let path = dir.join(manifest_name(appendfilename));
+    match fs::read_to_string(&path).await {
+        Ok(s) => Manifest::parse(&s)
+            .map(Some)
+            .context(format!("Parse AOF manifest {:?}", path)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context(format!("Read AOF manifest {:?}", path)),
+    }
+}
+
+/// Appends fed writes to the incremental file. Writes that queued up meanwhile are appended
+/// together and share a single fsync.
+async fn feed_loop(
+    state: Arc<Mutex<AofState>>,
+    mut feed: mpsc::UnboundedReceiver<WriteFeedItem>,
+    handled_tx: watch::Sender<u64>,
+) {
+    while let Some(item) = feed.recv().await {
+        let mut batch = vec![item];
+        while let Ok(item) = feed.try_recv() {
+            batch.push(item);
+        }
+
+        let mut state = state.lock().await;
+        let last_seq = batch.last().map_or(0, |(seq, _)| *seq);
+        if let Err(err) = append(&mut state, batch).await {
+            // Acknowledged writes must be durable, so with `always` a failing disk is fatal.
+            eprintln!("Error writing to the AOF: {:?}", err);
+            if state.policy == AppendFsync::Always {
+                std::process::exit(1);
+            }
+        }
+        drop(state);
+
+        handled_tx.send_replace(last_seq);
+    }
+}
+
+async fn append(state: &mut AofState, batch: Vec<WriteFeedItem>) -> anyhow::Result<()> {
+    let mut buf = Vec::new();
+    let mut buf_seq = state.written_seq;
+    for (seq, cmd) in batch {
+        if state.incr.is_none() || seq <= state.base_seq {
+            continue;
+        }
+        if state
+            .pending_incr
+            .as_ref()
+            .is_some_and(|(switch_seq, _)| seq > *switch_seq)
+        {
+            flush(state, &mut buf, buf_seq).await?;
+            state.switch_to_pending().await?;
+        }
+        buf.extend(cmd.to_bytes());
+        buf_seq = seq;
+    }
+
+    flush(state, &mut buf, buf_seq).await?;
+    if state.policy == AppendFsync::Always {
+        state.sync().await?;
+    }
+    Ok(())
+}
+
+async fn flush(state: &mut AofState, buf: &mut Vec<u8>, seq: u64) -> anyhow::Result<()> {
+    let Some(file) = state.incr.as_mut() else {
+        return Ok(());
+    };
+    if !buf.is_empty() {
+        file.write_all(buf).await.context("Append to AOF")?;
+        state.written_seq = seq;
+        state.current_size += buf.len() as u64;
+        buf.clear();
+    }
+    Ok(())
+}
+
+async fn fsync_loop(state: Arc<Mutex<AofState>>) {
+    let mut interval = time::interval(FSYNC_PERIOD);
+    loop {
+        interval.tick().await;
+        let mut state = state.lock().await;
+        if state.policy != AppendFsync::EverySec {
+            continue;
+        }
+        if let Err(err) = state.sync().await {
+            eprintln!("Error fsyncing the AOF: {:?}", err);
+        }
+    }
+}
+
+/// Checks that `databases` can be written as an AOF base of commands. The commands replayed
+/// from the AOF only create strings and streams, in a single database, with no expiry on
+/// streams; other data, loaded from an RDB file, needs `aof-use-rdb-preamble`.
+pub(crate) fn check_commands_base<'a>(
+    databases: impl IntoIterator<Item = &'a RedisDb>,
+) -> anyhow::Result<()> {
+    let unsupported = |what: &str| {
+        Err(anyhow::anyhow!(
+            "ERR The dataset holds {}, which an AOF base without aof-use-rdb-preamble cannot hold",
+            what
+        ))
+    };
+    let mut non_empty = 0;
+    for db in databases {
+        if !db.lists.is_empty() {
+            return unsupported("lists");
+        }
+        if !db.sets.is_empty() {
+            return unsupported("sets");
+        }
+        if !db.sorted_sets.is_empty() {
+            return unsupported("sorted sets");
+        }
+        if !db.hashes.is_empty() {
+            return unsupported("hashes");
+        }
+        if db.streams.keys().any(|key| db.expire_at.contains_key(key)) {
+            return unsupported("streams with an expiry");
+        }
+        if !db.keys().is_empty() {
+            non_empty += 1;
+        }
+    }
+    if non_empty > 1 {
+        return unsupported("keys in several databases");
+    }
+    Ok(())
+}
+
+/// Encodes the dataset as the commands recreating it, for a base without an RDB preamble.
+fn encode_aof_base(databases: &HashMap<u32, RedisDb>) -> anyhow::Result<Vec<u8>> {
+    check_commands_base(databases.values())?;
+
+    let now = SystemTime::now();
+    let mut buf = Vec::new();
+    for db in databases.values() {
+        for (key, value) in db.nonexpire_table.iter() {
+            buf.extend(
+                Command::Set {
+                    key: key.clone(),
+                    value: value.clone(),
+                    expiry: None,
+                }
+                .to_bytes(),
+            );
+        }
+        for (key, (value, expiry)) in db.expire_table.iter() {
+            if *expiry <= now {
+                continue;
+            }
+            buf.extend(
+                Command::Set {
+                    key: key.clone(),
+                    value: value.clone(),
+                    expiry: Some(SetExpiry::at(*expiry)),
+                }
+                .to_bytes(),
+            );
+        }
+        for (key, stream) in db.streams.iter() {
+            for (entry_id, data) in stream.entries() {
+                buf.extend(
+                    Command::XAdd {
+                        key: key.clone(),
+                        entry_id: Some(ReqStreamEntryID {
+                            millis: entry_id.millis,
+                            seq_num: Some(entry_id.seq_num),
+                        }),
+                        data: data.clone(),
+                        nomkstream: false,
+                        trim: None,
+                    }
+                    .to_bytes(),
+                );
+            }
+            // An empty stream is created by adding an entry and trimming it right away, its
+            // metadata being fixed up by XSETID below.
+            if stream.len() == 0 {
+                buf.extend(
+                    Command::XAdd {
+                        key: key.clone(),
+                        entry_id: Some(ReqStreamEntryID {
+                            millis: 0,
+                            seq_num: Some(1),
+                        }),
+                        data: vec![(b"x".to_vec(), b"y".to_vec())],
+                        nomkstream: false,
+                        trim: Some(StreamTrim {
+                            strategy: TrimStrategy::MaxLen(0),
+                            approx: false,
+                            limit: None,
+                        }),
+                    }
+                    .to_bytes(),
+                );
+            }
+            buf.extend(
+                Command::XSetId {
+                    key: key.clone(),
+                    last_id: stream.last_entry_id().clone(),
+                    entries_added: Some(stream.entries_added()),
+                    max_deleted_id: Some(stream.max_deleted_entry_id().clone()),
+                }
+                .to_bytes(),
+            );
+            for (name, group) in stream.groups() {
+                let mut commands = vec![Command::XGroup(XGroupArg::Create {
+                    key: key.clone(),
+                    group: name.clone(),
+                    id: Some(group.last_delivered.clone()),
+                    mkstream: true,
+                    entries_read: group.entries_read,
+                })];
+                commands.extend(group.consumers.keys().map(|consumer| {
+                    Command::XGroup(XGroupArg::CreateConsumer {
+                        key: key.clone(),
+                        group: name.clone(),
+                        consumer: consumer.clone(),
+                    })
+                }));
+                commands.extend(group.pel.iter().map(|(id, entry)| Command::XClaim {
+                    key: key.clone(),
+                    group: name.clone(),
+                    consumer: entry.consumer.clone(),
+                    min_idle_time: 0,
+                    ids: vec![id.clone()],
+                    options: XClaimOptions {
+                        time: Some(entry.delivery_time),
+                        retry_count: Some(entry.delivery_count),
+                        force: true,
+                        just_id: true,
+                        last_id: Some(group.last_delivered.clone()),
+                        ..Default::default()
+                    },
+                }));
+                for command in commands {
+                    buf.extend(command.to_bytes());
+                }
+            }
+        }
+    }
+    Ok(buf)
+}
+
+/// Replays the commands in `bytes` from `pos` on, returning the length of the file and the
+/// number of commands. A command cut short at the end of the last file, as left by a crash
+/// mid-write, is dropped and the file truncated to the last complete command.
+async fn replay_file(
+    store: &RedisStore,
+    path: &Path,
+    bytes: &[u8],
+    mut pos: usize,
+    is_last: bool,
+) -> anyhow::Result<(u64, usize)> {
+    let mut commands = 0;
+    while pos < bytes.len() {
+        let len = command_frame_len(&bytes[pos..]).context(format!(
+            "Bad file format reading the AOF {:?} at offset {}",
+            path, pos
+        ))?;
+        let Some(len) = len else {
+            if !is_last {
+                return Err(anyhow::anyhow!(
+                    "AOF {:?} is truncated but is not the last file",
+                    path
+                ));
+            }
+            eprintln!(
+                "AOF {:?} ends with a truncated command, truncating it to {} bytes",
+                path, pos
+            );
+            let file = OpenOptions::new()
+                .write(true)
+                .open(path)
+                .await
+                .context(format!("Open AOF {:?}", path))?;
+            file.set_len(pos as u64).await.context("Truncate AOF")?;
+            file.sync_all().await.context("Fsync AOF")?;
+            return Ok((pos as u64, commands));
+        };
+
+        let (cmd, _) = Command::from_bytes(&bytes[pos..pos + len])
+            .context(format!("Parse AOF command at offset {}", pos))?;
+        if !cmd.is_write() {
+            return Err(anyhow::anyhow!("Unexpected command in AOF: {:?}", cmd));
+        }
+        store
+            .apply(cmd)
+            .await
+            .context(format!("Replay AOF command at offset {}", pos))?;
+        pos += len;
+        commands += 1;
+    }
+    Ok((pos as u64, commands))
+}
+
+/// Adopts a single-file AOF from before multi-part AOFs as the base of a new manifest. The
+/// file is looked up in the AOF directory first, then in the working directory, from which
+/// it is moved into the AOF directory.
+async fn upgrade_legacy(config: &ServerConfig) -> anyhow::Result<Option<Manifest>> {
+    let dir = config.aof_dir();
+    let appendfilename = config.appendfilename();
+    let path = dir.join(appendfilename);
+    if fs::metadata(&path).await.is_err() {
+        let old_path = config.data_dir().join(appendfilename);
+        if fs::metadata(&old_path).await.is_err() {
+            return Ok(None);
+        }
+        fs::create_dir_all(&dir)
+            .await
+            .context(format!("Create AOF directory {:?}", dir))?;
+        fs::rename(&old_path, &path)
+            .await
+            .context(format!("Move AOF {:?} into {:?}", old_path, dir))?;
+    }
+
+    eprintln!("Upgrading AOF {:?} to a multi-part AOF", path);
+    let manifest = Manifest::from_legacy(appendfilename);
+    let name = manifest_name(appendfilename);
+    write_atomically(
+        &dir.join(&name),
+        &temp_name(&name),
+        manifest.to_string().as_bytes(),
+    )
+    .await
+    .context("Persist AOF manifest")?;
+    fsync_dir(&dir).await?;
+    Ok(Some(manifest))
+}
+
+/// A dataset rebuilt from the AOF, along with the layout logging resumes with.
+pub(crate) struct LoadedAof {
+    pub(crate) store: RedisStore,
+    manifest: Manifest,
+    size: u64,
+}
+
+/// Rebuilds the dataset from the base and incremental files listed in the AOF manifest, or
+/// returns `None` if there is no AOF.
+pub(crate) async fn load_aof(config: &ServerConfig) -> anyhow::Result<Option<LoadedAof>> {
+    let dir = config.aof_dir();
+    let manifest = match read_manifest(&dir, config.appendfilename()).await? {
+        Some(manifest) => manifest,
+        None => match upgrade_legacy(config).await? {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        },
+    };
+
+    let files = manifest.files().collect::<Vec<_>>();
+    let mut store = None;
+    let (mut size, mut commands) = (0, 0);
+    for (i, info) in files.iter().enumerate() {
+        let path = dir.join(&info.name);
+        let bytes = fs::read(&path)
+            .await
+            .context(format!("Read AOF {:?}", path))?;
+
+        let mut pos = 0;
+        if info.file_type == AofFileType::Base && bytes.starts_with(b"REDIS") {
+            let (rdb, len) =
+                parse_rdb_preamble(&bytes, config.rdbchecksum, config.stream_node_limits())
+                    .context(format!("Load AOF RDB base {:?}", path))?;
+            store = Some(RedisStore::from(rdb.databases));
+            pos = len;
+        }
+        let store = store.get_or_insert_with(RedisStore::new);
+        store.set_stream_node_limits(config.stream_node_limits());
+        let (len, n) = replay_file(store, &path, &bytes, pos, i + 1 == files.len()).await?;
+        size += len;
+        commands += n;
+    }
+
+    let store = store.unwrap_or_else(RedisStore::new);
+    eprintln!("Replayed {} commands from AOF {:?}", commands, dir);
+    store.clear_dirty(store.dirty());
+    Ok(Some(LoadedAof {
+        store,
+        manifest,
+        size,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::db::{
+        stream::{StreamEntryID, StreamNodeLimits},
+        RedisValueType,
+    };
+
+    fn test_config(name: &str) -> ServerConfig {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        ServerConfig {
+            dir: Some(dir.to_string_lossy().to_string()),
+            save: Vec::new(),
+            rdbcompression: false,
+            appendonly: true,
+            appendfsync: AppendFsync::Always,
+            ..ServerConfig::default()
+        }
+    }
+
+    /// Writes a single-file AOF in the AOF directory, as left by older versions.
+    async fn write_legacy_aof(config: &ServerConfig, bytes: &[u8]) -> PathBuf {
+        let dir = config.aof_dir();
+        fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join(config.appendfilename());
+        fs::write(&path, bytes).await.unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_load_aof_recovers_truncated_tail() {
+        // Arrange
+        let config = test_config("aof-truncated");
+        let set = Command::Set {
+            key: b"foo".to_vec(),
+            value: b"bar".to_vec(),
+            expiry: None,
+        }
+        .to_bytes();
+        let mut bytes = set.clone();
+        bytes.extend(b"*3\r\n$3\r\nSET\r\n$3\r\nbaz");
+        let path = write_legacy_aof(&config, &bytes).await;
+
+        // Act
+        let loaded = load_aof(&config).await.unwrap().expect("AOF exists");
+
+        // Assert
+        assert_eq!(
+            loaded.store.get(&b"foo".to_vec()).await,
+            Some(b"bar".to_vec())
+        );
+        assert_eq!(loaded.store.get(&b"baz".to_vec()).await, None);
+        assert_eq!(fs::read(&path).await.unwrap(), set);
+        fs::remove_dir_all(config.data_dir()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_aof_rejects_corruption() {
+        let config = test_config("aof-corrupt");
+        write_legacy_aof(&config, b"*1\r\n$4\r\nPING\r\ngarbage\r\n").await;
+        assert!(load_aof(&config).await.is_err());
+        fs::remove_dir_all(config.data_dir()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_aof_rejects_truncated_base() {
+        // Arrange
+        let config = test_config("aof-truncated-base");
+        let dir = config.aof_dir();
+        fs::create_dir_all(&dir).await.unwrap();
+        fs::write(
+            dir.join("appendonly.aof.manifest"),
+            "file appendonly.aof.1.base.aof seq 1 type b\n\
+             file appendonly.aof.1.incr.aof seq 1 type i\n",
+        )
+        .await
+        .unwrap();
+        fs::write(dir.join("appendonly.aof.1.base.aof"), b"*3\r\n$3\r\nSET")
+            .await
+            .unwrap();
+        fs::write(dir.join("appendonly.aof.1.incr.aof"), b"")
+            .await
+            .unwrap();
+
+        // Act
+        let res = load_aof(&config).await;
+
+        // Assert
+        assert!(res.is_err());
+        fs::remove_dir_all(config.data_dir()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_aof_with_rdb_preamble() {
+        // Arrange
+        let config = test_config("aof-preamble");
+        let mut db = crate::db::RedisDb::new();
+        db.set(&b"foo".to_vec(), b"bar".to_vec(), None);
+        let mut bytes = encode_rdb(&HashMap::from([(0, db)]), false, true, None);
+        bytes.extend(
+            Command::Set {
+                key: b"baz".to_vec(),
+                value: b"qux".to_vec(),
+                expiry: None,
+            }
+            .to_bytes(),
+        );
+        write_legacy_aof(&config, &bytes).await;
+
+        // Act
+        let loaded = load_aof(&config).await.unwrap().expect("AOF exists");
+
+        // Assert
+        assert_eq!(
+            loaded.store.get(&b"foo".to_vec()).await,
+            Some(b"bar".to_vec())
+        );
+        assert_eq!(
+            loaded.store.get(&b"baz".to_vec()).await,
+            Some(b"qux".to_vec())
+        );
+        assert!(
+            fs::metadata(config.aof_dir().join("appendonly.aof.manifest"))
+                .await
+                .is_ok()
+        );
+        fs::remove_dir_all(config.data_dir()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_aof_logs_fed_writes() {
+        // Arrange
+        let config = test_config("aof-logged");
+        let mut store = RedisStore::new();
+        let aof = Aof::new(&mut store, &config);
+        store.set(&b"before".to_vec(), b"1".to_vec(), None).await;
+        aof.start(&store, &config, None).await.unwrap();
+
+        // Act
+        store.set(&b"after".to_vec(), b"2".to_vec(), None).await;
+        aof.wait_durable(store.write_seq()).await;
+
+        // Assert
+        let loaded = load_aof(&config).await.unwrap().expect("AOF exists");
+        assert_eq!(
+            loaded.store.get(&b"before".to_vec()).await,
+            Some(b"1".to_vec())
+        );
+        assert_eq!(
+            loaded.store.get(&b"after".to_vec()).await,
+            Some(b"2".to_vec())
+        );
+        fs::remove_dir_all(config.data_dir()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_aof_rewrite_replaces_base() {
+        // Arrange
+        let mut config = test_config("aof-rewrite");
+        config.aof_use_rdb_preamble = false;
+        let mut store = RedisStore::new();
+        let aof = Aof::new(&mut store, &config);
+        aof.start(&store, &config, None).await.unwrap();
+        store.set(&b"foo".to_vec(), b"1".to_vec(), None).await;
+        store.set(&b"foo".to_vec(), b"2".to_vec(), None).await;
+
+        // Act
+        aof.rewrite(&store, &config).await.unwrap();
+        store.set(&b"bar".to_vec(), b"3".to_vec(), None).await;
+        aof.wait_durable(store.write_seq()).await;
+
+        // Assert
+        let dir = config.aof_dir();
+        assert_eq!(
+            fs::read_to_string(dir.join("appendonly.aof.manifest"))
+                .await
+                .unwrap(),
+            "file appendonly.aof.2.base.aof seq 2 type b\n\
+             file appendonly.aof.2.incr.aof seq 2 type i\n"
+        );
+        assert!(fs::metadata(dir.join("appendonly.aof.1.incr.aof"))
+            .await
+            .is_err());
+        let loaded = load_aof(&config).await.unwrap().expect("AOF exists");
+        assert_eq!(
+            loaded.store.get(&b"foo".to_vec()).await,
+            Some(b"2".to_vec())
+        );
+        assert_eq!(
+            loaded.store.get(&b"bar".to_vec()).await,
+            Some(b"3".to_vec())
+        );
+        fs::remove_dir_all(config.data_dir()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_aof_rewrite_keeps_stream_metadata() {
+        // Arrange
+        let mut config = test_config("aof-rewrite-stream");
+        config.aof_use_rdb_preamble = false;
+        let mut store = RedisStore::new();
+        let aof = Aof::new(&mut store, &config);
+        aof.start(&store, &config, None).await.unwrap();
+        let key = b"stream".to_vec();
+        let id = StreamEntryID {
+            millis: 5,
+            seq_num: 1,
+        };
+        let entry_id = Some(ReqStreamEntryID {
+            millis: 5,
+            seq_num: Some(1),
+        });
+        let data = vec![(b"f".to_vec(), b"v".to_vec())];
+        store.xadd(&key, entry_id, data, false, None).await.unwrap();
+        store.xdel(&key, std::slice::from_ref(&id)).await;
+
+        // Act
+        aof.rewrite(&store, &config).await.unwrap();
+
+        // Assert
+        let loaded = load_aof(&config).await.unwrap().expect("AOF exists");
+        let info = loaded.store.xinfo_stream(&key, None).await.unwrap();
+        assert_eq!(info.length, 0);
+        assert_eq!(info.last_generated_id, id);
+        assert_eq!(info.max_deleted_entry_id, id);
+        assert_eq!(info.entries_added, 1);
+        fs::remove_dir_all(config.data_dir()).await.unwrap();
+    }
+
+    #[test]
+    fn test_check_commands_base() {
+        // Arrange
+        let key = b"key".to_vec();
+        let data = vec![(b"f".to_vec(), b"v".to_vec())];
+        let limits = StreamNodeLimits::default();
+        let mut strings = RedisDb::new();
+        strings.set(&key, b"v".to_vec(), None);
+        let mut list = RedisDb::new();
+        list.lists.insert(key.clone(), [b"v".to_vec()].into());
+        let mut expiring_stream = RedisDb::new();
+        expiring_stream
+            .xadd(&key, None, data.clone(), false, &limits)
+            .unwrap();
+        expiring_stream
+            .expire_at
+            .insert(key.clone(), SystemTime::now() + Duration::from_secs(60));
+
+        // Act
+        let single = check_commands_base([&strings]);
+        let with_list = check_commands_base([&strings, &list]);
+        let with_expiring_stream = check_commands_base([&expiring_stream]);
+        let several = check_commands_base([&strings, &strings]);
+
+        // Assert
+        assert!(single.is_ok());
+        assert!(with_list.unwrap_err().to_string().contains("lists"));
+        assert!(with_expiring_stream
+            .unwrap_err()
+            .to_string()
+            .contains("streams with an expiry"));
+        assert!(several
+            .unwrap_err()
+            .to_string()
+            .contains("several databases"));
+    }
+
+    #[tokio::test]
+    async fn test_aof_rewrite_falls_back_to_rdb_base() {
+        // Arrange
+        let mut config = test_config("aof-rewrite-fallback");
+        config.aof_use_rdb_preamble = false;
+        let key = b"list".to_vec();
+        let mut db = RedisDb::new();
+        db.lists.insert(key.clone(), [b"v".to_vec()].into());
+        let mut store = RedisStore::from(HashMap::from([(0, db)]));
+        let aof = Aof::new(&mut store, &config);
+
+        // Act
+        let checked = store.check_commands_base().await;
+        aof.start(&store, &config, None).await.unwrap();
+
+        // Assert
+        assert!(checked.is_err());
+        let manifest = fs::read_to_string(config.aof_dir().join("appendonly.aof.manifest"))
+            .await
+            .unwrap();
+        assert!(manifest.contains("appendonly.aof.1.base.rdb"));
+        let loaded = load_aof(&config).await.unwrap().expect("AOF exists");
+        assert!(matches!(
+            loaded.store.lookup_type(&key).await,
+            Some(RedisValueType::List)
+        ));
+        fs::remove_dir_all(config.data_dir()).await.unwrap();
+    }
+}
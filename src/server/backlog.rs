@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+/// Circular buffer of the most recently propagated bytes, letting a replica that reconnects
+/// resume from its offset instead of resyncing the whole dataset.
+#[derive(Clone)]
+pub(crate) struct ReplBacklog {
+    size: usize,
+    /// Propagated bytes, `None` until a replica first connects or after the backlog expired.
+    buf: Option<VecDeque<u8>>,
+    /// Replication offset of the first byte in `buf`.
+    start_offset: usize,
+}
+
+impl ReplBacklog {
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            size,
+            buf: None,
+            start_offset: 0,
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.buf.is_some()
+    }
+
+    /// Starts recording propagated bytes, the next of which is at `offset`.
+    pub(crate) fn create(&mut self, offset: usize) {
+        self.buf = Some(VecDeque::with_capacity(self.size));
+        self.start_offset = offset;
+    }
+
+    pub(crate) fn free(&mut self) {
+        self.buf = None;
+    }
+
+    /// Changes the size, keeping as much of the most recent history as fits.
+    pub(crate) fn resize(&mut self, size: usize) {
+        self.size = size;
+        if let Some(buf) = self.buf.as_mut() {
+            if buf.len() > size {
+                let excess = buf.len() - size;
+                buf.drain(..excess);
+                self.start_offset += excess;
+            }
+            buf.shrink_to(size);
+        }
+    }
+
+    pub(crate) fn feed(&mut self, bytes: &[u8]) {
+        let Some(buf) = self.buf.as_mut() else {
+            return;
+        };
+        buf.extend(bytes);
+        if buf.len() > self.size {
+            let excess = buf.len() - self.size;
+            buf.drain(..excess);
+            self.start_offset += excess;
+        }
+    }
+
+    /// Number of bytes held.
+    pub(crate) fn histlen(&self) -> usize {
+        self.buf.as_ref().map_or(0, |buf| buf.len())
+    }
+
+    /// Replication offset of the oldest byte held, counting from 1 as Redis reports it.
+    pub(crate) fn first_byte_offset(&self) -> usize {
+        self.start_offset + 1
+    }
+
+    /// Bytes propagated from `offset` on, or `None` if they are no longer all held.
+    pub(crate) fn range_from(&self, offset: usize) -> Option<Vec<u8>> {
+        let buf = self.buf.as_ref()?;
+        if offset < self.start_offset || offset > self.start_offset + buf.len() {
+            return None;
+        }
+        Some(buf.range(offset - self.start_offset..).copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backlog_range_from() {
+        // Arrange
+        let mut backlog = ReplBacklog::new(4);
+        backlog.create(10);
+
+        // Act
+        backlog.feed(b"abc");
+        backlog.feed(b"def");
+
+        // Assert
+        assert_eq!(backlog.histlen(), 4);
+        assert_eq!(backlog.first_byte_offset(), 13);
+        assert_eq!(backlog.range_from(12), Some(b"cdef".to_vec()));
+        assert_eq!(backlog.range_from(15), Some(b"f".to_vec()));
+        assert_eq!(backlog.range_from(16), Some(Vec::new()));
+        assert_eq!(backlog.range_from(11), None);
+        assert_eq!(backlog.range_from(17), None);
+    }
+
+    #[test]
+    fn test_backlog_inactive() {
+        let mut backlog = ReplBacklog::new(4);
+        backlog.feed(b"abc");
+        assert_eq!(backlog.range_from(0), None);
+        assert_eq!(backlog.histlen(), 0);
+    }
+
+    #[test]
+    fn test_backlog_resize_keeps_recent_bytes() {
+        // Arrange
+        let mut backlog = ReplBacklog::new(8);
+        backlog.create(0);
+        backlog.feed(b"abcdef");
+
+        // Act
+        backlog.resize(2);
+
+        // Assert
+        assert_eq!(backlog.range_from(4), Some(b"ef".to_vec()));
+        assert_eq!(backlog.range_from(3), None);
+    }
+}
@@ -0,0 +1,457 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use tokio::sync::RwLock;
+
+use crate::db::stream::StreamNodeLimits;
+
+const DEFAULT_DIR: &str = ".";
+const DEFAULT_DBFILENAME: &str = "dump.rdb";
+const DEFAULT_APPENDFILENAME: &str = "appendonly.aof";
+const DEFAULT_APPENDDIRNAME: &str = "appendonlydir";
+pub const DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE: u64 = 100;
+pub const DEFAULT_AUTO_AOF_REWRITE_MIN_SIZE: &str = "64mb";
+pub const DEFAULT_SAVE_PARAMS: &str = "3600 1 300 100 60 10000";
+pub const DEFAULT_REPL_BACKLOG_SIZE: &str = "1mb";
+pub const DEFAULT_REPL_BACKLOG_TTL: u64 = 3600;
+pub const DEFAULT_REPL_PING_REPLICA_PERIOD: u64 = 10;
+pub const DEFAULT_REPL_TIMEOUT: u64 = 60;
+pub const DEFAULT_MIN_REPLICAS_MAX_LAG: u64 = 10;
+pub const DEFAULT_REPL_DISKLESS_SYNC_DELAY: u64 = 5;
+pub const DEFAULT_STREAM_NODE_MAX_BYTES: &str = "4kb";
+pub const DEFAULT_STREAM_NODE_MAX_ENTRIES: u64 = 100;
+
+/// Snapshot automatically once `changes` writes happened and `seconds` elapsed since the last
+/// save.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaveParam {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+pub fn parse_save_params(s: &str) -> anyhow::Result<Vec<SaveParam>> {
+    let args = s.split_whitespace().collect::<Vec<&str>>();
+    if args.len() % 2 != 0 {
+        return Err(anyhow::anyhow!(
+            "Invalid save parameters, expect <seconds> <changes> pairs: {}",
+            s
+        ));
+    }
+
+    args.chunks_exact(2)
+        .map(|pair| {
+            Ok(SaveParam {
+                seconds: pair[0].parse().context("Parse save seconds")?,
+                changes: pair[1].parse().context("Parse save changes")?,
+            })
+        })
+        .collect()
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+pub fn parse_yes_no(s: &str) -> anyhow::Result<bool> {
+    match &s.to_ascii_lowercase()[..] {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(anyhow::anyhow!("Expect yes or no, found: {}", s)),
+    }
+}
+
+pub fn parse_positive(s: &str) -> anyhow::Result<u64> {
+    match s.parse::<u64>() {
+        Ok(0) => Err(anyhow::anyhow!("Expect a positive number, found: {}", s)),
+        res => res.context(format!("Parse number: {}", s)),
+    }
+}
+
+/// Parses a size such as `64mb`. As in Redis, `k`/`m`/`g` are powers of 1000 while
+/// `kb`/`mb`/`gb` are powers of 1024.
+pub fn parse_memory(s: &str) -> anyhow::Result<u64> {
+    let lower = s.to_ascii_lowercase();
+    let split = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1 << 10,
+        "m" => 1000 * 1000,
+        "mb" => 1 << 20,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1 << 30,
+        _ => return Err(anyhow::anyhow!("Invalid memory unit: {}", s)),
+    };
+    let number = number
+        .parse::<u64>()
+        .context(format!("Parse memory amount: {}", s))?;
+    number
+        .checked_mul(multiplier)
+        .context(format!("Memory amount out of range: {}", s))
+}
+
+/// When the append-only file is flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppendFsync {
+    /// After every write, before replying to the client.
+    Always,
+    /// Once per second in the background; up to a second of writes may be lost.
+    EverySec,
+    /// Whenever the operating system decides to.
+    No,
+}
+
+impl AppendFsync {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match &s.to_ascii_lowercase()[..] {
+            "always" => Ok(Self::Always),
+            "everysec" => Ok(Self::EverySec),
+            "no" => Ok(Self::No),
+            _ => Err(anyhow::anyhow!(
+                "Expect always, everysec or no, found: {}",
+                s
+            )),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::EverySec => "everysec",
+            Self::No => "no",
+        }
+    }
+}
+
+/// How a replica loads the snapshot its master sends on a full resync.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplDisklessLoad {
+    /// Save it to disk first, then load the file.
+    Disabled,
+    /// Load it straight from the socket when the dataset is empty, and from disk otherwise.
+    OnEmptyDb,
+    /// Load it straight from the socket, serving the current dataset until it is complete.
+    Swapdb,
+}
+
+impl ReplDisklessLoad {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match &s.to_ascii_lowercase()[..] {
+            "disabled" => Ok(Self::Disabled),
+            "on-empty-db" => Ok(Self::OnEmptyDb),
+            "swapdb" => Ok(Self::Swapdb),
+            _ => Err(anyhow::anyhow!(
+                "Expect disabled, on-empty-db or swapdb, found: {}",
+                s
+            )),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Disabled => "disabled",
+            Self::OnEmptyDb => "on-empty-db",
+            Self::Swapdb => "swapdb",
+        }
+    }
+}
+
+/// Configuration shared by all connections, so `CONFIG SET` applies server-wide.
+pub(crate) type SharedConfig = Arc<RwLock<ServerConfig>>;
+
+#[derive(Clone)]
+pub struct ServerConfig {
+    pub dir: Option<String>,
+    pub dbfilename: Option<String>,
+    pub save: Vec<SaveParam>,
+    pub rdbcompression: bool,
+    pub rdbchecksum: bool,
+    pub appendonly: bool,
+    pub appendfilename: Option<String>,
+    pub appenddirname: Option<String>,
+    pub appendfsync: AppendFsync,
+    pub aof_use_rdb_preamble: bool,
+    pub auto_aof_rewrite_percentage: u64,
+    pub auto_aof_rewrite_min_size: u64,
+    pub repl_backlog_size: u64,
+    /// Seconds without replicas after which the backlog is freed; 0 keeps it forever.
+    pub repl_backlog_ttl: u64,
+    /// Whether a replica answers queries while its link with the master is down.
+    pub replica_serve_stale_data: bool,
+    /// Whether a replica refuses writes from its clients. Accepted ones stay local and are not
+    /// propagated to its own replicas.
+    pub replica_read_only: bool,
+    /// Seconds between the PINGs a master sends its replicas.
+    pub repl_ping_replica_period: u64,
+    /// Seconds without hearing from the other side after which a replication link is dropped.
+    pub repl_timeout: u64,
+    /// Writes are refused unless this many replicas acknowledged within `min_replicas_max_lag`
+    /// seconds; 0 disables the check.
+    pub min_replicas_to_write: u64,
+    pub min_replicas_max_lag: u64,
+    /// Whether a master streams snapshots to its replicas rather than sending them by length.
+    pub repl_diskless_sync: bool,
+    /// Seconds a diskless transfer waits for more replicas to share it.
+    pub repl_diskless_sync_delay: u64,
+    pub repl_diskless_load: ReplDisklessLoad,
+    /// Bytes past which stream entries go to a new node; 0 disables the limit.
+    pub stream_node_max_bytes: u64,
+    /// Entries past which stream entries go to a new node; 0 disables the limit.
+    pub stream_node_max_entries: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            dbfilename: None,
+            save: parse_save_params(DEFAULT_SAVE_PARAMS).expect("Valid default save params"),
+            rdbcompression: true,
+            rdbchecksum: true,
+            appendonly: false,
+            appendfilename: None,
+            appenddirname: None,
+            appendfsync: AppendFsync::EverySec,
+            aof_use_rdb_preamble: true,
+            auto_aof_rewrite_percentage: DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE,
+            auto_aof_rewrite_min_size: parse_memory(DEFAULT_AUTO_AOF_REWRITE_MIN_SIZE)
+                .expect("Valid default size"),
+            repl_backlog_size: parse_memory(DEFAULT_REPL_BACKLOG_SIZE).expect("Valid default size"),
+            repl_backlog_ttl: DEFAULT_REPL_BACKLOG_TTL,
+            replica_serve_stale_data: true,
+            replica_read_only: true,
+            repl_ping_replica_period: DEFAULT_REPL_PING_REPLICA_PERIOD,
+            repl_timeout: DEFAULT_REPL_TIMEOUT,
+            min_replicas_to_write: 0,
+            min_replicas_max_lag: DEFAULT_MIN_REPLICAS_MAX_LAG,
+            repl_diskless_sync: false,
+            repl_diskless_sync_delay: DEFAULT_REPL_DISKLESS_SYNC_DELAY,
+            repl_diskless_load: ReplDisklessLoad::Disabled,
+            stream_node_max_bytes: parse_memory(DEFAULT_STREAM_NODE_MAX_BYTES)
+                .expect("Valid default size"),
+            stream_node_max_entries: DEFAULT_STREAM_NODE_MAX_ENTRIES,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Working directory the RDB and AOF files live in.
+    pub(crate) fn data_dir(&self) -> PathBuf {
+        PathBuf::from(self.dir.as_deref().unwrap_or(DEFAULT_DIR))
+    }
+
+    pub(crate) fn rdb_path(&self) -> PathBuf {
+        self.data_dir()
+            .join(self.dbfilename.as_deref().unwrap_or(DEFAULT_DBFILENAME))
+    }
+
+    /// Directory holding the files of the multi-part AOF.
+    pub(crate) fn aof_dir(&self) -> PathBuf {
+        self.data_dir().join(
+            self.appenddirname
+                .as_deref()
+                .unwrap_or(DEFAULT_APPENDDIRNAME),
+        )
+    }
+
+    pub(crate) fn appendfilename(&self) -> &str {
+        self.appendfilename
+            .as_deref()
+            .unwrap_or(DEFAULT_APPENDFILENAME)
+    }
+
+    /// Whether the AOF is on and rewrites its base as commands rather than in RDB format.
+    pub(crate) fn aof_commands_base(&self) -> bool {
+        self.appendonly && !self.aof_use_rdb_preamble
+    }
+
+    pub(crate) fn stream_node_limits(&self) -> StreamNodeLimits {
+        StreamNodeLimits {
+            max_bytes: self.stream_node_max_bytes as usize,
+            max_entries: self.stream_node_max_entries as usize,
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "dir" => self.dir.clone().unwrap_or_default(),
+            "dbfilename" => self.dbfilename.clone().unwrap_or_default(),
+            "save" => self
+                .save
+                .iter()
+                .map(|p| format!("{} {}", p.seconds, p.changes))
+                .collect::<Vec<String>>()
+                .join(" "),
+            "rdbcompression" => yes_no(self.rdbcompression),
+            "rdbchecksum" => yes_no(self.rdbchecksum),
+            "appendonly" => yes_no(self.appendonly),
+            "appendfilename" => self.appendfilename().to_string(),
+            "appenddirname" => self
+                .appenddirname
+                .as_deref()
+                .unwrap_or(DEFAULT_APPENDDIRNAME)
+                .to_string(),
+            "appendfsync" => self.appendfsync.as_str().to_string(),
+            "aof-use-rdb-preamble" => yes_no(self.aof_use_rdb_preamble),
+            "auto-aof-rewrite-percentage" => self.auto_aof_rewrite_percentage.to_string(),
+            "auto-aof-rewrite-min-size" => self.auto_aof_rewrite_min_size.to_string(),
+            "repl-backlog-size" => self.repl_backlog_size.to_string(),
+            "repl-backlog-ttl" => self.repl_backlog_ttl.to_string(),
+            "replica-serve-stale-data" => yes_no(self.replica_serve_stale_data),
+            "replica-read-only" => yes_no(self.replica_read_only),
+            "repl-ping-replica-period" => self.repl_ping_replica_period.to_string(),
+            "repl-timeout" => self.repl_timeout.to_string(),
+            "min-replicas-to-write" => self.min_replicas_to_write.to_string(),
+            "min-replicas-max-lag" => self.min_replicas_max_lag.to_string(),
+            "repl-diskless-sync" => yes_no(self.repl_diskless_sync),
+            "repl-diskless-sync-delay" => self.repl_diskless_sync_delay.to_string(),
+            "repl-diskless-load" => self.repl_diskless_load.as_str().to_string(),
+            "stream-node-max-bytes" => self.stream_node_max_bytes.to_string(),
+            "stream-node-max-entries" => self.stream_node_max_entries.to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Applies `CONFIG SET key value`. File locations of the append-only file can only be set at
+    /// startup.
+    pub(crate) fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        let invalid = || format!("ERR Invalid argument '{}' for CONFIG SET '{}'", value, key);
+        match key {
+            "dir" => self.dir = Some(value.to_string()),
+            "dbfilename" => self.dbfilename = Some(value.to_string()),
+            "save" => self.save = parse_save_params(value).context(invalid())?,
+            "rdbcompression" => self.rdbcompression = parse_yes_no(value).context(invalid())?,
+            "rdbchecksum" => self.rdbchecksum = parse_yes_no(value).context(invalid())?,
+            "appendonly" => self.appendonly = parse_yes_no(value).context(invalid())?,
+            "appendfsync" => self.appendfsync = AppendFsync::parse(value).context(invalid())?,
+            "aof-use-rdb-preamble" => {
+                self.aof_use_rdb_preamble = parse_yes_no(value).context(invalid())?
+            }
+            "auto-aof-rewrite-percentage" => {
+                self.auto_aof_rewrite_percentage = value.parse().context(invalid())?
+            }
+            "auto-aof-rewrite-min-size" => {
+                self.auto_aof_rewrite_min_size = parse_memory(value).context(invalid())?
+            }
+            "repl-backlog-size" => {
+                self.repl_backlog_size = parse_memory(value).context(invalid())?
+            }
+            "repl-backlog-ttl" => self.repl_backlog_ttl = value.parse().context(invalid())?,
+            "replica-serve-stale-data" => {
+                self.replica_serve_stale_data = parse_yes_no(value).context(invalid())?
+            }
+            "replica-read-only" => {
+                self.replica_read_only = parse_yes_no(value).context(invalid())?
+            }
+            "repl-ping-replica-period" => {
+                self.repl_ping_replica_period = parse_positive(value).context(invalid())?
+            }
+            "repl-timeout" => self.repl_timeout = parse_positive(value).context(invalid())?,
+            "min-replicas-to-write" => {
+                self.min_replicas_to_write = value.parse().context(invalid())?
+            }
+            "min-replicas-max-lag" => {
+                self.min_replicas_max_lag = value.parse().context(invalid())?
+            }
+            "repl-diskless-sync" => {
+                self.repl_diskless_sync = parse_yes_no(value).context(invalid())?
+            }
+            "repl-diskless-sync-delay" => {
+                self.repl_diskless_sync_delay = value.parse().context(invalid())?
+            }
+            "repl-diskless-load" => {
+                self.repl_diskless_load = ReplDisklessLoad::parse(value).context(invalid())?
+            }
+            "stream-node-max-bytes" => {
+                self.stream_node_max_bytes = parse_memory(value).context(invalid())?
+            }
+            "stream-node-max-entries" => {
+                self.stream_node_max_entries = value.parse().context(invalid())?
+            }
+            "appendfilename" | "appenddirname" => {
+                return Err(anyhow::anyhow!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config",
+                    key
+                ));
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                    key
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_save_params() {
+        // Arrange
+        let expected = vec![
+            SaveParam {
+                seconds: 3600,
+                changes: 1,
+            },
+            SaveParam {
+                seconds: 300,
+                changes: 100,
+            },
+        ];
+
+        // Act
+        let actual = parse_save_params("3600 1 300 100").unwrap();
+
+        // Assert
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_save_params_empty_disables() {
+        assert!(parse_save_params("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_save_params_odd_count() {
+        assert!(parse_save_params("3600 1 300").is_err());
+    }
+
+    #[test]
+    fn test_config_set() {
+        // Arrange
+        let mut config = ServerConfig::default();
+
+        // Act
+        config.set("appendonly", "yes").unwrap();
+        config.set("appendfsync", "always").unwrap();
+        config.set("replica-read-only", "no").unwrap();
+
+        // Assert
+        assert_eq!(config.get("appendonly"), Some("yes".to_string()));
+        assert_eq!(config.get("appendfsync"), Some("always".to_string()));
+        assert_eq!(config.get("replica-read-only"), Some("no".to_string()));
+        assert_eq!(
+            config.get("appendfilename"),
+            Some("appendonly.aof".to_string())
+        );
+        assert!(config.set("appendfsync", "sometimes").is_err());
+        assert!(config.set("appendfilename", "other.aof").is_err());
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("64mb").unwrap(), 64 << 20);
+        assert_eq!(parse_memory("1k").unwrap(), 1000);
+        assert_eq!(parse_memory("1GB").unwrap(), 1 << 30);
+        assert_eq!(parse_memory("100").unwrap(), 100);
+        assert!(parse_memory("10xb").is_err());
+    }
+}
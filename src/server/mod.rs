@@ -1,37 +1,99 @@
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::command::Command;
 use crate::resp::RespValue;
 use anyhow::Context;
 use async_trait::async_trait;
-use tokio::io::AsyncReadExt;
 use tokio::{io::AsyncWriteExt, net::TcpStream};
 
-use self::store::RedisStore;
+use self::{backlog::ReplBacklog, store::RedisStore};
 
-pub mod master;
-pub mod replica;
+mod aof;
+mod backlog;
+pub mod config;
+mod persistence;
+pub mod redis_server;
+mod replica;
+mod replication;
 mod store;
 
+const DEFAULT_REPL_BACKLOG_SIZE: usize = 1 << 20;
+
 #[derive(Clone)]
 pub(crate) struct MasterInfo {
     pub(crate) repl_id: [char; 40],
     pub(crate) repl_offset: usize,
+    /// Replication ID of the previous master, still accepted for partial resyncs of history
+    /// up to `second_repl_offset`.
+    pub(crate) repl_id2: [char; 40],
+    pub(crate) second_repl_offset: Option<usize>,
+    pub(crate) backlog: ReplBacklog,
 }
 
 impl MasterInfo {
     pub fn new() -> Self {
         Self {
-            repl_id: "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb"
-                .chars()
-                .collect::<Vec<char>>()
-                .try_into()
-                .expect("40 characters"),
+            repl_id: new_repl_id(),
             repl_offset: 0,
+            repl_id2: ['0'; 40],
+            second_repl_offset: None,
+            backlog: ReplBacklog::new(DEFAULT_REPL_BACKLOG_SIZE),
+        }
+    }
+
+    /// Switches to the history `new_repl_id`, as a promoted replica does. The previous history
+    /// stays known up to the current offset, so its replicas can still continue partially.
+    pub(crate) fn shift_repl_id(&mut self, new_repl_id: [char; 40]) {
+        self.repl_id2 = self.repl_id;
+        self.second_repl_offset = Some(self.repl_offset + 1);
+        self.repl_id = new_repl_id;
+    }
+
+    /// Bytes missing from a replica that has the history of `repl_id` up to `psync_offset`
+    /// (the offset of the next byte it expects, counting from 1), or `None` if that history
+    /// is unknown or no longer in the backlog and a full resync is needed.
+    pub(crate) fn partial_sync_backlog(
+        &self,
+        repl_id: &[char; 40],
+        psync_offset: usize,
+    ) -> Option<Vec<u8>> {
+        let known_history = *repl_id == self.repl_id
+            || (*repl_id == self.repl_id2
+                && self
+                    .second_repl_offset
+                    .is_some_and(|offset| psync_offset <= offset));
+        if !known_history || psync_offset == 0 {
+            return None;
         }
+        self.backlog.range_from(psync_offset - 1)
     }
 }
 
+/// A fresh replication ID: 40 random hex characters.
+pub(crate) fn new_repl_id() -> [char; 40] {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let hex = (0..3u64)
+        .map(|i| {
+            // Every RandomState is seeded with different random keys.
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(i);
+            hasher.write_u128(nanos);
+            format!("{:016x}", hasher.finish())
+        })
+        .collect::<String>();
+    hex.chars()
+        .take(40)
+        .collect::<Vec<char>>()
+        .try_into()
+        .expect("40 characters")
+}
+
 async fn send_resp(socket: &mut TcpStream, value: &RespValue) {
     let buf = value.to_bytes();
     socket
@@ -57,34 +119,22 @@ async fn send_simple_error(socket: &mut TcpStream, msg: &str) {
     send_resp(socket, &RespValue::SimpleError(msg.to_string())).await;
 }
 
-async fn send_cmd(socket: &mut TcpStream, cmd: &Command) {
-    let send_buf = cmd.to_bytes();
-    socket
-        .write_all(&send_buf)
-        .await
-        .context(format!("Send {:?}", cmd))
-        .unwrap();
-}
-
-async fn assert_recv_simple_string(buf: &mut [u8], socket: &mut TcpStream, expected: &str) {
-    socket.read(buf).await.context("Read from client").unwrap();
-    let expected_encoded = &RespValue::SimpleString(expected.to_string()).to_bytes();
-    assert!(
-        buf.starts_with(expected_encoded),
-        "Expected: {:?}; Found: {:?}",
-        expected_encoded,
-        &buf[..expected_encoded.len()]
-    );
-}
-
 #[async_trait]
 pub trait RedisServerHandler {
     async fn handle_conn(&mut self, mut socket: TcpStream);
 }
 
-async fn handle_info(socket: &mut TcpStream, role: &str, master_info: &MasterInfo) {
+/// Lines of the replication section of INFO, with `fields` specific to the role.
+fn replication_info(
+    role: &str,
+    master_info: &MasterInfo,
+    fields: Vec<(String, String)>,
+) -> Vec<u8> {
     let mut info_map = HashMap::<Vec<u8>, Vec<u8>>::new();
     info_map.insert(b"role".to_vec(), role.as_bytes().to_vec());
+    for (key, value) in fields {
+        info_map.insert(key.as_bytes().to_vec(), value.into_bytes());
+    }
 
     info_map.insert(
         b"master_replid".to_vec(),
@@ -99,7 +149,36 @@ async fn handle_info(socket: &mut TcpStream, role: &str, master_info: &MasterInf
         b"master_repl_offset".to_vec(),
         master_info.repl_offset.to_string().into(),
     );
-    let lines = info_map
+    info_map.insert(
+        b"master_replid2".to_vec(),
+        master_info.repl_id2.iter().collect::<String>().into(),
+    );
+    info_map.insert(
+        b"second_repl_offset".to_vec(),
+        master_info
+            .second_repl_offset
+            .map_or(-1, |offset| offset as i64)
+            .to_string()
+            .into(),
+    );
+    let backlog = &master_info.backlog;
+    info_map.insert(
+        b"repl_backlog_active".to_vec(),
+        (backlog.is_active() as u8).to_string().into(),
+    );
+    info_map.insert(
+        b"repl_backlog_size".to_vec(),
+        backlog.size().to_string().into(),
+    );
+    info_map.insert(
+        b"repl_backlog_first_byte_offset".to_vec(),
+        backlog.first_byte_offset().to_string().into(),
+    );
+    info_map.insert(
+        b"repl_backlog_histlen".to_vec(),
+        backlog.histlen().to_string().into(),
+    );
+    info_map
         .into_iter()
         .fold(Vec::new(), |mut acc, (mut k, mut v)| {
             if !acc.is_empty() {
@@ -109,16 +188,7 @@ async fn handle_info(socket: &mut TcpStream, role: &str, master_info: &MasterInf
             acc.push(b':');
             acc.append(&mut v);
             acc
-        });
-
-    let res = RespValue::BulkString(lines);
-    let buf = res.to_bytes();
-
-    socket
-        .write_all(&buf)
-        .await
-        .context("Send INFO response")
-        .unwrap();
+        })
 }
 
 async fn handle_get(socket: &mut TcpStream, store: &RedisStore, key: &Vec<u8>) {
@@ -126,7 +196,7 @@ async fn handle_get(socket: &mut TcpStream, store: &RedisStore, key: &Vec<u8>) {
 
     let value = store.get(key).await;
 
-    let res = value.map_or(RespValue::NullBulkString, |x| RespValue::BulkString(x));
+    let res = value.map_or(RespValue::NullBulkString, RespValue::BulkString);
     let buf = res.to_bytes();
 
     socket
@@ -141,7 +211,7 @@ async fn handle_ping(socket: &mut TcpStream) {
     send_simple_string(socket, "PONG").await;
 }
 
-async fn handle_echo(socket: &mut TcpStream, val: &Vec<u8>) {
+async fn handle_echo(socket: &mut TcpStream, val: &[u8]) {
     eprintln!("Handling ECHO from client");
     send_bulk_string(socket, val).await;
 }
@@ -153,3 +223,62 @@ async fn handle_type(socket: &mut TcpStream, store: &RedisStore, key: &Vec<u8>)
         .map_or("none".to_string(), |t| t.to_string());
     send_simple_string(socket, &res).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_sync_backlog() {
+        // Arrange
+        let mut master_info = MasterInfo::new();
+        master_info.backlog.create(0);
+        master_info.backlog.feed(b"abcdef");
+        master_info.repl_offset = 6;
+        let repl_id = master_info.repl_id;
+        let other_id = ['1'; 40];
+
+        // Act & Assert
+        assert_eq!(
+            master_info.partial_sync_backlog(&repl_id, 5),
+            Some(b"ef".to_vec())
+        );
+        assert_eq!(
+            master_info.partial_sync_backlog(&repl_id, 7),
+            Some(Vec::new())
+        );
+        assert_eq!(master_info.partial_sync_backlog(&repl_id, 8), None);
+        assert_eq!(master_info.partial_sync_backlog(&other_id, 5), None);
+
+        master_info.repl_id2 = other_id;
+        master_info.second_repl_offset = Some(5);
+        assert_eq!(
+            master_info.partial_sync_backlog(&other_id, 5),
+            Some(b"ef".to_vec())
+        );
+        assert_eq!(master_info.partial_sync_backlog(&other_id, 6), None);
+    }
+
+    #[test]
+    fn test_shift_repl_id() {
+        // Arrange
+        let mut master_info = MasterInfo::new();
+        master_info.backlog.create(0);
+        master_info.backlog.feed(b"abcdef");
+        master_info.repl_offset = 6;
+        let old_id = master_info.repl_id;
+
+        // Act
+        master_info.shift_repl_id(new_repl_id());
+
+        // Assert
+        assert_ne!(master_info.repl_id, old_id);
+        assert!(master_info.repl_id.iter().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(master_info.repl_id2, old_id);
+        assert_eq!(
+            master_info.partial_sync_backlog(&old_id, 7),
+            Some(Vec::new())
+        );
+        assert_eq!(master_info.partial_sync_backlog(&old_id, 8), None);
+    }
+}
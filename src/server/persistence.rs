@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+    sync::Mutex,
+    task, time,
+};
+
+use crate::{
+    db::RedisDb,
+    rdb::{writer::encode_rdb, RdbReplInfo},
+};
+
+use super::{
+    config::{ServerConfig, SharedConfig},
+    replication::SharedReplication,
+    store::{RedisStore, Snapshot},
+};
+
+// How long to wait before retrying an automatic save after a failed one.
+const BGSAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+const SAVE_CRON_PERIOD: Duration = Duration::from_secs(1);
+
+struct SaveState {
+    last_save: SystemTime,
+    last_bgsave_try: SystemTime,
+    last_bgsave_ok: bool,
+    bgsave_in_progress: bool,
+}
+
+#[derive(Clone)]
+pub(crate) struct RdbPersistence {
+    state: Arc<Mutex<SaveState>>,
+    replication: SharedReplication,
+}
+
+/// Writes `bytes` to the temporary file `tmp_name` next to `path` and renames it into place, so
+/// readers never observe a partially written file. Used for RDB dumps and AOF files alike.
+pub(crate) async fn write_atomically(
+    path: &Path,
+    tmp_name: &str,
+    bytes: &[u8],
+) -> anyhow::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let tmp_path = dir.join(tmp_name);
+    let write = async {
+        let mut file = File::create(&tmp_path)
+            .await
+            .context(format!("Create temporary file {:?}", tmp_path))?;
+        file.write_all(bytes)
+            .await
+            .context(format!("Write {:?}", tmp_path))?;
+        file.sync_all()
+            .await
+            .context(format!("Fsync {:?}", tmp_path))?;
+        fs::rename(&tmp_path, path)
+            .await
+            .context(format!("Rename {:?} into {:?}", tmp_path, path))
+    };
+
+    let res = write.await;
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path).await;
+    }
+    res
+}
+
+async fn write_rdb(
+    databases: HashMap<u32, RedisDb>,
+    repl_info: RdbReplInfo,
+    config: &ServerConfig,
+) -> anyhow::Result<()> {
+    let path = config.rdb_path();
+    let (compression, checksum) = (config.rdbcompression, config.rdbchecksum);
+    let bytes = task::spawn_blocking(move || {
+        encode_rdb(&databases, compression, checksum, Some(&repl_info))
+    })
+    .await
+    .context("Join RDB writer")?;
+    let tmp_name = format!("temp-{}.rdb", std::process::id());
+    write_atomically(&path, &tmp_name, &bytes)
+        .await
+        .context("Write RDB")
+}
+
+impl RdbPersistence {
+    pub(crate) fn new(replication: SharedReplication) -> Self {
+        let now = SystemTime::now();
+        Self {
+            state: Arc::new(Mutex::new(SaveState {
+                last_save: now,
+                last_bgsave_try: now,
+                last_bgsave_ok: true,
+                bgsave_in_progress: false,
+            })),
+            replication,
+        }
+    }
+
+    /// Takes a snapshot along with the point of the replication history it was taken at.
+    async fn snapshot(&self, store: &RedisStore) -> (Snapshot, RdbReplInfo) {
+        store
+            .snapshot_with(|_| self.replication.lock().unwrap().rdb_repl_info())
+            .await
+    }
+
+    /// Unix time in seconds of the last successful save.
+    pub(crate) async fn lastsave(&self) -> u64 {
+        self.state
+            .lock()
+            .await
+            .last_save
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs()
+    }
+
+    /// Saves the dataset before returning.
+    pub(crate) async fn save(
+        &self,
+        store: &RedisStore,
+        config: &ServerConfig,
+    ) -> anyhow::Result<()> {
+        let mut state = self.state.lock().await;
+        if state.bgsave_in_progress {
+            return Err(anyhow::anyhow!("ERR Background save already in progress"));
+        }
+
+        let (snapshot, repl_info) = self.snapshot(store).await;
+        write_rdb(snapshot.databases, repl_info, config)
+            .await
+            .context("ERR Error saving DB on disk")?;
+
+        store.clear_dirty(snapshot.dirty);
+        state.last_save = SystemTime::now();
+        Ok(())
+    }
+
+    /// Takes a snapshot of the dataset and saves it in the background.
+    pub(crate) async fn bgsave(
+        &self,
+        store: &RedisStore,
+        config: &ServerConfig,
+    ) -> anyhow::Result<()> {
+        let mut state = self.state.lock().await;
+        if state.bgsave_in_progress {
+            return Err(anyhow::anyhow!("ERR Background save already in progress"));
+        }
+        state.bgsave_in_progress = true;
+        state.last_bgsave_try = SystemTime::now();
+        drop(state);
+
+        let (snapshot, repl_info) = self.snapshot(store).await;
+
+        let persistence = self.clone();
+        let store = store.clone();
+        let config = config.clone();
+        tokio::spawn(async move {
+            let res = write_rdb(snapshot.databases, repl_info, &config).await;
+
+            let mut state = persistence.state.lock().await;
+            state.bgsave_in_progress = false;
+            state.last_bgsave_ok = res.is_ok();
+            match res {
+                Ok(_) => {
+                    eprintln!("Background saving terminated with success");
+                    store.clear_dirty(snapshot.dirty);
+                    state.last_save = SystemTime::now();
+                }
+                Err(err) => {
+                    eprintln!("Background saving error: {:?}", err);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn should_save(&self, store: &RedisStore, config: &ServerConfig) -> bool {
+        let state = self.state.lock().await;
+        if state.bgsave_in_progress {
+            return false;
+        }
+
+        let now = SystemTime::now();
+        let elapsed = |since: SystemTime| now.duration_since(since).unwrap_or_default();
+        if !state.last_bgsave_ok && elapsed(state.last_bgsave_try) < BGSAVE_RETRY_DELAY {
+            return false;
+        }
+
+        let dirty = store.dirty();
+        config.save.iter().any(|param| {
+            dirty >= param.changes && elapsed(state.last_save) >= Duration::from_secs(param.seconds)
+        })
+    }
+
+    /// Periodically checks the `save` rules and triggers a background save when one is met.
+    pub(crate) fn spawn_save_cron(&self, store: RedisStore, config: SharedConfig) {
+        let persistence = self.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(SAVE_CRON_PERIOD);
+            loop {
+                interval.tick().await;
+                let config = config.read().await.clone();
+                if persistence.should_save(&store, &config).await {
+                    eprintln!("Save point reached, saving in the background");
+                    if let Err(err) = persistence.bgsave(&store, &config).await {
+                        eprintln!("Unable to start background save: {:?}", err);
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,1388 @@
+use std::{
+    sync::{self, Arc},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{tcp::OwnedWriteHalf, TcpStream},
+    sync::{mpsc, oneshot, Notify, RwLock},
+    task, time,
+};
+
+use crate::{
+    command::{Command, ConfigArg, ReplConfArg, XGroupArg, XInfoArg},
+    db::{
+        stream::{now_millis, GroupInfo, StreamInfo},
+        GroupEntries, StreamEntries,
+    },
+    rdb::{parse_rdb, writer::encode_rdb, Rdb, RdbReplInfo},
+    resp::RespValue,
+    server::{
+        send_bulk_string, send_integer, send_simple_error, send_simple_string,
+        store::{RedisStore, Snapshot},
+    },
+};
+
+use super::{
+    aof::{load_aof, Aof},
+    config::{ServerConfig, SharedConfig},
+    handle_echo, handle_get, handle_ping, handle_type, new_repl_id,
+    persistence::RdbPersistence,
+    replica::{LinkContext, ReplicaLink},
+    replication::{
+        read_acks, DisklessWaiter, FullSyncRdb, ReplicaConn, ReplicaState, ReplicaStatus,
+        Replication, ReplicationSink, SharedReplication,
+    },
+    replication_info, send_resp, MasterInfo, RedisServerHandler,
+};
+
+const REPLICATION_CRON_PERIOD: Duration = Duration::from_secs(1);
+
+/// A server acting as a master, or as a replica of another server while its replication link
+/// is set. The role can be switched at runtime with REPLICAOF.
+#[derive(Clone)]
+pub struct RedisServer {
+    /// Port clients connect to, announced to masters.
+    port: u16,
+    config: SharedConfig,
+    replication: SharedReplication,
+    store: RedisStore,
+    persistence: RdbPersistence,
+    aof: Aof,
+}
+
+impl RedisServer {
+    /// Loads the dataset and starts the server, replicating from `replicaof` if given.
+    pub async fn new(
+        config: ServerConfig,
+        port: u16,
+        replicaof: Option<(String, u16)>,
+    ) -> anyhow::Result<Self> {
+        // With AOF enabled the log is the authoritative copy of the dataset.
+        let loaded_aof = if config.appendonly {
+            load_aof(&config).await?
+        } else {
+            None
+        };
+        // Point of the replication history the loaded dataset is at, if known.
+        let mut repl_info = None;
+        let mut store = match loaded_aof.as_ref() {
+            Some(loaded) => loaded.store.clone(),
+            None => match load_rdb(&config).await? {
+                Some(rdb) => {
+                    repl_info = rdb.repl_info;
+                    RedisStore::from(rdb.databases)
+                }
+                None => RedisStore::new(),
+            },
+        };
+
+        store.set_stream_node_limits(config.stream_node_limits());
+        if config.aof_commands_base() {
+            store
+                .check_commands_base()
+                .await
+                .context("Start with aof-use-rdb-preamble no")?;
+        }
+
+        let mut master_info = MasterInfo::new();
+        master_info
+            .backlog
+            .resize(config.repl_backlog_size as usize);
+        if let Some(repl_info) = repl_info {
+            eprintln!(
+                "Resuming replication ID {} at offset {}",
+                repl_info.repl_id.iter().collect::<String>(),
+                repl_info.repl_offset
+            );
+            master_info.repl_id = repl_info.repl_id;
+            master_info.repl_offset = repl_info.repl_offset;
+            // Replicas that were in sync before the restart continue from an empty backlog.
+            master_info.backlog.create(repl_info.repl_offset);
+        }
+        let replication = Arc::new(sync::Mutex::new(Replication::new(master_info)));
+        store.add_write_sink(Arc::new(ReplicationSink(replication.clone())));
+
+        let aof = Aof::new(&mut store, &config);
+        if config.appendonly {
+            aof.start(&store, &config, loaded_aof).await?;
+        }
+
+        let config = Arc::new(RwLock::new(config));
+        let persistence = RdbPersistence::new(replication.clone());
+        persistence.spawn_save_cron(store.clone(), config.clone());
+        aof.spawn_rewrite_cron(store.clone(), config.clone());
+
+        let server = Self {
+            port,
+            config,
+            replication,
+            store,
+            persistence,
+            aof,
+        };
+        server.spawn_replication_cron();
+        if let Some((host, master_port)) = replicaof {
+            // A dataset loaded at startup can only be resumed if the RDB tells which history it
+            // belongs to.
+            let link = ReplicaLink::start(
+                server.link_context(),
+                host,
+                master_port,
+                repl_info.is_some(),
+            );
+            server.replication.lock().unwrap().master = Some(link);
+        }
+        Ok(server)
+    }
+
+    fn link_context(&self) -> LinkContext {
+        LinkContext {
+            listening_port: self.port,
+            config: self.config.clone(),
+            store: self.store.clone(),
+            replication: self.replication.clone(),
+            aof: self.aof.clone(),
+        }
+    }
+
+    /// Applies REPLICAOF, returning the reply. Replicas of this server are disconnected, so
+    /// they resync with the new history.
+    fn replicaof(&self, master: Option<(String, u16)>) -> &'static str {
+        let mut replication = self.replication.lock().unwrap();
+        match master {
+            None => {
+                if replication.master.take().is_none() {
+                    return "OK";
+                }
+                // The dataset continues under a new history; the master's stays valid up to
+                // here for replicas that followed it.
+                replication.info.shift_repl_id(new_repl_id());
+                let repl_offset = replication.info.repl_offset;
+                if !replication.info.backlog.is_active() {
+                    replication.info.backlog.create(repl_offset);
+                }
+                eprintln!("Promoted to master");
+            }
+            Some((host, port)) => {
+                if replication
+                    .master
+                    .as_ref()
+                    .is_some_and(|link| link.master_host == host && link.master_port == port)
+                {
+                    return "OK Already connected to specified master";
+                }
+                // The dataset, whether it is our own or the one of a previous master, is
+                // offered to the new master for a partial resync.
+                eprintln!("Replicating from {}:{}", host, port);
+                replication.master =
+                    Some(ReplicaLink::start(self.link_context(), host, port, true));
+            }
+        }
+        replication.disconnect_replicas();
+        "OK"
+    }
+
+    /// Whether `cmd` may be answered: always while this is a master or its link is up, and
+    /// with possibly stale data otherwise unless `replica-serve-stale-data` is off.
+    async fn serves(&self, cmd: &Command) -> bool {
+        !self.link_down()
+            || matches!(
+                cmd,
+                Command::Ping
+                    | Command::Info(_)
+                    | Command::ReplConf(_)
+                    | Command::ReplicaOf(_)
+                    | Command::Config(_)
+            )
+            || self.config.read().await.replica_serve_stale_data
+    }
+
+    /// Whether a write may be applied: on a master with `min-replicas-to-write` set, enough
+    /// replicas must have acknowledged within `min-replicas-max-lag` seconds.
+    async fn accepts_writes(&self) -> bool {
+        let (min_replicas, max_lag) = {
+            let config = self.config.read().await;
+            (config.min_replicas_to_write, config.min_replicas_max_lag)
+        };
+        let replication = self.replication.lock().unwrap();
+        min_replicas == 0
+            || replication.master.is_some()
+            || replication.good_replicas(max_lag) as u64 >= min_replicas
+    }
+
+    /// Whether this is a replica whose link with the master is not up.
+    fn link_down(&self) -> bool {
+        self.replication
+            .lock()
+            .unwrap()
+            .master
+            .as_ref()
+            .is_some_and(|link| !link.is_up())
+    }
+
+    fn is_replica(&self) -> bool {
+        self.replication.lock().unwrap().master.is_some()
+    }
+
+    /// Applies `CONFIG SET`. Either every parameter is applied or none is.
+    async fn config_set(&self, params: Vec<(String, String)>) -> anyhow::Result<()> {
+        let mut config = self.config.write().await;
+        let mut updated = config.clone();
+        for (key, value) in params {
+            updated.set(&key, &value)?;
+        }
+        if updated.aof_commands_base() && !config.aof_commands_base() {
+            self.store.check_commands_base().await?;
+        }
+
+        if updated.appendonly && !config.appendonly {
+            self.aof
+                .start(&self.store, &updated, None)
+                .await
+                .context("ERR Failed to enable AOF")?;
+        } else if !updated.appendonly && config.appendonly {
+            self.aof.stop().await.context("ERR Failed to disable AOF")?;
+        }
+        self.aof.set_fsync(updated.appendfsync).await;
+        self.store
+            .set_stream_node_limits(updated.stream_node_limits());
+        if updated.repl_backlog_size != config.repl_backlog_size {
+            self.replication
+                .lock()
+                .unwrap()
+                .info
+                .backlog
+                .resize(updated.repl_backlog_size as usize);
+        }
+
+        *config = updated;
+        Ok(())
+    }
+
+    /// Pings the replicas of a master every `repl-ping-replica-period` seconds, disconnects the
+    /// ones silent for `repl-timeout` seconds, and frees the backlog once there were no replicas
+    /// for `repl-backlog-ttl` seconds. Replicas keep their backlog, to continue with it once
+    /// promoted.
+    fn spawn_replication_cron(&self) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(REPLICATION_CRON_PERIOD);
+            let mut no_replicas_since = None;
+            let mut last_ping = Instant::now();
+            loop {
+                interval.tick().await;
+                let (ttl, ping_period, timeout) = {
+                    let config = server.config.read().await;
+                    (
+                        config.repl_backlog_ttl,
+                        Duration::from_secs(config.repl_ping_replica_period),
+                        Duration::from_secs(config.repl_timeout),
+                    )
+                };
+                let mut replication = server.replication.lock().unwrap();
+                replication.remove_disconnected();
+                replication.remove_timed_out(timeout);
+                // A replica forwards its master's pings instead.
+                if last_ping.elapsed() >= ping_period {
+                    if replication.master.is_none() && !replication.replicas.is_empty() {
+                        replication.propagate(&Command::Ping.to_bytes());
+                    }
+                    last_ping = Instant::now();
+                }
+                if replication.master.is_some()
+                    || !replication.replicas.is_empty()
+                    || !replication.info.backlog.is_active()
+                {
+                    no_replicas_since = None;
+                    continue;
+                }
+
+                let since = *no_replicas_since.get_or_insert_with(Instant::now);
+                if ttl > 0 && since.elapsed() >= Duration::from_secs(ttl) {
+                    eprintln!(
+                        "Replication backlog freed after {} seconds without replicas",
+                        ttl
+                    );
+                    replication.info.backlog.free();
+                    no_replicas_since = None;
+                }
+            }
+        });
+    }
+
+    /// Waits until `numreplicas` replicas acknowledged the replication stream up to `offset`,
+    /// or for `timeout` unless it is zero, returning the number of replicas that did.
+    async fn wait_replicas(&self, offset: usize, numreplicas: usize, timeout: Duration) -> usize {
+        let deadline = (!timeout.is_zero()).then(|| time::Instant::now() + timeout);
+        let acks = self.replication.lock().unwrap().acks.clone();
+        let mut getack_sent = false;
+        loop {
+            // Created before counting, so an acknowledgement in between is not missed.
+            let notified = acks.notified();
+            {
+                let mut replication = self.replication.lock().unwrap();
+                let acked = replication.acked_replicas(offset);
+                if acked >= numreplicas {
+                    return acked;
+                }
+                if !getack_sent {
+                    // Part of the stream, so replicas count it towards their offset like the
+                    // master does.
+                    replication.propagate(&Command::ReplConf(ReplConfArg::GetAck).to_bytes());
+                    getack_sent = true;
+                }
+            }
+
+            match deadline {
+                Some(deadline) => {
+                    if time::timeout_at(deadline, notified).await.is_err() {
+                        return self.replication.lock().unwrap().acked_replicas(offset);
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+
+    /// Encodes a snapshot sent to replicas, taken at `repl_offset`.
+    async fn encode_snapshot(
+        &self,
+        snapshot: Snapshot,
+        repl_offset: usize,
+    ) -> anyhow::Result<Arc<Vec<u8>>> {
+        let config = self.config.read().await.clone();
+        let repl_info = RdbReplInfo {
+            repl_id: self.replication.lock().unwrap().info.repl_id,
+            repl_offset,
+        };
+        let rdb = task::spawn_blocking(move || {
+            encode_rdb(
+                &snapshot.databases,
+                config.rdbcompression,
+                config.rdbchecksum,
+                Some(&repl_info),
+            )
+        })
+        .await
+        .context("Join RDB writer")?;
+        Ok(Arc::new(rdb))
+    }
+
+    /// Adds a replica to the next diskless transfer, which starts `repl-diskless-sync-delay`
+    /// seconds after its first replica joined so that replicas arriving meanwhile share it.
+    async fn join_diskless_transfer(&self, conn: ReplicaConn) -> oneshot::Receiver<FullSyncRdb> {
+        let delay = Duration::from_secs(self.config.read().await.repl_diskless_sync_delay);
+        let (tx, rx) = oneshot::channel();
+        let first = {
+            let mut replication = self.replication.lock().unwrap();
+            replication
+                .diskless_waiters
+                .push(DisklessWaiter { conn, rdb: tx });
+            replication.diskless_waiters.len() == 1
+        };
+        if first {
+            let server = self.clone();
+            tokio::spawn(async move {
+                time::sleep(delay).await;
+                if let Err(err) = server.start_diskless_transfer().await {
+                    eprintln!("Diskless transfer failed: {:?}", err);
+                }
+            });
+        }
+        rx
+    }
+
+    /// Takes a single snapshot for every replica waiting for a diskless transfer.
+    async fn start_diskless_transfer(&self) -> anyhow::Result<()> {
+        let (snapshot, (repl_offset, waiters)) = self
+            .store
+            .snapshot_with(|_| {
+                let mut replication = self.replication.lock().unwrap();
+                let waiters = std::mem::take(&mut replication.diskless_waiters);
+                let conns = waiters.iter().map(|waiter| waiter.conn.clone());
+                (replication.start_full_sync(conns), waiters)
+            })
+            .await;
+        // The waiters were disconnected in the meantime.
+        if waiters.is_empty() {
+            return Ok(());
+        }
+
+        eprintln!("Starting diskless transfer to {} replicas", waiters.len());
+        let rdb = self.encode_snapshot(snapshot, repl_offset).await?;
+        for waiter in waiters {
+            let _ = waiter.rdb.send((repl_offset, rdb.clone()));
+        }
+        Ok(())
+    }
+
+    /// Handles PSYNC. A replica whose history is still in the backlog continues from its
+    /// offset; any other gets a snapshot of the dataset. Either way, propagated writes are then
+    /// streamed to it until it disconnects. On a replica, that is the stream of its own master,
+    /// under the master's replication ID and offsets.
+    async fn sync_replica(
+        &self,
+        socket: TcpStream,
+        listening_port: Option<u16>,
+        repl_id: Option<[char; 40]>,
+        psync_offset: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let ip = socket.peer_addr().context("Get replica address")?.ip();
+        let (reader, mut writer) = socket.into_split();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        // Only the registered connection may keep the queue open, so dropping it from the
+        // replicas closes the connection.
+        let status = Arc::new(sync::Mutex::new(ReplicaStatus::new(ReplicaState::Online)));
+        let new_conn = |state| {
+            status.lock().unwrap().state = state;
+            ReplicaConn {
+                writes: tx.clone(),
+                ip,
+                listening_port,
+                status: status.clone(),
+            }
+        };
+
+        // The backlog and the queue are filled under the replication lock, so together they
+        // hold every write after the replica's offset exactly once.
+        let (cur_repl_id, missing) = {
+            let mut replication = self.replication.lock().unwrap();
+            let info = &replication.info;
+            let missing = match (repl_id, psync_offset) {
+                (Some(repl_id), Some(psync_offset)) => {
+                    info.partial_sync_backlog(&repl_id, psync_offset)
+                }
+                _ => None,
+            };
+            let cur_repl_id = info.repl_id.iter().collect::<String>();
+            if let Some(missing) = missing.as_ref() {
+                eprintln!("Partial resync, sending {} bytes of backlog", missing.len());
+                let _ = tx.send(missing.clone());
+                replication.replicas.push(new_conn(ReplicaState::Online));
+            }
+            (cur_repl_id, missing)
+        };
+
+        // Writes are fed to the replication stream with their database locked, so registering
+        // the replica while the snapshot holds every lock splits the writes exactly between the
+        // snapshot and the queue.
+        let full_sync = match missing {
+            Some(_) => None,
+            None if self.config.read().await.repl_diskless_sync => Some(FullSync::Diskless(
+                self.join_diskless_transfer(new_conn(ReplicaState::WaitBgsave))
+                    .await,
+            )),
+            None => {
+                let (snapshot, repl_offset) = self
+                    .store
+                    .snapshot_with(|_| {
+                        self.replication
+                            .lock()
+                            .unwrap()
+                            .start_full_sync([new_conn(ReplicaState::WaitBgsave)])
+                    })
+                    .await;
+                Some(FullSync::Bulk(repl_offset, snapshot))
+            }
+        };
+        drop(tx);
+        let acks = self.replication.lock().unwrap().acks.clone();
+
+        let transfer = async {
+            let Some(full_sync) = full_sync else {
+                let reply = RespValue::SimpleString(format!("CONTINUE {}", cur_repl_id));
+                writer
+                    .write_all(&reply.to_bytes())
+                    .await
+                    .context("Send CONTINUE")?;
+                return stream_to_replica(&mut writer, &mut rx).await;
+            };
+
+            let (repl_offset, rdb, eof_mark) = match full_sync {
+                FullSync::Bulk(repl_offset, snapshot) => {
+                    let rdb = self.encode_snapshot(snapshot, repl_offset).await?;
+                    (repl_offset, rdb, None)
+                }
+                FullSync::Diskless(rdb) => {
+                    let (repl_offset, rdb) = rdb.await.context("Diskless transfer aborted")?;
+                    let eof_mark = new_repl_id().iter().collect::<String>();
+                    (repl_offset, rdb, Some(eof_mark))
+                }
+            };
+            let reply =
+                RespValue::SimpleString(format!("FULLRESYNC {} {}", cur_repl_id, repl_offset));
+            writer
+                .write_all(&reply.to_bytes())
+                .await
+                .context("Send FULLRESYNC")?;
+
+            eprintln!("Sending RDB of {} bytes to replica", rdb.len());
+            status.lock().unwrap().state = ReplicaState::SendBulk;
+            // A diskless transfer is delimited by a random mark rather than announced by its
+            // length, which is unknown when streaming.
+            let header = match eof_mark.as_ref() {
+                Some(eof_mark) => format!("$EOF:{}\r\n", eof_mark),
+                None => format!("${}\r\n", rdb.len()),
+            };
+            writer
+                .write_all(header.as_bytes())
+                .await
+                .context("Send RDB header")?;
+            writer.write_all(&rdb).await.context("Send RDB")?;
+            if let Some(eof_mark) = eof_mark {
+                writer
+                    .write_all(eof_mark.as_bytes())
+                    .await
+                    .context("Send RDB EOF mark")?;
+            }
+            set_online(&status);
+
+            stream_to_replica(&mut writer, &mut rx).await
+        };
+        // Acknowledgements are read on their own, so they are recorded even while the replica
+        // receives its snapshot.
+        let res = tokio::select! {
+            res = transfer => res,
+            res = read_acks(reader, status.clone(), acks) => res,
+        };
+
+        rx.close();
+        self.replication.lock().unwrap().remove_disconnected();
+        res
+    }
+}
+
+/// How the snapshot of a full resync reaches a replica.
+enum FullSync {
+    /// Taken for this replica alone and sent along with its length.
+    Bulk(usize, Snapshot),
+    /// Shared with the other replicas of a diskless transfer, once it starts.
+    Diskless(oneshot::Receiver<FullSyncRdb>),
+}
+
+/// Marks a replica that received its snapshot as online. Its lag counts from now, as it could
+/// not acknowledge anything while loading the snapshot.
+fn set_online(status: &sync::Mutex<ReplicaStatus>) {
+    let mut status = status.lock().unwrap();
+    status.state = ReplicaState::Online;
+    status.ack_time = Instant::now();
+}
+
+/// Sends queued writes to a replica until it disconnects.
+async fn stream_to_replica(
+    writer: &mut OwnedWriteHalf,
+    rx: &mut mpsc::UnboundedReceiver<Vec<u8>>,
+) -> anyhow::Result<()> {
+    while let Some(buf) = rx.recv().await {
+        writer
+            .write_all(&buf)
+            .await
+            .context("Propagate to replica")?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl RedisServerHandler for RedisServer {
+    async fn handle_conn(&mut self, mut socket: TcpStream) {
+        let mut buf = [0u8; 1024];
+        // Replication offset right after this client's last write, which WAIT waits for.
+        let mut write_offset = 0;
+        // Port announced by a replica with `REPLCONF listening-port`.
+        let mut listening_port = None;
+        loop {
+            let n = socket
+                .read(&mut buf)
+                .await
+                .context("Read from client")
+                .unwrap();
+            if n == 0 {
+                eprintln!("Client closed the connection");
+                break;
+            }
+            let cmd = match Command::from_bytes(&buf[..n]) {
+                Ok((cmd, _)) => cmd,
+                Err(err) => {
+                    send_simple_error(&mut socket, &err.to_string()).await;
+                    continue;
+                }
+            };
+            let is_write = cmd.is_write();
+
+            if !self.serves(&cmd).await {
+                send_simple_error(
+                    &mut socket,
+                    "MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.",
+                )
+                .await;
+                continue;
+            }
+            if is_write && self.is_replica() && self.config.read().await.replica_read_only {
+                send_simple_error(
+                    &mut socket,
+                    "READONLY You can't write against a read only replica.",
+                )
+                .await;
+                continue;
+            }
+            if is_write && !self.accepts_writes().await {
+                send_simple_error(&mut socket, "NOREPLICAS Not enough good replicas to write.")
+                    .await;
+                continue;
+            }
+
+            match cmd {
+                Command::Ping => {
+                    handle_ping(&mut socket).await;
+                }
+                Command::Echo(val) => {
+                    handle_echo(&mut socket, &val).await;
+                }
+                Command::Set { key, value, expiry } => {
+                    eprintln!("Handling SET from client");
+                    self.store.set(&key, value, expiry).await;
+                    self.aof.wait_durable(self.store.write_seq()).await;
+                    send_simple_string(&mut socket, "OK").await;
+                }
+                Command::Get(key) => {
+                    handle_get(&mut socket, &self.store, &key).await;
+                }
+                Command::Info(_) => {
+                    eprintln!("Handling INFO from client");
+                    let info = {
+                        let replication = self.replication.lock().unwrap();
+                        match replication.master.as_ref() {
+                            Some(link) => {
+                                let mut fields = link.info_fields();
+                                fields.push((
+                                    "slave_repl_offset".to_string(),
+                                    replication.info.repl_offset.to_string(),
+                                ));
+                                fields.extend(replication.replicas_info());
+                                replication_info("slave", &replication.info, fields)
+                            }
+                            None => replication_info(
+                                "master",
+                                &replication.info,
+                                replication.replicas_info(),
+                            ),
+                        }
+                    };
+                    send_bulk_string(&mut socket, &info).await;
+                }
+                Command::ReplConf(arg) => {
+                    eprintln!("Handling REPLCONF from client");
+                    if let ReplConfArg::ListeningPort(port) = arg {
+                        listening_port = Some(port);
+                    }
+                    send_simple_string(&mut socket, "OK").await;
+                }
+                Command::PSync {
+                    repl_id,
+                    repl_offset,
+                } => {
+                    eprintln!("Handling PSYNC from client");
+                    if self.link_down() {
+                        send_simple_error(
+                            &mut socket,
+                            "NOMASTERLINK Can't SYNC while not connected with my master",
+                        )
+                        .await;
+                        continue;
+                    }
+                    if let Err(err) = self
+                        .sync_replica(socket, listening_port, repl_id, repl_offset)
+                        .await
+                    {
+                        eprintln!("Replica sync failed: {:?}", err);
+                    }
+                    break;
+                }
+                Command::Wait {
+                    repl_ack_num,
+                    timeout_dur,
+                } => {
+                    eprintln!("Handling WAIT from client");
+                    if self.is_replica() {
+                        send_simple_error(
+                            &mut socket,
+                            "ERR WAIT cannot be used with replica instances.",
+                        )
+                        .await;
+                        continue;
+                    }
+                    let acked = self
+                        .wait_replicas(write_offset, repl_ack_num, timeout_dur)
+                        .await;
+                    send_integer(&mut socket, acked as i64).await;
+                }
+                Command::Config(arg) => match arg {
+                    ConfigArg::Get(key) => {
+                        let value = self.config.read().await.get(&key.to_ascii_lowercase());
+                        let resp = match value {
+                            Some(value) => RespValue::Array(vec![
+                                RespValue::BulkString(key.as_bytes().to_vec()),
+                                RespValue::BulkString(value.into_bytes()),
+                            ]),
+                            None => RespValue::Array(vec![]),
+                        };
+                        send_resp(&mut socket, &resp).await;
+                    }
+                    ConfigArg::Set(params) => match self.config_set(params).await {
+                        Ok(_) => send_simple_string(&mut socket, "OK").await,
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    },
+                },
+                Command::Save => {
+                    eprintln!("Handling SAVE");
+                    let config = self.config.read().await.clone();
+                    match self.persistence.save(&self.store, &config).await {
+                        Ok(_) => send_simple_string(&mut socket, "OK").await,
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    }
+                }
+                Command::BgSave => {
+                    eprintln!("Handling BGSAVE");
+                    let config = self.config.read().await.clone();
+                    match self.persistence.bgsave(&self.store, &config).await {
+                        Ok(_) => send_simple_string(&mut socket, "Background saving started").await,
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    }
+                }
+                Command::BgRewriteAof => {
+                    eprintln!("Handling BGREWRITEAOF");
+                    let config = self.config.read().await.clone();
+                    match self.aof.bgrewrite(&self.store, &config).await {
+                        Ok(_) => {
+                            send_simple_string(
+                                &mut socket,
+                                "Background append only file rewriting started",
+                            )
+                            .await
+                        }
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    }
+                }
+                Command::LastSave => {
+                    let lastsave = self.persistence.lastsave().await;
+                    send_integer(&mut socket, lastsave as i64).await;
+                }
+                Command::Keys => {
+                    let keys = self.store.keys().await;
+                    let resp =
+                        RespValue::Array(keys.into_iter().map(RespValue::BulkString).collect());
+                    send_resp(&mut socket, &resp).await;
+                }
+                Command::LookupType(key) => {
+                    handle_type(&mut socket, &self.store, &key).await;
+                }
+                Command::ReplicaOf(master) => {
+                    eprintln!("Handling REPLICAOF");
+                    send_simple_string(&mut socket, self.replicaof(master)).await;
+                }
+                Command::XAdd {
+                    key,
+                    entry_id,
+                    data,
+                    nomkstream,
+                    trim,
+                } => {
+                    eprintln!("Handling XADD");
+                    match self
+                        .store
+                        .xadd(&key, entry_id, data, nomkstream, trim)
+                        .await
+                    {
+                        Ok(Some(res)) => {
+                            self.aof.wait_durable(self.store.write_seq()).await;
+                            send_bulk_string(&mut socket, &res.as_bytes()).await;
+                        }
+                        Ok(None) => {
+                            send_resp(&mut socket, &RespValue::NullBulkString).await;
+                        }
+                        Err(err) => {
+                            send_simple_error(&mut socket, &err.to_string()).await;
+                        }
+                    }
+                }
+                Command::XLen(key) => {
+                    eprintln!("Handling XLEN");
+                    let len = self.store.xlen(&key).await;
+                    send_integer(&mut socket, len as i64).await;
+                }
+                Command::XDel { key, ids } => {
+                    eprintln!("Handling XDEL");
+                    let deleted = self.store.xdel(&key, &ids).await;
+                    self.aof.wait_durable(self.store.write_seq()).await;
+                    send_integer(&mut socket, deleted as i64).await;
+                }
+                Command::XTrim { key, trim } => {
+                    eprintln!("Handling XTRIM");
+                    let trimmed = self.store.xtrim(&key, &trim).await;
+                    self.aof.wait_durable(self.store.write_seq()).await;
+                    send_integer(&mut socket, trimmed as i64).await;
+                }
+                Command::XSetId {
+                    key,
+                    last_id,
+                    entries_added,
+                    max_deleted_id,
+                } => {
+                    eprintln!("Handling XSETID");
+                    match self
+                        .store
+                        .xsetid(&key, last_id, entries_added, max_deleted_id)
+                        .await
+                    {
+                        Ok(()) => {
+                            self.aof.wait_durable(self.store.write_seq()).await;
+                            send_simple_string(&mut socket, "OK").await;
+                        }
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    }
+                }
+                Command::XRange {
+                    key,
+                    start,
+                    end,
+                    count,
+                } => {
+                    eprintln!("Handling XRANGE");
+                    let data = self.store.xrange(&key, &start, &end, count, false).await;
+                    send_resp(&mut socket, &stream_entries_resp(data)).await;
+                }
+                Command::XRevRange {
+                    key,
+                    start,
+                    end,
+                    count,
+                } => {
+                    eprintln!("Handling XREVRANGE");
+                    let data = self.store.xrange(&key, &start, &end, count, true).await;
+                    send_resp(&mut socket, &stream_entries_resp(data)).await;
+                }
+                Command::XRead {
+                    count,
+                    block,
+                    streams,
+                } => {
+                    eprintln!("Handling XREAD");
+                    let streams = match block {
+                        Some(_) => self.store.xread_resolve(&streams).await,
+                        None => streams,
+                    };
+                    let deadline = block
+                        .filter(|dur| !dur.is_zero())
+                        .map(|dur| time::Instant::now() + dur);
+                    let waiter = block.map(|_| Arc::new(Notify::new()));
+                    let data = loop {
+                        let data = self.store.xread(&streams, count, waiter.as_ref()).await;
+                        let Some(waiter) = waiter.as_ref().filter(|_| data.is_empty()) else {
+                            break data;
+                        };
+                        if !wait_for_entries(waiter, deadline).await {
+                            break data;
+                        }
+                    };
+                    if let Some(waiter) = &waiter {
+                        self.store.unblock_streams(&streams, waiter).await;
+                    }
+
+                    let resp = if block.is_some() && data.is_empty() {
+                        RespValue::NullBulkString
+                    } else {
+                        RespValue::Array(
+                            data.into_iter()
+                                .map(|(key, data)| {
+                                    RespValue::Array(vec![
+                                        RespValue::BulkString(key),
+                                        stream_entries_resp(data),
+                                    ])
+                                })
+                                .collect(),
+                        )
+                    };
+                    send_resp(&mut socket, &resp).await;
+                }
+                Command::XGroup(arg) => {
+                    eprintln!("Handling XGROUP");
+                    let replies_ok =
+                        matches!(arg, XGroupArg::Create { .. } | XGroupArg::SetId { .. });
+                    match self.store.xgroup(arg).await {
+                        Ok(_) if replies_ok => {
+                            self.aof.wait_durable(self.store.write_seq()).await;
+                            send_simple_string(&mut socket, "OK").await;
+                        }
+                        Ok(count) => {
+                            self.aof.wait_durable(self.store.write_seq()).await;
+                            send_integer(&mut socket, count as i64).await;
+                        }
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    }
+                }
+                Command::XReadGroup {
+                    group,
+                    consumer,
+                    count,
+                    block,
+                    noack,
+                    streams,
+                } => {
+                    eprintln!("Handling XREADGROUP");
+                    let deadline = block
+                        .filter(|dur| !dur.is_zero())
+                        .map(|dur| time::Instant::now() + dur);
+                    let waiter = block.map(|_| Arc::new(Notify::new()));
+                    let res = loop {
+                        let res = self
+                            .store
+                            .xreadgroup(&group, &consumer, count, noack, &streams, waiter.as_ref())
+                            .await;
+                        let Some(waiter) = waiter
+                            .as_ref()
+                            .filter(|_| res.as_ref().is_ok_and(Vec::is_empty))
+                        else {
+                            break res;
+                        };
+                        if !wait_for_entries(waiter, deadline).await {
+                            break res;
+                        }
+                    };
+                    if let Some(waiter) = &waiter {
+                        self.store.unblock_streams(&streams, waiter).await;
+                    }
+
+                    match res {
+                        Ok(data) if data.is_empty() => {
+                            send_resp(&mut socket, &RespValue::NullBulkString).await;
+                        }
+                        Ok(data) => {
+                            self.aof.wait_durable(self.store.write_seq()).await;
+                            let resp = RespValue::Array(
+                                data.into_iter()
+                                    .map(|(key, entries)| {
+                                        RespValue::Array(vec![
+                                            RespValue::BulkString(key),
+                                            group_entries_resp(entries),
+                                        ])
+                                    })
+                                    .collect(),
+                            );
+                            send_resp(&mut socket, &resp).await;
+                        }
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    }
+                }
+                Command::XAck { key, group, ids } => {
+                    eprintln!("Handling XACK");
+                    let acked = self.store.xack(&key, &group, &ids).await;
+                    self.aof.wait_durable(self.store.write_seq()).await;
+                    send_integer(&mut socket, acked as i64).await;
+                }
+                Command::XInfo(arg) => {
+                    eprintln!("Handling XINFO");
+                    let resp = match arg {
+                        XInfoArg::Stream { key, full } => self
+                            .store
+                            .xinfo_stream(&key, full)
+                            .await
+                            .map(|info| stream_info_resp(info, full.is_some())),
+                        XInfoArg::Groups(key) => {
+                            self.store.xinfo_groups(&key).await.map(|groups| {
+                                RespValue::Array(
+                                    groups
+                                        .into_iter()
+                                        .map(|group| {
+                                            let mut fields = vec![
+                                                info_field("name"),
+                                                RespValue::BulkString(group.name.clone()),
+                                                info_field("consumers"),
+                                                RespValue::Integer(group.consumers.len() as i64),
+                                                info_field("pending"),
+                                                RespValue::Integer(group.pel_count as i64),
+                                            ];
+                                            fields.extend(group_progress_fields(&group));
+                                            RespValue::Array(fields)
+                                        })
+                                        .collect(),
+                                )
+                            })
+                        }
+                        XInfoArg::Consumers { key, group } => {
+                            let now = now_millis();
+                            self.store
+                                .xinfo_consumers(&key, &group)
+                                .await
+                                .map(|consumers| {
+                                    RespValue::Array(
+                                        consumers
+                                            .into_iter()
+                                            .map(|consumer| {
+                                                let idle = now.saturating_sub(consumer.seen_time);
+                                                let inactive = consumer.active_time.map_or(
+                                                    -1,
+                                                    |active_time| {
+                                                        now.saturating_sub(active_time) as i64
+                                                    },
+                                                );
+                                                RespValue::Array(vec![
+                                                    info_field("name"),
+                                                    RespValue::BulkString(consumer.name),
+                                                    info_field("pending"),
+                                                    RespValue::Integer(consumer.pel_count as i64),
+                                                    info_field("idle"),
+                                                    RespValue::Integer(idle as i64),
+                                                    info_field("inactive"),
+                                                    RespValue::Integer(inactive),
+                                                ])
+                                            })
+                                            .collect(),
+                                    )
+                                })
+                        }
+                    };
+                    match resp {
+                        Ok(resp) => send_resp(&mut socket, &resp).await,
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    }
+                }
+                Command::XPending { key, group, range } => {
+                    eprintln!("Handling XPENDING");
+                    let resp = match range {
+                        None => self.store.xpending_summary(&key, &group).await.map(
+                            |(count, bounds, consumers)| {
+                                let (first, last) = match bounds {
+                                    Some((first, last)) => (
+                                        RespValue::BulkString(first.as_bytes()),
+                                        RespValue::BulkString(last.as_bytes()),
+                                    ),
+                                    None => (RespValue::NullBulkString, RespValue::NullBulkString),
+                                };
+                                let consumers = if consumers.is_empty() {
+                                    RespValue::NullBulkString
+                                } else {
+                                    RespValue::Array(
+                                        consumers
+                                            .into_iter()
+                                            .map(|(name, count)| {
+                                                RespValue::Array(vec![
+                                                    RespValue::BulkString(name),
+                                                    RespValue::BulkString(
+                                                        count.to_string().into_bytes(),
+                                                    ),
+                                                ])
+                                            })
+                                            .collect(),
+                                    )
+                                };
+                                RespValue::Array(vec![
+                                    RespValue::Integer(count as i64),
+                                    first,
+                                    last,
+                                    consumers,
+                                ])
+                            },
+                        ),
+                        Some(range) => {
+                            self.store
+                                .xpending_range(&key, &group, &range)
+                                .await
+                                .map(|entries| {
+                                    RespValue::Array(
+                                        entries
+                                            .into_iter()
+                                            .map(|(id, consumer, idle, delivery_count)| {
+                                                RespValue::Array(vec![
+                                                    RespValue::BulkString(id.as_bytes()),
+                                                    RespValue::BulkString(consumer),
+                                                    RespValue::Integer(idle as i64),
+                                                    RespValue::Integer(delivery_count as i64),
+                                                ])
+                                            })
+                                            .collect(),
+                                    )
+                                })
+                        }
+                    };
+                    match resp {
+                        Ok(resp) => send_resp(&mut socket, &resp).await,
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    }
+                }
+                Command::XClaim {
+                    key,
+                    group,
+                    consumer,
+                    min_idle_time,
+                    ids,
+                    options,
+                } => {
+                    eprintln!("Handling XCLAIM");
+                    match self
+                        .store
+                        .xclaim(&key, &group, &consumer, min_idle_time, &ids, &options)
+                        .await
+                    {
+                        Ok(entries) => {
+                            self.aof.wait_durable(self.store.write_seq()).await;
+                            let resp = if options.just_id {
+                                group_entry_ids_resp(entries)
+                            } else {
+                                group_entries_resp(entries)
+                            };
+                            send_resp(&mut socket, &resp).await;
+                        }
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    }
+                }
+                Command::XAutoClaim {
+                    key,
+                    group,
+                    consumer,
+                    min_idle_time,
+                    start,
+                    count,
+                    just_id,
+                } => {
+                    eprintln!("Handling XAUTOCLAIM");
+                    match self
+                        .store
+                        .xautoclaim(
+                            &key,
+                            &group,
+                            &consumer,
+                            min_idle_time,
+                            &start,
+                            count,
+                            just_id,
+                        )
+                        .await
+                    {
+                        Ok((next, entries, dropped)) => {
+                            self.aof.wait_durable(self.store.write_seq()).await;
+                            let entries = if just_id {
+                                group_entry_ids_resp(entries)
+                            } else {
+                                group_entries_resp(entries)
+                            };
+                            let dropped = RespValue::Array(
+                                dropped
+                                    .into_iter()
+                                    .map(|id| RespValue::BulkString(id.as_bytes()))
+                                    .collect(),
+                            );
+                            let resp = RespValue::Array(vec![
+                                RespValue::BulkString(next.as_bytes()),
+                                entries,
+                                dropped,
+                            ]);
+                            send_resp(&mut socket, &resp).await;
+                        }
+                        Err(err) => send_simple_error(&mut socket, &err.to_string()).await,
+                    }
+                }
+            };
+            if is_write {
+                write_offset = self.replication.lock().unwrap().info.repl_offset;
+            }
+        }
+    }
+}
+
+/// Waits for `waiter` to be notified of new stream entries, forever without a `deadline`.
+/// Returns whether it was notified before the deadline.
+async fn wait_for_entries(waiter: &Notify, deadline: Option<time::Instant>) -> bool {
+    match deadline {
+        Some(deadline) => time::timeout_at(deadline, waiter.notified()).await.is_ok(),
+        None => {
+            waiter.notified().await;
+            true
+        }
+    }
+}
+
+fn stream_entry_resp((id, data): (Vec<u8>, Vec<Vec<u8>>)) -> RespValue {
+    RespValue::Array(vec![
+        RespValue::BulkString(id),
+        RespValue::Array(data.into_iter().map(RespValue::BulkString).collect()),
+    ])
+}
+
+/// Stream entries as `[ID, [field, value, ...]]`.
+fn stream_entries_resp(entries: StreamEntries) -> RespValue {
+    RespValue::Array(entries.into_iter().map(stream_entry_resp).collect())
+}
+
+fn info_field(name: &str) -> RespValue {
+    RespValue::BulkString(name.as_bytes().to_vec())
+}
+
+fn optional_integer_resp(value: Option<u64>) -> RespValue {
+    value.map_or(RespValue::NullBulkString, |value| {
+        RespValue::Integer(value as i64)
+    })
+}
+
+/// Fields on how far a group read, which XINFO GROUPS and XINFO STREAM FULL both report.
+fn group_progress_fields(group: &GroupInfo) -> [RespValue; 6] {
+    [
+        info_field("last-delivered-id"),
+        RespValue::BulkString(group.last_delivered.as_bytes()),
+        info_field("entries-read"),
+        optional_integer_resp(group.entries_read),
+        info_field("lag"),
+        optional_integer_resp(group.lag),
+    ]
+}
+
+fn stream_info_resp(info: StreamInfo, full: bool) -> RespValue {
+    let mut fields = vec![
+        info_field("length"),
+        RespValue::Integer(info.length as i64),
+        info_field("radix-tree-keys"),
+        RespValue::Integer(info.radix_tree_keys as i64),
+        info_field("radix-tree-nodes"),
+        RespValue::Integer(info.radix_tree_nodes as i64),
+        info_field("last-generated-id"),
+        RespValue::BulkString(info.last_generated_id.as_bytes()),
+        info_field("max-deleted-entry-id"),
+        RespValue::BulkString(info.max_deleted_entry_id.as_bytes()),
+        info_field("entries-added"),
+        RespValue::Integer(info.entries_added as i64),
+        info_field("recorded-first-entry-id"),
+        RespValue::BulkString(info.recorded_first_entry_id.as_bytes()),
+    ];
+    if !full {
+        let entry_resp =
+            |entry: Option<_>| entry.map_or(RespValue::NullBulkString, stream_entry_resp);
+        fields.extend([
+            info_field("groups"),
+            RespValue::Integer(info.groups.len() as i64),
+            info_field("first-entry"),
+            entry_resp(info.first_entry),
+            info_field("last-entry"),
+            entry_resp(info.last_entry),
+        ]);
+        return RespValue::Array(fields);
+    }
+
+    let groups = info.groups.into_iter().map(|group| {
+        let mut fields = vec![
+            info_field("name"),
+            RespValue::BulkString(group.name.clone()),
+        ];
+        fields.extend(group_progress_fields(&group));
+        fields.extend([
+            info_field("pel-count"),
+            RespValue::Integer(group.pel_count as i64),
+            info_field("pending"),
+            RespValue::Array(
+                group
+                    .pending
+                    .into_iter()
+                    .map(|(id, entry)| {
+                        RespValue::Array(vec![
+                            RespValue::BulkString(id.as_bytes()),
+                            RespValue::BulkString(entry.consumer),
+                            RespValue::Integer(entry.delivery_time as i64),
+                            RespValue::Integer(entry.delivery_count as i64),
+                        ])
+                    })
+                    .collect(),
+            ),
+            info_field("consumers"),
+            RespValue::Array(
+                group
+                    .consumers
+                    .into_iter()
+                    .map(|consumer| {
+                        RespValue::Array(vec![
+                            info_field("name"),
+                            RespValue::BulkString(consumer.name),
+                            info_field("seen-time"),
+                            RespValue::Integer(consumer.seen_time as i64),
+                            info_field("active-time"),
+                            RespValue::Integer(consumer.active_time.map_or(-1, |t| t as i64)),
+                            info_field("pel-count"),
+                            RespValue::Integer(consumer.pel_count as i64),
+                            info_field("pending"),
+                            RespValue::Array(
+                                consumer
+                                    .pending
+                                    .into_iter()
+                                    .map(|(id, delivery_time, delivery_count)| {
+                                        RespValue::Array(vec![
+                                            RespValue::BulkString(id.as_bytes()),
+                                            RespValue::Integer(delivery_time as i64),
+                                            RespValue::Integer(delivery_count as i64),
+                                        ])
+                                    })
+                                    .collect(),
+                            ),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ]);
+        RespValue::Array(fields)
+    });
+    fields.extend([
+        info_field("entries"),
+        stream_entries_resp(info.entries),
+        info_field("groups"),
+        RespValue::Array(groups.collect()),
+    ]);
+    RespValue::Array(fields)
+}
+
+/// Entries delivered to a consumer as `[ID, [field, value, ...]]`, with a null in place of the
+/// pairs of deleted entries.
+fn group_entries_resp(entries: GroupEntries) -> RespValue {
+    RespValue::Array(
+        entries
+            .into_iter()
+            .map(|(id, data)| {
+                let data = match data {
+                    Some(data) => {
+                        RespValue::Array(data.into_iter().map(RespValue::BulkString).collect())
+                    }
+                    None => RespValue::NullBulkString,
+                };
+                RespValue::Array(vec![RespValue::BulkString(id), data])
+            })
+            .collect(),
+    )
+}
+
+fn group_entry_ids_resp(entries: GroupEntries) -> RespValue {
+    RespValue::Array(
+        entries
+            .into_iter()
+            .map(|(id, _)| RespValue::BulkString(id))
+            .collect(),
+    )
+}
+
+/// Loads the configured RDB file. A missing file starts an empty dataset, while a corrupt one is
+/// an error.
+async fn load_rdb(config: &ServerConfig) -> anyhow::Result<Option<Rdb>> {
+    let (Some(_), Some(_)) = (&config.dir, &config.dbfilename) else {
+        return Ok(None);
+    };
+
+    let path = config.rdb_path();
+    let bytes = match fs::read(&path).await {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context(format!("Read RDB file {:?}", path)),
+    };
+    let rdb = parse_rdb(&bytes, config.rdbchecksum, config.stream_node_limits())
+        .context(format!("Load RDB file {:?}", path))?;
+    Ok(Some(rdb))
+}
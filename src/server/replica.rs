@@ -1,245 +1,683 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{self, Arc},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Context;
-use async_trait::async_trait;
-use tokio::{io::AsyncReadExt, net::TcpStream, sync::Mutex};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinHandle,
+    time,
+};
 
 use crate::{
     command::{Command, ReplConfArg},
-    rdb::{parse_rdb, Rdb},
-    resp::{decode, RespValue},
-    server::{handle_info, send_cmd},
-    utils::{bytes2usize, split_by_clrf},
+    db::{stream::StreamNodeLimits, RedisDb},
+    rdb::{Rdb, RdbLoader},
+    resp::command_frame_len,
+    utils::bytes2usize,
 };
 
 use super::{
-    assert_recv_simple_string, handle_echo, handle_get, handle_ping, handle_type,
-    store::RedisStore, MasterInfo, RedisServerHandler,
+    aof::Aof,
+    config::{ReplDisklessLoad, SharedConfig},
+    replication::{SharedReplication, APPLYING_MASTER_WRITE},
+    store::RedisStore,
+    MasterInfo,
 };
 
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How often the offset applied so far is acknowledged to the master.
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+/// Length of the random mark ending a diskless transfer.
+const EOF_MARK_LEN: usize = 40;
+/// Size of the chunks a snapshot saved to disk is loaded in.
+const RDB_LOAD_CHUNK: usize = 64 * 1024;
+
+/// Progress of the link with the master. Every connection attempt goes through the states in
+/// order and falls back to `Connect` when the link is lost.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReplState {
+    /// Waiting to (re)connect.
+    Connect,
+    Connecting,
+    /// Exchanging PING, REPLCONF and PSYNC with the master.
+    Handshake,
+    /// Receiving the snapshot of a full resync.
+    Transfer,
+    /// Applying the master's replication stream.
+    Connected,
+}
+
+struct LinkStatus {
+    state: ReplState,
+    /// Whether the replication ID and offset of the server describe the dataset, so the next
+    /// connection can ask to continue from them with a partial resync.
+    resumable: bool,
+    /// When anything was last received from the master.
+    last_io: Option<Instant>,
+    /// When an established link was last lost.
+    down_since: Option<Instant>,
+}
+
+/// Reply to PSYNC.
+#[derive(Debug, PartialEq)]
+enum PsyncReply {
+    /// The master sends a snapshot taken at `repl_offset`.
+    FullResync {
+        repl_id: [char; 40],
+        repl_offset: usize,
+    },
+    /// The master continues from the offset requested, possibly under a new replication ID.
+    Continue(Option<[char; 40]>),
+}
+
+/// Parts of the server the link with the master updates.
 #[derive(Clone)]
-pub struct ReplicaServer {
-    master_info: MasterInfo,
-    store: RedisStore,
-    offset: Arc<Mutex<usize>>,
+pub(crate) struct LinkContext {
+    /// Port the server listens on, announced to the master.
+    pub(crate) listening_port: u16,
+    pub(crate) config: SharedConfig,
+    pub(crate) store: RedisStore,
+    pub(crate) replication: SharedReplication,
+    pub(crate) aof: Aof,
 }
 
-#[async_trait]
-impl RedisServerHandler for ReplicaServer {
-    async fn handle_conn(&mut self, mut socket: TcpStream) {
-        let mut buf = [0u8; 1024];
-        loop {
-            socket
-                .read(&mut buf)
-                .await
-                .context("Read from client")
-                .unwrap();
-            let (cmd, _) = Command::from_bytes(&buf).unwrap();
+/// Link with the master of a replica. It is established in the background and re-established
+/// whenever it is lost, until the link is dropped.
+pub(crate) struct ReplicaLink {
+    pub(crate) master_host: String,
+    pub(crate) master_port: u16,
+    status: Arc<sync::Mutex<LinkStatus>>,
+    task: JoinHandle<()>,
+}
 
-            match cmd {
-                Command::Ping => {
-                    handle_ping(&mut socket).await;
-                }
-                Command::Echo(val) => {
-                    handle_echo(&mut socket, &val).await;
-                }
-                Command::Get(key) => {
-                    handle_get(&mut socket, &self.store, &key).await;
-                }
-                Command::Info(_) => {
-                    eprintln!("Handling INFO from client");
-                    handle_info(&mut socket, "slave", &self.master_info).await;
-                }
-                Command::LookupType(key) => {
-                    handle_type(&mut socket, &self.store, &key).await;
-                }
-                c => {
-                    panic!("Replica does not support command: {:?}", c);
-                }
-            }
-        }
+impl Drop for ReplicaLink {
+    fn drop(&mut self) {
+        self.task.abort();
     }
 }
 
-impl ReplicaServer {
-    fn parse_fullresync(buf: &[u8]) -> anyhow::Result<(MasterInfo, &[u8])> {
-        let (val, remaining) = decode(&buf).context("Parse FULLRESYNC response from master")?;
-        let text = match val {
-            RespValue::SimpleString(x) => x,
-            o => return Err(anyhow::anyhow!("Unexpected value: {:?}", o)),
+impl ReplicaLink {
+    /// Starts replicating from `master_host:master_port`. With `resumable`, the current
+    /// replication ID and offset of the server are offered to the master for a partial resync.
+    pub(crate) fn start(
+        ctx: LinkContext,
+        master_host: String,
+        master_port: u16,
+        resumable: bool,
+    ) -> Self {
+        let status = Arc::new(sync::Mutex::new(LinkStatus {
+            state: ReplState::Connect,
+            resumable,
+            last_io: None,
+            down_since: None,
+        }));
+        let supervisor = LinkSupervisor {
+            ctx,
+            master_host: master_host.clone(),
+            master_port,
+            status: status.clone(),
         };
+        let task = tokio::spawn(supervisor.maintain_link());
+        Self {
+            master_host,
+            master_port,
+            status,
+            task,
+        }
+    }
 
-        let args = text.split(" ").collect::<Vec<&str>>();
+    pub(crate) fn is_up(&self) -> bool {
+        self.status.lock().unwrap().state == ReplState::Connected
+    }
 
-        let (arg, args) = args.split_first().context("Extract FULLRESYNC verb")?;
-        if arg.to_ascii_lowercase() != "fullresync" {
-            return Err(anyhow::anyhow!("Command is not FULLRESYNC but: {}", arg));
+    /// Fields describing the link in the replication section of INFO.
+    pub(crate) fn info_fields(&self) -> Vec<(String, String)> {
+        let status = self.status.lock().unwrap();
+        let connected = status.state == ReplState::Connected;
+        let mut fields = vec![
+            ("master_host".to_string(), self.master_host.clone()),
+            ("master_port".to_string(), self.master_port.to_string()),
+            (
+                "master_link_status".to_string(),
+                if connected { "up" } else { "down" }.to_string(),
+            ),
+            (
+                "master_last_io_seconds_ago".to_string(),
+                status
+                    .last_io
+                    .filter(|_| connected)
+                    .map_or(-1, |at| at.elapsed().as_secs() as i64)
+                    .to_string(),
+            ),
+            (
+                "master_sync_in_progress".to_string(),
+                ((status.state == ReplState::Transfer) as u8).to_string(),
+            ),
+        ];
+        if !connected {
+            fields.push((
+                "master_link_down_since_seconds".to_string(),
+                status
+                    .down_since
+                    .map_or(-1, |at| at.elapsed().as_secs() as i64)
+                    .to_string(),
+            ));
         }
+        fields
+    }
+}
 
-        let (repl_id, args) = args.split_first().context("Extract REPL_ID")?;
-        let repl_id: [char; 40] = match repl_id.chars().collect::<Vec<char>>().try_into() {
-            Ok(x) => x,
-            Err(_) => return Err(anyhow::anyhow!("Replication ID must be 40 chars long")),
-        };
+/// Task keeping the link with the master up.
+struct LinkSupervisor {
+    ctx: LinkContext,
+    master_host: String,
+    master_port: u16,
+    status: Arc<sync::Mutex<LinkStatus>>,
+}
 
-        let (repl_offset, args) = args.split_first().context("Extract replication offset")?;
-        let repl_offset = repl_offset
-            .parse::<usize>()
-            .context("Parse replication offset to usize")?;
+impl LinkSupervisor {
+    fn set_state(&self, state: ReplState) {
+        self.status.lock().unwrap().state = state;
+    }
 
-        assert!(args.is_empty());
+    /// Reconnects with exponential backoff whenever the link is lost. The backoff starts over
+    /// once a link was fully established.
+    async fn maintain_link(self) {
+        let mut delay = MIN_RECONNECT_DELAY;
+        loop {
+            match self.run_link().await {
+                Ok(_) => eprintln!("Master closed the connection"),
+                Err(err) => eprintln!("Link with master failed: {:?}", err),
+            }
 
-        Ok((
-            MasterInfo {
+            let was_connected = {
+                let mut status = self.status.lock().unwrap();
+                let was_connected = status.state == ReplState::Connected;
+                if was_connected {
+                    status.down_since = Some(Instant::now());
+                }
+                status.state = ReplState::Connect;
+                was_connected
+            };
+            if was_connected {
+                delay = MIN_RECONNECT_DELAY;
+            }
+
+            eprintln!("Reconnecting to master in {:?}", delay);
+            time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    /// Connects to the master, syncs with it and applies its replication stream until the link
+    /// is lost.
+    async fn run_link(&self) -> anyhow::Result<()> {
+        self.set_state(ReplState::Connecting);
+        let timeout = Duration::from_secs(self.ctx.config.read().await.repl_timeout);
+        let socket = time::timeout(
+            timeout,
+            TcpStream::connect((self.master_host.as_str(), self.master_port)),
+        )
+        .await
+        .context("Timeout connecting to master")?
+        .context("Connect to master")?;
+        let mut conn = MasterConn {
+            socket,
+            buf: Vec::new(),
+            timeout,
+            status: self.status.clone(),
+        };
+
+        self.set_state(ReplState::Handshake);
+        conn.request(&Command::Ping, "PONG").await?;
+        conn.request(
+            &Command::ReplConf(ReplConfArg::ListeningPort(self.ctx.listening_port)),
+            "OK",
+        )
+        .await?;
+        conn.request(
+            &Command::ReplConf(ReplConfArg::Capa(vec![String::from("psync2")])),
+            "OK",
+        )
+        .await?;
+
+        // Ask to continue from the next byte after the history we already have.
+        let resumable = self.status.lock().unwrap().resumable;
+        let (repl_id, psync_offset) = if resumable {
+            let replication = self.ctx.replication.lock().unwrap();
+            (
+                Some(replication.info.repl_id),
+                Some(replication.info.repl_offset + 1),
+            )
+        } else {
+            (None, None)
+        };
+        conn.send(&Command::PSync {
+            repl_id,
+            repl_offset: psync_offset,
+        })
+        .await?;
+        let reply = conn.read_line().await?;
+        let reply = std::str::from_utf8(&reply).context("UTF-8 decode PSYNC reply")?;
+
+        match parse_psync_reply(reply)? {
+            PsyncReply::FullResync {
                 repl_id,
                 repl_offset,
-            },
-            remaining,
-        ))
+            } => {
+                self.set_state(ReplState::Transfer);
+                let rdb = self
+                    .receive_rdb(&mut conn)
+                    .await
+                    .context("Receive RDB from master")?;
+                self.load_dataset(rdb.databases, repl_id, repl_offset).await;
+            }
+            PsyncReply::Continue(new_repl_id) => {
+                eprintln!("Partial resync with master");
+                let mut replication = self.ctx.replication.lock().unwrap();
+                // The master was promoted since: our history stays valid under the old ID.
+                if let Some(new_repl_id) = new_repl_id.filter(|id| *id != replication.info.repl_id)
+                {
+                    replication.info.shift_repl_id(new_repl_id);
+                    // Our replicas learn the new ID when they reconnect.
+                    replication.disconnect_replicas();
+                }
+            }
+        }
+
+        self.set_state(ReplState::Connected);
+        self.stream_from_master(&mut conn).await
     }
 
-    fn parse_rdb(buf: &[u8]) -> anyhow::Result<(Rdb, &[u8])> {
-        // $<length>\r\n<contents>
-        if !buf.starts_with(b"$") {
-            return Err(anyhow::anyhow!(
-                "Expect RDB to start with '$', found: {:?}",
-                buf
-            ));
+    /// Receives the snapshot of a full resync. Depending on `repl-diskless-load`, it is saved to
+    /// disk and loaded from there, or parsed as it arrives.
+    async fn receive_rdb(&self, conn: &mut MasterConn) -> anyhow::Result<Rdb> {
+        let (diskless_load, rdb_path, stream_node_limits) = {
+            let config = self.ctx.config.read().await;
+            (
+                config.repl_diskless_load,
+                config.rdb_path(),
+                config.stream_node_limits(),
+            )
+        };
+        let mut transfer = conn.start_rdb_transfer().await?;
+        let diskless = match diskless_load {
+            ReplDisklessLoad::Disabled => false,
+            ReplDisklessLoad::OnEmptyDb => self.ctx.store.is_empty().await,
+            ReplDisklessLoad::Swapdb => true,
+        };
+
+        if diskless {
+            eprintln!("Loading RDB from master without saving it to disk");
+            let mut loader = RdbLoader::new(true, stream_node_limits);
+            while let Some(chunk) = conn.next_rdb_chunk(&mut transfer).await? {
+                loader.push(&chunk).context("Parse RDB from master")?;
+            }
+            return loader.finish().context("Parse RDB from master");
         }
 
-        let (length, remaining) = split_by_clrf(&buf[1..]).context("Extract length bytes")?;
-        let length = bytes2usize(&length)?;
+        let tmp_path = temp_rdb_path(&rdb_path);
+        let res = async {
+            let mut file = fs::File::create(&tmp_path)
+                .await
+                .context(format!("Create temporary file {:?}", tmp_path))?;
+            let mut size = 0;
+            while let Some(chunk) = conn.next_rdb_chunk(&mut transfer).await? {
+                file.write_all(&chunk).await.context("Write RDB")?;
+                size += chunk.len();
+            }
+            file.sync_all().await.context("Fsync RDB")?;
+            eprintln!("Received RDB of {} bytes from master", size);
 
-        let (rdb, remaining) = remaining.split_at(length);
-        let rdb = parse_rdb(rdb)?;
-        Ok((rdb, remaining))
+            let rdb = load_rdb_file(&tmp_path, stream_node_limits)
+                .await
+                .context("Parse RDB from master")?;
+            fs::rename(&tmp_path, &rdb_path)
+                .await
+                .context(format!("Rename RDB into {:?}", rdb_path))?;
+            Ok(rdb)
+        }
+        .await;
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp_path).await;
+        }
+        res
     }
 
-    async fn handle_cmd_from_master(
-        &mut self,
-        recv_buf: &[u8],
-        buf_len: usize,
-        socket: &mut TcpStream,
-    ) -> anyhow::Result<()> {
-        let mut ptr = 0;
-        while ptr < buf_len {
-            let (cmd, remaining_bytes) = Command::from_bytes(&recv_buf[ptr..])?;
-            let offset_delta = recv_buf.len() - remaining_bytes.len() - ptr;
-            ptr += offset_delta;
-
-            match cmd {
-                Command::Set { key, value, px } => {
-                    eprintln!("Handling SET propagation from master");
-                    self.store.set(&key, value, px).await;
-                }
-                Command::ReplConf(ReplConfArg::GetAck) => {
-                    eprintln!("Handling REPLCONF GETACK * from master");
+    /// Replaces the dataset with the one received in a full resync, whose history is
+    /// `repl_id` up to `repl_offset`.
+    async fn load_dataset(
+        &self,
+        databases: HashMap<u32, RedisDb>,
+        repl_id: [char; 40],
+        repl_offset: usize,
+    ) {
+        self.ctx.store.replace(databases).await;
+        {
+            let mut replication = self.ctx.replication.lock().unwrap();
+            let backlog_size = replication.info.backlog.size();
+            replication.info = MasterInfo {
+                repl_id,
+                repl_offset,
+                ..MasterInfo::new()
+            };
+            replication.info.backlog.resize(backlog_size);
+            replication.info.backlog.create(repl_offset);
+            // Our replicas hold the previous dataset and have to resync as well.
+            replication.disconnect_replicas();
+        }
+        self.status.lock().unwrap().resumable = true;
+
+        // The AOF no longer describes the dataset, so it starts over from a rewrite.
+        let config = self.ctx.config.read().await.clone();
+        if config.appendonly {
+            let res = match self.ctx.aof.stop().await {
+                Ok(_) => self.ctx.aof.start(&self.ctx.store, &config, None).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = res {
+                eprintln!("Unable to restart AOF after sync with master: {:?}", err);
+            }
+        }
+    }
 
-                    let offset = self.offset.lock().await;
-                    let cmd = Command::ReplConf(ReplConfArg::Ack(*offset));
-                    drop(offset);
-                    send_cmd(socket, &cmd).await;
+    /// Applies commands from the master as they arrive, acknowledging the offset every second
+    /// so the master knows the link is alive and how far behind we are.
+    async fn stream_from_master(&self, conn: &mut MasterConn) -> anyhow::Result<()> {
+        let mut ack_interval = time::interval(ACK_PERIOD);
+        loop {
+            while let Some(len) = command_frame_len(&conn.buf)? {
+                let frame = conn.buf.drain(..len).collect::<Vec<u8>>();
+                self.handle_cmd_from_master(&frame, conn).await?;
+            }
+            tokio::select! {
+                more = conn.read_more() => {
+                    if !more? {
+                        return Ok(());
+                    }
                 }
-                _ => {
-                    // Ignore all other commands
+                _ = ack_interval.tick() => {
+                    // Reads are restarted on every tick, so their own timeout never expires.
+                    if conn.idle() > conn.timeout {
+                        return Err(anyhow::anyhow!("Timeout, no data from master"));
+                    }
+                    let offset = self.ctx.replication.lock().unwrap().info.repl_offset;
+                    conn.send(&Command::ReplConf(ReplConfArg::Ack(offset)))
+                        .await?;
                 }
             }
+        }
+    }
 
-            let mut offset = self.offset.lock().await;
-            *offset += offset_delta;
-            drop(offset);
+    async fn handle_cmd_from_master(
+        &self,
+        frame: &[u8],
+        conn: &mut MasterConn,
+    ) -> anyhow::Result<()> {
+        self.ctx.replication.lock().unwrap().pending_frame = Some(frame.to_vec());
+        match Command::from_bytes(frame) {
+            Ok((cmd, _)) if cmd.is_write() => {
+                eprintln!("Applying write propagated from master: {:?}", cmd);
+                let applied = APPLYING_MASTER_WRITE.scope((), self.ctx.store.apply(cmd));
+                if let Err(err) = applied.await {
+                    eprintln!("Error applying write from master: {:?}", err);
+                }
+            }
+            Ok((Command::ReplConf(ReplConfArg::GetAck), _)) => {
+                eprintln!("Handling REPLCONF GETACK * from master");
+                let offset = self.ctx.replication.lock().unwrap().info.repl_offset;
+                conn.send(&Command::ReplConf(ReplConfArg::Ack(offset)))
+                    .await?;
+            }
+            Ok(_) => {
+                // Ignore all other commands
+            }
+            Err(err) => eprintln!("Ignoring unknown command from master: {:?}", err),
         }
 
+        // Every byte of the stream counts towards the offset and is forwarded to our replicas,
+        // whether or not it was applied.
+        let mut replication = self.ctx.replication.lock().unwrap();
+        if let Some(frame) = replication.pending_frame.take() {
+            replication.propagate(&frame);
+        }
         Ok(())
     }
+}
 
-    pub async fn new(port: u16, mut socket: TcpStream) -> anyhow::Result<Self> {
-        let mut recv_buf = [0u8; 1024];
+/// Connection to the master, with the bytes read but not consumed yet.
+struct MasterConn {
+    socket: TcpStream,
+    buf: Vec<u8>,
+    /// How long the master may stay silent before the link is considered lost.
+    timeout: Duration,
+    status: Arc<sync::Mutex<LinkStatus>>,
+}
 
-        // Send PING
-        send_cmd(&mut socket, &Command::Ping).await;
-        assert_recv_simple_string(&mut recv_buf, &mut socket, "PONG").await;
+impl MasterConn {
+    async fn send(&mut self, cmd: &Command) -> anyhow::Result<()> {
+        self.socket
+            .write_all(&cmd.to_bytes())
+            .await
+            .context(format!("Send {:?}", cmd))
+    }
 
-        // Send REPLCONF listening-port
-        send_cmd(
-            &mut socket,
-            &Command::ReplConf(ReplConfArg::ListeningPort(port)),
-        )
-        .await;
-        assert_recv_simple_string(&mut recv_buf, &mut socket, "OK").await;
+    /// Time since anything was last received from the master.
+    fn idle(&self) -> Duration {
+        self.status
+            .lock()
+            .unwrap()
+            .last_io
+            .map_or(Duration::ZERO, |at| at.elapsed())
+    }
 
-        // Send REPLCONF capa
-        send_cmd(
-            &mut socket,
-            &Command::ReplConf(ReplConfArg::Capa(vec![String::from("psync2")])),
-        )
-        .await;
-        assert_recv_simple_string(&mut recv_buf, &mut socket, "OK").await;
-
-        // Send PSYNC
-        send_cmd(
-            &mut socket,
-            &Command::PSync {
-                repl_id: None,
-                repl_offset: None,
-            },
-        )
-        .await;
-        // receive FULLRESYNC <REPL_ID> 0 & RDB file
-        let mut n = socket
-            .read(&mut recv_buf)
+    /// Reads more bytes from the master into `buf`, returning false if the master closed the
+    /// connection. Fails if nothing arrives within the timeout.
+    async fn read_more(&mut self) -> anyhow::Result<bool> {
+        let mut chunk = [0u8; 4096];
+        let n = time::timeout(self.timeout, self.socket.read(&mut chunk))
             .await
-            .context("Read from master")
-            .unwrap();
-        let (master_info, mut remaining) = Self::parse_fullresync(&recv_buf)?;
-        if recv_buf.len() - remaining.len() >= n {
-            let _n = socket
-                .read(&mut recv_buf)
-                .await
-                .context("Read from master")
-                .unwrap();
-            remaining = &recv_buf[..];
-            n = _n;
+            .context("Timeout reading from master")?
+            .context("Read from master")?;
+        self.status.lock().unwrap().last_io = Some(Instant::now());
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n > 0)
+    }
+
+    async fn read_more_during_sync(&mut self) -> anyhow::Result<()> {
+        if !self.read_more().await? {
+            return Err(anyhow::anyhow!("Master closed the connection during sync"));
         }
-        let (rdb, remaining) = Self::parse_rdb(&remaining)?;
+        Ok(())
+    }
 
-        let mut server = Self {
-            master_info: master_info,
-            store: RedisStore::from(rdb.databases),
-            offset: Arc::new(Mutex::new(0)),
+    /// Consumes a line, returning it without the CRLF.
+    async fn read_line(&mut self) -> anyhow::Result<Vec<u8>> {
+        loop {
+            if let Some(i) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let mut line = self.buf.drain(..i + 2).collect::<Vec<u8>>();
+                line.truncate(i);
+                return Ok(line);
+            }
+            self.read_more_during_sync().await?;
+        }
+    }
+
+    /// Sends `cmd` and checks the master replies with the simple string `expected`.
+    async fn request(&mut self, cmd: &Command, expected: &str) -> anyhow::Result<()> {
+        self.send(cmd).await?;
+        let reply = self.read_line().await?;
+        if reply.first() != Some(&b'+') || !reply[1..].eq_ignore_ascii_case(expected.as_bytes()) {
+            return Err(anyhow::anyhow!(
+                "Expect +{} in reply to {:?}, found: {:?}",
+                expected,
+                cmd,
+                String::from_utf8_lossy(&reply)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Consumes the header of the snapshot the master transfers: `$<length>`, or `$EOF:<mark>`
+    /// for a diskless transfer ended by the mark.
+    async fn start_rdb_transfer(&mut self) -> anyhow::Result<RdbTransfer> {
+        let header = self.read_line().await?;
+        let Some(header) = header.strip_prefix(b"$") else {
+            return Err(anyhow::anyhow!(
+                "Expect RDB to start with '$', found: {:?}",
+                header
+            ));
         };
+        match header.strip_prefix(b"EOF:") {
+            Some(mark) if mark.len() == EOF_MARK_LEN => Ok(RdbTransfer::EofMark(mark.to_vec())),
+            Some(mark) => Err(anyhow::anyhow!("Invalid RDB EOF mark: {:?}", mark)),
+            None => Ok(RdbTransfer::Length(
+                bytes2usize(header).context("Parse RDB length")?,
+            )),
+        }
+    }
 
-        // Handle additional commands from master, if any
-        let ptr = recv_buf.len() - remaining.len();
-        server
-            .handle_cmd_from_master(&remaining, n - ptr, &mut socket)
-            .await?;
+    /// Consumes the next part of the snapshot, returning `None` once it was received whole.
+    async fn next_rdb_chunk(
+        &mut self,
+        transfer: &mut RdbTransfer,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        loop {
+            match transfer {
+                RdbTransfer::Length(0) | RdbTransfer::Done => {
+                    *transfer = RdbTransfer::Done;
+                    return Ok(None);
+                }
+                RdbTransfer::Length(left) if !self.buf.is_empty() => {
+                    let n = self.buf.len().min(*left);
+                    *left -= n;
+                    return Ok(Some(self.buf.drain(..n).collect()));
+                }
+                RdbTransfer::Length(_) => {}
+                RdbTransfer::EofMark(mark) => {
+                    if let Some(pos) = self.buf.windows(mark.len()).position(|w| w == mark) {
+                        let chunk = self.buf.drain(..pos).collect();
+                        self.buf.drain(..mark.len());
+                        *transfer = RdbTransfer::Done;
+                        return Ok(Some(chunk));
+                    }
+                    // The end of the buffer may be the beginning of the mark.
+                    let n = self.buf.len().saturating_sub(mark.len() - 1);
+                    if n > 0 {
+                        return Ok(Some(self.buf.drain(..n).collect()));
+                    }
+                }
+            }
+            self.read_more_during_sync().await?;
+        }
+    }
+}
 
-        // spawn a watcher to master socket here
-        let mut master_watcher = server.clone();
-        tokio::spawn(async move { master_watcher.watch_master(socket).await });
+/// Snapshot being received from the master.
+enum RdbTransfer {
+    /// Announced by its length, of which this many bytes are left.
+    Length(usize),
+    /// Streamed until the mark.
+    EofMark(Vec<u8>),
+    Done,
+}
 
-        Ok(server)
+/// Temporary file next to `rdb_path` that a snapshot from the master is saved to.
+fn temp_rdb_path(rdb_path: &Path) -> PathBuf {
+    let dir = rdb_path.parent().unwrap_or(Path::new("."));
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    dir.join(format!("temp-{}.{}.rdb", secs, std::process::id()))
+}
+
+/// Loads an RDB file chunk by chunk, without reading it into memory whole.
+async fn load_rdb_file(path: &Path, stream_node_limits: StreamNodeLimits) -> anyhow::Result<Rdb> {
+    let mut file = fs::File::open(path)
+        .await
+        .context(format!("Open {:?}", path))?;
+    let mut loader = RdbLoader::new(true, stream_node_limits);
+    let mut chunk = vec![0; RDB_LOAD_CHUNK];
+    loop {
+        let n = file.read(&mut chunk).await.context("Read RDB")?;
+        if n == 0 {
+            return loader.finish();
+        }
+        loader.push(&chunk[..n])?;
     }
+}
 
-    pub async fn watch_master(&mut self, mut socket: TcpStream) {
-        let mut recv_buf = [0u8; 1024];
-        loop {
-            let n = socket
-                .read(&mut recv_buf)
-                .await
-                .context("Read from master")
-                .unwrap();
+fn parse_repl_id(s: &str) -> anyhow::Result<[char; 40]> {
+    s.chars()
+        .collect::<Vec<char>>()
+        .try_into()
+        .ok()
+        .context("Replication ID must be 40 chars long")
+}
 
-            match self
-                .handle_cmd_from_master(&mut recv_buf, n, &mut socket)
-                .await
-            {
-                Ok(_) => {
-                    continue;
-                }
-                Err(_) => todo!(), // Read more bytes into buffer
+fn parse_psync_reply(line: &str) -> anyhow::Result<PsyncReply> {
+    let text = line
+        .strip_prefix('+')
+        .context(format!("Unexpected reply to PSYNC: {:?}", line))?;
+    let args = text.split(' ').collect::<Vec<&str>>();
+
+    let (verb, args) = args.split_first().context("Extract PSYNC reply verb")?;
+    match &verb.to_ascii_lowercase()[..] {
+        "fullresync" => {
+            let [repl_id, repl_offset] = args else {
+                return Err(anyhow::anyhow!("Invalid FULLRESYNC reply: {:?}", line));
             };
+            Ok(PsyncReply::FullResync {
+                repl_id: parse_repl_id(repl_id)?,
+                repl_offset: repl_offset
+                    .parse::<usize>()
+                    .context("Parse replication offset to usize")?,
+            })
         }
+        "continue" => match args {
+            [] => Ok(PsyncReply::Continue(None)),
+            [repl_id] => Ok(PsyncReply::Continue(Some(parse_repl_id(repl_id)?))),
+            _ => Err(anyhow::anyhow!("Invalid CONTINUE reply: {:?}", line)),
+        },
+        _ => Err(anyhow::anyhow!("Unexpected reply to PSYNC: {:?}", line)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_psync_reply() {
+        // Arrange
+        let repl_id = "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb";
+        let expected_id = parse_repl_id(repl_id).unwrap();
+
+        // Act & Assert
+        assert_eq!(
+            parse_psync_reply(&format!("+FULLRESYNC {} 42", repl_id)).unwrap(),
+            PsyncReply::FullResync {
+                repl_id: expected_id,
+                repl_offset: 42
+            }
+        );
+        assert_eq!(
+            parse_psync_reply("+CONTINUE").unwrap(),
+            PsyncReply::Continue(None)
+        );
+        assert_eq!(
+            parse_psync_reply(&format!("+CONTINUE {}", repl_id)).unwrap(),
+            PsyncReply::Continue(Some(expected_id))
+        );
+        assert!(parse_psync_reply("-ERR no").is_err());
+        assert!(parse_psync_reply("+FULLRESYNC abc 1").is_err());
     }
 }
@@ -0,0 +1,352 @@
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use tokio::{
+    io::AsyncReadExt,
+    net::tcp::OwnedReadHalf,
+    sync::{mpsc, oneshot, Notify},
+};
+
+use crate::{
+    command::{Command, ReplConfArg},
+    rdb::RdbReplInfo,
+    resp::command_frame_len,
+};
+
+use super::{replica::ReplicaLink, store::WriteSink, MasterInfo};
+
+/// Progress of a replica's sync.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ReplicaState {
+    /// Waiting for the snapshot of a full resync.
+    WaitBgsave,
+    /// Receiving the snapshot.
+    SendBulk,
+    /// Receiving the replication stream.
+    Online,
+}
+
+impl ReplicaState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::WaitBgsave => "wait_bgsave",
+            Self::SendBulk => "send_bulk",
+            Self::Online => "online",
+        }
+    }
+}
+
+/// What is known about a replica, updated by the task serving it.
+pub(crate) struct ReplicaStatus {
+    pub(crate) state: ReplicaState,
+    /// Replication offset the replica last acknowledged with `REPLCONF ACK`.
+    pub(crate) ack_offset: usize,
+    /// When the replica last acknowledged, or synced if it has not yet.
+    pub(crate) ack_time: Instant,
+}
+
+impl ReplicaStatus {
+    pub(crate) fn new(state: ReplicaState) -> Self {
+        Self {
+            state,
+            ack_offset: 0,
+            ack_time: Instant::now(),
+        }
+    }
+}
+
+/// Connection to a replica. Propagated writes are queued and sent by the task owning the
+/// connection, so writes made while the replica still receives its snapshot follow it.
+#[derive(Clone)]
+pub(crate) struct ReplicaConn {
+    pub(crate) writes: mpsc::UnboundedSender<Vec<u8>>,
+    pub(crate) ip: IpAddr,
+    /// Port the replica listens on, as announced with `REPLCONF listening-port`.
+    pub(crate) listening_port: Option<u16>,
+    pub(crate) status: Arc<Mutex<ReplicaStatus>>,
+}
+
+/// Snapshot of a full resync: the replication offset it was taken at and its RDB encoding.
+pub(crate) type FullSyncRdb = (usize, Arc<Vec<u8>>);
+
+/// Replica waiting for the next diskless transfer. It is registered along with the others once
+/// the snapshot they share is taken.
+pub(crate) struct DisklessWaiter {
+    pub(crate) conn: ReplicaConn,
+    pub(crate) rdb: oneshot::Sender<FullSyncRdb>,
+}
+
+/// Replication state of the server: the replication stream, the replicas it is sent to and,
+/// while the server is itself a replica, the link with its master.
+pub(crate) struct Replication {
+    pub(crate) info: MasterInfo,
+    pub(crate) replicas: Vec<ReplicaConn>,
+    /// Replicas waiting for the next diskless transfer.
+    pub(crate) diskless_waiters: Vec<DisklessWaiter>,
+    /// Link with the master while this server is a replica. The replication stream then is the
+    /// master's, forwarded as is, so writes made here are not propagated.
+    pub(crate) master: Option<ReplicaLink>,
+    /// Frame of the master's stream being applied. Writes are forwarded by the sink with their
+    /// database locked, so snapshots split the forwarded stream exactly as they do on a master.
+    pub(crate) pending_frame: Option<Vec<u8>>,
+    /// Notified whenever a replica acknowledges an offset.
+    pub(crate) acks: Arc<Notify>,
+}
+
+pub(crate) type SharedReplication = Arc<Mutex<Replication>>;
+
+impl Replication {
+    pub(crate) fn new(info: MasterInfo) -> Self {
+        Self {
+            info,
+            replicas: Vec::new(),
+            diskless_waiters: Vec::new(),
+            master: None,
+            pending_frame: None,
+            acks: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Appends `buf` to the replication stream: advances the offset, records it in the backlog
+    /// and queues it for every replica.
+    pub(crate) fn propagate(&mut self, buf: &[u8]) {
+        self.info.repl_offset += buf.len();
+        self.info.backlog.feed(buf);
+        for replica in self.replicas.iter() {
+            let _ = replica.writes.send(buf.to_vec());
+        }
+    }
+
+    /// Point of the replication history the dataset is at, saved along with it.
+    pub(crate) fn rdb_repl_info(&self) -> RdbReplInfo {
+        RdbReplInfo {
+            repl_id: self.info.repl_id,
+            repl_offset: self.info.repl_offset,
+        }
+    }
+
+    /// Registers replicas sent a snapshot taken right now, returning the offset it is taken at.
+    /// The backlog is created if needed, so they can later resume from it.
+    pub(crate) fn start_full_sync(
+        &mut self,
+        conns: impl IntoIterator<Item = ReplicaConn>,
+    ) -> usize {
+        let repl_offset = self.info.repl_offset;
+        if !self.info.backlog.is_active() {
+            self.info.backlog.create(repl_offset);
+        }
+        self.replicas.extend(conns);
+        repl_offset
+    }
+
+    /// Closes the connection of every replica, which reconnects and resyncs.
+    pub(crate) fn disconnect_replicas(&mut self) {
+        let count = self.replicas.len() + self.diskless_waiters.len();
+        if count > 0 {
+            eprintln!("Disconnecting {} replicas", count);
+        }
+        self.replicas.clear();
+        self.diskless_waiters.clear();
+    }
+
+    /// Number of connected replicas that acknowledged the stream up to `offset`.
+    pub(crate) fn acked_replicas(&self, offset: usize) -> usize {
+        self.replicas
+            .iter()
+            .filter(|conn| {
+                !conn.writes.is_closed() && conn.status.lock().unwrap().ack_offset >= offset
+            })
+            .count()
+    }
+
+    /// Number of online replicas that acknowledged within the last `max_lag` seconds.
+    pub(crate) fn good_replicas(&self, max_lag: u64) -> usize {
+        self.replicas
+            .iter()
+            .filter(|conn| {
+                let status = conn.status.lock().unwrap();
+                !conn.writes.is_closed()
+                    && status.state == ReplicaState::Online
+                    && status.ack_time.elapsed().as_secs() <= max_lag
+            })
+            .count()
+    }
+
+    /// Disconnects online replicas that did not acknowledge anything for `timeout`.
+    pub(crate) fn remove_timed_out(&mut self, timeout: Duration) {
+        self.replicas.retain(|conn| {
+            let status = conn.status.lock().unwrap();
+            let timed_out =
+                status.state == ReplicaState::Online && status.ack_time.elapsed() > timeout;
+            if timed_out {
+                eprintln!("Disconnecting timedout replica at {}", conn.ip);
+            }
+            !timed_out
+        });
+    }
+
+    /// Fields describing the replicas in the replication section of INFO.
+    pub(crate) fn replicas_info(&self) -> Vec<(String, String)> {
+        let replicas = self
+            .replicas
+            .iter()
+            .filter(|conn| !conn.writes.is_closed())
+            .collect::<Vec<_>>();
+        let mut fields = vec![("connected_slaves".to_string(), replicas.len().to_string())];
+        for (i, conn) in replicas.into_iter().enumerate() {
+            let status = conn.status.lock().unwrap();
+            fields.push((
+                format!("slave{}", i),
+                format!(
+                    "ip={},port={},state={},offset={},lag={}",
+                    conn.ip,
+                    conn.listening_port.unwrap_or(0),
+                    status.state.as_str(),
+                    status.ack_offset,
+                    status.ack_time.elapsed().as_secs()
+                ),
+            ));
+        }
+        fields
+    }
+
+    /// Forgets replicas whose connection is gone.
+    pub(crate) fn remove_disconnected(&mut self) {
+        self.replicas.retain(|conn| !conn.writes.is_closed());
+    }
+}
+
+/// Reads what a replica sends back, recording the offsets it acknowledges, until it
+/// disconnects.
+pub(crate) async fn read_acks(
+    mut reader: OwnedReadHalf,
+    status: Arc<Mutex<ReplicaStatus>>,
+    acks: Arc<Notify>,
+) -> anyhow::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        while let Some(len) = command_frame_len(&buf)? {
+            let frame = buf.drain(..len).collect::<Vec<u8>>();
+            match Command::from_bytes(&frame) {
+                Ok((Command::ReplConf(ReplConfArg::Ack(offset)), _)) => {
+                    {
+                        let mut status = status.lock().unwrap();
+                        status.ack_offset = offset;
+                        status.ack_time = Instant::now();
+                    }
+                    acks.notify_waiters();
+                }
+                Ok(_) => {
+                    // Ignore all other commands
+                }
+                Err(err) => eprintln!("Ignoring unknown command from replica: {:?}", err),
+            }
+        }
+
+        let n = reader.read(&mut chunk).await.context("Read from replica")?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+tokio::task_local! {
+    /// Set while a write from the master is applied, telling it apart from the local writes a
+    /// replica accepts with `replica-read-only` off.
+    pub(crate) static APPLYING_MASTER_WRITE: ();
+}
+
+/// Appends the store's writes to the replication stream. Since the store calls it with the
+/// written database locked, a snapshot taken with [`super::store::RedisStore::snapshot_with`]
+/// and the stream agree on which writes the snapshot holds.
+pub(crate) struct ReplicationSink(pub(crate) SharedReplication);
+
+impl WriteSink for ReplicationSink {
+    fn write(&self, _seq: u64, cmd: &Command) {
+        let mut replication = self.0.lock().unwrap();
+        if replication.master.is_none() {
+            replication.propagate(&cmd.to_bytes());
+        } else if APPLYING_MASTER_WRITE.try_with(|_| ()).is_ok() {
+            if let Some(frame) = replication.pending_frame.take() {
+                replication.propagate(&frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acked_replicas_and_info() {
+        // Arrange
+        let mut replication = Replication::new(MasterInfo::new());
+        let mut queues = Vec::new();
+        for offset in [10, 20, 30] {
+            let (tx, rx) = mpsc::unbounded_channel();
+            queues.push(rx);
+            let mut status = ReplicaStatus::new(ReplicaState::Online);
+            status.ack_offset = offset;
+            replication.replicas.push(ReplicaConn {
+                writes: tx,
+                ip: IpAddr::from([127, 0, 0, 1]),
+                listening_port: Some(6380),
+                status: Arc::new(Mutex::new(status)),
+            });
+        }
+        queues.pop();
+
+        // Act & Assert
+        assert_eq!(replication.acked_replicas(0), 2);
+        assert_eq!(replication.acked_replicas(15), 1);
+        assert_eq!(replication.acked_replicas(25), 0);
+        assert_eq!(
+            replication.replicas_info(),
+            vec![
+                ("connected_slaves".to_string(), "2".to_string()),
+                (
+                    "slave0".to_string(),
+                    "ip=127.0.0.1,port=6380,state=online,offset=10,lag=0".to_string()
+                ),
+                (
+                    "slave1".to_string(),
+                    "ip=127.0.0.1,port=6380,state=online,offset=20,lag=0".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_good_replicas() {
+        // Arrange
+        let mut replication = Replication::new(MasterInfo::new());
+        let mut queues = Vec::new();
+        for (state, lag) in [
+            (ReplicaState::Online, 0),
+            (ReplicaState::Online, 30),
+            (ReplicaState::WaitBgsave, 0),
+        ] {
+            let (tx, rx) = mpsc::unbounded_channel();
+            queues.push(rx);
+            let mut status = ReplicaStatus::new(state);
+            status.ack_time = Instant::now() - Duration::from_secs(lag);
+            replication.replicas.push(ReplicaConn {
+                writes: tx,
+                ip: IpAddr::from([127, 0, 0, 1]),
+                listening_port: Some(6380),
+                status: Arc::new(Mutex::new(status)),
+            });
+        }
+
+        // Act & Assert
+        assert_eq!(replication.good_replicas(10), 1);
+        assert_eq!(replication.good_replicas(60), 2);
+    }
+}
@@ -1,25 +1,60 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::{Mutex, MutexGuard, Notify};
 
 use crate::{
-    command::XReadStreamArg,
+    command::{
+        Command, SetExpiry, XClaimOptions, XGroupArg, XPendingRange, XReadStart, XReadStreamArg,
+    },
     db::{
-        stream::{ReqStreamEntryID, StreamEntryID},
-        RedisDb, RedisValueType,
+        stream::{
+            now_millis, ConsumerInfo, GroupInfo, ReqStreamEntryID, StreamEntryData, StreamEntryID,
+            StreamInfo, StreamNodeLimits, StreamTrim, TrimStrategy,
+        },
+        GroupEntries, PendingEntries, PendingSummary, RedisDb, RedisValueType, StreamEntries,
     },
 };
 
+use super::aof::check_commands_base;
+
+/// A write applied to the store, numbered in the order writes were applied.
+pub(crate) type WriteFeedItem = (u64, Command);
+
+/// Receives every write the store applies, in the order they were applied. Called while the
+/// written database is still locked, so implementations must not block.
+pub(crate) trait WriteSink: Send + Sync {
+    fn write(&self, seq: u64, cmd: &Command);
+}
+
+/// A point-in-time copy of every database.
+pub(crate) struct Snapshot {
+    pub(crate) databases: HashMap<u32, RedisDb>,
+    /// Number of changes since the last save that the copy includes.
+    pub(crate) dirty: u64,
+    /// Sequence number of the last write the copy includes.
+    pub(crate) write_seq: u64,
+}
+
 #[derive(Clone)]
 pub(crate) struct RedisStore {
     databases: HashMap<u32, Arc<Mutex<RedisDb>>>,
     cur_db_num: u32,
+    dirty: Arc<AtomicU64>,
+    write_seq: Arc<AtomicU64>,
+    sinks: Vec<Arc<dyn WriteSink>>,
+    stream_node_limits: Arc<std::sync::Mutex<StreamNodeLimits>>,
 }
 
 impl RedisStore {
     pub(crate) fn new() -> Self {
         let mut databases = HashMap::new();
-        databases.insert(0 as u32, RedisDb::new());
+        databases.insert(0_u32, RedisDb::new());
         Self::from(databases)
     }
 
@@ -28,10 +63,14 @@ impl RedisStore {
             .into_iter()
             .map(|(k, v)| (k, Arc::new(Mutex::new(v))))
             .collect::<HashMap<u32, Arc<Mutex<RedisDb>>>>();
-        if let Some(&k) = &databases.keys().into_iter().min() {
+        if let Some(&k) = &databases.keys().min() {
             Self {
                 databases,
                 cur_db_num: k,
+                dirty: Arc::new(AtomicU64::new(0)),
+                write_seq: Arc::new(AtomicU64::new(0)),
+                sinks: Vec::new(),
+                stream_node_limits: Arc::new(std::sync::Mutex::new(StreamNodeLimits::default())),
             }
         } else {
             Self::new()
@@ -44,19 +83,215 @@ impl RedisStore {
             .expect("Current db number is valid")
     }
 
-    pub(crate) async fn get_stream_receiver(
-        &self,
-        key: &Vec<u8>,
-    ) -> broadcast::Receiver<(StreamEntryID, HashMap<Vec<u8>, Vec<u8>>)> {
-        self.get_cur_db().lock().await.get_stream_receiver(key)
+    /// Number of changes since the last successful save.
+    pub(crate) fn dirty(&self) -> u64 {
+        self.dirty.load(Ordering::SeqCst)
+    }
+
+    /// Marks `changes` as persisted, keeping any writes made while the snapshot was being saved.
+    pub(crate) fn clear_dirty(&self, changes: u64) {
+        self.dirty.fetch_sub(changes, Ordering::SeqCst);
+    }
+
+    /// Copies every database along with the counters describing which writes it contains.
+    pub(crate) async fn snapshot(&self) -> Snapshot {
+        self.snapshot_with(|_| ()).await.0
+    }
+
+    /// Locks every database, in ascending order so that concurrent callers cannot deadlock.
+    async fn lock_all(&self) -> Vec<(u32, MutexGuard<'_, RedisDb>)> {
+        let mut db_nums = self.databases.keys().copied().collect::<Vec<u32>>();
+        db_nums.sort();
+        let mut locked = Vec::with_capacity(db_nums.len());
+        for db_num in db_nums {
+            locked.push((db_num, self.databases[&db_num].lock().await));
+        }
+        locked
+    }
+
+    /// Checks that the dataset can be written as an AOF base of commands, see
+    /// [`check_commands_base`].
+    pub(crate) async fn check_commands_base(&self) -> anyhow::Result<()> {
+        let locked = self.lock_all().await;
+        check_commands_base(locked.iter().map(|(_, db)| &**db))
+    }
+
+    /// Takes a snapshot and, before any further write is applied, calls `f` with the sequence
+    /// number of the last write it includes. Write sinks can thereby tell apart the writes in
+    /// the snapshot from the ones after it.
+    pub(crate) async fn snapshot_with<T>(&self, f: impl FnOnce(u64) -> T) -> (Snapshot, T) {
+        let locked = self.lock_all().await;
+
+        let snapshot = Snapshot {
+            databases: locked
+                .iter()
+                .map(|(db_num, db)| (*db_num, db.snapshot()))
+                .collect(),
+            dirty: self.dirty(),
+            write_seq: self.write_seq(),
+        };
+        let res = f(snapshot.write_seq);
+        (snapshot, res)
+    }
+
+    /// Replaces the whole dataset, as a replica does after a full resync. Databases the store
+    /// was not created with are dropped.
+    pub(crate) async fn replace(&self, mut databases: HashMap<u32, RedisDb>) {
+        let mut locked = self.lock_all().await;
+
+        for (db_num, db) in locked.iter_mut() {
+            **db = databases.remove(db_num).unwrap_or_default();
+        }
+        if !databases.is_empty() {
+            eprintln!(
+                "Dropped {} databases not known to the store",
+                databases.len()
+            );
+        }
+    }
+
+    /// Sets the size of the nodes new stream entries are packed into. Shared with all clones.
+    pub(crate) fn set_stream_node_limits(&self, limits: StreamNodeLimits) {
+        *self.stream_node_limits.lock().unwrap() = limits;
+    }
+
+    fn stream_node_limits(&self) -> StreamNodeLimits {
+        *self.stream_node_limits.lock().unwrap()
+    }
+
+    /// Sends every subsequent write to `sink`. Clones made afterwards share the sink.
+    pub(crate) fn add_write_sink(&mut self, sink: Arc<dyn WriteSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Sequence number of the last applied write.
+    pub(crate) fn write_seq(&self) -> u64 {
+        self.write_seq.load(Ordering::SeqCst)
+    }
+
+    /// Records an applied write. Must be called while holding the lock of the database the write
+    /// was applied to, so the feed sees writes in the order they were applied.
+    fn feed(&self, cmd: Command) {
+        self.dirty.fetch_add(1, Ordering::SeqCst);
+        let seq = self.write_seq.fetch_add(1, Ordering::SeqCst) + 1;
+        for sink in self.sinks.iter() {
+            sink.write(seq, &cmd);
+        }
+    }
+
+    /// Applies a write received from the master or read back from the AOF.
+    pub(crate) async fn apply(&self, cmd: Command) -> anyhow::Result<()> {
+        match cmd {
+            Command::Set { key, value, expiry } => self.set(&key, value, expiry).await,
+            Command::XAdd {
+                key,
+                entry_id,
+                data,
+                nomkstream,
+                trim,
+            } => {
+                self.xadd(&key, entry_id, data, nomkstream, trim).await?;
+            }
+            Command::XDel { key, ids } => {
+                self.xdel(&key, &ids).await;
+            }
+            Command::XTrim { key, trim } => {
+                self.xtrim(&key, &trim).await;
+            }
+            Command::XSetId {
+                key,
+                last_id,
+                entries_added,
+                max_deleted_id,
+            } => {
+                self.xsetid(&key, last_id, entries_added, max_deleted_id)
+                    .await?;
+            }
+            Command::XGroup(arg) => {
+                self.xgroup(arg).await?;
+            }
+            Command::XReadGroup {
+                group,
+                consumer,
+                count,
+                noack,
+                streams,
+                ..
+            } => {
+                self.xreadgroup(&group, &consumer, count, noack, &streams, None)
+                    .await?;
+            }
+            Command::XAck { key, group, ids } => {
+                self.xack(&key, &group, &ids).await;
+            }
+            Command::XClaim {
+                key,
+                group,
+                consumer,
+                min_idle_time,
+                ids,
+                options,
+            } => {
+                self.xclaim(&key, &group, &consumer, min_idle_time, &ids, &options)
+                    .await?;
+            }
+            Command::XAutoClaim {
+                key,
+                group,
+                consumer,
+                min_idle_time,
+                start,
+                count,
+                just_id,
+            } => {
+                self.xautoclaim(
+                    &key,
+                    &group,
+                    &consumer,
+                    min_idle_time,
+                    &start,
+                    count,
+                    just_id,
+                )
+                .await?;
+            }
+            o => return Err(anyhow::anyhow!("Not a write command: {:?}", o)),
+        }
+        Ok(())
+    }
+
+    /// Drops `waiter` from the keys of `streams`, see [`RedisDb::unblock_streams`].
+    pub(crate) async fn unblock_streams(&self, streams: &[XReadStreamArg], waiter: &Arc<Notify>) {
+        self.get_cur_db()
+            .lock()
+            .await
+            .unblock_streams(streams.iter().map(|arg| &arg.key), waiter);
     }
 
     pub(crate) async fn get(&self, key: &Vec<u8>) -> Option<Vec<u8>> {
         self.get_cur_db().lock().await.get(key)
     }
 
-    pub(crate) async fn set(&self, key: &Vec<u8>, value: Vec<u8>, px: Option<Duration>) {
-        self.get_cur_db().lock().await.set(key, value, px)
+    pub(crate) async fn set(&self, key: &Vec<u8>, value: Vec<u8>, expiry: Option<SetExpiry>) {
+        let expire_at = expiry.map(|expiry| expiry.deadline());
+        let mut db = self.get_cur_db().lock().await;
+        db.set(key, value.clone(), expire_at);
+        // Log the absolute expiry so replaying the write doesn't extend the key's lifetime.
+        self.feed(Command::Set {
+            key: key.clone(),
+            value,
+            expiry: expire_at.map(SetExpiry::at),
+        });
+    }
+
+    /// Whether no database holds any key.
+    pub(crate) async fn is_empty(&self) -> bool {
+        for db in self.databases.values() {
+            if !db.lock().await.keys().is_empty() {
+                return false;
+            }
+        }
+        true
     }
 
     pub(crate) async fn keys(&self) -> Vec<Vec<u8>> {
@@ -67,28 +302,443 @@ impl RedisStore {
         self.get_cur_db().lock().await.lookup_type(key)
     }
 
+    /// Adds an entry to stream `key` and trims it, see [`RedisDb::xadd`].
     pub(crate) async fn xadd(
         &self,
         key: &Vec<u8>,
         entry_id: Option<ReqStreamEntryID>,
-        data: HashMap<Vec<u8>, Vec<u8>>,
-    ) -> anyhow::Result<StreamEntryID> {
-        self.get_cur_db().lock().await.xadd(key, entry_id, data)
+        data: StreamEntryData,
+        nomkstream: bool,
+        trim: Option<StreamTrim>,
+    ) -> anyhow::Result<Option<StreamEntryID>> {
+        let limits = self.stream_node_limits();
+        let mut db = self.get_cur_db().lock().await;
+        let Some(res) = db.xadd(key, entry_id, data.clone(), nomkstream, &limits)? else {
+            return Ok(None);
+        };
+        let trim = trim.map(|trim| {
+            db.xtrim(key, &trim, &limits);
+            exact_trim(&db, key, trim)
+        });
+        // Log the generated ID so replaying the write reproduces the same entry.
+        self.feed(Command::XAdd {
+            key: key.clone(),
+            entry_id: Some(ReqStreamEntryID {
+                millis: res.millis,
+                seq_num: Some(res.seq_num),
+            }),
+            data,
+            nomkstream: false,
+            trim,
+        });
+        Ok(Some(res))
+    }
+
+    pub(crate) async fn xlen(&self, key: &Vec<u8>) -> usize {
+        self.get_cur_db().lock().await.xlen(key)
+    }
+
+    pub(crate) async fn xdel(&self, key: &Vec<u8>, ids: &[StreamEntryID]) -> usize {
+        let mut db = self.get_cur_db().lock().await;
+        let deleted = db.xdel(key, ids);
+        if deleted > 0 {
+            self.feed(Command::XDel {
+                key: key.clone(),
+                ids: ids.to_vec(),
+            });
+        }
+        deleted
+    }
+
+    pub(crate) async fn xtrim(&self, key: &Vec<u8>, trim: &StreamTrim) -> usize {
+        let limits = self.stream_node_limits();
+        let mut db = self.get_cur_db().lock().await;
+        let trimmed = db.xtrim(key, trim, &limits);
+        if trimmed > 0 {
+            self.feed(Command::XTrim {
+                key: key.clone(),
+                trim: exact_trim(&db, key, trim.clone()),
+            });
+        }
+        trimmed
+    }
+
+    pub(crate) async fn xsetid(
+        &self,
+        key: &Vec<u8>,
+        last_id: StreamEntryID,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamEntryID>,
+    ) -> anyhow::Result<()> {
+        let mut db = self.get_cur_db().lock().await;
+        db.xsetid(key, last_id.clone(), entries_added, max_deleted_id.clone())?;
+        self.feed(Command::XSetId {
+            key: key.clone(),
+            last_id,
+            entries_added,
+            max_deleted_id,
+        });
+        Ok(())
+    }
+
+    pub(crate) async fn xinfo_stream(
+        &self,
+        key: &Vec<u8>,
+        full: Option<usize>,
+    ) -> anyhow::Result<StreamInfo> {
+        self.get_cur_db().lock().await.xinfo_stream(key, full)
+    }
+
+    pub(crate) async fn xinfo_groups(&self, key: &Vec<u8>) -> anyhow::Result<Vec<GroupInfo>> {
+        self.get_cur_db().lock().await.xinfo_groups(key)
+    }
+
+    pub(crate) async fn xinfo_consumers(
+        &self,
+        key: &Vec<u8>,
+        group: &[u8],
+    ) -> anyhow::Result<Vec<ConsumerInfo>> {
+        self.get_cur_db().lock().await.xinfo_consumers(key, group)
     }
 
     pub(crate) async fn xrange(
         &self,
         key: &Vec<u8>,
-        start: StreamEntryID,
-        end: StreamEntryID,
-    ) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
-        self.get_cur_db().lock().await.xrange(key, start, end)
+        start: &StreamEntryID,
+        end: &StreamEntryID,
+        count: Option<usize>,
+        rev: bool,
+    ) -> StreamEntries {
+        self.get_cur_db()
+            .lock()
+            .await
+            .xrange(key, start, end, count, rev)
+    }
+
+    pub(crate) async fn xread_resolve(&self, args: &[XReadStreamArg]) -> Vec<XReadStreamArg> {
+        self.get_cur_db().lock().await.xread_resolve(args)
     }
 
+    /// Reads `args`, see [`RedisDb::xread`]. If nothing is read, `waiter` is registered under
+    /// their keys before the database is unlocked, so no entry added after the read is missed.
     pub(crate) async fn xread(
         &self,
-        args: &Vec<XReadStreamArg>,
-    ) -> Vec<(Vec<u8>, Vec<(Vec<u8>, Vec<Vec<u8>>)>)> {
-        self.get_cur_db().lock().await.xread(args)
+        args: &[XReadStreamArg],
+        count: Option<usize>,
+        waiter: Option<&Arc<Notify>>,
+    ) -> Vec<(Vec<u8>, StreamEntries)> {
+        let mut db = self.get_cur_db().lock().await;
+        let data = db.xread(args, count);
+        if let Some(waiter) = waiter.filter(|_| data.is_empty()) {
+            db.block_on_streams(args.iter().map(|arg| &arg.key), waiter);
+        }
+        data
+    }
+
+    /// Runs an XGROUP subcommand, returning the count DESTROY, CREATECONSUMER and DELCONSUMER
+    /// reply with.
+    pub(crate) async fn xgroup(&self, arg: XGroupArg) -> anyhow::Result<usize> {
+        let now = now_millis();
+        let mut db = self.get_cur_db().lock().await;
+        match arg {
+            XGroupArg::Create {
+                key,
+                group,
+                id,
+                mkstream,
+                entries_read,
+            } => {
+                let id = db.xgroup_create(&key, &group, id, mkstream, entries_read)?;
+                let entries_read = db
+                    .stream_group(&key, &group)
+                    .and_then(|group| group.entries_read);
+                // Log the resolved ID so replaying the write doesn't depend on the stream's end.
+                self.feed(Command::XGroup(XGroupArg::Create {
+                    key,
+                    group,
+                    id: Some(id),
+                    mkstream,
+                    entries_read,
+                }));
+                Ok(0)
+            }
+            XGroupArg::SetId {
+                key,
+                group,
+                id,
+                entries_read,
+            } => {
+                let id = db.xgroup_setid(&key, &group, id, entries_read)?;
+                let entries_read = db
+                    .stream_group(&key, &group)
+                    .and_then(|group| group.entries_read);
+                self.feed(Command::XGroup(XGroupArg::SetId {
+                    key,
+                    group,
+                    id: Some(id),
+                    entries_read,
+                }));
+                Ok(0)
+            }
+            XGroupArg::Destroy { key, group } => {
+                let destroyed = db.xgroup_destroy(&key, &group)?;
+                if destroyed {
+                    self.feed(Command::XGroup(XGroupArg::Destroy { key, group }));
+                }
+                Ok(destroyed as usize)
+            }
+            XGroupArg::CreateConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let created = db.xgroup_createconsumer(&key, &group, &consumer, now)?;
+                if created {
+                    self.feed(Command::XGroup(XGroupArg::CreateConsumer {
+                        key,
+                        group,
+                        consumer,
+                    }));
+                }
+                Ok(created as usize)
+            }
+            XGroupArg::DelConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let pending = db.xgroup_delconsumer(&key, &group, &consumer)?;
+                self.feed(Command::XGroup(XGroupArg::DelConsumer {
+                    key,
+                    group,
+                    consumer,
+                }));
+                Ok(pending)
+            }
+        }
+    }
+
+    /// Logs the creation of `consumer` by a read or claim if it does not exist yet.
+    fn feed_new_consumer(&self, db: &RedisDb, key: &Vec<u8>, group: &[u8], consumer: &[u8]) {
+        if db
+            .stream_group(key, group)
+            .is_some_and(|group| !group.consumers.contains_key(consumer))
+        {
+            self.feed(Command::XGroup(XGroupArg::CreateConsumer {
+                key: key.clone(),
+                group: group.to_vec(),
+                consumer: consumer.to_vec(),
+            }));
+        }
+    }
+
+    /// Logs the deliveries of `ids` to `consumer` as XCLAIMs forcing their pending state, and
+    /// the entries no longer pending as XCLAIMs dropping them. Replaying these doesn't depend on
+    /// the time or on which entries the group would deliver next.
+    fn feed_claims(
+        &self,
+        db: &RedisDb,
+        key: &Vec<u8>,
+        group: &[u8],
+        consumer: &[u8],
+        ids: &[StreamEntryID],
+    ) {
+        let Some(stream_group) = db.stream_group(key, group) else {
+            return;
+        };
+        for id in ids {
+            let options = match stream_group.pel.get(id) {
+                Some(entry) => XClaimOptions {
+                    time: Some(entry.delivery_time),
+                    retry_count: Some(entry.delivery_count),
+                    force: true,
+                    just_id: true,
+                    last_id: Some(stream_group.last_delivered.clone()),
+                    ..Default::default()
+                },
+                None => XClaimOptions {
+                    just_id: true,
+                    ..Default::default()
+                },
+            };
+            self.feed(Command::XClaim {
+                key: key.clone(),
+                group: group.to_vec(),
+                consumer: consumer.to_vec(),
+                min_idle_time: 0,
+                ids: vec![id.clone()],
+                options,
+            });
+        }
+    }
+
+    /// Logs the last delivered ID of a group.
+    fn feed_group_id(&self, db: &RedisDb, key: &Vec<u8>, group: &[u8]) {
+        if let Some(stream_group) = db.stream_group(key, group) {
+            self.feed(Command::XGroup(XGroupArg::SetId {
+                key: key.clone(),
+                group: group.to_vec(),
+                id: Some(stream_group.last_delivered.clone()),
+                entries_read: stream_group.entries_read,
+            }));
+        }
+    }
+
+    /// Reads `streams` as `consumer` of `group`. Streams without new entries are left out of
+    /// the result, while reads of pending entries are always included.
+    pub(crate) async fn xreadgroup(
+        &self,
+        group: &[u8],
+        consumer: &[u8],
+        count: Option<usize>,
+        noack: bool,
+        streams: &[XReadStreamArg],
+        waiter: Option<&Arc<Notify>>,
+    ) -> anyhow::Result<Vec<(Vec<u8>, GroupEntries)>> {
+        let now = now_millis();
+        let mut db = self.get_cur_db().lock().await;
+        let new_consumer = streams
+            .iter()
+            .map(|arg| {
+                db.stream_group(&arg.key, group)
+                    .is_some_and(|group| !group.consumers.contains_key(consumer))
+            })
+            .collect::<Vec<bool>>();
+        let read = db.xreadgroup(group, consumer, count, noack, streams, now)?;
+
+        let mut res = Vec::new();
+        for ((arg, ids), new_consumer) in streams.iter().zip(read).zip(new_consumer) {
+            if new_consumer {
+                self.feed(Command::XGroup(XGroupArg::CreateConsumer {
+                    key: arg.key.clone(),
+                    group: group.to_vec(),
+                    consumer: consumer.to_vec(),
+                }));
+            }
+            let entries = db.group_entries(&arg.key, &ids);
+            if matches!(arg.start, XReadStart::After(_)) {
+                // Deleted entries stay pending as they are.
+                let delivered = ids
+                    .iter()
+                    .zip(entries.iter())
+                    .filter(|(_, (_, data))| data.is_some())
+                    .map(|(id, _)| id.clone())
+                    .collect::<Vec<_>>();
+                self.feed_claims(&db, &arg.key, group, consumer, &delivered);
+                res.push((arg.key.clone(), entries));
+            } else if !ids.is_empty() {
+                if !noack {
+                    self.feed_claims(&db, &arg.key, group, consumer, &ids);
+                }
+                self.feed_group_id(&db, &arg.key, group);
+                res.push((arg.key.clone(), entries));
+            }
+        }
+        // Only reads of new entries come back empty, see `xread` for the waiter.
+        if let Some(waiter) = waiter.filter(|_| res.is_empty()) {
+            db.block_on_streams(streams.iter().map(|arg| &arg.key), waiter);
+        }
+        Ok(res)
+    }
+
+    /// Acknowledges entries of a group, returning how many were pending.
+    pub(crate) async fn xack(&self, key: &Vec<u8>, group: &[u8], ids: &[StreamEntryID]) -> usize {
+        let mut db = self.get_cur_db().lock().await;
+        let acked = db.xack(key, group, ids);
+        if acked > 0 {
+            self.feed(Command::XAck {
+                key: key.clone(),
+                group: group.to_vec(),
+                ids: ids.to_vec(),
+            });
+        }
+        acked
+    }
+
+    pub(crate) async fn xpending_summary(
+        &self,
+        key: &Vec<u8>,
+        group: &[u8],
+    ) -> anyhow::Result<PendingSummary> {
+        self.get_cur_db().lock().await.xpending_summary(key, group)
+    }
+
+    pub(crate) async fn xpending_range(
+        &self,
+        key: &Vec<u8>,
+        group: &[u8],
+        range: &XPendingRange,
+    ) -> anyhow::Result<PendingEntries> {
+        self.get_cur_db()
+            .lock()
+            .await
+            .xpending_range(key, group, range, now_millis())
+    }
+
+    /// Claims pending entries for `consumer`, returning the claimed ones.
+    pub(crate) async fn xclaim(
+        &self,
+        key: &Vec<u8>,
+        group: &[u8],
+        consumer: &[u8],
+        min_idle_time: u64,
+        ids: &[StreamEntryID],
+        options: &XClaimOptions,
+    ) -> anyhow::Result<GroupEntries> {
+        let now = now_millis();
+        let mut db = self.get_cur_db().lock().await;
+        self.feed_new_consumer(&db, key, group, consumer);
+        let (claimed, dropped) =
+            db.xclaim(key, group, consumer, min_idle_time, ids, options, now)?;
+        self.feed_claims(&db, key, group, consumer, &claimed);
+        self.feed_claims(&db, key, group, consumer, &dropped);
+        if options.last_id.is_some() {
+            self.feed_group_id(&db, key, group);
+        }
+        Ok(db.group_entries(key, &claimed))
+    }
+
+    /// Claims idle pending entries for `consumer`, returning the ID to continue from, the
+    /// claimed entries and the IDs of the entries dropped since they were deleted.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn xautoclaim(
+        &self,
+        key: &Vec<u8>,
+        group: &[u8],
+        consumer: &[u8],
+        min_idle_time: u64,
+        start: &StreamEntryID,
+        count: usize,
+        just_id: bool,
+    ) -> anyhow::Result<(StreamEntryID, GroupEntries, Vec<StreamEntryID>)> {
+        let now = now_millis();
+        let mut db = self.get_cur_db().lock().await;
+        self.feed_new_consumer(&db, key, group, consumer);
+        let (next, claimed, dropped) = db.xautoclaim(
+            key,
+            group,
+            consumer,
+            min_idle_time,
+            start,
+            count,
+            just_id,
+            now,
+        )?;
+        self.feed_claims(&db, key, group, consumer, &claimed);
+        self.feed_claims(&db, key, group, consumer, &dropped);
+        Ok((next, db.group_entries(key, &claimed), dropped))
+    }
+}
+
+/// Trimming to propagate once `trim` was applied to stream `key`. Approximate trimming depends
+/// on how entries are laid out in nodes, so it is replaced with the length the stream ended up at.
+fn exact_trim(db: &RedisDb, key: &Vec<u8>, trim: StreamTrim) -> StreamTrim {
+    if !trim.approx {
+        return trim;
+    }
+    StreamTrim {
+        strategy: TrimStrategy::MaxLen(db.xlen(key)),
+        approx: false,
+        limit: None,
     }
 }
@@ -9,7 +9,7 @@ pub fn split_by_clrf(bytes: &[u8]) -> Option<(Vec<u8>, &[u8])> {
         return None;
     }
 
-    return Some((data.into(), &remaining[1..]));
+    Some((data.into(), &remaining[1..]))
 }
 
 pub fn bytes2usize(bytes: &[u8]) -> anyhow::Result<usize> {